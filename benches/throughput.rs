@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use banking_exercise::models::{account::Account, csv_record::CsvLayout, transaction::Transaction};
+use banking_exercise::processor::TransactionProcessor;
+use banking_exercise::workload::Workload;
+
+const TRANSACTIONS: u64 = 100_000;
+const WORKER_COUNTS: [usize; 4] = [1, 2, 4, 8];
+const BATCH_SIZE: usize = 1_000;
+
+fn workload() -> Workload {
+    Workload::new(42).with_transactions(TRANSACTIONS)
+}
+
+// Submitting every transaction of the workload and waiting for the workers to apply them, one at a
+// time and in batches.
+fn end_to_end(c: &mut Criterion) {
+    let txns = workload().generate();
+    let mut group = c.benchmark_group("end_to_end");
+    group
+        .sample_size(20)
+        .throughput(Throughput::Elements(TRANSACTIONS));
+    for workers in WORKER_COUNTS {
+        group.bench_with_input(BenchmarkId::new("txn", workers), &workers, |b, &workers| {
+            b.iter(|| {
+                let processor = TransactionProcessor::new(workers);
+                for txn in &txns {
+                    processor.process_txn(txn.clone()).unwrap();
+                }
+                processor.shutdown().unwrap()
+            })
+        });
+        group.bench_with_input(
+            BenchmarkId::new("batch", workers),
+            &workers,
+            |b, &workers| {
+                b.iter(|| {
+                    let processor = TransactionProcessor::new(workers);
+                    for batch in txns.chunks(BATCH_SIZE) {
+                        processor.process_batch(batch).unwrap();
+                    }
+                    processor.shutdown().unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+// The stages of the pipeline that a single transaction goes through, each on its own: reading the
+// CSV records, deserializing them through serde or parsing them directly, and applying them to the
+// accounts.
+fn stages(c: &mut Criterion) {
+    let workload = workload();
+    let mut csv = vec![];
+    workload.write_csv(&mut csv).unwrap();
+    let txns = workload.generate();
+
+    let mut group = c.benchmark_group("stages");
+    group.throughput(Throughput::Elements(TRANSACTIONS));
+    group.bench_function("read", |b| {
+        b.iter(|| {
+            let mut reader = csv::Reader::from_reader(csv.as_slice());
+            let mut record = csv::ByteRecord::new();
+            let mut records = 0;
+            while reader.read_byte_record(&mut record).unwrap() {
+                records += 1;
+            }
+            records
+        })
+    });
+
+    let mut reader = csv::Reader::from_reader(csv.as_slice());
+    let headers = reader.byte_headers().unwrap().clone();
+    let records: Vec<_> = reader.byte_records().map(Result::unwrap).collect();
+    group.bench_function("deserialize", |b| {
+        b.iter(|| {
+            records
+                .iter()
+                .map(|record| record.deserialize::<Transaction>(Some(&headers)).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+    let layout = CsvLayout::from_headers(&headers).unwrap();
+    group.bench_function("parse", |b| {
+        b.iter(|| {
+            records
+                .iter()
+                .map(|record| layout.parse(record).unwrap())
+                .collect::<Vec<_>>()
+        })
+    });
+
+    group.bench_function("apply", |b| {
+        b.iter(|| {
+            let mut accounts = HashMap::new();
+            for txn in &txns {
+                let _ = accounts
+                    .entry(txn.account_id())
+                    .or_insert_with(|| Account::new(txn.account_id()))
+                    .process_txn(txn.clone());
+            }
+            accounts
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, end_to_end, stages);
+criterion_main!(benches);
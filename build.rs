@@ -0,0 +1,12 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto/banking.proto");
+
+    // The protobuf types are generated from our schema by prost, with the schema compiled by
+    // protox rather than protoc, so that building them needs nothing installed but Rust.
+    #[cfg(feature = "protobuf")]
+    {
+        let descriptors = protox::compile(["banking.proto"], ["proto"])?;
+        prost_build::Config::new().compile_fds(descriptors)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+
+use banking_exercise::invariants;
+use banking_exercise::models::{
+    account::Account,
+    transaction::{RawTransactionId, Transaction, TransactionId, TransactionType},
+};
+
+// A transaction of the account, whose ID is drawn from a small range so that disputes, resolutions
+// and chargebacks often refer to transactions that were applied, and deposits and withdrawals
+// often reuse their IDs.
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Deposit { tx: u8, amount: u32 },
+    Withdrawal { tx: u8, amount: u32 },
+    Dispute { tx: u8 },
+    Resolve { tx: u8 },
+    Chargeback { tx: u8 },
+}
+
+impl Op {
+    fn into_txn(self) -> Transaction {
+        let (tx, txn_type) = match self {
+            Op::Deposit { tx, amount } => (
+                tx,
+                TransactionType::Deposit {
+                    amount: Decimal::new(amount.into(), 4),
+                },
+            ),
+            Op::Withdrawal { tx, amount } => (
+                tx,
+                TransactionType::Withdrawal {
+                    amount: Decimal::new(amount.into(), 4),
+                },
+            ),
+            Op::Dispute { tx } => (tx, TransactionType::Dispute),
+            Op::Resolve { tx } => (tx, TransactionType::Resolve),
+            Op::Chargeback { tx } => (tx, TransactionType::Chargeback),
+        };
+        Transaction::new(
+            TransactionId::from(RawTransactionId::from(tx % 16)),
+            1.into(),
+            txn_type,
+        )
+    }
+}
+
+// Applies an arbitrary sequence of transactions to an account, checking that each had exactly the
+// effect it should have had, and left the account consistent.
+fuzz_target!(|ops: Vec<Op>| {
+    let mut account = Account::new(1.into());
+    for op in ops {
+        let txn = op.into_txn();
+        let before = account.clone();
+        let result = account.process_txn(txn);
+        let failures = invariants::check_transition(&before, &txn, &result, &account);
+        assert!(failures.is_empty(), "{txn}: {failures:?}");
+    }
+});
@@ -0,0 +1,55 @@
+#![no_main]
+
+use std::collections::HashMap;
+
+use libfuzzer_sys::fuzz_target;
+
+use banking_exercise::models::{
+    account::Account,
+    transaction::{LenientTransaction, Transaction},
+};
+
+// Reads arbitrary bytes as a transactions file in our own CSV format, both strictly and leniently,
+// and applies whatever parses to the accounts, as a run would.
+fuzz_target!(|data: &[u8]| {
+    for lenient in [false, true] {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(if lenient {
+                csv::Trim::All
+            } else {
+                csv::Trim::None
+            })
+            .from_reader(data);
+        let Ok(headers) = reader.byte_headers().cloned() else {
+            return;
+        };
+
+        let mut accounts = HashMap::new();
+        for record in reader.byte_records() {
+            let Ok(record) = record else {
+                break;
+            };
+            let txn = if lenient {
+                record
+                    .deserialize::<LenientTransaction>(Some(&headers))
+                    .map(Transaction::from)
+            } else {
+                record.deserialize::<Transaction>(Some(&headers))
+            };
+            let Ok(txn) = txn else {
+                continue;
+            };
+            let txn = if txn.has_excess_precision() {
+                txn.round_amount()
+            } else {
+                txn
+            };
+
+            let account = accounts
+                .entry(txn.account_key())
+                .or_insert_with(|| Account::new(txn.account_id()).with_tenant(txn.tenant()));
+            let _ = account.process_txn(txn);
+            assert_eq!(account.total(), account.available() + account.held());
+        }
+    }
+});
@@ -0,0 +1,20 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use banking_exercise::models::transaction::Transaction;
+
+// Reads arbitrary bytes as a transaction in JSON, as they are recorded in write-ahead logs, and
+// checks that any that parses reads back the same once written out again.
+fuzz_target!(|data: &[u8]| {
+    let Ok(txn) = serde_json::from_slice::<Transaction>(data) else {
+        return;
+    };
+    let json = serde_json::to_string(&txn).expect("a transaction can always be serialized");
+    let reread: Transaction =
+        serde_json::from_str(&json).expect("a serialized transaction can be read back");
+    assert_eq!(
+        serde_json::to_string(&reread).expect("a transaction can always be serialized"),
+        json
+    );
+});
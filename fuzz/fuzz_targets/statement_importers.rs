@@ -0,0 +1,28 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use banking_exercise::bank_profile::BankProfile;
+use banking_exercise::importer::{
+    Camt053Importer, Importer, MappedCsvImporter, OfxImporter, QifImporter,
+};
+use banking_exercise::qif;
+
+// Reads arbitrary bytes as each of the statement formats that are imported whole.
+fuzz_target!(|data: &[u8]| {
+    let importers: [Box<dyn Importer>; 4] = [
+        Box::new(OfxImporter),
+        Box::new(Camt053Importer),
+        Box::new(QifImporter(qif::default_profile())),
+        Box::new(MappedCsvImporter(BankProfile::native())),
+    ];
+    for importer in importers {
+        for record in importer.import(data) {
+            if let Ok(txn) = record.result {
+                if let Some(amount) = txn.txn_type().amount() {
+                    assert!(amount.is_sign_positive() || amount.is_zero());
+                }
+            }
+        }
+    }
+});
@@ -0,0 +1,230 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+// How the worker threads are placed on the CPUs of the machine. By default they float freely, and
+// are moved between CPUs by the scheduler as it sees fit. On machines with several NUMA nodes,
+// this has workers reaching across nodes for their accounts, which were allocated on whichever
+// node they happened to be running on at the time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WorkerPlacement {
+    // Leaves the workers to the scheduler.
+    Float,
+    // Pins each worker to a CPU of its own, spread across the nodes in turn.
+    Cores,
+    // Pins each worker to the CPUs of a node, with the workers shared out across the nodes in turn.
+    Numa,
+    // Pins workers to nodes on machines with more than one, and leaves them float otherwise.
+    Auto,
+}
+
+impl WorkerPlacement {
+    // The CPUs each worker is to be pinned to, or none for a worker left to float.
+    pub fn cpus_for_workers(&self, topology: &CpuTopology, num_workers: usize) -> Vec<Vec<usize>> {
+        let placement = match self {
+            Self::Auto if topology.nodes.len() > 1 => Self::Numa,
+            Self::Auto => Self::Float,
+            placement => *placement,
+        };
+        let nodes = &topology.nodes;
+        match placement {
+            Self::Float | Self::Auto => vec![vec![]; num_workers],
+            Self::Numa => (0..num_workers)
+                .map(|worker_idx| nodes[worker_idx % nodes.len()].clone())
+                .collect(),
+            Self::Cores => {
+                // Takes a CPU from each node in turn, so that workers are spread evenly across
+                // them. Beyond one worker per CPU, the CPUs are shared.
+                let deepest = nodes.iter().map(Vec::len).max().unwrap_or(0);
+                let cpus: Vec<usize> = (0..deepest)
+                    .flat_map(|idx| nodes.iter().filter_map(move |node| node.get(idx).copied()))
+                    .collect();
+                (0..num_workers)
+                    .map(|worker_idx| vec![cpus[worker_idx % cpus.len()]])
+                    .collect()
+            }
+        }
+    }
+}
+
+impl FromStr for WorkerPlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "float" => Ok(Self::Float),
+            "cores" => Ok(Self::Cores),
+            "numa" => Ok(Self::Numa),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!("'{s}' is not a valid worker placement.")),
+        }
+    }
+}
+
+// The CPUs of each NUMA node of the machine that the process is allowed to run on. Nodes without
+// any such CPUs are left out, and every node has at least one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CpuTopology {
+    nodes: Vec<Vec<usize>>,
+}
+
+impl CpuTopology {
+    // Detects the topology of the machine from sysfs, or treats it as a single node where that
+    // isn't available.
+    pub fn detect() -> io::Result<Self> {
+        let allowed = allowed_cpus()?;
+        let nodes = node_cpus(Path::new("/sys/devices/system/node")).unwrap_or_default();
+        Ok(Self::new(nodes, allowed))
+    }
+
+    fn new(nodes: Vec<Vec<usize>>, allowed: Vec<usize>) -> Self {
+        let mut nodes: Vec<Vec<usize>> = nodes
+            .into_iter()
+            .map(|cpus| {
+                cpus.into_iter()
+                    .filter(|cpu| allowed.contains(cpu))
+                    .collect()
+            })
+            .filter(|cpus: &Vec<usize>| !cpus.is_empty())
+            .collect();
+        if nodes.is_empty() {
+            nodes.push(allowed);
+        }
+        Self { nodes }
+    }
+
+    pub fn nodes(&self) -> &[Vec<usize>] {
+        &self.nodes
+    }
+}
+
+// The CPUs of each node, in the order of the nodes.
+fn node_cpus(dir: &Path) -> io::Result<Vec<Vec<usize>>> {
+    let mut nodes = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(node) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix("node"))
+            .and_then(|node| node.parse::<usize>().ok())
+        else {
+            continue;
+        };
+        let cpus = fs::read_to_string(entry.path().join("cpulist"))?;
+        nodes.push((
+            node,
+            parse_cpu_list(&cpus).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid CPU list: {cpus}"),
+                )
+            })?,
+        ));
+    }
+    nodes.sort();
+    Ok(nodes.into_iter().map(|(_, cpus)| cpus).collect())
+}
+
+// Parses a list of CPUs as sysfs writes them, e.g. "0-3,8-11".
+fn parse_cpu_list(list: &str) -> Option<Vec<usize>> {
+    let list = list.trim();
+    if list.is_empty() {
+        return Some(vec![]);
+    }
+    let mut cpus = vec![];
+    for range in list.split(',') {
+        let (start, end) = range.split_once('-').unwrap_or((range, range));
+        cpus.extend(start.parse::<usize>().ok()?..=end.parse().ok()?);
+    }
+    Some(cpus)
+}
+
+// The CPUs the process is allowed to run on.
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    // Safety: the set is zeroed before use, and only read within its size.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> io::Result<Vec<usize>> {
+    Ok((0..num_cpus::get()).collect())
+}
+
+// Pins the current thread to the given CPUs. Memory the thread goes on to allocate is then taken
+// from the node of those CPUs, by the kernel's default policy of allocating memory on the node of
+// the CPU that first touches it.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> io::Result<()> {
+    // Safety: the set is zeroed before use, and only CPUs within its size are set.
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &cpu in cpus.iter().filter(|&&cpu| cpu < libc::CPU_SETSIZE as usize) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "threads can only be pinned on Linux",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn placements() -> Result<(), String> {
+        assert_eq!(parse_cpu_list("0-3,8-9\n"), Some(vec![0, 1, 2, 3, 8, 9]));
+        assert_eq!(parse_cpu_list("0-x"), None);
+
+        // CPUs the process isn't allowed to run on are left out, along with the nodes emptied.
+        let topology = CpuTopology::new(
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8]],
+            vec![0, 1, 2, 4, 5, 6, 7],
+        );
+        assert_eq!(topology.nodes(), [vec![0, 1, 2], vec![4, 5, 6, 7]]);
+
+        let cores = "cores".parse::<WorkerPlacement>()?;
+        assert_eq!(
+            cores.cpus_for_workers(&topology, 9),
+            [[0], [4], [1], [5], [2], [6], [7], [0], [4]],
+            "workers should be spread across the nodes"
+        );
+        let numa = "numa".parse::<WorkerPlacement>()?;
+        assert_eq!(
+            numa.cpus_for_workers(&topology, 3),
+            [vec![0, 1, 2], vec![4, 5, 6, 7], vec![0, 1, 2]]
+        );
+        let auto = "auto".parse::<WorkerPlacement>()?;
+        assert_eq!(
+            auto.cpus_for_workers(&topology, 3),
+            numa.cpus_for_workers(&topology, 3)
+        );
+        let single = CpuTopology::new(vec![], vec![0, 1]);
+        assert!(
+            auto.cpus_for_workers(&single, 2).iter().all(Vec::is_empty),
+            "workers should float on a single node"
+        );
+
+        Ok(())
+    }
+}
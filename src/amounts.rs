@@ -0,0 +1,302 @@
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, RandomState};
+use std::io::Write;
+use std::sync::{Mutex, OnceLock};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::{
+    account::{Account, AccountKey, TransactionError},
+    transaction::{OrderedTransaction, TransactionType},
+};
+use crate::observer::EventObserver;
+
+// The number of bounds of the buckets amounts are counted in, which run 1, 2, 5 a decade from 0.01
+// to 5,000,000.
+pub const NUM_BOUNDS: usize = 27;
+
+// The number of locks the amounts of a run are counted under, so that workers counting the amounts
+// of different accounts seldom wait on one another.
+const NUM_SHARDS: usize = 16;
+
+// The upper bound of the given bucket, inclusive.
+pub fn bound(bucket: usize) -> Decimal {
+    bounds()[bucket]
+}
+
+fn bounds() -> &'static [Decimal; NUM_BOUNDS] {
+    static BOUNDS: OnceLock<[Decimal; NUM_BOUNDS]> = OnceLock::new();
+    BOUNDS.get_or_init(|| {
+        std::array::from_fn(|bucket| {
+            let mantissa = [1, 2, 5][bucket % 3];
+            let exponent = bucket as i32 / 3 - 2;
+            if exponent < 0 {
+                Decimal::new(mantissa, exponent.unsigned_abs())
+            } else {
+                Decimal::from(mantissa * 10_i64.pow(exponent as u32))
+            }
+        })
+    })
+}
+
+// The distribution of a set of amounts, counted in buckets of fixed bounds, so that it takes the
+// same space however many amounts it's made up of. Percentiles are estimated from the buckets, as
+// the bound of the bucket they fall in, so are only as precise as the buckets are fine.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AmountHistogram {
+    // The number of amounts in each bucket, where the last holds those larger than every bound.
+    buckets: [u64; NUM_BOUNDS + 1],
+    count: u64,
+    sum: Decimal,
+    min: Option<Decimal>,
+    max: Option<Decimal>,
+}
+
+impl AmountHistogram {
+    pub fn record(&mut self, amount: Decimal) {
+        self.buckets[bounds().partition_point(|bound| *bound < amount)] += 1;
+        self.count += 1;
+        self.sum += amount;
+        self.min = Some(self.min.map_or(amount, |min| min.min(amount)));
+        self.max = Some(self.max.map_or(amount, |max| max.max(amount)));
+    }
+
+    // Adds the amounts of another histogram to this one's.
+    pub fn merge(&mut self, other: &Self) {
+        for (bucket, count) in self.buckets.iter_mut().zip(other.buckets) {
+            *bucket += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> Decimal {
+        self.sum
+    }
+
+    pub fn min(&self) -> Option<Decimal> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Decimal> {
+        self.max
+    }
+
+    // The number of amounts no larger than each bound, followed by the number of all of them.
+    pub fn cumulative_counts(&self) -> impl Iterator<Item = u64> + '_ {
+        self.buckets.iter().scan(0, |count, bucket| {
+            *count += bucket;
+            Some(*count)
+        })
+    }
+
+    // An estimate of the amount that the given proportion of amounts are no larger than, between 0
+    // and 1, unless there are no amounts at all.
+    pub fn percentile(&self, proportion: f64) -> Option<Decimal> {
+        let (min, max) = (self.min?, self.max?);
+        let rank = ((proportion * self.count as f64).ceil() as u64).max(1);
+        let bucket = self.cumulative_counts().position(|count| count >= rank)?;
+        let estimate = if bucket < NUM_BOUNDS {
+            bound(bucket)
+        } else {
+            max
+        };
+        Some(estimate.clamp(min, max))
+    }
+}
+
+// The distributions of the amounts of deposits and withdrawals.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AmountDistribution {
+    pub deposits: AmountHistogram,
+    pub withdrawals: AmountHistogram,
+}
+
+impl AmountDistribution {
+    fn record(&mut self, txn_type: TransactionType) {
+        match txn_type {
+            TransactionType::Deposit { amount } => self.deposits.record(amount),
+            TransactionType::Withdrawal { amount } => self.withdrawals.record(amount),
+            _ => (),
+        }
+    }
+
+    fn merge(&mut self, other: &Self) {
+        self.deposits.merge(&other.deposits);
+        self.withdrawals.merge(&other.withdrawals);
+    }
+}
+
+// A row of the amount stats export.
+#[derive(Serialize)]
+struct AmountStatsRow {
+    scope: String,
+    #[serde(rename = "type")]
+    txn_type: &'static str,
+    count: u64,
+    min: Option<Decimal>,
+    p50: Option<Decimal>,
+    p90: Option<Decimal>,
+    p99: Option<Decimal>,
+    max: Option<Decimal>,
+}
+
+// Counts the amounts of the deposits and withdrawals of a run, to help spot an input that looks
+// unlike those that came before it, such as one with amounts in cents rather than dollars, before
+// it's posted. Every deposit and withdrawal the workers are handed is counted, whether it's applied
+// or not, and they may also be counted for each client.
+#[derive(Debug)]
+pub struct AmountStats {
+    // The amounts of the run, sharded by account.
+    run: Vec<Mutex<AmountDistribution>>,
+    hasher: RandomState,
+    clients: Option<Mutex<BTreeMap<AccountKey, AmountDistribution>>>,
+}
+
+impl AmountStats {
+    pub fn new(by_client: bool) -> Self {
+        Self {
+            run: (0..NUM_SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+            clients: by_client.then(Mutex::default),
+        }
+    }
+
+    // The distributions of the whole run.
+    pub fn run(&self) -> AmountDistribution {
+        let mut run = AmountDistribution::default();
+        for shard in &self.run {
+            run.merge(&shard.lock().expect("amount stats lock poisoned"));
+        }
+        run
+    }
+
+    fn record(&self, txn: &OrderedTransaction) {
+        let txn = txn.txn();
+        if !matches!(
+            txn.txn_type(),
+            TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+        ) {
+            return;
+        }
+        let shard = self.hasher.hash_one(txn.account_key()) as usize % NUM_SHARDS;
+        self.run[shard]
+            .lock()
+            .expect("amount stats lock poisoned")
+            .record(txn.txn_type());
+        if let Some(clients) = &self.clients {
+            clients
+                .lock()
+                .expect("amount stats lock poisoned")
+                .entry(txn.account_key())
+                .or_default()
+                .record(txn.txn_type());
+        }
+    }
+
+    // Writes the distributions as CSV, with the count, minimum, estimated 50th, 90th and 99th
+    // percentiles, and maximum of the deposits and withdrawals of the whole run, under the scope
+    // `run`, followed by those of each client in order, if they were counted.
+    pub fn write(&self, writer: impl Write) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let mut write = |scope: String, distribution: &AmountDistribution| {
+            for (txn_type, histogram) in [
+                ("deposit", &distribution.deposits),
+                ("withdrawal", &distribution.withdrawals),
+            ] {
+                writer.serialize(AmountStatsRow {
+                    scope: scope.clone(),
+                    txn_type,
+                    count: histogram.count(),
+                    min: histogram.min(),
+                    p50: histogram.percentile(0.5),
+                    p90: histogram.percentile(0.9),
+                    p99: histogram.percentile(0.99),
+                    max: histogram.max(),
+                })?;
+            }
+            Ok::<_, csv::Error>(())
+        };
+        write("run".to_owned(), &self.run())?;
+        if let Some(clients) = &self.clients {
+            for ((tenant, id), distribution) in
+                clients.lock().expect("amount stats lock poisoned").iter()
+            {
+                let scope = match tenant {
+                    Some(tenant) => format!("{tenant}/{id}"),
+                    None => id.to_string(),
+                };
+                write(scope, distribution)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl EventObserver for AmountStats {
+    fn on_applied(&self, txn: &OrderedTransaction, _account: &Account) {
+        self.record(txn);
+    }
+
+    fn on_rejected(&self, txn: &OrderedTransaction, _account: &Account, _err: &TransactionError) {
+        self.record(txn);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::Transaction;
+    use std::error::Error;
+
+    #[test]
+    fn estimates_percentiles() -> Result<(), Box<dyn Error>> {
+        assert_eq!(bound(0), "0.01".parse()?);
+        assert_eq!(bound(9), "10".parse()?);
+        assert_eq!(bound(NUM_BOUNDS - 1), "5000000".parse()?);
+
+        let stats = AmountStats::new(true);
+        let account = Account::new(1.into());
+        for (order, client, amount) in
+            [(1, 1, "3"), (2, 1, "4"), (3, 1, "40"), (4, 2, "1000000000")]
+        {
+            let amount = amount.parse()?;
+            let deposit =
+                Transaction::new(1.into(), client.into(), TransactionType::Deposit { amount });
+            stats.on_applied(&OrderedTransaction::new(order, deposit), &account);
+        }
+        let run = stats.run();
+        assert_eq!(run.deposits.count(), 4);
+        assert_eq!(
+            run.deposits.cumulative_counts().nth(8),
+            Some(2),
+            "3 and 4 are both no more than 5"
+        );
+        assert_eq!(run.deposits.percentile(0.5), Some("5".parse()?));
+        assert_eq!(run.deposits.percentile(0.99), Some("1000000000".parse()?));
+        assert_eq!(run.withdrawals.percentile(0.5), None);
+
+        let mut out = vec![];
+        stats.write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "scope,type,count,min,p50,p90,p99,max\n\
+             run,deposit,4,3,5,1000000000,1000000000,1000000000\n\
+             run,withdrawal,0,,,,,\n\
+             1,deposit,3,3,5,40,40,40\n\
+             1,withdrawal,0,,,,,\n\
+             2,deposit,1,1000000000,1000000000,1000000000,1000000000,1000000000\n\
+             2,withdrawal,0,,,,,\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use std::io::{Cursor, Write};
+use std::sync::Arc;
+
+use arrow::array::RecordBatch;
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::{FileReader, StreamReader};
+use arrow::ipc::writer::FileWriter;
+use arrow::json::reader::{Decoder, ReaderBuilder};
+use arrow::util::display::{ArrayFormatter, FormatOptions};
+use snafu::{ResultExt, Whatever};
+
+use crate::bank_profile::BankProfile;
+use crate::importer::{Importer, StatementRecord};
+use crate::metadata::MetadataTable;
+use crate::models::account::{Account, OutputFormat};
+use crate::rejects::Rejection;
+use crate::sink::{self, AccountSink};
+
+// The number of accounts written to each record batch.
+const BATCH_ROWS: usize = 8192;
+
+// Balances are written with the full precision they're held with as decimals of this scale, which
+// is the most that they can have, and of a precision that fits even the largest of them at it.
+const FULL_PRECISION: (u8, i8) = (76, 28);
+
+// Imports the rows of an Arrow IPC file (also known as Feather) or stream, whose columns are mapped
+// onto transactions by the profile. Rows are numbered from one across all of the record batches,
+// and are reported by their number should they be rejected.
+pub struct ArrowImporter {
+    pub profile: BankProfile,
+}
+
+impl Importer for ArrowImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        let mut records = vec![];
+        if let Err(err) = self.read(data, &mut records) {
+            let line = records.len() as u64 + 1;
+            records.push(StatementRecord {
+                line,
+                result: Err(Rejection {
+                    line: Some(line),
+                    column: None,
+                    raw_field: None,
+                    reason: format!("The file is not readable as Arrow IPC: {err}"),
+                    memo: None,
+                }),
+            });
+        }
+        records
+    }
+
+    fn amount_column(&self) -> &str {
+        self.profile.amount_column()
+    }
+}
+
+impl ArrowImporter {
+    fn read(&self, data: &[u8], records: &mut Vec<StatementRecord>) -> Result<(), ArrowError> {
+        // Files start with a magic number, whereas streams start with their schema.
+        let batches: Box<dyn Iterator<Item = Result<RecordBatch, ArrowError>>> =
+            if data.starts_with(b"ARROW1") {
+                Box::new(FileReader::try_new(Cursor::new(data), None)?)
+            } else {
+                Box::new(StreamReader::try_new(Cursor::new(data), None)?)
+            };
+
+        let options = FormatOptions::default();
+        for batch in batches {
+            let batch = batch?;
+            let schema = batch.schema();
+            let columns = batch
+                .columns()
+                .iter()
+                .map(|column| ArrayFormatter::try_new(column.as_ref(), &options))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            for row in 0..batch.num_rows() {
+                let line = records.len() as u64 + 1;
+                let values: Vec<Option<String>> = columns
+                    .iter()
+                    .zip(batch.columns())
+                    .map(|(formatter, column)| {
+                        column
+                            .is_valid(row)
+                            .then(|| formatter.value(row).to_string())
+                    })
+                    .collect();
+                let field = |name: &str| values.get(schema.index_of(name).ok()?)?.as_deref();
+                let result = self.profile.transaction(field, line);
+                records.push(StatementRecord { line, result });
+            }
+        }
+        Ok(())
+    }
+}
+
+// Writes accounts as an Arrow IPC file (also known as Feather), with the same columns as the CSV
+// output. Balances are written as decimals of the scale that they're rendered with.
+pub struct ArrowSink<W: Write> {
+    writer: FileWriter<W>,
+    decoder: Decoder,
+    rows: usize,
+    format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+}
+
+impl<W: Write> ArrowSink<W> {
+    pub fn new(
+        writer: W,
+        format: OutputFormat,
+        metadata: Option<Arc<MetadataTable>>,
+    ) -> Result<Self, Whatever> {
+        let schema = schema(format, metadata.is_some());
+        let decoder = ReaderBuilder::new(schema.clone())
+            // Numeric client IDs are written to the same column as alphanumeric ones.
+            .with_coerce_primitive(true)
+            .build_decoder()
+            .whatever_context("unable to write accounts as Arrow")?;
+        let writer = FileWriter::try_new(writer, &schema)
+            .whatever_context("unable to write accounts as Arrow")?;
+        Ok(Self {
+            writer,
+            decoder,
+            rows: 0,
+            format,
+            metadata,
+        })
+    }
+
+    // Writes out the accounts decoded since the last batch was written.
+    fn write_batch(&mut self) -> Result<(), Whatever> {
+        self.rows = 0;
+        let batch = self
+            .decoder
+            .flush()
+            .whatever_context("unable to write accounts as Arrow")?;
+        match batch {
+            Some(batch) => self
+                .writer
+                .write(&batch)
+                .whatever_context("unable to write accounts as Arrow"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<W: Write> AccountSink for ArrowSink<W> {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        let output = sink::output(account, self.format, self.metadata.as_deref());
+        self.decoder
+            .serialize(&[output])
+            .whatever_context("unable to write account as Arrow")?;
+        self.rows += 1;
+        if self.rows == BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.write_batch()?;
+        self.writer
+            .finish()
+            .whatever_context("unable to write accounts as Arrow")
+    }
+}
+
+// The schema of the accounts, with the same columns as they have as CSV.
+fn schema(format: OutputFormat, metadata: bool) -> SchemaRef {
+    let balance = match format.decimal_places {
+        Some(places) => DataType::Decimal128(38, places as i8),
+        None => DataType::Decimal256(FULL_PRECISION.0, FULL_PRECISION.1),
+    };
+    let mut fields = vec![
+        Field::new("client", DataType::Utf8, false),
+        Field::new("available", balance.clone(), false),
+        Field::new("held", balance.clone(), false),
+        Field::new("total", balance.clone(), false),
+        Field::new("locked", DataType::Boolean, false),
+    ];
+    if format.extended {
+        fields.extend([
+            Field::new("transactions_applied", DataType::UInt64, false),
+            Field::new("open_disputes", DataType::UInt64, false),
+            Field::new("lifetime_deposits", balance.clone(), false),
+            Field::new("lifetime_withdrawals", balance, false),
+            Field::new("last_order", DataType::UInt64, true),
+            Field::new("last_applied_at", DataType::UInt64, true),
+        ]);
+    }
+    if metadata {
+        fields.extend([
+            Field::new("name", DataType::Utf8, true),
+            Field::new("opened_at", DataType::Utf8, true),
+            Field::new("region", DataType::Utf8, true),
+        ]);
+    }
+    Arc::new(Schema::new(fields))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use arrow::array::{Array, AsArray, Decimal128Array, Int64Array, StringArray};
+    use arrow::datatypes::Decimal128Type;
+    use rust_decimal::Decimal;
+
+    use crate::models::{
+        account::{AccountId, RawAccountId},
+        transaction::{Transaction, TransactionId, TransactionType},
+    };
+
+    #[test]
+    fn reads_files() -> Result<(), Box<dyn Error>> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("type", DataType::Utf8, false),
+            Field::new("client", DataType::Int64, false),
+            Field::new("tx", DataType::Int64, false),
+            Field::new("amount", DataType::Decimal128(18, 4), true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["deposit", "dispute", "withdrawal"])),
+                Arc::new(Int64Array::from(vec![1, 1, 2])),
+                Arc::new(Int64Array::from(vec![10, 10, 11])),
+                Arc::new(
+                    Decimal128Array::from(vec![Some(25_000), None, None])
+                        .with_precision_and_scale(18, 4)?,
+                ),
+            ],
+        )?;
+        let mut writer = FileWriter::try_new(vec![], &schema)?;
+        writer.write(&batch)?;
+        writer.write(&batch.slice(0, 1))?;
+        writer.finish()?;
+        let data = writer.into_inner()?;
+
+        let importer = ArrowImporter {
+            profile: BankProfile::native(),
+        };
+        let records = importer.import(&data);
+        assert_eq!(records.len(), 4);
+
+        let deposit = records[0].result.clone()?;
+        assert_eq!(deposit.txn.id(), TransactionId::Number(10));
+        assert_eq!(deposit.txn.account_id(), AccountId::Number(1));
+        assert_eq!(deposit.txn.txn_type().amount(), Some(Decimal::new(25, 1)));
+        assert_eq!(records[1].result.clone()?.txn.txn_type().name(), "dispute");
+
+        let rejection = records[2].result.clone().unwrap_err();
+        assert_eq!(rejection.line, Some(3));
+        assert_eq!(rejection.column.as_deref(), Some("amount"));
+        assert_eq!(
+            records[3].line, 4,
+            "rows should be numbered across record batches"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn writes_accounts() -> Result<(), Box<dyn Error>> {
+        let id: RawAccountId = 3;
+        let mut account = Account::new(id.into());
+        account.process_txn(Transaction::new(
+            TransactionId::Number(1),
+            id.into(),
+            TransactionType::Deposit {
+                amount: Decimal::new(15, 1),
+            },
+        ))?;
+        let named = Account::new(AccountId::parse("GB33BUKB20201555555555")?);
+
+        let format = OutputFormat {
+            decimal_places: Some(4),
+            extended: false,
+            tenant: false,
+        };
+        let mut sink = ArrowSink::new(vec![], format, None)?;
+        sink.write(&account)?;
+        sink.write(&named)?;
+        sink.finish()?;
+
+        let data = sink.writer.into_inner()?;
+        let batches =
+            FileReader::try_new(Cursor::new(data), None)?.collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        let clients = batch.column(0).as_string::<i32>();
+        assert_eq!(clients.value(0), "3");
+        assert_eq!(clients.value(1), "GB33BUKB20201555555555");
+        let available = batch.column(1).as_primitive::<Decimal128Type>();
+        assert_eq!(available.value(0), 15_000);
+        assert!(!available.is_null(1));
+        Ok(())
+    }
+}
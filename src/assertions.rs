@@ -0,0 +1,208 @@
+use rust_decimal::Decimal;
+
+use crate::invariants;
+use crate::models::{
+    account::{Account, TransactionError},
+    transaction::{OrderedTransaction, TransactionType},
+};
+
+// The state of an account just before a transaction is applied to it, which is compared against
+// its state afterwards to check that the transaction had exactly the effect it should have had.
+pub(crate) struct Before {
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    last_order: Option<u64>,
+    txns_applied: u64,
+    recorded_txns: usize,
+    evicted_txns: usize,
+    forgotten_txns: u64,
+    had_txn: bool,
+    disputed_amount: Option<Decimal>,
+}
+
+impl Before {
+    pub(crate) fn capture(account: &Account, txn: &OrderedTransaction) -> Self {
+        let txn_id = txn.txn().id();
+        Self {
+            available: account.available(),
+            held: account.held(),
+            locked: account.locked(),
+            last_order: account.last_order(),
+            txns_applied: account.transactions_applied(),
+            recorded_txns: account.recorded_txns(),
+            evicted_txns: account.evicted_txns(),
+            forgotten_txns: account.forgotten_txns(),
+            had_txn: account.has_applied(txn_id),
+            disputed_amount: account.disputed_amount(txn_id),
+        }
+    }
+
+    fn total(&self) -> Decimal {
+        self.available + self.held
+    }
+}
+
+// Checks that applying the transaction left the account in a consistent state, returning a
+// description of every assertion that failed. These checks are exhaustive rather than cheap, and
+// are intended for shadow runs that validate a release before it is trusted with production files.
+// Duplicates are only checked against the account's history if `exact_dedup` is set, i.e. if the
+// history is the only means by which duplicates are detected.
+pub(crate) fn check(
+    before: &Before,
+    txn: &OrderedTransaction,
+    result: &Result<(), TransactionError>,
+    account: &Account,
+    exact_dedup: bool,
+) -> Vec<String> {
+    let mut failures = vec![];
+    macro_rules! ensure {
+        ($holds:expr, $($failure:tt)+) => {
+            if !$holds {
+                failures.push(format!($($failure)+));
+            }
+        };
+    }
+
+    // Transactions must be applied to an account in the order they were submitted.
+    if let Some(last_order) = before.last_order {
+        ensure!(
+            txn.order() > last_order,
+            "order {} was applied after order {last_order}",
+            txn.order()
+        );
+    }
+
+    let total_change = account.total() - before.total();
+    let held_change = account.held() - before.held;
+    // A transaction evicted from the history to make room for another is still recorded, by ID,
+    // until it's forgotten for falling outside of the dedup window.
+    let recorded_change = account.recorded_txns() as i64 - before.recorded_txns as i64
+        + account.evicted_txns() as i64
+        - before.evicted_txns as i64
+        + (account.forgotten_txns() - before.forgotten_txns) as i64;
+    match result {
+        Err(err) => {
+            ensure!(
+                account.available() == before.available
+                    && account.held() == before.held
+                    && account.locked() == before.locked,
+                "balances changed despite the transaction failing with: {err}"
+            );
+            ensure!(
+                account.transactions_applied() == before.txns_applied && recorded_change == 0,
+                "history changed despite the transaction failing with: {err}"
+            );
+            if exact_dedup && matches!(err, TransactionError::TransactionAlreadyProcessed { .. }) {
+                ensure!(
+                    before.had_txn,
+                    "transaction was reported as a duplicate but is not in the history"
+                );
+            }
+        }
+
+        Ok(()) => {
+            ensure!(
+                account.transactions_applied() == before.txns_applied + 1,
+                "applied transaction was not counted"
+            );
+
+            match txn.txn().txn_type() {
+                TransactionType::Deposit { amount } | TransactionType::Withdrawal { amount } => {
+                    let expected_change = match txn.txn().txn_type() {
+                        TransactionType::Deposit { .. } => amount,
+                        _ => -amount,
+                    };
+                    ensure!(
+                        total_change == expected_change,
+                        "total changed by {total_change} rather than {expected_change}"
+                    );
+                    ensure!(held_change.is_zero(), "held changed by {held_change}");
+                    ensure!(
+                        !before.had_txn && recorded_change == 1,
+                        "transaction was applied without being recorded exactly once"
+                    );
+                }
+
+                TransactionType::Dispute => {
+                    ensure!(total_change.is_zero(), "total changed by {total_change}");
+                    ensure!(
+                        account.disputed_amount(txn.txn().id()) == Some(held_change),
+                        "held changed by {held_change} rather than the disputed amount"
+                    );
+                }
+
+                TransactionType::Resolve => {
+                    ensure!(total_change.is_zero(), "total changed by {total_change}");
+                    ensure!(
+                        before.disputed_amount == Some(-held_change),
+                        "held changed by {held_change} rather than the disputed amount"
+                    );
+                }
+
+                TransactionType::Chargeback => {
+                    ensure!(
+                        before.disputed_amount == Some(-total_change),
+                        "total changed by {total_change} rather than the disputed amount"
+                    );
+                    ensure!(account.locked(), "account was not locked by a chargeback");
+                }
+
+                // Only the handler knows what effect a custom transaction should have.
+                TransactionType::Custom { .. } => (),
+            }
+        }
+    }
+
+    failures.extend(invariants::check_account_invariants(account));
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::models::transaction::{RawTransactionId, Transaction, TransactionId};
+
+    fn apply(
+        account: &mut Account,
+        order: u64,
+        txn_id: RawTransactionId,
+        txn_type: TransactionType,
+    ) -> Vec<String> {
+        let txn = OrderedTransaction::new(
+            order,
+            Transaction::new(TransactionId::from(txn_id), account.id(), txn_type),
+        );
+        let before = Before::capture(account, &txn);
+        let result = account.process_txn(txn.txn().clone());
+        if result.is_ok() {
+            account.record_applied(order, 0, None);
+        }
+        check(&before, &txn, &result, account, true)
+    }
+
+    #[test]
+    fn assertions() -> Result<(), Box<dyn Error>> {
+        let mut account = Account::new(1.into());
+        let deposit = TransactionType::Deposit {
+            amount: "10".parse()?,
+        };
+
+        assert!(apply(&mut account, 0, 1, deposit.clone()).is_empty());
+        assert!(apply(&mut account, 1, 1, deposit.clone()).is_empty());
+        assert!(apply(&mut account, 2, 1, TransactionType::Dispute).is_empty());
+        assert!(apply(&mut account, 3, 1, TransactionType::Chargeback).is_empty());
+
+        let mut account = Account::new(2.into());
+        assert!(apply(&mut account, 5, 2, deposit.clone()).is_empty());
+        assert_eq!(
+            apply(&mut account, 4, 3, deposit.clone()),
+            ["order 4 was applied after order 5"],
+            "transactions applied out of order should be caught"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,122 @@
+use std::time::Duration;
+
+// The load of a worker over the last interval of an autoscaled run: how many messages are queued
+// for it, and the share of the interval it spent applying transactions.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WorkerLoad {
+    pub queued: usize,
+    pub utilization: f64,
+}
+
+// How the workers of a run that stays resident are scaled to their load. The load is sampled once
+// an interval, and a worker is added when they're falling behind, or retired when they're mostly
+// idle, within the given bounds. Workers are added or retired one at a time, so the number of
+// workers settles rather than swinging back and forth with every burst.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoScaling {
+    pub min_workers: usize,
+    pub max_workers: usize,
+    pub interval: Duration,
+    // A worker is added when the workers have more than this many messages queued on average, or
+    // are busier than the utilization on average.
+    pub scale_up_queued: usize,
+    pub scale_up_utilization: f64,
+    // A worker is retired when the workers are idler than this on average, with nothing queued.
+    pub scale_down_utilization: f64,
+}
+
+impl AutoScaling {
+    pub fn new(min_workers: usize, max_workers: usize) -> Self {
+        Self {
+            min_workers,
+            max_workers,
+            interval: Duration::from_secs(1),
+            scale_up_queued: 1000,
+            scale_up_utilization: 0.9,
+            scale_down_utilization: 0.25,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    // Decides how many workers there should be, given the load of each worker there is.
+    pub fn workers_for(&self, load: &[WorkerLoad]) -> usize {
+        let workers = load.len();
+        if workers == 0 {
+            return self.min_workers;
+        }
+        let queued = load.iter().map(|load| load.queued).sum::<usize>() / workers;
+        let utilization = load.iter().map(|load| load.utilization).sum::<f64>() / workers as f64;
+
+        if workers < self.max_workers
+            && (queued > self.scale_up_queued || utilization > self.scale_up_utilization)
+        {
+            workers + 1
+        } else if workers > self.min_workers
+            && queued == 0
+            && utilization < self.scale_down_utilization
+            // The work of the retired worker is shared out among the rest, which mustn't leave
+            // them busy enough to have a worker added straight back.
+            && utilization * workers as f64 / (workers - 1) as f64 <= self.scale_up_utilization
+        {
+            workers - 1
+        } else {
+            workers.clamp(self.min_workers, self.max_workers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(queued: usize, utilization: f64) -> WorkerLoad {
+        WorkerLoad {
+            queued,
+            utilization,
+        }
+    }
+
+    #[test]
+    fn scales_within_bounds() {
+        let scaling = AutoScaling::new(1, 3);
+        assert_eq!(
+            scaling.workers_for(&[load(5000, 0.5)]),
+            2,
+            "queues are backing up"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(0, 0.95); 2]),
+            3,
+            "workers are saturated"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(5000, 1.0); 3]),
+            3,
+            "at the most workers"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(10, 0.5); 2]),
+            2,
+            "load is steady"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(0, 0.1); 3]),
+            2,
+            "workers are idle"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(3, 0.1); 3]),
+            3,
+            "work is still queued"
+        );
+        assert_eq!(
+            scaling.workers_for(&[load(0, 0.0)]),
+            1,
+            "at the fewest workers"
+        );
+    }
+}
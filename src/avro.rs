@@ -0,0 +1,319 @@
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+use apache_avro::{types::Value, Reader, Schema};
+use rust_decimal::Decimal;
+
+use crate::bank_profile::BankProfile;
+use crate::calendar;
+use crate::importer::{ImportedTransaction, Importer, StatementRecord};
+use crate::rejects::Rejection;
+
+// Imports the records of an Avro object container file. Each record is mapped onto a transaction
+// by the profile, taking its fields as columns, and is reported by its number within the file
+// should it be rejected.
+//
+// Records are read with the schema embedded in the file unless one is provided, in which case they
+// are resolved against it, so that files written with an older or newer version of our schema can
+// be read as long as the two are compatible. A record that can't be resolved stops the file from
+// being read any further, as there's no telling where the next record starts.
+pub struct AvroImporter {
+    schema: Option<Schema>,
+    pub profile: BankProfile,
+}
+
+impl AvroImporter {
+    // An importer of files whose records are to be resolved against the schema in the given
+    // file, if any.
+    pub fn new(schema: Option<&Path>, profile: BankProfile) -> Result<Self, String> {
+        let schema = schema
+            .map(|path| {
+                let text = fs::read_to_string(path).map_err(|err| {
+                    format!("Unable to read the Avro schema {}: {err}", path.display())
+                })?;
+                Schema::parse_str(&text).map_err(|err| {
+                    format!("The Avro schema {} is not valid: {err}", path.display())
+                })
+            })
+            .transpose()?;
+        Ok(Self { schema, profile })
+    }
+
+    fn transaction(
+        &self,
+        value: &Value,
+        schema: &Schema,
+        line: u64,
+    ) -> Result<ImportedTransaction, Rejection> {
+        let (Value::Record(values), Schema::Record(record)) = (value, schema) else {
+            return Err(rejection(line, "The value is not a record".to_owned()));
+        };
+        let fields: Vec<(&str, Option<String>)> = values
+            .iter()
+            .map(|(name, value)| {
+                let text = record
+                    .fields
+                    .iter()
+                    .find(|field| field.name == *name)
+                    .and_then(|field| field_text(value, &field.schema));
+                (name.as_str(), text)
+            })
+            .collect();
+        let field = |column: &str| {
+            fields
+                .iter()
+                .find(|(name, _)| *name == column)
+                .and_then(|(_, text)| text.as_deref())
+        };
+        self.profile.transaction(field, line)
+    }
+}
+
+impl Importer for AvroImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        let reader = match Reader::builder(Cursor::new(data))
+            .maybe_reader_schema(self.schema.as_ref())
+            .build()
+        {
+            Ok(reader) => reader,
+            Err(err) => {
+                return vec![StatementRecord {
+                    line: 0,
+                    result: Err(rejection(
+                        0,
+                        format!("The file is not an Avro container file: {err}"),
+                    )),
+                }]
+            }
+        };
+        let schema = self
+            .schema
+            .clone()
+            .unwrap_or_else(|| reader.writer_schema().clone());
+        if !matches!(schema, Schema::Record(_)) {
+            return vec![StatementRecord {
+                line: 0,
+                result: Err(rejection(
+                    0,
+                    "The schema of the file is not a record".to_owned(),
+                )),
+            }];
+        }
+
+        let mut records = vec![];
+        for (idx, value) in reader.enumerate() {
+            let line = idx as u64 + 1;
+            match value {
+                Ok(value) => records.push(StatementRecord {
+                    line,
+                    result: self.transaction(&value, &schema, line),
+                }),
+                Err(err) => {
+                    records.push(StatementRecord {
+                        line,
+                        result: Err(rejection(
+                            line,
+                            format!("The record does not match the schema: {err}"),
+                        )),
+                    });
+                    break;
+                }
+            }
+        }
+        records
+    }
+
+    fn amount_column(&self) -> &str {
+        self.profile.amount_column()
+    }
+}
+
+// The rejection of the record with the given number, or of the whole file if it's zero.
+fn rejection(line: u64, reason: String) -> Rejection {
+    Rejection {
+        line: (line > 0).then_some(line),
+        column: None,
+        raw_field: None,
+        reason,
+        memo: None,
+    }
+}
+
+// The text of a field as it would appear in a CSV file, or `None` if it's null or can't be
+// written as text, such as an array. Dates and timestamps are written as e.g. `2024-01-02`, so a
+// profile checking the dates of Avro records should expect them in the format `YYYY-MM-DD`.
+fn field_text(value: &Value, schema: &Schema) -> Option<String> {
+    const MILLIS_PER_DAY: i64 = 86_400_000;
+
+    let date = |days: i64| {
+        let (year, month, day) = calendar::civil_from_days(days);
+        format!("{year:04}-{month:02}-{day:02}")
+    };
+    Some(match value {
+        Value::Null | Value::Array(_) | Value::Map(_) | Value::Record(_) => return None,
+        Value::Union(variant, value) => {
+            let schema = match schema {
+                Schema::Union(union) => union.variants().get(*variant as usize)?,
+                schema => schema,
+            };
+            return field_text(value, schema);
+        }
+        Value::Boolean(value) => value.to_string(),
+        Value::Int(value) => value.to_string(),
+        Value::Long(value) => value.to_string(),
+        Value::Float(value) => value.to_string(),
+        Value::Double(value) => value.to_string(),
+        Value::String(value) | Value::Enum(_, value) => value.clone(),
+        Value::Bytes(bytes) | Value::Fixed(_, bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        Value::Uuid(uuid) => uuid.to_string(),
+        Value::Decimal(decimal) => {
+            let Schema::Decimal(decimal_schema) = schema else {
+                return None;
+            };
+            let bytes = Vec::<u8>::try_from(decimal).ok()?;
+            // The unscaled value is a big-endian two's complement integer, sign extended here to
+            // the width of an `i128`.
+            let sign = if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+                0xFF
+            } else {
+                0
+            };
+            let mut unscaled = [sign; 16];
+            unscaled
+                .get_mut(16usize.checked_sub(bytes.len())?..)?
+                .copy_from_slice(&bytes);
+            let scale = u32::try_from(decimal_schema.scale).ok()?;
+            Decimal::try_from_i128_with_scale(i128::from_be_bytes(unscaled), scale)
+                .ok()?
+                .to_string()
+        }
+        Value::BigDecimal(decimal) => decimal.to_string(),
+        Value::Date(days) => date(i64::from(*days)),
+        Value::TimestampMillis(millis) | Value::LocalTimestampMillis(millis) => {
+            date(millis.div_euclid(MILLIS_PER_DAY))
+        }
+        Value::TimestampMicros(micros) | Value::LocalTimestampMicros(micros) => {
+            date(micros.div_euclid(MILLIS_PER_DAY * 1_000))
+        }
+        Value::TimestampNanos(nanos) | Value::LocalTimestampNanos(nanos) => {
+            date(nanos.div_euclid(MILLIS_PER_DAY * 1_000_000))
+        }
+        Value::TimeMillis(_) | Value::TimeMicros(_) | Value::Duration(_) => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use apache_avro::Writer;
+
+    use crate::models::{account::AccountId, transaction::TransactionId};
+
+    const SCHEMA: &str = r#"{
+        "type": "record",
+        "name": "Transaction",
+        "fields": [
+            {"name": "type", "type": "string"},
+            {"name": "client", "type": "int"},
+            {"name": "tx", "type": "long"},
+            {"name": "amount", "type": ["null", {"type": "bytes", "logicalType": "decimal", "precision": 18, "scale": 4}]},
+            {"name": "memo", "type": ["null", "string"], "default": null}
+        ]
+    }"#;
+
+    fn container(records: &[(&str, i32, i64, Option<i64>)]) -> Result<Vec<u8>, Box<dyn Error>> {
+        let schema = Schema::parse_str(SCHEMA)?;
+        let mut writer = Writer::new(&schema, vec![])?;
+        for (txn_type, client, tx, amount) in records {
+            let amount = match amount {
+                Some(amount) => Value::Union(
+                    1,
+                    Box::new(Value::Decimal(amount.to_be_bytes().to_vec().into())),
+                ),
+                None => Value::Union(0, Box::new(Value::Null)),
+            };
+            writer.append_value(Value::Record(vec![
+                ("type".to_owned(), Value::String((*txn_type).to_owned())),
+                ("client".to_owned(), Value::Int(*client)),
+                ("tx".to_owned(), Value::Long(*tx)),
+                ("amount".to_owned(), amount),
+                (
+                    "memo".to_owned(),
+                    Value::Union(1, Box::new(Value::String("Archived".to_owned()))),
+                ),
+            ]))?;
+        }
+        Ok(writer.into_inner()?)
+    }
+
+    #[test]
+    fn maps_records() -> Result<(), Box<dyn Error>> {
+        let data = container(&[
+            ("deposit", 1, 10, Some(25_000)),
+            ("dispute", 1, 10, None),
+            ("withdrawal", 1, 11, None),
+        ])?;
+        let importer = AvroImporter::new(None, BankProfile::native())?;
+        let records = importer.import(&data);
+        assert_eq!(records.len(), 3);
+
+        let deposit = records[0].result.clone()?;
+        assert_eq!(records[0].line, 1);
+        assert_eq!(deposit.txn.id(), TransactionId::Number(10));
+        assert_eq!(deposit.txn.account_id(), AccountId::Number(1));
+        assert_eq!(deposit.txn.txn_type().amount(), Some(Decimal::new(25, 1)));
+        assert_eq!(deposit.memo.as_deref(), Some("Archived"));
+
+        assert_eq!(records[1].result.clone()?.txn.txn_type().name(), "dispute");
+
+        let rejection = records[2].result.clone().unwrap_err();
+        assert_eq!(rejection.line, Some(3));
+        assert_eq!(rejection.column.as_deref(), Some("amount"));
+        assert_eq!(rejection.raw_field, None);
+        Ok(())
+    }
+
+    #[test]
+    fn resolves_records_against_a_provided_schema() -> Result<(), Box<dyn Error>> {
+        let data = container(&[("deposit", 1, 10, Some(10_000))])?;
+        let dir = std::env::temp_dir().join(format!("avro-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir)?;
+
+        // A newer schema, with a field the file doesn't have but which has a default.
+        let compatible = dir.join("compatible.avsc");
+        fs::write(
+            &compatible,
+            SCHEMA.replace(
+                r#"{"name": "memo""#,
+                r#"{"name": "channel", "type": "string", "default": "batch"}, {"name": "memo""#,
+            ),
+        )?;
+        let importer = AvroImporter::new(Some(&compatible), BankProfile::native())?;
+        let records = importer.import(&data);
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            records[0].result.clone()?.txn.id(),
+            TransactionId::Number(10)
+        );
+
+        // One that needs a field the file doesn't have.
+        let incompatible = dir.join("incompatible.avsc");
+        fs::write(
+            &incompatible,
+            SCHEMA.replace(
+                r#"{"name": "memo""#,
+                r#"{"name": "channel", "type": "string"}, {"name": "memo""#,
+            ),
+        )?;
+        let importer = AvroImporter::new(Some(&incompatible), BankProfile::native())?;
+        let records = importer.import(&data);
+        assert_eq!(records.len(), 1);
+        assert!(records[0].result.is_err());
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
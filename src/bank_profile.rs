@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+
+use crate::importer::{ImportedTransaction, StatementRecord};
+use crate::models::{
+    account::AccountId,
+    transaction::{self, Transaction, TransactionId, TransactionType},
+};
+use crate::rejects::Rejection;
+
+// Describes how a partner's files map onto our transactions: which of their columns hold which of
+// our fields, and how their amounts, signs, dates, and transaction types are written. A profile is
+// loaded from a TOML file, so that a new partner needs a profile rather than a preprocessing
+// script, e.g.
+//
+// ```toml
+// delimiter = ";"
+// decimal_comma = true
+// date_format = "DD.MM.YYYY"
+//
+// [columns]
+// client = "Kontonummer"
+// tx = "Referenz"
+// amount = "Betrag"
+// memo = "Verwendungszweck"
+// date = "Buchungstag"
+// ```
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BankProfile {
+    // The account every transaction is for, for files that only ever cover a single account and
+    // so don't say which it is.
+    pub account: Option<String>,
+    // The delimiter between the fields of a CSV file, which must be a single ASCII character.
+    pub delimiter: Option<char>,
+    pub columns: Columns,
+    // Whether amounts are written with a decimal comma and, if so, with points between the
+    // thousands, e.g. `1.234,56`. Otherwise any commas are taken to be between the thousands.
+    pub decimal_comma: bool,
+    // Whether negative amounts are credits and positive ones debits, as on a credit card
+    // statement, rather than the other way round. Only applies when the type of a transaction is
+    // given by the sign of its amount.
+    pub negative_is_deposit: bool,
+    // The format dates must be written in, made up of `YYYY`, `YY`, `MM`, and `DD` along with any
+    // separators, e.g. `DD/MM/YYYY`. Dates aren't imported along with transactions, which are
+    // only dated by the timestamp and effective_date columns of our own CSV input, so this only
+    // serves to reject records whose dates show that they have been misread.
+    pub date_format: Option<String>,
+    // Maps the values of the type column onto the names of our transaction types, e.g.
+    // `CR = "deposit"`. Values that aren't mapped are taken to be the names of types themselves.
+    pub types: HashMap<String, String>,
+}
+
+// The names of the columns that hold each of our fields. Any of them may be left out, as long as
+// the profile can otherwise tell what each transaction is and who it's for.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Columns {
+    pub client: Option<String>,
+    pub tx: Option<String>,
+    // The type of each transaction. Without it, the type is given by the sign of the amount.
+    #[serde(rename = "type")]
+    pub txn_type: Option<String>,
+    pub amount: Option<String>,
+    // Separate columns for debits and credits, for files that split amounts between two columns
+    // rather than signing them.
+    pub debit: Option<String>,
+    pub credit: Option<String>,
+    pub memo: Option<String>,
+    pub date: Option<String>,
+}
+
+#[derive(Debug, Snafu)]
+pub enum BankProfileError {
+    #[snafu(display("Unable to read the bank profile {}: {source}", path.display()))]
+    ReadProfile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("The bank profile {} is invalid: {source}", path.display()))]
+    ParseProfile {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display(
+        "The bank profile {} is invalid: the delimiter '{delimiter}' is not a single ASCII character.",
+        path.display()
+    ))]
+    InvalidDelimiter { path: PathBuf, delimiter: char },
+}
+
+impl BankProfile {
+    // The profile of files laid out like our own CSV files, for formats that have columns but no
+    // layout of their own.
+    pub fn native() -> Self {
+        Self {
+            columns: Columns {
+                client: Some("client".to_owned()),
+                tx: Some("tx".to_owned()),
+                txn_type: Some("type".to_owned()),
+                amount: Some("amount".to_owned()),
+                memo: Some("memo".to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, BankProfileError> {
+        let text = fs::read_to_string(path).context(ReadProfileSnafu { path })?;
+        let profile: Self = toml::from_str(&text).context(ParseProfileSnafu { path })?;
+        // CSV files are split on a single byte, as with --delimiter.
+        if let Some(delimiter) = profile.delimiter.filter(|c| !c.is_ascii()) {
+            return InvalidDelimiterSnafu { path, delimiter }.fail();
+        }
+        Ok(profile)
+    }
+
+    // The name of the column that holds amounts, which rejected amounts are reported against.
+    pub fn amount_column(&self) -> &str {
+        let columns = &self.columns;
+        [&columns.amount, &columns.credit, &columns.debit]
+            .into_iter()
+            .find_map(|column| column.as_deref())
+            .unwrap_or("amount")
+    }
+
+    // Maps a record onto a transaction, given a way of looking up the values of its columns.
+    pub fn transaction<'a>(
+        &self,
+        field: impl Fn(&str) -> Option<&'a str>,
+        line: u64,
+    ) -> Result<ImportedTransaction, Rejection> {
+        let columns = &self.columns;
+        let value = |column: &Option<String>| {
+            column
+                .as_deref()
+                .and_then(&field)
+                .map(str::trim)
+                .filter(|value| !value.is_empty())
+        };
+        let memo = value(&columns.memo);
+        let reject = |column: &Option<String>, reason: String| Rejection {
+            line: Some(line),
+            column: column.clone(),
+            raw_field: value(column).map(str::to_owned),
+            reason,
+            memo: memo.map(str::to_owned),
+        };
+
+        let account_id = match value(&columns.client).or(self.account.as_deref()) {
+            Some(account) => {
+                AccountId::parse(account).map_err(|err| reject(&columns.client, err))?
+            }
+            None => {
+                return Err(reject(
+                    &columns.client,
+                    "The record doesn't say which account it is for".to_owned(),
+                ))
+            }
+        };
+
+        let id = value(&columns.tx)
+            .map(TransactionId::from_reference)
+            .ok_or_else(|| reject(&columns.tx, "The record has no reference".to_owned()))?;
+
+        if let (Some(date), Some(format)) = (value(&columns.date), &self.date_format) {
+            if !matches_date_format(date, format) {
+                return Err(reject(
+                    &columns.date,
+                    format!("The date is not in the format {format}"),
+                ));
+            }
+        }
+
+        let parse_amount = |column: &Option<String>| {
+            value(column)
+                .map(|amount| {
+                    self.parse_amount(amount).ok_or_else(|| {
+                        reject(column, format!("The amount '{amount}' is not a number"))
+                    })
+                })
+                .transpose()
+        };
+        let (amount_column, amount) = match (
+            parse_amount(&columns.amount)?,
+            parse_amount(&columns.debit)?,
+            parse_amount(&columns.credit)?,
+        ) {
+            (Some(amount), _, _) => (&columns.amount, Some(amount)),
+            (None, Some(debit), _) => (&columns.debit, Some(-debit.abs())),
+            (None, None, Some(credit)) => (&columns.credit, Some(credit.abs())),
+            (None, None, None) => (&columns.amount, None),
+        };
+
+        let txn_type = match value(&columns.txn_type) {
+            Some(raw_type) => {
+                let name = self.types.get(raw_type).map_or_else(
+                    || raw_type.to_ascii_lowercase(),
+                    |name| name.to_ascii_lowercase(),
+                );
+                TransactionType::from_name(&name, amount.map(|amount| amount.abs()))
+                    .map_err(|err| reject(amount_column, err))?
+            }
+            None => {
+                let amount = amount
+                    .ok_or_else(|| reject(amount_column, "The record has no amount".to_owned()))?;
+                let is_deposit = amount.is_sign_negative() == self.negative_is_deposit;
+                let name = if is_deposit { "deposit" } else { "withdrawal" };
+                TransactionType::from_name(name, Some(amount.abs()))
+                    .map_err(|err| reject(amount_column, err))?
+            }
+        };
+
+        Ok(ImportedTransaction {
+            txn: Transaction::new(id, account_id, txn_type),
+            memo: memo.and_then(transaction::memo),
+        })
+    }
+
+    fn parse_amount(&self, amount: &str) -> Option<Decimal> {
+        let amount = if self.decimal_comma {
+            amount.replace('.', "").replace(',', ".")
+        } else {
+            amount.replace(',', "")
+        };
+        amount.trim_start_matches('+').parse().ok()
+    }
+
+    // Reads the whole of a CSV file laid out as the profile describes.
+    pub fn import_csv(&self, text: &str) -> Vec<StatementRecord> {
+        // Loading the profile checked that the delimiter is ASCII.
+        let delimiter = self.delimiter.map_or(b',', |c| c as u8);
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(text.as_bytes());
+        let headers = match reader.headers() {
+            Ok(headers) => headers.clone(),
+            Err(err) => return vec![unreadable(1, &err)],
+        };
+
+        let mut records = vec![];
+        for record in reader.records() {
+            let record = match record {
+                Ok(record) => record,
+                Err(err) => {
+                    let line = err.position().map_or(0, |pos| pos.line());
+                    records.push(unreadable(line, &err));
+                    continue;
+                }
+            };
+
+            let line = record.position().map_or(0, |pos| pos.line());
+            let field = |column: &str| {
+                headers
+                    .iter()
+                    .position(|header| header.trim() == column)
+                    .and_then(|idx| record.get(idx))
+            };
+            records.push(StatementRecord {
+                line,
+                result: self.transaction(field, line),
+            });
+        }
+        records
+    }
+}
+
+fn unreadable(line: u64, err: &csv::Error) -> StatementRecord {
+    StatementRecord {
+        line,
+        result: Err(Rejection {
+            line: Some(line),
+            column: None,
+            raw_field: None,
+            reason: err.to_string(),
+            memo: None,
+        }),
+    }
+}
+
+// Whether a date is written in the given format, and is a plausible date.
+fn matches_date_format(date: &str, format: &str) -> bool {
+    let mut date = date;
+    let mut format = format;
+    while !format.is_empty() {
+        let (len, max) = match format {
+            _ if format.starts_with("YYYY") => (4, 9999),
+            _ if format.starts_with("YY") => (2, 99),
+            _ if format.starts_with("MM") => (2, 12),
+            _ if format.starts_with("DD") => (2, 31),
+            _ => {
+                let mut chars = format.chars();
+                let separator = chars.next().expect("format is not empty");
+                format = chars.as_str();
+                match date.strip_prefix(separator) {
+                    Some(rest) => date = rest,
+                    None => return false,
+                }
+                continue;
+            }
+        };
+
+        let Some(digits) = date.get(..len) else {
+            return false;
+        };
+        if !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        let number: u32 = digits.parse().unwrap_or_default();
+        let is_month_or_day = len == 2 && (format.starts_with("MM") || format.starts_with("DD"));
+        if number > max || (is_month_or_day && number == 0) {
+            return false;
+        }
+        date = &date[len..];
+        format = &format[len..];
+    }
+    date.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn maps_csv_files() -> Result<(), Box<dyn Error>> {
+        let profile: BankProfile = toml::from_str(
+            r#"
+            delimiter = ";"
+            decimal_comma = true
+            date_format = "DD.MM.YYYY"
+
+            [columns]
+            client = "Konto"
+            tx = "Referenz"
+            amount = "Betrag"
+            memo = "Verwendungszweck"
+            date = "Buchungstag"
+            "#,
+        )?;
+        let file = "Buchungstag;Konto;Referenz;Betrag;Verwendungszweck\n\
+                    02.01.2024;DE89370400440532013000;R-1;1.500,00;Gehalt\n\
+                    03.01.2024;DE89370400440532013000;R-2;-42,50;\n\
+                    2024-01-04;DE89370400440532013000;R-3;-1,00;\n";
+        let records = profile.import_csv(file);
+        assert_eq!(records.len(), 3);
+
+        let salary = records[0].result.clone()?;
+        assert_eq!(salary.txn.id(), TransactionId::from_reference("R-1"));
+        assert_eq!(
+            salary.txn.account_id(),
+            AccountId::parse("DE89370400440532013000")?
+        );
+        assert_eq!(salary.txn.txn_type().name(), "deposit");
+        assert_eq!(salary.txn.txn_type().amount(), Some(Decimal::new(1500, 0)));
+        assert_eq!(salary.memo.as_deref(), Some("Gehalt"));
+
+        let debit = records[1].result.clone()?;
+        assert_eq!(debit.txn.txn_type().name(), "withdrawal");
+        assert_eq!(debit.txn.txn_type().amount(), Some(Decimal::new(4250, 2)));
+        assert_eq!(debit.memo.as_deref(), None);
+
+        let rejection = records[2].result.clone().unwrap_err();
+        assert_eq!(rejection.line, Some(4));
+        assert_eq!(rejection.column.as_deref(), Some("Buchungstag"));
+        Ok(())
+    }
+
+    #[test]
+    fn maps_types_and_split_amounts() -> Result<(), Box<dyn Error>> {
+        let profile: BankProfile = toml::from_str(
+            r#"
+            account = "4111"
+
+            [columns]
+            tx = "Ref"
+            type = "Kind"
+            debit = "Paid Out"
+            credit = "Paid In"
+
+            [types]
+            CR = "deposit"
+            DR = "withdrawal"
+            DSP = "dispute"
+            "#,
+        )?;
+        let file = "Ref,Kind,Paid Out,Paid In\n\
+                    1,CR,,10.00\n\
+                    2,DR,\"1,250.00\",\n\
+                    1,DSP,,\n";
+        let txns = profile
+            .import_csv(file)
+            .into_iter()
+            .map(|record| record.result)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let types: Vec<_> = txns
+            .iter()
+            .map(|txn| (txn.txn.type_name(), txn.txn.txn_type().amount()))
+            .collect();
+        assert_eq!(
+            types,
+            [
+                ("deposit", Some(Decimal::new(10, 0))),
+                ("withdrawal", Some(Decimal::new(1250, 0))),
+                ("dispute", None)
+            ]
+        );
+        assert!(txns
+            .iter()
+            .all(|txn| txn.txn.account_id() == AccountId::Number(4111)));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_non_ascii_delimiters() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("profile-{}.toml", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "delimiter = \"§\"\n")?;
+        let err = BankProfile::load(&path).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                BankProfileError::InvalidDelimiter {
+                    delimiter: '§', ..
+                }
+            ),
+            "{err}"
+        );
+
+        std::fs::write(&path, "delimiter = \"|\"\n")?;
+        assert_eq!(BankProfile::load(&path)?.delimiter, Some('|'));
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn date_formats() {
+        assert!(matches_date_format("02.01.2024", "DD.MM.YYYY"));
+        assert!(matches_date_format("12/31/24", "MM/DD/YY"));
+        assert!(!matches_date_format("31/12/24", "MM/DD/YY"));
+        assert!(!matches_date_format("2024-01-02", "DD.MM.YYYY"));
+        assert!(!matches_date_format("02.01.2024 ", "DD.MM.YYYY"));
+    }
+}
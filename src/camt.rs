@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+
+use quick_xml::{escape, events::Event, Reader};
+use rust_decimal::Decimal;
+
+use crate::importer::{ImportedTransaction, StatementRecord};
+use crate::models::{
+    account::AccountId,
+    transaction::{self, Transaction, TransactionId, TransactionType},
+};
+use crate::rejects::Rejection;
+
+// The elements of an entry that may identify it, in order of preference. Of these, the reference
+// given by the account servicer is the one that is meant to be unique.
+const REFERENCES: [&str; 5] = [
+    "AcctSvcrRef",
+    "NtryRef",
+    "NtryDtls/TxDtls/Refs/AcctSvcrRef",
+    "NtryDtls/TxDtls/Refs/TxId",
+    "NtryDtls/TxDtls/Refs/EndToEndId",
+];
+
+// The elements of an entry that describe it, in order of preference.
+const MEMOS: [&str; 2] = ["NtryDtls/TxDtls/RmtInf/Ustrd", "AddtlNtryInf"];
+
+// Reads the entries of an ISO 20022 camt.053 bank-to-customer statement. Credits become deposits
+// and debits withdrawals, to the account of the statement they're listed in, and each is
+// identified by its reference. Only booked entries are read, as any others have yet to settle.
+//
+// If the document isn't well-formed XML, it is read up to the point that it stops making sense,
+// and the remainder of it is rejected.
+pub fn parse(text: &str) -> Vec<StatementRecord> {
+    let mut reader = Reader::from_str(text);
+
+    let mut records = vec![];
+    let mut path: Vec<String> = vec![];
+    let mut account: Option<String> = None;
+    // The elements of the entry being read, if any, by their path within the entry, and the line
+    // it started on.
+    let mut entry: Option<(u64, HashMap<String, String>)> = None;
+    let mut value = String::new();
+    loop {
+        let event = match reader.read_event() {
+            Ok(event) => event,
+            Err(err) => {
+                let line = line_at(text, reader.error_position());
+                records.push(StatementRecord {
+                    line,
+                    result: Err(Rejection {
+                        line: Some(line),
+                        column: None,
+                        raw_field: None,
+                        reason: format!("The statement is not well-formed XML: {err}"),
+                        memo: None,
+                    }),
+                });
+                break;
+            }
+        };
+
+        match event {
+            Event::Start(element) => {
+                let name = String::from_utf8_lossy(element.local_name().as_ref()).into_owned();
+                match name.as_str() {
+                    "Stmt" => account = None,
+                    "Ntry" => {
+                        entry = Some((line_at(text, reader.buffer_position()), HashMap::new()))
+                    }
+                    _ => {}
+                }
+                path.push(name);
+                value.clear();
+            }
+            Event::Text(content) => value.push_str(&content.xml_content().unwrap_or_default()),
+            Event::CData(content) => value.push_str(&content.decode().unwrap_or_default()),
+            Event::GeneralRef(reference) => {
+                let name = reference.decode().unwrap_or_default();
+                match reference.resolve_char_ref() {
+                    Ok(Some(ch)) => value.push(ch),
+                    _ => value.push_str(escape::resolve_predefined_entity(&name).unwrap_or("")),
+                }
+            }
+            Event::End(_) => {
+                match (&mut entry, path.iter().rposition(|name| name == "Ntry")) {
+                    (Some((_, fields)), Some(start)) if start + 1 < path.len() => {
+                        fields
+                            .entry(path[start + 1..].join("/"))
+                            .or_insert_with(|| value.trim().to_owned());
+                    }
+                    (Some(_), Some(start)) if start + 1 == path.len() => {
+                        let (line, fields) = entry.take().expect("entry is being read");
+                        if is_booked(&fields) {
+                            records.push(StatementRecord {
+                                line,
+                                result: to_transaction(account.as_deref(), &fields, line),
+                            });
+                        }
+                    }
+                    _ if path.ends_with(&["Acct", "Id", "IBAN"].map(String::from))
+                        || path.ends_with(&["Acct", "Id", "Othr", "Id"].map(String::from)) =>
+                    {
+                        account = Some(value.trim().to_owned());
+                    }
+                    _ => {}
+                }
+                path.pop();
+                value.clear();
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    records
+}
+
+// Whether the entry has been booked. Earlier versions of the format give the status as a bare
+// code, and later ones nest it within a `<Cd>` element.
+fn is_booked(fields: &HashMap<String, String>) -> bool {
+    ["Sts", "Sts/Cd"]
+        .iter()
+        .filter_map(|name| fields.get(*name))
+        .find(|status| !status.is_empty())
+        .is_none_or(|status| status == "BOOK")
+}
+
+fn to_transaction(
+    account: Option<&str>,
+    fields: &HashMap<String, String>,
+    line: u64,
+) -> Result<ImportedTransaction, Rejection> {
+    let memo = MEMOS
+        .iter()
+        .filter_map(|name| fields.get(*name))
+        .find(|memo| !memo.is_empty());
+    let reject = |column: &str, reason: String| Rejection {
+        line: Some(line),
+        column: Some(column.to_owned()),
+        raw_field: fields.get(column).cloned(),
+        reason,
+        memo: memo.cloned(),
+    };
+
+    let account = account.ok_or_else(|| {
+        reject(
+            "IBAN",
+            "The entry is not within a statement of an account".to_owned(),
+        )
+    })?;
+    let account_id = AccountId::parse(account).map_err(|err| reject("IBAN", err))?;
+
+    let id = REFERENCES
+        .iter()
+        .filter_map(|name| fields.get(*name))
+        .find(|reference| !reference.is_empty() && *reference != "NOTPROVIDED")
+        .map(|reference| TransactionId::from_reference(reference))
+        .ok_or_else(|| reject("AcctSvcrRef", "The entry has no reference".to_owned()))?;
+
+    let amount: Decimal = fields
+        .get("Amt")
+        .ok_or_else(|| reject("Amt", "The entry has no amount".to_owned()))?
+        .parse()
+        .map_err(|err| reject("Amt", format!("The amount is not a number: {err}")))?;
+    let amount =
+        transaction::validate_amount(amount).map_err(|err| reject("Amt", err.to_string()))?;
+    let txn_type = match fields.get("CdtDbtInd").map(String::as_str) {
+        Some("CRDT") => TransactionType::Deposit { amount },
+        Some("DBIT") => TransactionType::Withdrawal { amount },
+        _ => {
+            return Err(reject(
+                "CdtDbtInd",
+                "The entry is neither a credit (CRDT) nor a debit (DBIT)".to_owned(),
+            ))
+        }
+    };
+
+    Ok(ImportedTransaction {
+        txn: Transaction::new(id, account_id, txn_type),
+        memo: memo.and_then(|memo| transaction::memo(memo)),
+    })
+}
+
+// The line of the text that the given byte offset falls on.
+fn line_at(text: &str, position: u64) -> u64 {
+    let position = usize::try_from(position).map_or(text.len(), |pos| pos.min(text.len()));
+    text.as_bytes()[..position]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count() as u64
+        + 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn reads_booked_entries() -> Result<(), Box<dyn Error>> {
+        let statement = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+  <BkToCstmrStmt>
+    <Stmt>
+      <Id>STMT-2024-01</Id>
+      <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+      <Ntry>
+        <Amt Ccy="EUR">1500.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <AcctSvcrRef>2024010200001</AcctSvcrRef>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+          <RmtInf><Ustrd>Salary January</Ustrd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">42.50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>BOOK</Cd></Sts>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>INV-77/2024</EndToEndId></Refs>
+        </TxDtls></NtryDtls>
+        <AddtlNtryInf>Card payment &amp; fee</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">10.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts><Cd>PDNG</Cd></Sts>
+        <AcctSvcrRef>2024010200003</AcctSvcrRef>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">-5.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <AcctSvcrRef>2024010200004</AcctSvcrRef>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#;
+        let records = parse(statement);
+        assert_eq!(
+            records.len(),
+            3,
+            "entries that aren't booked should be skipped"
+        );
+
+        let account_id = AccountId::parse("DE89370400440532013000")?;
+        let credit = records[0].result.clone()?;
+        assert_eq!(records[0].line, 7);
+        assert_eq!(
+            credit.txn.id(),
+            TransactionId::from_reference("2024010200001")
+        );
+        assert_eq!(credit.txn.account_id(), account_id);
+        assert_eq!(credit.txn.txn_type().name(), "deposit");
+        assert_eq!(
+            credit.txn.txn_type().amount(),
+            Some(Decimal::new(150000, 2))
+        );
+        assert_eq!(credit.memo.as_deref(), Some("Salary January"));
+
+        let debit = records[1].result.clone()?;
+        assert_eq!(
+            debit.txn.id(),
+            TransactionId::from_reference("INV-77/2024"),
+            "the end-to-end ID should be used in lieu of any other reference"
+        );
+        assert_eq!(debit.txn.txn_type().name(), "withdrawal");
+        assert_eq!(debit.txn.txn_type().amount(), Some(Decimal::new(4250, 2)));
+        assert_eq!(debit.memo.as_deref(), Some("Card payment & fee"));
+
+        let rejection = records[2].result.clone().unwrap_err();
+        assert_eq!(rejection.line, Some(32));
+        assert_eq!(rejection.column.as_deref(), Some("Amt"));
+        assert_eq!(rejection.raw_field.as_deref(), Some("-5.00"));
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_the_rest_of_malformed_documents() {
+        let statement = "<Document><BkToCstmrStmt><Stmt>\n\
+                         <Acct><Id><Othr><Id>4111</Id></Othr></Id></Acct>\n\
+                         <Ntry><Amt>1.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><NtryRef>1</NtryRef></Ntry>\n\
+                         <Ntry><Amt>2.00</Amt></Ntry\n";
+        let records = parse(statement);
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0]
+                .result
+                .as_ref()
+                .map(|imported| imported.txn.account_id())
+                .ok(),
+            Some(AccountId::Number(4111))
+        );
+        assert!(records[1].result.is_err());
+    }
+}
@@ -0,0 +1,188 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::workload::SplitMix64;
+
+// Faults to inject into the delivery of transactions from the dispatcher to the workers, so that
+// tests can check that ordering and shutdown hold up when messages go astray. Each fault befalls a
+// delivery with the given probability, drawn from a generator seeded per worker, so that a failure
+// can be reproduced from its seed. This is only built with the chaos feature, and is never meant
+// for a real run.
+//
+// Only transactions are faulted. Snapshots, flushes and requests to stop are always delivered, and
+// in order, as the processor can't be expected to get by without them.
+#[derive(Clone, Debug, Default)]
+pub struct Chaos {
+    seed: u64,
+    drop: f64,
+    duplicate: f64,
+    delay: f64,
+    max_delay: Duration,
+    reorder: f64,
+    injected: Arc<FaultCounters>,
+}
+
+impl Chaos {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+
+    // Drops deliveries entirely. A receipt for a dropped transaction fails rather than resolving.
+    pub fn with_drops(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    // Delivers transactions twice.
+    pub fn with_duplicates(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+
+    // Holds up the dispatcher for up to the given time before delivering.
+    pub fn with_delays(mut self, probability: f64, max_delay: Duration) -> Self {
+        self.delay = probability;
+        self.max_delay = max_delay;
+        self
+    }
+
+    // Holds deliveries back until after the next one to the same worker.
+    pub fn with_reorders(mut self, probability: f64) -> Self {
+        self.reorder = probability;
+        self
+    }
+
+    // The faults injected so far, across every worker.
+    pub fn injected(&self) -> InjectedFaults {
+        let count = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        InjectedFaults {
+            dropped: count(&self.injected.dropped),
+            duplicated: count(&self.injected.duplicated),
+            delayed: count(&self.injected.delayed),
+            reordered: count(&self.injected.reordered),
+        }
+    }
+
+    pub(crate) fn injector<M: Clone>(&self, worker_idx: usize) -> FaultInjector<M> {
+        FaultInjector {
+            chaos: self.clone(),
+            rng: SplitMix64(self.seed ^ worker_idx as u64),
+            held_back: None,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct FaultCounters {
+    dropped: AtomicU64,
+    duplicated: AtomicU64,
+    delayed: AtomicU64,
+    reordered: AtomicU64,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct InjectedFaults {
+    pub dropped: u64,
+    pub duplicated: u64,
+    pub delayed: u64,
+    pub reordered: u64,
+}
+
+// Injects faults into the messages delivered to a single worker.
+pub(crate) struct FaultInjector<M> {
+    chaos: Chaos,
+    rng: SplitMix64,
+    held_back: Option<M>,
+}
+
+impl<M: Clone> FaultInjector<M> {
+    // The messages to deliver in place of the given one, in order. Messages that can't be faulted
+    // are delivered as they are, after any that was held back.
+    pub(crate) fn inject(&mut self, message: M, faultable: bool) -> Vec<M> {
+        let injected = &self.chaos.injected;
+        if !faultable {
+            return self.held_back.take().into_iter().chain([message]).collect();
+        }
+        if self.rng.chance(self.chaos.drop) {
+            injected.dropped.fetch_add(1, Ordering::Relaxed);
+            return vec![];
+        }
+        if self.rng.chance(self.chaos.delay) {
+            injected.delayed.fetch_add(1, Ordering::Relaxed);
+            let max_nanos = self.chaos.max_delay.as_nanos().max(1) as u64;
+            thread::sleep(Duration::from_nanos(self.rng.below(max_nanos)));
+        }
+
+        let mut messages = vec![message];
+        if self.rng.chance(self.chaos.duplicate) {
+            injected.duplicated.fetch_add(1, Ordering::Relaxed);
+            messages.push(messages[0].clone());
+        }
+        if self.held_back.is_none() && self.rng.chance(self.chaos.reorder) {
+            injected.reordered.fetch_add(1, Ordering::Relaxed);
+            self.held_back = messages.pop();
+            return messages;
+        }
+        messages.extend(self.held_back.take());
+        messages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::processor::{BatchReceipt, TransactionProcessor};
+    use crate::workload::Workload;
+
+    #[test]
+    fn processor_tolerates_faults() -> Result<(), Box<dyn Error>> {
+        let chaos = Chaos::new(3)
+            .with_drops(0.05)
+            .with_duplicates(0.05)
+            .with_delays(0.05, Duration::from_millis(2))
+            .with_reorders(0.1);
+        let failures = Arc::new(AtomicU64::new(0));
+        let processor = TransactionProcessor::builder(4)
+            .assertions(failures.clone())
+            .chaos(chaos.clone())
+            .build();
+
+        let txns = Workload::new(1)
+            .with_transactions(2_000)
+            .with_clients(20)
+            .generate();
+        let receipts = txns
+            .chunks(10)
+            .map(|batch| processor.process_batch(batch))
+            .collect::<Result<Vec<_>, _>>()?;
+        for txn in &txns[..500] {
+            processor.process_txn(txn.clone())?;
+        }
+
+        // However the messages went astray, the workers still shut down.
+        let report = processor.shutdown_with_timeout(Duration::from_secs(10))?;
+        assert!(report.failed_workers.is_empty());
+        let injected = chaos.injected();
+        assert!(injected.dropped > 0 && injected.duplicated > 0);
+        assert!(injected.delayed > 0 && injected.reordered > 0);
+
+        // Every batch has either been processed or failed, rather than leaving its receipt hanging.
+        let failed = receipts
+            .into_iter()
+            .map(BatchReceipt::wait)
+            .filter(Result::is_err)
+            .count();
+        assert!(failed > 0, "dropped batches should fail");
+
+        // Transactions applied twice or out of order are caught.
+        assert!(failures.load(Ordering::Relaxed) > 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,499 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{Cursor, SeekFrom};
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver};
+use memmap2::Mmap;
+
+use crate::models::{
+    csv_record::{ParserCache, RecordParser},
+    transaction::Transaction,
+};
+use crate::pool::Pool;
+
+// The size of the chunks a file is split into for parsing, before being extended to the end of the
+// row they'd otherwise end in the middle of.
+const CHUNK_SIZE: usize = 1 << 20;
+
+// The number of records kept for reuse by default, which is a few chunks' worth.
+const DEFAULT_RECORD_POOL: usize = 1 << 16;
+
+// How the fields of a CSV file are delimited, quoted and trimmed.
+#[derive(Clone, Copy, Debug)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    // The character that quotes fields, unless quoting is turned off.
+    pub quote: Option<u8>,
+    pub trim: bool,
+    pub has_headers: bool,
+}
+
+impl CsvDialect {
+    pub fn reader_builder(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .delimiter(self.delimiter)
+            .quote(self.quote.unwrap_or(b'"'))
+            .quoting(self.quote.is_some())
+            .has_headers(self.has_headers)
+            .trim(if self.trim {
+                csv::Trim::All
+            } else {
+                csv::Trim::None
+            });
+        builder
+    }
+}
+
+struct ParsedRecord {
+    record: csv::ByteRecord,
+    txn: Result<Transaction, csv::Error>,
+    // The byte offset of the end of the record.
+    end: u64,
+}
+
+struct ParsedChunk {
+    records: VecDeque<ParsedRecord>,
+    // The number of lines the chunk spans.
+    lines: u64,
+    // The error that stopped the chunk from being read to its end, if any.
+    error: Option<csv::Error>,
+}
+
+// Reads the transactions of a memory-mapped CSV file, split on row boundaries into chunks that are
+// parsed in parallel by a pool of threads. The parsed chunks are handed back in order, so records
+// are read in the same order as they would be from a stream.
+//
+// The records, and the buffers of the chunks holding them, are pooled for reuse once read, so that
+// they're not allocated afresh for each of the millions of records of a large file.
+//
+// Each chunk is parsed without knowing how many lines came before it, so the positions of its
+// records are relative to the start of the chunk, and only made absolute as the chunk is read.
+// Records that fail to parse, and chunks that fail to be read, are parsed again on the reading
+// thread from their true position, so that errors point at where in the file they lie.
+pub struct ChunkedReader {
+    data: Arc<Mmap>,
+    dialect: CsvDialect,
+    parser: Arc<RecordParser>,
+    chunks: Vec<Range<usize>>,
+    parsed: Vec<Receiver<ParsedChunk>>,
+    buffers: Buffers,
+    // The chunk to read next, and where it starts.
+    next_chunk: usize,
+    next_line: u64,
+    current: VecDeque<ParsedRecord>,
+    error: Option<csv::Error>,
+    records_read: u64,
+    position: u64,
+}
+
+// Buffers shared between the threads parsing chunks and the thread reading them.
+#[derive(Clone)]
+struct Buffers {
+    records: Pool<csv::ByteRecord>,
+    chunks: Pool<VecDeque<ParsedRecord>>,
+}
+
+pub struct ChunkedReaderBuilder {
+    dialect: CsvDialect,
+    headers: Option<csv::ByteRecord>,
+    lenient: bool,
+    threads: usize,
+    record_pool: usize,
+    parsers: Option<Arc<ParserCache>>,
+}
+
+impl ChunkedReaderBuilder {
+    // Parses a file without a header row as though it had the given headers.
+    pub fn headers(mut self, headers: csv::ByteRecord) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+
+    // Parses records leniently, as `LenientTransaction`s.
+    pub fn lenient(mut self) -> Self {
+        self.lenient = true;
+        self
+    }
+
+    // Looks up the parser of the file's headers in the cache of those of files read before it.
+    pub fn parsers(mut self, parsers: Arc<ParserCache>) -> Self {
+        self.parsers = Some(parsers);
+        self
+    }
+
+    // The number of threads parsing chunks, which defaults to one.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    // The number of records kept for reuse once they've been read. Any more than that are freed,
+    // and a file with more records in flight at once has them allocated afresh.
+    pub fn record_pool(mut self, records: usize) -> Self {
+        self.record_pool = records;
+        self
+    }
+
+    // Maps the file, and starts parsing its chunks.
+    pub fn open(self, path: &Path) -> Result<ChunkedReader, Box<dyn Error>> {
+        let dialect = self.dialect;
+        let file = File::open(path)?;
+        // The file mustn't be truncated while it's mapped, which is why it can't be followed.
+        let data = Arc::new(unsafe { Mmap::map(&file)? });
+
+        let (headers, start, next_line) = if dialect.has_headers {
+            let mut reader = dialect.reader_builder().from_reader(&data[..]);
+            let headers = reader.byte_headers()?.clone();
+            let end = row_end(&data, dialect).unwrap_or(data.len());
+            (
+                headers,
+                end,
+                1 + memchr::memchr_iter(b'\n', &data[..end]).count() as u64,
+            )
+        } else {
+            (
+                self.headers
+                    .ok_or("a file without headers needs them given")?,
+                0,
+                1,
+            )
+        };
+        let parser = match &self.parsers {
+            Some(parsers) => parsers.parser(headers, self.lenient),
+            None => Arc::new(RecordParser::new(headers, self.lenient)),
+        };
+        let chunks = split_rows(&data, start, dialect);
+
+        // Chunks are dealt out to the threads in turn, so that each of them can be received in
+        // order from the thread that parsed it. A thread gets no more than a chunk ahead of the
+        // reading thread.
+        let threads = self.threads.clamp(1, chunks.len().max(1));
+        let buffers = Buffers {
+            records: Pool::new(self.record_pool),
+            chunks: Pool::new(threads + 1),
+        };
+        let parsed = (0..threads)
+            .map(|thread_idx| {
+                let (sender, receiver) = bounded(1);
+                let (data, parser) = (data.clone(), parser.clone());
+                let (chunks, buffers) = (chunks.clone(), buffers.clone());
+                thread::spawn(move || {
+                    for chunk in chunks.into_iter().skip(thread_idx).step_by(threads) {
+                        let start = position(chunk.start as u64, 1, 0);
+                        let parsed = parse_chunk(&data, chunk, start, dialect, &parser, &buffers);
+                        if sender.send(parsed).is_err() {
+                            break;
+                        }
+                    }
+                });
+                receiver
+            })
+            .collect();
+
+        Ok(ChunkedReader {
+            data,
+            dialect,
+            parser,
+            chunks,
+            parsed,
+            buffers,
+            next_chunk: 0,
+            next_line,
+            current: VecDeque::new(),
+            error: None,
+            records_read: 0,
+            position: start as u64,
+        })
+    }
+}
+
+impl ChunkedReader {
+    pub fn builder(dialect: CsvDialect) -> ChunkedReaderBuilder {
+        ChunkedReaderBuilder {
+            dialect,
+            headers: None,
+            lenient: false,
+            threads: 1,
+            record_pool: DEFAULT_RECORD_POOL,
+            parsers: None,
+        }
+    }
+
+    pub fn headers(&self) -> &csv::ByteRecord {
+        self.parser.headers()
+    }
+
+    pub fn parser(&self) -> &Arc<RecordParser> {
+        &self.parser
+    }
+
+    // The byte offset of the end of the last record read.
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    // Reads the next record into the given one, returning the transaction parsed from it, or
+    // `None` once every record has been read.
+    #[allow(clippy::type_complexity)]
+    pub fn read_byte_record(
+        &mut self,
+        record: &mut csv::ByteRecord,
+    ) -> csv::Result<Option<Result<Transaction, csv::Error>>> {
+        loop {
+            if let Some(mut parsed) = self.current.pop_front() {
+                mem::swap(record, &mut parsed.record);
+                self.buffers.records.give(parsed.record);
+                self.records_read += 1;
+                self.position = parsed.end;
+                return Ok(Some(parsed.txn));
+            }
+            if let Some(err) = self.error.take() {
+                return Err(err);
+            }
+            if !self.next_parsed_chunk() {
+                return Ok(None);
+            }
+        }
+    }
+
+    // Receives the next chunk, and makes the positions of its records absolute.
+    fn next_parsed_chunk(&mut self) -> bool {
+        let Some(chunk) = self.chunks.get(self.next_chunk).cloned() else {
+            return false;
+        };
+        let receiver = &self.parsed[self.next_chunk % self.parsed.len()];
+        self.next_chunk += 1;
+        let first_record = self.records_read + u64::from(self.dialect.has_headers);
+        let mut parsed = receiver
+            .recv()
+            .expect("chunks are parsed until they're all received");
+
+        if parsed.error.is_some() {
+            let start = position(chunk.start as u64, self.next_line, first_record);
+            let mut stale = mem::take(&mut parsed.records);
+            for stale in stale.drain(..) {
+                self.buffers.records.give(stale.record);
+            }
+            self.buffers.chunks.give(stale);
+            parsed = parse_chunk(
+                &self.data,
+                chunk,
+                start,
+                self.dialect,
+                &self.parser,
+                &self.buffers,
+            );
+        } else {
+            for (idx, parsed) in parsed.records.iter_mut().enumerate() {
+                if let Some(relative) = parsed.record.position() {
+                    let line = self.next_line + relative.line() - 1;
+                    let pos = position(relative.byte(), line, first_record + idx as u64);
+                    parsed.record.set_position(Some(pos));
+                }
+                // Errors tell of where the record lies, so are made again from its true position.
+                if parsed.txn.is_err() {
+                    parsed.txn = self.parser.parse(&parsed.record);
+                }
+            }
+        }
+        self.next_line += parsed.lines;
+        let spent = mem::replace(&mut self.current, parsed.records);
+        self.buffers.chunks.give(spent);
+        self.error = parsed.error;
+        true
+    }
+}
+
+fn position(byte: u64, line: u64, record: u64) -> csv::Position {
+    let mut pos = csv::Position::new();
+    pos.set_byte(byte).set_line(line).set_record(record);
+    pos
+}
+
+// Parses the records of a chunk of the data, reading them as though the chunk started at the given
+// position. The first row of the data is read beforehand, so that records of the chunk with a
+// different number of fields to it are caught, as they would be when streaming the whole file.
+fn parse_chunk(
+    data: &[u8],
+    chunk: Range<usize>,
+    start: csv::Position,
+    dialect: CsvDialect,
+    parser: &RecordParser,
+    buffers: &Buffers,
+) -> ParsedChunk {
+    let mut reader = CsvDialect {
+        has_headers: true,
+        ..dialect
+    }
+    .reader_builder()
+    .from_reader(Cursor::new(data));
+    let mut records = buffers.chunks.take(VecDeque::new);
+    let mut error = reader
+        .seek_raw(SeekFrom::Start(chunk.start as u64), start)
+        .err();
+    while error.is_none() && reader.position().byte() < chunk.end as u64 {
+        let mut record = buffers.records.take(csv::ByteRecord::new);
+        match reader.read_byte_record(&mut record) {
+            Ok(true) => {
+                let txn = parser.parse(&record);
+                let end = reader.position().byte();
+                records.push_back(ParsedRecord { record, txn, end });
+            }
+            Ok(false) => {
+                buffers.records.give(record);
+                break;
+            }
+            Err(err) => error = Some(err),
+        }
+    }
+    ParsedChunk {
+        records,
+        lines: memchr::memchr_iter(b'\n', &data[chunk]).count() as u64,
+        error,
+    }
+}
+
+// Splits the data, from the given offset, into chunks of about `CHUNK_SIZE` bytes that each end at
+// the end of a row.
+fn split_rows(data: &[u8], start: usize, dialect: CsvDialect) -> Vec<Range<usize>> {
+    let mut chunks = vec![];
+    let mut start = start;
+    let mut quotes = QuoteTracker::new(dialect);
+    while start < data.len() {
+        let target = (start + CHUNK_SIZE).min(data.len());
+        let end = quotes.row_end(data, target).unwrap_or(data.len());
+        chunks.push(start..end);
+        start = end;
+    }
+    chunks
+}
+
+// The offset just past the end of the first row of the data, if it has a complete row.
+fn row_end(data: &[u8], dialect: CsvDialect) -> Option<usize> {
+    QuoteTracker::new(dialect).row_end(data, 0)
+}
+
+// Tracks whether each point of the data is within a quoted field, as a newline within one doesn't
+// end its row. A quote only opens a field at the start of it, as elsewhere it's read as is, and is
+// escaped within a quoted field by doubling it.
+struct QuoteTracker {
+    delimiter: u8,
+    quote: Option<u8>,
+    // How far through the data has been scanned, and whether that's within a quoted field.
+    scanned: usize,
+    quoted: bool,
+}
+
+impl QuoteTracker {
+    fn new(dialect: CsvDialect) -> Self {
+        Self {
+            delimiter: dialect.delimiter,
+            quote: dialect.quote,
+            scanned: 0,
+            quoted: false,
+        }
+    }
+
+    // The offset just past the first newline at or after `from` that ends a row.
+    fn row_end(&mut self, data: &[u8], from: usize) -> Option<usize> {
+        let Some(quote) = self.quote else {
+            return memchr::memchr(b'\n', &data[from..]).map(|idx| from + idx + 1);
+        };
+
+        while let Some(idx) = memchr::memchr2(quote, b'\n', &data[self.scanned..]) {
+            let idx = self.scanned + idx;
+            self.scanned = idx + 1;
+            if data[idx] == b'\n' {
+                if !self.quoted && idx >= from {
+                    return Some(idx + 1);
+                }
+            } else if self.quoted {
+                if data.get(idx + 1) == Some(&quote) {
+                    self.scanned += 1;
+                } else {
+                    self.quoted = false;
+                }
+            } else if idx == 0 || [b'\n', b'\r', self.delimiter].contains(&data[idx - 1]) {
+                self.quoted = true;
+            }
+        }
+        self.scanned = data.len();
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn reads_chunks_in_order() -> Result<(), Box<dyn Error>> {
+        // Quoted fields span lines now and again, and a record far into the file is short of a
+        // field, which is only caught against the header row.
+        let mut input = String::from("type,client,tx,amount\n");
+        for tx in 1..=100_000 {
+            input.push_str(&format!("deposit,{},{tx},1.5\n", tx % 7 + 1));
+            if tx % 1_000 == 0 {
+                input.push_str("\"deposit\",1,\"not a\nnumber\",1.5\n");
+            }
+            if tx == 90_000 {
+                input.push_str("dispute,1,1\n");
+            }
+        }
+        let path = env::temp_dir().join(format!("chunked-{}.csv", std::process::id()));
+        fs::write(&path, &input)?;
+
+        let dialect = CsvDialect {
+            delimiter: b',',
+            quote: Some(b'"'),
+            trim: false,
+            has_headers: true,
+        };
+        let mut chunked = ChunkedReader::builder(dialect)
+            .threads(4)
+            .record_pool(1_000)
+            .open(&path)?;
+        assert!(chunked.chunks.len() > 1, "the file spans several chunks");
+        let mut reader = dialect.reader_builder().from_reader(input.as_bytes());
+        let parser = RecordParser::new(reader.byte_headers()?.clone(), false);
+
+        let describe = |txn: Result<Transaction, csv::Error>| {
+            txn.map(|txn| txn.to_string())
+                .map_err(|err| err.to_string())
+        };
+        let (mut expected, mut record) = (csv::ByteRecord::new(), csv::ByteRecord::new());
+        let mut read = 0;
+        loop {
+            match (
+                reader.read_byte_record(&mut expected),
+                chunked.read_byte_record(&mut record),
+            ) {
+                (Ok(true), Ok(Some(txn))) => {
+                    assert_eq!(record, expected);
+                    assert_eq!(record.position(), expected.position());
+                    assert_eq!(describe(txn), describe(parser.parse(&expected)));
+                    assert_eq!(chunked.position(), reader.position().byte());
+                    read += 1;
+                }
+                (Err(expected), Err(err)) => {
+                    assert_eq!(err.to_string(), expected.to_string());
+                    break;
+                }
+                (expected, read) => panic!("expected {expected:?}, but read {read:?}"),
+            }
+        }
+        assert_eq!(read, 90_090);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
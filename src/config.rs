@@ -0,0 +1,199 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use snafu::{ResultExt, Snafu};
+
+// The prefix of the environment variables that stand for options.
+const ENV_PREFIX: &str = "BANKING_";
+
+#[derive(Debug, Snafu)]
+pub enum ConfigError {
+    #[snafu(display("Unable to read the configuration file {}: {source}", path.display()))]
+    ReadConfig { path: PathBuf, source: io::Error },
+
+    #[snafu(display("The configuration file {} is invalid: {source}", path.display()))]
+    ParseConfig {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("The setting {key} in {} is invalid: {reason}", path.display()))]
+    InvalidSetting {
+        path: PathBuf,
+        key: String,
+        reason: String,
+    },
+}
+
+// The options of a run as read from a TOML file, so that a long invocation can be kept in a file
+// rather than a shell script. Each setting is named after the long command line option it stands
+// in for, with either dashes or underscores, e.g.
+//
+// ```toml
+// num-workers = 8
+// partitioner = "consistent-hash"
+// dedup_window = "24h"
+// output = ["csv:accounts.csv", "json:accounts.jsonl"]
+// journal = "journal.csv"
+// deterministic = true
+// ```
+//
+// A flag is set by `true` and left unset by `false`, and an option that may be given multiple
+// times takes an array of its values.
+#[derive(Debug)]
+pub struct Config {
+    settings: Vec<(String, Vec<String>)>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = fs::read_to_string(path).context(ReadConfigSnafu { path })?;
+        let table: toml::Table = toml::from_str(&text).context(ParseConfigSnafu { path })?;
+        let mut settings = vec![];
+        for (key, value) in table {
+            let option = key.replace('_', "-");
+            let invalid = |reason: &str| {
+                InvalidSettingSnafu {
+                    path,
+                    key: key.clone(),
+                    reason,
+                }
+                .build()
+            };
+            if option == "config" {
+                return Err(invalid("a configuration file can't name another one"));
+            }
+            let values =
+                match value {
+                    toml::Value::Array(values) => values
+                        .into_iter()
+                        .map(|value| {
+                            argument(value).ok_or_else(|| {
+                                invalid("arrays may only hold strings, numbers, or dates")
+                            })
+                        })
+                        .collect::<Result<_, _>>()?,
+                    toml::Value::Boolean(true) => vec![],
+                    toml::Value::Boolean(false) => continue,
+                    value => vec![argument(value)
+                        .ok_or_else(|| invalid("tables don't stand for any option"))?],
+                };
+            settings.push((option, values));
+        }
+        Ok(Self { settings })
+    }
+
+    // The options given by `BANKING_*` environment variables, for deployments such as containers
+    // where a long command line can't be templated. Each variable stands for the long option of the
+    // same name, e.g. `BANKING_NUM_WORKERS=8` for `--num-workers=8`. As in a file, a flag is set by
+    // `true` and left unset by `false`, but an option that may be given multiple times can only be
+    // given one value. `BANKING_CONFIG` names a configuration file, so isn't an option itself.
+    pub fn from_env(vars: impl IntoIterator<Item = (OsString, OsString)>) -> Self {
+        let settings = vars
+            .into_iter()
+            .filter_map(|(var, value)| {
+                let option = var.to_str()?.strip_prefix(ENV_PREFIX)?;
+                let option = option.to_ascii_lowercase().replace('_', "-");
+                let values = match value.into_string().ok()?.as_str() {
+                    "true" => vec![],
+                    "false" => return None,
+                    value => vec![value.to_owned()],
+                };
+                (option != "config").then_some((option, values))
+            })
+            .collect();
+        Self { settings }
+    }
+
+    // The long options the settings stand for.
+    pub fn options(&self) -> impl Iterator<Item = &str> {
+        self.settings.iter().map(|(option, _)| option.as_str())
+    }
+
+    // The command line arguments the settings stand for, leaving out those of options that were
+    // already given by the command line or a layer above, which take precedence.
+    pub fn args(&self, given: &HashSet<String>) -> Vec<OsString> {
+        let mut args = vec![];
+        for (option, values) in &self.settings {
+            if given.contains(option) {
+                continue;
+            }
+            if values.is_empty() {
+                args.push(format!("--{option}").into());
+            }
+            for value in values {
+                args.push(format!("--{option}={value}").into());
+            }
+        }
+        args
+    }
+}
+
+// The environment variable that stands for the given long option.
+pub fn env_var(option: &str) -> String {
+    format!(
+        "{ENV_PREFIX}{}",
+        option.to_ascii_uppercase().replace('-', "_")
+    )
+}
+
+// The value of an option as it would be given on the command line.
+fn argument(value: toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Datetime(value) => Some(value.to_string()),
+        toml::Value::Boolean(_) | toml::Value::Array(_) | toml::Value::Table(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn settings_stand_for_options() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("config-{}.toml", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "num_workers = 8\n\
+             output = [\"csv:accounts.csv\", \"json\"]\n\
+             deterministic = true\n\
+             progress = false\n\
+             partitioner = \"consistent-hash\"\n",
+        )?;
+        let config = Config::load(&path)?;
+        let given = HashSet::from(["partitioner".to_owned()]);
+        assert_eq!(
+            config.args(&given),
+            [
+                "--deterministic",
+                "--num-workers=8",
+                "--output=csv:accounts.csv",
+                "--output=json",
+            ]
+        );
+
+        let env = Config::from_env([
+            ("BANKING_NUM_WORKERS".into(), "4".into()),
+            ("BANKING_DETERMINISTIC".into(), "true".into()),
+            ("BANKING_PROGRESS".into(), "false".into()),
+            ("BANKING_CONFIG".into(), "run.toml".into()),
+            ("HOME".into(), "/root".into()),
+        ]);
+        assert_eq!(env_var("num-workers"), "BANKING_NUM_WORKERS");
+        let given = HashSet::from(["deterministic".to_owned()]);
+        assert_eq!(env.args(&given), ["--num-workers=4"]);
+
+        fs::write(&path, "[sinks]\noutput = \"json\"\n")?;
+        assert!(Config::load(&path).is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
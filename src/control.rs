@@ -0,0 +1,203 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use snafu::{ResultExt, Whatever};
+
+// How often the channel checks whether it has been asked to stop while no one is connected.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long a client is given to send its command, so that one that never does can't hold up the
+// channel.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+// A command sent over the control channel of a resident run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ControlCommand {
+    // Reads the transactions of the given file, and submits them for processing.
+    Submit(PathBuf),
+    // Waits for every transaction submitted so far to be processed.
+    Flush,
+    // Writes a snapshot of every account into the given directory.
+    Snapshot(PathBuf),
+    // Reports how far behind the workers are.
+    Stats,
+    // Stops serving commands, so that the run can complete.
+    Stop,
+}
+
+impl FromStr for ControlCommand {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (command, arg) = match s.trim().split_once(char::is_whitespace) {
+            Some((command, arg)) => (command, Some(arg.trim())),
+            None => (s.trim(), None),
+        };
+        match (command, arg) {
+            ("submit", Some(path)) => Ok(Self::Submit(path.into())),
+            ("snapshot", Some(dir)) => Ok(Self::Snapshot(dir.into())),
+            ("flush", None) => Ok(Self::Flush),
+            ("stats", None) => Ok(Self::Stats),
+            ("stop", None) => Ok(Self::Stop),
+            ("submit", None) => Err("submit needs the path of a transactions file".to_owned()),
+            ("snapshot", None) => Err("snapshot needs the directory to write it into".to_owned()),
+            _ => Err(format!("'{}' is not a valid command", s.trim())),
+        }
+    }
+}
+
+// The control channel of a resident run, served over a Unix socket. Each connection sends a single
+// command on a line of its own, and is answered with a line starting with `ok` or `error`, e.g.
+// `echo 'submit today.csv' | nc -U banking.sock`. Commands are handled one at a time, in the order
+// their connections were accepted.
+pub struct ControlChannel {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ControlChannel {
+    // Listens on a socket at the given path. A socket left behind by a run that didn't shut down
+    // cleanly is replaced, but any other file at the path is left alone.
+    pub fn bind(path: &Path) -> Result<Self, Whatever> {
+        if fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            fs::remove_file(path).with_whatever_context(|_| {
+                format!("unable to remove stale control socket {}", path.display())
+            })?;
+        }
+        let listener = UnixListener::bind(path).with_whatever_context(|_| {
+            format!("unable to listen on control socket {}", path.display())
+        })?;
+        listener
+            .set_nonblocking(true)
+            .with_whatever_context(|_| format!("unable to listen on {}", path.display()))?;
+        Ok(Self {
+            listener,
+            path: path.to_path_buf(),
+        })
+    }
+
+    // Serves commands until asked to stop, either by a `stop` command or by `stop` being set. Every
+    // other command is handed to `handle`, whose result is sent back as the reply.
+    pub fn serve(
+        &self,
+        stop: &AtomicBool,
+        mut handle: impl FnMut(ControlCommand) -> Result<String, String>,
+    ) -> Result<(), Whatever> {
+        while !stop.load(Ordering::Relaxed) {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                Err(err) => {
+                    return Err(err).with_whatever_context(|_| {
+                        format!("unable to accept on {}", self.path.display())
+                    })
+                }
+            };
+
+            let command = match read_command(&stream) {
+                Ok(command) => command,
+                Err(err) => {
+                    tracing::warn!(
+                        error_code = "control_read_failed",
+                        "Unable to read a command from the control channel: {err}"
+                    );
+                    continue;
+                }
+            };
+            tracing::info!(%command, "Received a command over the control channel");
+            let (reply, stopping) = match command.parse::<ControlCommand>() {
+                Ok(ControlCommand::Stop) => (Ok("stopping".to_owned()), true),
+                Ok(command) => (handle(command), false),
+                Err(err) => (Err(err), false),
+            };
+            let reply = match reply {
+                Ok(reply) => format!("ok {reply}\n"),
+                Err(err) => format!("error {err}\n"),
+            };
+            if let Err(err) = (&stream).write_all(reply.as_bytes()) {
+                tracing::warn!(
+                    error_code = "control_reply_failed",
+                    "Unable to reply over the control channel: {err}"
+                );
+            }
+            if stopping {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ControlChannel {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn read_command(stream: &UnixStream) -> io::Result<String> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(COMMAND_TIMEOUT))?;
+    let mut command = String::new();
+    BufReader::new(stream).read_line(&mut command)?;
+    Ok(command.trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::io::Read;
+
+    use uuid::Uuid;
+
+    #[test]
+    fn serves_commands() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("control-{}.sock", Uuid::new_v4()));
+        let channel = ControlChannel::bind(&path)?;
+
+        let client = {
+            let path = path.clone();
+            thread::spawn(move || -> io::Result<Vec<String>> {
+                ["submit today.csv", "rewind", "stop"]
+                    .into_iter()
+                    .map(|command| {
+                        let mut stream = UnixStream::connect(&path)?;
+                        writeln!(stream, "{command}")?;
+                        let mut reply = String::new();
+                        stream.read_to_string(&mut reply)?;
+                        Ok(reply)
+                    })
+                    .collect()
+            })
+        };
+
+        let mut handled = vec![];
+        channel.serve(&AtomicBool::new(false), |command| {
+            handled.push(command);
+            Ok("records=2".to_owned())
+        })?;
+        assert_eq!(handled, [ControlCommand::Submit("today.csv".into())]);
+        assert_eq!(
+            client.join().expect("client panicked")?,
+            [
+                "ok records=2\n",
+                "error 'rewind' is not a valid command\n",
+                "ok stopping\n"
+            ]
+        );
+
+        drop(channel);
+        assert!(!path.exists(), "the socket should be removed once closed");
+        Ok(())
+    }
+}
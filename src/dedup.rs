@@ -0,0 +1,79 @@
+use std::f64::consts::LN_2;
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+// A Bloom filter, used to detect duplicate transactions in a fixed amount of memory however many
+// transactions pass through it. It never misses a transaction it has seen, but may mistake a new
+// transaction for one it has seen at roughly the false positive rate it was sized for, provided no
+// more than its capacity of transactions are inserted into it.
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    pub fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        // The optimal number of bits and hash functions for the given capacity and false positive
+        // rate, per the standard formulae.
+        let capacity = capacity.max(1) as f64;
+        let num_bits = (-capacity * false_positive_rate.ln() / (LN_2 * LN_2)).ceil() as u64;
+        let num_bits = num_bits.max(64);
+        let num_hashes = ((num_bits as f64 / capacity) * LN_2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    pub fn insert<T: Hash>(&mut self, item: &T) {
+        for idx in self.bit_indices(item) {
+            self.bits[(idx / 64) as usize] |= 1 << (idx % 64);
+        }
+    }
+
+    pub fn contains<T: Hash>(&self, item: &T) -> bool {
+        self.bit_indices(item)
+            .all(|idx| self.bits[(idx / 64) as usize] & (1 << (idx % 64)) != 0)
+    }
+
+    // The bits that represent the item. Rather than hashing the item once per hash function, the
+    // bits are derived from two independent hashes of it, which is just as effective.
+    fn bit_indices<T: Hash>(&self, item: &T) -> impl Iterator<Item = u64> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let num_bits = self.num_bits;
+        (0..u64::from(self.num_hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bloom_filter() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for item in 0..10_000u32 {
+            filter.insert(&item);
+        }
+
+        assert!(
+            (0..10_000u32).all(|item| filter.contains(&item)),
+            "every item inserted should be found"
+        );
+        let false_positives = (10_000..20_000u32)
+            .filter(|item| filter.contains(item))
+            .count();
+        assert!(
+            false_positives < 200,
+            "{false_positives} false positives is well above the expected rate"
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+
+use crate::models::transaction::{Source, Transaction};
+
+// A transaction read from the input, along with when it was posted and when it took effect, in
+// milliseconds since the Unix epoch, if the input says. A backdated correction is posted after it
+// takes effect.
+#[derive(Clone, Debug)]
+pub struct DatedTransaction {
+    pub txn: Transaction,
+    pub source: Source,
+    pub memo: Option<Arc<str>>,
+    pub posted_at: Option<u64>,
+    pub effective_at: Option<u64>,
+}
+
+impl DatedTransaction {
+    // When the transaction took effect: as of its effective date if it has one, or else when it was
+    // posted.
+    pub fn effective_at(&self) -> Option<u64> {
+        self.effective_at.or(self.posted_at)
+    }
+}
+
+// Holds back the transactions of the input until all of it has been read, so that they can be
+// applied in order of when they took effect rather than the order they were posted in.
+#[derive(Debug, Default)]
+pub struct EffectiveDateOrder {
+    txns: Vec<(u64, DatedTransaction)>,
+    // When the last transaction held back took effect, which those without a date of their own are
+    // taken to have taken effect along with.
+    last_effective_at: u64,
+}
+
+impl EffectiveDateOrder {
+    pub fn push(&mut self, txn: DatedTransaction) {
+        if let Some(effective_at) = txn.effective_at() {
+            self.last_effective_at = effective_at;
+        }
+        self.txns.push((self.last_effective_at, txn));
+    }
+
+    pub fn len(&self) -> usize {
+        self.txns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.txns.is_empty()
+    }
+
+    // The transactions held back, in order of when they took effect, along with when that was.
+    // Those that took effect at the same time are left in the order they were read.
+    pub fn into_sorted(mut self) -> impl Iterator<Item = (u64, DatedTransaction)> {
+        self.txns.sort_by_key(|(effective_at, _)| *effective_at);
+        self.txns.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::TransactionType;
+    use std::path::Path;
+
+    #[test]
+    fn orders_by_effective_date() {
+        let mut order = EffectiveDateOrder::default();
+        let dated = |line, posted_at, effective_at| DatedTransaction {
+            txn: Transaction::new(1.into(), 1.into(), TransactionType::Dispute),
+            source: Source::new(Path::new("txns.csv"), line),
+            memo: None,
+            posted_at,
+            effective_at,
+        };
+        order.push(dated(1, Some(10), None));
+        order.push(dated(2, Some(20), None));
+        // A backdated correction, and a transaction that takes effect along with it.
+        order.push(dated(3, Some(30), Some(5)));
+        order.push(dated(4, None, None));
+        order.push(dated(5, Some(40), Some(20)));
+        assert_eq!(order.len(), 5);
+
+        let sorted: Vec<_> = order
+            .into_sorted()
+            .map(|(effective_at, dated)| (effective_at, dated.source.line()))
+            .collect();
+        assert_eq!(sorted, [(5, 3), (5, 4), (10, 1), (20, 2), (20, 5)]);
+    }
+}
@@ -0,0 +1,151 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use derive_more::Display;
+
+use crate::rejects::Rejection;
+
+// The broad class of failure that ended a run, which determines the process's exit code. This lets
+// whatever scheduled the run tell failures that are worth retrying apart from those that will fail
+// the same way again.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+pub enum FailureClass {
+    // Reading the input or writing the results failed, other than for a missing file or a lack of
+    // permission, which are down to how the run was set up.
+    #[display(fmt = "I/O error")]
+    Io,
+    // A record in the input could not be parsed.
+    #[display(fmt = "parse error")]
+    Parse,
+    // The results failed verification, reconciliation, or an assertion.
+    #[display(fmt = "invariant violation")]
+    InvariantViolation,
+    // Transactions were not applied, of a class the run was asked to fail on.
+    #[display(fmt = "transaction rejected")]
+    TransactionRejected,
+    // The run was asked to stop before it had read all of its input, leaving its results partial.
+    #[display(fmt = "interrupted")]
+    Interrupted,
+    // Anything else, such as invalid configuration.
+    #[display(fmt = "error")]
+    Other,
+}
+
+impl FailureClass {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            Self::Other => 1,
+            Self::Io => 2,
+            Self::Parse => 3,
+            Self::InvariantViolation => 4,
+            Self::TransactionRejected => 5,
+            Self::Interrupted => 6,
+        }
+    }
+
+    // Whether the run might succeed if retried as is. Only I/O errors and interruptions can be
+    // transient; everything else is down to the input or configuration, and will fail the same way
+    // again.
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::Io | Self::Interrupted)
+    }
+
+    // Classifies an error by the first error in its chain of sources that is recognised.
+    pub fn classify(err: &(dyn Error + 'static)) -> Self {
+        let mut next = Some(err);
+        while let Some(err) = next {
+            if let Some(failure) = err.downcast_ref::<Failure>() {
+                return failure.class;
+            }
+            if let Some(err) = err.downcast_ref::<io::Error>() {
+                return Self::classify_io(err);
+            }
+            if err.is::<ureq::Error>() {
+                return Self::Io;
+            }
+            if let Some(err) = err.downcast_ref::<csv::Error>() {
+                return match err.kind() {
+                    csv::ErrorKind::Io(err) => Self::classify_io(err),
+                    _ => Self::Parse,
+                };
+            }
+            if err.is::<Rejection>() {
+                return Self::Parse;
+            }
+            next = err.source();
+        }
+        Self::Other
+    }
+
+    // A file that doesn't exist, or that the run isn't allowed to use, will be the same when
+    // retried, so is a mistake in the run's configuration rather than a transient I/O error.
+    fn classify_io(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::PermissionDenied => Self::Other,
+            _ => Self::Io,
+        }
+    }
+}
+
+// A failure of a run that is explicitly classified, rather than classified by its type.
+#[derive(Debug)]
+pub struct Failure {
+    pub class: FailureClass,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(class: FailureClass, message: impl Into<String>) -> Self {
+        Self {
+            class,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for Failure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use snafu::{ResultExt, Whatever};
+
+    #[test]
+    fn classify() {
+        let io_err = io::Error::other("disk on fire");
+        assert_eq!(FailureClass::classify(&io_err), FailureClass::Io);
+
+        let wrapped: Result<(), Whatever> = Err(io_err).whatever_context("unable to write");
+        assert_eq!(
+            FailureClass::classify(&wrapped.unwrap_err()),
+            FailureClass::Io,
+            "errors should be classified by their sources"
+        );
+
+        let failure = Failure::new(FailureClass::InvariantViolation, "1 invariant violations");
+        assert_eq!(
+            FailureClass::classify(&failure),
+            FailureClass::InvariantViolation
+        );
+
+        let missing: Result<(), Whatever> = Err(io::Error::from(io::ErrorKind::NotFound))
+            .whatever_context("unable to open the input");
+        assert_eq!(
+            FailureClass::classify(&missing.unwrap_err()),
+            FailureClass::Other,
+            "a missing file won't turn up if retried"
+        );
+        let denied = csv::Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(FailureClass::classify(&denied), FailureClass::Other);
+
+        let other: Box<dyn Error> = "no workers".into();
+        assert_eq!(FailureClass::classify(other.as_ref()), FailureClass::Other);
+    }
+}
@@ -0,0 +1,77 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// How long to wait before checking whether more data has been appended to the input.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+// Reads from a file that is still being written to, like `tail -f`. Rather than reporting the end
+// of the file when it catches up with the writer, it waits for more data to be appended. The end of
+// the file is only reported once `stop` has been set, after which any remaining data is still read.
+pub struct FollowReader<R> {
+    inner: R,
+    stop: Arc<AtomicBool>,
+}
+
+impl<R: Read> FollowReader<R> {
+    pub fn new(inner: R, stop: Arc<AtomicBool>) -> Self {
+        Self { inner, stop }
+    }
+}
+
+impl<R: Read> Read for FollowReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            // Check for the stop before reading, so that nothing appended before it was set is
+            // missed.
+            let stopping = self.stop.load(Ordering::Relaxed);
+            let n = self.inner.read(buf)?;
+            if n > 0 || stopping || buf.is_empty() {
+                return Ok(n);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    // An input that is appended to by another thread.
+    #[derive(Clone, Default)]
+    struct GrowingInput(Arc<Mutex<VecDeque<u8>>>);
+
+    impl Read for GrowingInput {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().read(buf)
+        }
+    }
+
+    #[test]
+    fn follows_until_stopped() -> io::Result<()> {
+        let input = GrowingInput::default();
+        let stop = Arc::new(AtomicBool::new(false));
+        input.0.lock().unwrap().extend(b"type,client");
+
+        let writer = {
+            let (input, stop) = (input.clone(), stop.clone());
+            thread::spawn(move || {
+                thread::sleep(POLL_INTERVAL * 2);
+                input.0.lock().unwrap().extend(b",tx,amount\n");
+                stop.store(true, Ordering::Relaxed);
+            })
+        };
+
+        let mut contents = String::new();
+        FollowReader::new(input, stop).read_to_string(&mut contents)?;
+        writer.join().unwrap();
+        assert_eq!(contents, "type,client,tx,amount\n");
+
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+use std::sync::Mutex;
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::calendar;
+use crate::ledger::Side;
+use crate::models::{account::Account, transaction::OrderedTransaction};
+use crate::observer::EventObserver;
+use crate::stats::RunId;
+
+// A single line of the general ledger export, posting an amount to one sub-ledger of an account.
+// The columns are those finance imports into their general ledger system, so shouldn't change.
+#[derive(Debug, Serialize)]
+struct GeneralLedgerLine {
+    date: String,
+    account: String,
+    debit: Option<Decimal>,
+    credit: Option<Decimal>,
+    reference: String,
+}
+
+// Exports the entries posted by every transaction applied in a run as a general ledger CSV, with a
+// line for each side of each entry. Accounts are named by their client and sub-ledger, e.g.
+// `1/available`, led by their tenant in a multi-tenant run. Each line is referenced by the run and
+// order of its transaction, as `{run_id}/{order}`, which ties it back to the audit journal, and is
+// dated by when its transaction was posted if the input says, or else by when it was applied.
+pub struct GeneralLedger {
+    run_id: RunId,
+    writer: Mutex<csv::Writer<BufWriter<File>>>,
+}
+
+impl GeneralLedger {
+    pub fn create(path: &Path, run_id: RunId) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Ok(Self {
+            run_id,
+            writer: Mutex::new(csv::Writer::from_writer(BufWriter::new(file))),
+        })
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.writer
+            .lock()
+            .expect("general ledger lock poisoned")
+            .flush()
+    }
+}
+
+impl EventObserver for GeneralLedger {
+    fn on_applied(&self, txn: &OrderedTransaction, account: &Account) {
+        if account.last_entries().is_empty() {
+            return;
+        }
+        let date = txn
+            .posted_at()
+            .or(account.last_applied_at())
+            .map_or_else(String::new, date);
+        let client = match account.tenant() {
+            Some(tenant) => format!("{tenant}/{}", account.id()),
+            None => account.id().to_string(),
+        };
+        let reference = format!("{}/{}", self.run_id, txn.order());
+
+        let mut writer = self.writer.lock().expect("general ledger lock poisoned");
+        for posting in account
+            .last_entries()
+            .iter()
+            .flat_map(|entry| entry.postings())
+        {
+            let line = GeneralLedgerLine {
+                date: date.clone(),
+                account: format!("{client}/{}", posting.sub_ledger),
+                debit: (posting.side == Side::Debit).then_some(posting.amount),
+                credit: (posting.side == Side::Credit).then_some(posting.amount),
+                reference: reference.clone(),
+            };
+            if let Err(err) = writer.serialize(&line) {
+                tracing::error!(
+                    account_id = %account.id(),
+                    txn_id = %txn.txn().id(),
+                    error_code = "general_ledger_write_failed",
+                    "Unable to write entry to the general ledger: {err}"
+                );
+                return;
+            }
+        }
+    }
+}
+
+// The date of the given time in milliseconds since the Unix epoch, as YYYY-MM-DD.
+fn date(millis: u64) -> String {
+    let (year, month, day) = calendar::civil_from_days((millis / 86_400_000) as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::{Transaction, TransactionType};
+    use std::error::Error;
+    use std::fs;
+
+    #[test]
+    fn exports_postings() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("gl-{}.csv", uuid::Uuid::new_v4()));
+        let run_id = RunId::generate();
+        let general_ledger = GeneralLedger::create(&path, run_id)?;
+
+        let mut account = Account::new(1.into());
+        let amount = "2.5".parse()?;
+        let deposit = Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount });
+        let dispute = Transaction::new(1.into(), 1.into(), TransactionType::Dispute);
+        for (order, txn) in [deposit, dispute].into_iter().enumerate() {
+            account.process_txn(txn.clone())?;
+            account.record_applied(order as u64, 86_400_000, None);
+            general_ledger.on_applied(&OrderedTransaction::new(order as u64, txn), &account);
+        }
+        general_ledger.flush()?;
+
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            format!(
+                "date,account,debit,credit,reference\n\
+                 1970-01-02,1/suspense,2.5,,{run_id}/0\n\
+                 1970-01-02,1/available,,2.5,{run_id}/0\n\
+                 1970-01-02,1/available,2.5,,{run_id}/1\n\
+                 1970-01-02,1/held,,2.5,{run_id}/1\n"
+            )
+        );
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+// The maps and sets on the hot path of applying transactions: the history of each account, and the
+// accounts of each worker. By default these are the standard library's, hashed with SipHash, which
+// shows up prominently in profiles of dispute-heavy workloads. Builds with the fast-hash feature
+// use hashbrown maps hashed with aHash instead, which is much faster for keys as small as ours,
+// while still being seeded randomly so that collisions can't be forced by whoever writes the input.
+#[cfg(feature = "fast-hash")]
+pub type FastHashMap<K, V> = hashbrown::HashMap<K, V, ahash::RandomState>;
+#[cfg(feature = "fast-hash")]
+pub type FastHashSet<T> = hashbrown::HashSet<T, ahash::RandomState>;
+
+#[cfg(not(feature = "fast-hash"))]
+pub type FastHashMap<K, V> = std::collections::HashMap<K, V>;
+#[cfg(not(feature = "fast-hash"))]
+pub type FastHashSet<T> = std::collections::HashSet<T>;
@@ -0,0 +1,171 @@
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "arrow")]
+use crate::arrow_ipc;
+#[cfg(feature = "avro")]
+use crate::avro;
+use crate::bank_profile::BankProfile;
+use crate::input::InputFormat;
+use crate::models::transaction::Transaction;
+#[cfg(feature = "protobuf")]
+use crate::protobuf;
+use crate::rejects::Rejection;
+#[cfg(feature = "xlsx")]
+use crate::xlsx;
+use crate::{camt, ofx, qif};
+
+// A transaction read from a bank statement or partner file, or the reason it could not be, along
+// with the line of the file it starts on.
+#[derive(Debug)]
+pub struct StatementRecord {
+    pub line: u64,
+    pub result: Result<ImportedTransaction, Rejection>,
+}
+
+// A transaction imported from a file, along with its memo, if the file gives it one.
+#[derive(Clone, Debug)]
+pub struct ImportedTransaction {
+    pub txn: Transaction,
+    pub memo: Option<Arc<str>>,
+}
+
+// The records of a file, as they're imported from it.
+pub type StatementRecords<'a> = Box<dyn Iterator<Item = io::Result<StatementRecord>> + 'a>;
+
+// Turns the whole of a file that isn't in our own CSV format into transactions. Our own format is
+// streamed in rather than imported, as it needs no mapping and files of it can be huge.
+pub trait Importer: Send + Sync {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord>;
+
+    // Imports the records of a file as it's read, for formats that can be imported a record at a
+    // time, so that the file needn't be held in memory. Otherwise, the whole of the file is read
+    // before any of it is imported.
+    fn records<'a>(&'a self, mut reader: Box<dyn Read + 'a>) -> io::Result<StatementRecords<'a>> {
+        let mut data = vec![];
+        reader.read_to_end(&mut data)?;
+        Ok(Box::new(
+            std::iter::once_with(move || self.import(&data))
+                .flatten()
+                .map(Ok),
+        ))
+    }
+
+    // The name of the column or element that holds each transaction's amount, which rejected
+    // amounts are reported against.
+    fn amount_column(&self) -> &str;
+}
+
+pub struct OfxImporter;
+
+impl Importer for OfxImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        ofx::parse(&String::from_utf8_lossy(data))
+    }
+
+    fn amount_column(&self) -> &str {
+        "TRNAMT"
+    }
+}
+
+pub struct Camt053Importer;
+
+impl Importer for Camt053Importer {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        camt::parse(&String::from_utf8_lossy(data))
+    }
+
+    fn amount_column(&self) -> &str {
+        "Amt"
+    }
+}
+
+// Imports CSV files laid out as a bank profile describes.
+pub struct MappedCsvImporter(pub BankProfile);
+
+impl Importer for MappedCsvImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        self.0.import_csv(&String::from_utf8_lossy(data))
+    }
+
+    fn amount_column(&self) -> &str {
+        self.0.amount_column()
+    }
+}
+
+pub struct QifImporter(pub BankProfile);
+
+impl Importer for QifImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        qif::parse(&String::from_utf8_lossy(data), &self.0)
+    }
+
+    fn amount_column(&self) -> &str {
+        self.0.amount_column()
+    }
+}
+
+// The importer for files of the given format, mapped by the given profile if there is one, or
+// `None` if they are in our own CSV format. Workbooks are read from the given sheet, or otherwise
+// the first, and the records of Avro files are resolved against the given schema, if any.
+pub fn for_format(
+    format: InputFormat,
+    profile: Option<BankProfile>,
+    sheet: Option<String>,
+    avro_schema: Option<&Path>,
+) -> Result<Option<Box<dyn Importer>>, String> {
+    Ok(match (format, profile) {
+        (InputFormat::Csv, None) => None,
+        (InputFormat::Csv, Some(profile)) => Some(Box::new(MappedCsvImporter(profile))),
+        (InputFormat::Qif, profile) => Some(Box::new(QifImporter(
+            profile.unwrap_or_else(qif::default_profile),
+        ))),
+        (InputFormat::Ofx, None) => Some(Box::new(OfxImporter)),
+        (InputFormat::Camt053, None) => Some(Box::new(Camt053Importer)),
+        #[cfg(feature = "xlsx")]
+        (InputFormat::Xlsx, profile) => Some(Box::new(xlsx::XlsxImporter {
+            sheet,
+            profile: profile.unwrap_or_else(BankProfile::native),
+        })),
+        #[cfg(not(feature = "xlsx"))]
+        (InputFormat::Xlsx, _) => {
+            let _ = sheet;
+            return Err(
+                "Excel workbooks can only be read by builds with the xlsx feature".to_owned(),
+            );
+        }
+        #[cfg(feature = "avro")]
+        (InputFormat::Avro, profile) => Some(Box::new(avro::AvroImporter::new(
+            avro_schema,
+            profile.unwrap_or_else(BankProfile::native),
+        )?)),
+        #[cfg(not(feature = "avro"))]
+        (InputFormat::Avro, _) => {
+            let _ = avro_schema;
+            return Err("Avro files can only be read by builds with the avro feature".to_owned());
+        }
+        #[cfg(feature = "arrow")]
+        (InputFormat::Arrow, profile) => Some(Box::new(arrow_ipc::ArrowImporter {
+            profile: profile.unwrap_or_else(BankProfile::native),
+        })),
+        #[cfg(not(feature = "arrow"))]
+        (InputFormat::Arrow, _) => {
+            return Err("Arrow files can only be read by builds with the arrow feature".to_owned());
+        }
+        #[cfg(feature = "protobuf")]
+        (InputFormat::Protobuf, None) => Some(Box::new(protobuf::ProtobufImporter)),
+        #[cfg(not(feature = "protobuf"))]
+        (InputFormat::Protobuf, None) => {
+            return Err(
+                "Protobuf streams can only be read by builds with the protobuf feature".to_owned(),
+            );
+        }
+        (InputFormat::Ofx | InputFormat::Camt053 | InputFormat::Protobuf, Some(_)) => {
+            return Err(
+                "A bank profile can only be used with CSV, QIF, xlsx, Avro, or Arrow files"
+                    .to_owned(),
+            )
+        }
+    })
+}
@@ -0,0 +1,232 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use glob::Pattern;
+use serde::{Deserialize, Serialize};
+
+use crate::calendar::{days_from_civil, days_in_month};
+
+// The order in which the files of an input directory are processed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FileOrder {
+    // By file name.
+    Lexicographic,
+    // By last modification time, oldest first, and then by file name.
+    Mtime,
+}
+
+impl FromStr for FileOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lexicographic" => Ok(Self::Lexicographic),
+            "mtime" => Ok(Self::Mtime),
+            _ => Err(format!("'{s}' is not a valid file order.")),
+        }
+    }
+}
+
+// The format of the transactions files.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    // OFX bank statements, whose credits and debits are read as deposits and withdrawals.
+    Ofx,
+    // ISO 20022 camt.053 bank statements, whose entries are likewise read as deposits and
+    // withdrawals.
+    Camt053,
+    // QIF files, as exported by personal finance software.
+    Qif,
+    // Excel workbooks, laid out like our own CSV files unless a bank profile says otherwise.
+    Xlsx,
+    // Avro object container files, whose records are likewise laid out like our own CSV files
+    // unless a bank profile says otherwise.
+    Avro,
+    // Streams of length-delimited protobuf messages, of the `Transaction` type in
+    // `proto/banking.proto`.
+    Protobuf,
+    // Arrow IPC files (also known as Feather) or streams, whose columns are likewise those of our
+    // own CSV files unless a bank profile says otherwise.
+    Arrow,
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "ofx" => Ok(Self::Ofx),
+            "camt053" => Ok(Self::Camt053),
+            "qif" => Ok(Self::Qif),
+            "xlsx" => Ok(Self::Xlsx),
+            "avro" => Ok(Self::Avro),
+            "protobuf" => Ok(Self::Protobuf),
+            "arrow" => Ok(Self::Arrow),
+            _ => Err(format!("'{s}' is not a valid input format.")),
+        }
+    }
+}
+
+// Lists the files directly within `dir` whose names match `pattern`, in the given order.
+pub fn list_files(dir: &Path, pattern: &Pattern, order: FileOrder) -> io::Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() || !pattern.matches(&entry.file_name().to_string_lossy()) {
+            continue;
+        }
+
+        let modified = match order {
+            FileOrder::Lexicographic => SystemTime::UNIX_EPOCH,
+            FileOrder::Mtime => entry.metadata()?.modified()?,
+        };
+        files.push((modified, entry.path()));
+    }
+
+    files.sort();
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+// Whether the input is a stream, such as a named pipe or a process substitution like
+// `<(zcat txns.csv.gz)`, rather than a regular file. A stream can only be read through once, and
+// its size isn't known ahead of time.
+pub fn is_stream(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+
+        fs::metadata(path).is_ok_and(|meta| {
+            let file_type = meta.file_type();
+            file_type.is_fifo() || file_type.is_char_device()
+        })
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = path;
+        false
+    }
+}
+
+// Parses a date or time in UTC, as written in ISO 8601, into milliseconds since the Unix epoch:
+// either a date such as `2024-01-31`, which is taken to be midnight, or a date and time such as
+// `2024-01-31T09:30:00Z`, with or without its seconds or its trailing `Z`.
+pub fn parse_timestamp(s: &str) -> Option<u64> {
+    let (date, time) = match s.trim().split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time.strip_suffix('Z').unwrap_or(time))),
+        None => (s.trim(), None),
+    };
+    let number = |field: &str, digits: usize| {
+        (field.len() == digits && field.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| field.parse::<u32>().ok())
+            .flatten()
+    };
+
+    let mut fields = date.split('-');
+    let (year, month, day) = (
+        number(fields.next()?, 4)?,
+        number(fields.next()?, 2)?,
+        number(fields.next()?, 2)?,
+    );
+    if fields.next().is_some()
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year.into(), month)).contains(&day)
+    {
+        return None;
+    }
+
+    let seconds = match time {
+        None => 0,
+        Some(time) => {
+            let mut fields = time.split(':');
+            let (hours, minutes) = (number(fields.next()?, 2)?, number(fields.next()?, 2)?);
+            let seconds = fields
+                .next()
+                .map_or(Some(0), |seconds| number(seconds, 2))?;
+            if fields.next().is_some() || hours > 23 || minutes > 59 || seconds > 59 {
+                return None;
+            }
+            u64::from(hours * 3600 + minutes * 60 + seconds)
+        }
+    };
+    let days = u64::try_from(days_from_civil(year.into(), month, day)).ok()?;
+    Some(days * 86_400_000 + seconds * 1000)
+}
+
+// How far through one of the input files the run had read, so that a later run can pick up from
+// where this one left off.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct InputOffset {
+    pub file: PathBuf,
+    pub records: u64,
+    pub byte_offset: u64,
+    pub complete: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use uuid::Uuid;
+
+    #[test]
+    fn lists_matching_files_in_order() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("input-{}", Uuid::new_v4()));
+        fs::create_dir_all(dir.join("part-0.csv"))?;
+        for file in ["part-2.csv", "part-10.csv", "part-1.csv", "README"] {
+            fs::write(dir.join(file), "")?;
+        }
+
+        let files = list_files(&dir, &Pattern::new("part-*.csv")?, FileOrder::Lexicographic)?;
+        assert_eq!(
+            files,
+            [
+                dir.join("part-1.csv"),
+                dir.join("part-10.csv"),
+                dir.join("part-2.csv")
+            ],
+            "only files matching the pattern should be listed, ordered by name"
+        );
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recognises_streams() -> Result<(), Box<dyn Error>> {
+        let file = std::env::temp_dir().join(format!("input-{}.csv", Uuid::new_v4()));
+        fs::write(&file, "")?;
+
+        assert!(is_stream(Path::new("/dev/null")));
+        assert!(!is_stream(&file), "a regular file is not a stream");
+        assert!(!is_stream(&file.with_extension("missing")));
+
+        fs::remove_file(file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        assert_eq!(parse_timestamp("1970-01-02"), Some(86_400_000));
+        assert_eq!(
+            parse_timestamp("2024-01-31 09:30"),
+            parse_timestamp("2024-01-31T09:30:00Z")
+        );
+        for invalid in [
+            "2024-02-30",
+            "2024-1-31",
+            "2024-01-31T24:00",
+            "31/01/2024",
+            "",
+        ] {
+            assert_eq!(parse_timestamp(invalid), None, "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use crate::assertions::{self, Before};
+use crate::models::{
+    account::{Account, TransactionError},
+    transaction::{OrderedTransaction, Transaction},
+};
+
+// Checks that an account is consistent in itself, whatever was applied to it, returning a
+// description of every invariant that doesn't hold. These hold for custom transaction types too, as
+// a handler can only credit, debit or lock an account, and are meant for property tests of
+// extensions as much as our own.
+pub fn check_account_invariants(account: &Account) -> Vec<String> {
+    let mut failures = vec![];
+    macro_rules! ensure {
+        ($holds:expr, $($failure:tt)+) => {
+            if !$holds {
+                failures.push(format!($($failure)+));
+            }
+        };
+    }
+
+    ensure!(
+        !account.held().is_sign_negative() || account.held().is_zero(),
+        "{} is held, which is negative",
+        account.held()
+    );
+    ensure!(
+        account.total() == account.available() + account.held(),
+        "total of {} isn't the sum of {} available and {} held",
+        account.total(),
+        account.available(),
+        account.held()
+    );
+    // Funds only ever move between the sub-ledgers of the account in balanced entries.
+    ensure!(
+        account.ledger().is_balanced(),
+        "the sub-ledgers of the account don't balance: {:?}",
+        account.ledger()
+    );
+    // Funds are only ever held for transactions in dispute.
+    ensure!(
+        account.held() == account.disputed_total(),
+        "{} is held, but {} is in dispute",
+        account.held(),
+        account.disputed_total()
+    );
+
+    // The memory an account holds on to is bounded by the number of transactions applied to it.
+    ensure!(
+        account.recorded_txns() as u64 <= account.transactions_applied(),
+        "{} transactions are recorded, but only {} were applied",
+        account.recorded_txns(),
+        account.transactions_applied()
+    );
+    if let Some(limit) = account.history_limit() {
+        ensure!(
+            account.recorded_txns() <= limit.max(account.open_disputes()),
+            "{} transactions are recorded, beyond the limit of {limit}",
+            account.recorded_txns()
+        );
+    }
+    ensure!(
+        account.open_disputes() <= account.recorded_txns(),
+        "{} disputes are open, but only {} transactions are recorded",
+        account.open_disputes(),
+        account.recorded_txns()
+    );
+
+    failures
+}
+
+// Checks that applying a transaction to the account as it was `before` had exactly the effect it
+// should have had on it, leaving it as it is `after`, and that the account is still consistent.
+// This is the same check that `--verify` makes of every transaction of a run.
+pub fn check_transition(
+    before: &Account,
+    txn: &Transaction,
+    result: &Result<(), TransactionError>,
+    after: &Account,
+) -> Vec<String> {
+    let txn = OrderedTransaction::new(
+        before.last_order().map_or(0, |order| order + 1),
+        txn.clone(),
+    );
+    assertions::check(
+        &Before::capture(before, &txn),
+        &txn,
+        result,
+        after,
+        before.dedup_window().is_none(),
+    )
+}
+
+// Strategies for generating transactions, for property tests with proptest. Transaction IDs and
+// clients are drawn from small ranges, so that disputes, resolutions and chargebacks often refer to
+// transactions that were applied, and duplicates turn up often enough to be exercised.
+#[cfg(any(test, feature = "proptest"))]
+mod strategies {
+    use proptest::prelude::*;
+    use rust_decimal::Decimal;
+
+    use crate::models::{
+        account::RawAccountId,
+        transaction::{
+            RawTransactionId, Transaction, TransactionId, TransactionType, AMOUNT_DECIMAL_PLACES,
+        },
+    };
+
+    // Amounts of up to 100,000, to the most decimal places accepted.
+    fn amount() -> impl Strategy<Value = Decimal> {
+        (0..1_000_000_000i64).prop_map(|units| Decimal::new(units, AMOUNT_DECIMAL_PLACES))
+    }
+
+    // Only the transaction types we natively understand, as custom types need a handler.
+    impl Arbitrary for TransactionType {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            prop_oneof![
+                3 => amount().prop_map(|amount| TransactionType::Deposit { amount }),
+                2 => amount().prop_map(|amount| TransactionType::Withdrawal { amount }),
+                2 => Just(TransactionType::Dispute),
+                1 => Just(TransactionType::Resolve),
+                1 => Just(TransactionType::Chargeback),
+            ]
+            .boxed()
+        }
+    }
+
+    impl Arbitrary for Transaction {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Self>;
+
+        fn arbitrary_with(_: Self::Parameters) -> Self::Strategy {
+            (
+                1..=16 as RawTransactionId,
+                1..=4 as RawAccountId,
+                any::<TransactionType>(),
+            )
+                .prop_map(|(id, client, txn_type)| {
+                    Transaction::new(TransactionId::from(id), client.into(), txn_type)
+                })
+                .boxed()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn accounts_stay_consistent(txns in prop::collection::vec(any::<Transaction>(), 0..200)) {
+            let mut accounts = HashMap::new();
+            for txn in txns {
+                let account = accounts
+                    .entry(txn.account_id())
+                    .or_insert_with(|| Account::new(txn.account_id()));
+                let before = account.clone();
+                let result = account.process_txn(txn.clone());
+                let failures = check_transition(&before, &txn, &result, account);
+                prop_assert!(failures.is_empty(), "{txn}: {failures:?}");
+            }
+        }
+    }
+}
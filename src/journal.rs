@@ -0,0 +1,406 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::metadata::{AccountMetadata, MetadataTable};
+use crate::models::{
+    account::{Account, AccountId, TransactionError},
+    transaction::{OrderedTransaction, TransactionId},
+};
+use crate::observer::EventObserver;
+use crate::stats::RunId;
+
+// The columns of the audit journal, as named by the fields of `JournalEntry`.
+const COLUMNS: &[&str] = &[
+    "client",
+    "tx",
+    "type",
+    "amount",
+    "outcome",
+    "error_code",
+    "error",
+    "available",
+    "held",
+    "total",
+    "locked",
+    "order",
+    "run_id",
+    "file",
+    "line",
+    "name",
+    "opened_at",
+    "region",
+    "memo",
+];
+
+// A single line of the audit journal. The journal's columns are part of its contract with
+// auditors, so fields should only ever be appended to the end of this struct, and to `COLUMNS`.
+#[derive(Debug, Serialize)]
+pub struct JournalEntry<'a> {
+    pub client: AccountId,
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub txn_type: &'a str,
+    pub amount: Option<Decimal>,
+    pub outcome: &'a str,
+    pub error_code: Option<&'a str>,
+    pub error: Option<String>,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub order: u64,
+    pub run_id: Uuid,
+    pub file: Option<&'a str>,
+    pub line: Option<u64>,
+    pub name: Option<&'a str>,
+    pub opened_at: Option<&'a str>,
+    pub region: Option<&'a str>,
+    pub memo: Option<&'a str>,
+}
+
+impl<'a> JournalEntry<'a> {
+    pub(crate) fn new(
+        run_id: RunId,
+        ordered_txn: &'a OrderedTransaction,
+        account: &Account,
+        metadata: &'a AccountMetadata,
+        err: Option<&TransactionError>,
+    ) -> Self {
+        let txn = ordered_txn.txn();
+        let txn_type = txn.txn_type();
+        Self {
+            client: txn.account_id(),
+            tx: txn.id(),
+            txn_type: txn.type_name(),
+            amount: txn_type.amount(),
+            outcome: err.map_or("applied", |err| err.class().name()),
+            error_code: err.map(|err| err.code()),
+            error: err.map(|err| err.to_string()),
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.locked(),
+            order: ordered_txn.order(),
+            run_id: run_id.id,
+            file: ordered_txn.source().map(|source| source.file()),
+            line: ordered_txn.source().map(|source| source.line()),
+            name: metadata.name.as_deref(),
+            opened_at: metadata.opened_at.as_deref(),
+            region: metadata.region.as_deref(),
+            memo: ordered_txn.memo(),
+        }
+    }
+}
+
+// A line of the audit journal as it is read back in, of which only the fields needed to replay its
+// transaction and check its outcome are kept.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JournalRecord {
+    pub client: AccountId,
+    pub tx: TransactionId,
+    #[serde(rename = "type")]
+    pub txn_type: String,
+    pub amount: Option<Decimal>,
+    pub outcome: String,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+    pub order: u64,
+    pub run_id: Uuid,
+}
+
+// Records every transaction attempted against an account, whether or not it was applied, along
+// with the balances of the account after the attempt. The journal is only ever appended to, so a
+// single journal file may span many runs, and each entry records the run it was written by.
+//
+// A journal missing some of the run's transactions can't be audited, so the first transaction that
+// couldn't be written to it is kept, and fails the run once the journal is flushed.
+//
+// Entries are only appended to a journal with the same columns, so that every line of it can be
+// read by its header. A journal with other columns, such as one written by a release from before
+// columns were added, is rotated out of the way to the first free path suffixed `.1`, `.2`, and so
+// on, and a new journal started in its place.
+pub struct Journal {
+    run_id: RunId,
+    writer: Mutex<csv::Writer<BufWriter<File>>>,
+    metadata: Option<Arc<MetadataTable>>,
+    error: Mutex<Option<io::Error>>,
+}
+
+impl Journal {
+    pub fn open(path: &Path, run_id: RunId) -> io::Result<Self> {
+        if let Some(columns) = existing_columns(path)? {
+            if columns != COLUMNS {
+                let rotated = rotated_path(path);
+                tracing::warn!(
+                    file = %path.display(),
+                    rotated = %rotated.display(),
+                    "The journal has different columns to those now written, so it has been rotated out of the way"
+                );
+                fs::rename(path, rotated)?;
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        // Only write out the header if we're starting a brand new journal.
+        let has_headers = file.metadata()?.len() == 0;
+        let writer = csv::WriterBuilder::new()
+            .has_headers(has_headers)
+            .from_writer(BufWriter::new(file));
+
+        Ok(Self {
+            run_id,
+            writer: Mutex::new(writer),
+            metadata: None,
+            error: Mutex::new(None),
+        })
+    }
+
+    // Records each account's metadata alongside its transactions. The metadata columns are always
+    // present, but are left empty without it.
+    pub fn with_metadata(mut self, metadata: Arc<MetadataTable>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    fn metadata(&self, account: &Account) -> &AccountMetadata {
+        self.metadata
+            .as_ref()
+            .map_or(&AccountMetadata::EMPTY, |metadata| {
+                metadata.get(account.id())
+            })
+    }
+
+    // Flushes the journal, failing if any transaction couldn't be written to it.
+    pub fn flush(&self) -> io::Result<()> {
+        if let Some(err) = self.error.lock().expect("journal lock poisoned").take() {
+            return Err(err);
+        }
+        self.writer.lock().expect("journal lock poisoned").flush()
+    }
+
+    fn record(&self, entry: JournalEntry) {
+        let mut writer = self.writer.lock().expect("journal lock poisoned");
+        if let Err(err) = writer.serialize(&entry) {
+            tracing::error!(
+                account_id = %entry.client,
+                txn_id = %entry.tx,
+                error_code = "journal_write_failed",
+                "Unable to write transaction to the journal: {err}"
+            );
+            self.error
+                .lock()
+                .expect("journal lock poisoned")
+                .get_or_insert_with(|| {
+                    io::Error::other(format!(
+                        "unable to write transaction {} of client {} to the journal: {err}",
+                        entry.tx, entry.client
+                    ))
+                });
+        }
+    }
+}
+
+// The columns named by the header of an existing journal, if there's one at the path. Only regular
+// files have a header, rather than e.g. a pipe the journal is written down.
+fn existing_columns(path: &Path) -> io::Result<Option<Vec<String>>> {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() => (),
+        Ok(_) => return Ok(None),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let file = File::open(path)?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(file);
+    let mut header = csv::StringRecord::new();
+    if !reader.read_record(&mut header)? {
+        return Ok(None);
+    }
+    Ok(Some(header.iter().map(str::to_owned).collect()))
+}
+
+// The first path that a journal can be rotated to without overwriting another.
+fn rotated_path(path: &Path) -> PathBuf {
+    (1..)
+        .map(|n| {
+            let mut rotated = path.as_os_str().to_owned();
+            rotated.push(format!(".{n}"));
+            PathBuf::from(rotated)
+        })
+        .find(|rotated| !rotated.exists())
+        .expect("there's a free path to rotate to")
+}
+
+impl EventObserver for Journal {
+    fn on_applied(&self, txn: &OrderedTransaction, account: &Account) {
+        let metadata = self.metadata(account);
+        self.record(JournalEntry::new(self.run_id, txn, account, metadata, None));
+    }
+
+    fn on_rejected(&self, txn: &OrderedTransaction, account: &Account, err: &TransactionError) {
+        let metadata = self.metadata(account);
+        self.record(JournalEntry::new(
+            self.run_id,
+            txn,
+            account,
+            metadata,
+            Some(err),
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::{RawTransactionId, Transaction, TransactionType};
+    use std::error::Error;
+
+    #[test]
+    fn reads_journal_back() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("journal-{}.csv", Uuid::new_v4()));
+        let amount = "2.5".parse()?;
+        let runs = [RunId::generate(), RunId::generate()];
+        let mut account = Account::new(1.into());
+        for (order, (run_id, txn_type)) in runs
+            .into_iter()
+            .zip([
+                TransactionType::Deposit { amount },
+                TransactionType::Withdrawal {
+                    amount: amount * Decimal::TWO,
+                },
+            ])
+            .enumerate()
+        {
+            // Each run appends to the journal the last one left.
+            let journal = Journal::open(&path, run_id)?;
+            let txn = OrderedTransaction::new(
+                order as u64,
+                Transaction::new(
+                    (order as RawTransactionId + 1).into(),
+                    account.id(),
+                    txn_type,
+                ),
+            )
+            .with_memo(Some("payroll".into()));
+            match account.process_txn(txn.txn().clone()) {
+                Ok(()) => journal.on_applied(&txn, &account),
+                Err(err) => journal.on_rejected(&txn, &account, &err),
+            }
+            journal.flush()?;
+        }
+
+        let mut reader = csv::Reader::from_path(&path)?;
+        assert_eq!(reader.headers()?.iter().next_back(), Some("memo"));
+        let records = reader
+            .deserialize()
+            .collect::<Result<Vec<JournalRecord>, _>>()?;
+        assert_eq!(records.len(), 2, "the header is only written once");
+        let [deposit, withdrawal] = &records[..] else {
+            unreachable!()
+        };
+        assert_eq!(
+            (
+                deposit.txn_type.as_str(),
+                deposit.amount,
+                deposit.outcome.as_str()
+            ),
+            ("deposit", Some(amount), "applied")
+        );
+        assert_eq!((deposit.order, deposit.run_id), (0, runs[0].id));
+        assert_eq!(
+            (withdrawal.txn_type.as_str(), withdrawal.outcome.as_str()),
+            ("withdrawal", "rejected")
+        );
+        assert_eq!((withdrawal.order, withdrawal.run_id), (1, runs[1].id));
+        assert_eq!(
+            (withdrawal.available, withdrawal.held, withdrawal.total),
+            (amount, Decimal::ZERO, amount)
+        );
+        assert!(!withdrawal.locked);
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rotates_journal_with_other_columns() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("journal-{}.csv", Uuid::new_v4()));
+        let old = "client,tx,type,amount\n1,1,deposit,1.0\n";
+        std::fs::write(&path, old)?;
+        let rotated = PathBuf::from(format!("{}.1", path.display()));
+        std::fs::write(&rotated, old)?;
+
+        let journal = Journal::open(&path, RunId::generate())?;
+        let account = Account::new(1.into());
+        let txn = OrderedTransaction::new(
+            0,
+            Transaction::new(
+                1.into(),
+                account.id(),
+                TransactionType::Deposit {
+                    amount: "1.0".parse()?,
+                },
+            ),
+        );
+        journal.on_applied(&txn, &account);
+        journal.flush()?;
+
+        // The old journal is kept, without overwriting one rotated before it.
+        let twice_rotated = PathBuf::from(format!("{}.2", path.display()));
+        assert_eq!(std::fs::read_to_string(&twice_rotated)?, old);
+        let mut reader = csv::Reader::from_path(&path)?;
+        assert_eq!(reader.headers()?, COLUMNS);
+        assert_eq!(reader.records().count(), 1);
+
+        // A journal with the same columns is appended to.
+        drop(Journal::open(&path, RunId::generate())?);
+        assert!(!PathBuf::from(format!("{}.3", path.display())).exists());
+
+        for path in [path, rotated, twice_rotated] {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fails_flush_once_unable_to_write() -> Result<(), Box<dyn Error>> {
+        // Every write to /dev/full fails as though the disk were full, once enough has been
+        // written to fill the journal's buffers.
+        let journal = Journal::open(Path::new("/dev/full"), RunId::generate())?;
+        let mut account = Account::new(1.into());
+        for id in 1..=1_000 {
+            let txn = OrderedTransaction::new(
+                id,
+                Transaction::new(
+                    (id as RawTransactionId).into(),
+                    account.id(),
+                    TransactionType::Deposit {
+                        amount: Decimal::ONE,
+                    },
+                ),
+            );
+            account.process_txn(txn.txn().clone())?;
+            journal.on_applied(&txn, &account);
+        }
+
+        let err = journal
+            .flush()
+            .err()
+            .ok_or("the journal should fail to flush")?;
+        assert!(
+            err.to_string().starts_with("unable to write transaction"),
+            "{err}"
+        );
+        Ok(())
+    }
+}
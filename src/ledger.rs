@@ -0,0 +1,171 @@
+use std::fmt;
+
+use rust_decimal::Decimal;
+
+// The sub-ledgers an account's funds are posted between. Every movement of funds is a debit of one
+// sub-ledger and a credit of another by the same amount, so the balances of an account's
+// sub-ledgers always add up to zero.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SubLedger {
+    // Funds the client may withdraw.
+    Available,
+    // Funds held for transactions in dispute.
+    Held,
+    // The other side of the funds deposited into and withdrawn out of the account, i.e. the money
+    // in transit between the account and the world outside of it.
+    Suspense,
+    // Funds reversed out of the account by chargebacks.
+    ChargebackExpense,
+}
+
+impl SubLedger {
+    pub const ALL: [Self; 4] = [
+        Self::Available,
+        Self::Held,
+        Self::Suspense,
+        Self::ChargebackExpense,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Available => "available",
+            Self::Held => "held",
+            Self::Suspense => "suspense",
+            Self::ChargebackExpense => "chargeback_expense",
+        }
+    }
+}
+
+impl fmt::Display for SubLedger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Side {
+    Debit,
+    Credit,
+}
+
+// One side of an entry, posted to a single sub-ledger.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Posting {
+    pub sub_ledger: SubLedger,
+    pub side: Side,
+    pub amount: Decimal,
+}
+
+// A balanced pair of postings, moving an amount out of the debited sub-ledger and into the
+// credited one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Entry {
+    pub debit: SubLedger,
+    pub credit: SubLedger,
+    pub amount: Decimal,
+}
+
+impl Entry {
+    pub fn new(debit: SubLedger, credit: SubLedger, amount: Decimal) -> Self {
+        Self {
+            debit,
+            credit,
+            amount,
+        }
+    }
+
+    pub fn postings(&self) -> [Posting; 2] {
+        [
+            Posting {
+                sub_ledger: self.debit,
+                side: Side::Debit,
+                amount: self.amount,
+            },
+            Posting {
+                sub_ledger: self.credit,
+                side: Side::Credit,
+                amount: self.amount,
+            },
+        ]
+    }
+}
+
+// The balances of an account's sub-ledgers, which only ever change by posting entries to them.
+// Each balance is the sum of its credits less the sum of its debits, so funds owed to the client
+// have positive balances, and the suspense sub-ledger holds the other side of them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Ledger {
+    balances: [Decimal; SubLedger::ALL.len()],
+}
+
+impl Ledger {
+    // Restores a ledger from the balances of the client's sub-ledgers, with the suspense sub-ledger
+    // balancing them out.
+    pub fn from_balances(available: Decimal, held: Decimal, chargebacks: Decimal) -> Self {
+        let mut ledger = Self::default();
+        ledger.balances[SubLedger::Available as usize] = available;
+        ledger.balances[SubLedger::Held as usize] = held;
+        ledger.balances[SubLedger::ChargebackExpense as usize] = chargebacks;
+        ledger.balances[SubLedger::Suspense as usize] = -(available + held + chargebacks);
+        ledger
+    }
+
+    pub fn balance(&self, sub_ledger: SubLedger) -> Decimal {
+        self.balances[sub_ledger as usize]
+    }
+
+    pub fn post(&mut self, entry: Entry) {
+        self.balances[entry.debit as usize] -= entry.amount;
+        self.balances[entry.credit as usize] += entry.amount;
+    }
+
+    // Whether the balances add up to zero, as they do unless they were restored inconsistently.
+    pub fn is_balanced(&self) -> bool {
+        self.balances.iter().sum::<Decimal>().is_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn posts_balanced_entries() -> Result<(), Box<dyn Error>> {
+        let mut ledger = Ledger::default();
+        let amount: Decimal = "10.5".parse()?;
+        ledger.post(Entry::new(
+            SubLedger::Suspense,
+            SubLedger::Available,
+            amount,
+        ));
+        ledger.post(Entry::new(SubLedger::Available, SubLedger::Held, amount));
+        ledger.post(Entry::new(
+            SubLedger::Held,
+            SubLedger::ChargebackExpense,
+            amount,
+        ));
+        assert!(ledger.is_balanced());
+        assert_eq!(ledger.balance(SubLedger::Available), Decimal::ZERO);
+        assert_eq!(ledger.balance(SubLedger::Held), Decimal::ZERO);
+        assert_eq!(ledger.balance(SubLedger::ChargebackExpense), amount);
+        assert_eq!(ledger.balance(SubLedger::Suspense), -amount);
+        assert_eq!(
+            ledger,
+            Ledger::from_balances(Decimal::ZERO, Decimal::ZERO, amount)
+        );
+
+        let [debit, credit] =
+            Entry::new(SubLedger::Available, SubLedger::Suspense, amount).postings();
+        assert_eq!(
+            (debit.sub_ledger, debit.side),
+            (SubLedger::Available, Side::Debit)
+        );
+        assert_eq!(
+            (credit.sub_ledger, credit.side),
+            (SubLedger::Suspense, Side::Credit)
+        );
+
+        Ok(())
+    }
+}
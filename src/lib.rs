@@ -1,5 +1,68 @@
 #![allow(dead_code)]
 
+pub mod affinity;
+pub mod amounts;
+#[cfg(feature = "arrow")]
+pub mod arrow_ipc;
+mod assertions;
+pub mod autoscale;
+#[cfg(feature = "avro")]
+pub mod avro;
+pub mod bank_profile;
+pub mod calendar;
+pub mod camt;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+pub mod chunked;
+pub mod config;
+#[cfg(unix)]
+pub mod control;
+pub mod dedup;
+pub mod effective_date;
+pub mod failure;
+pub mod follow;
+pub mod general_ledger;
+pub mod hash;
+pub mod importer;
+pub mod input;
+pub mod invariants;
+pub mod journal;
+pub mod ledger;
+pub mod metadata;
+pub mod metrics;
 pub mod models;
+pub mod mt940;
+pub mod observer;
+pub mod ofx;
 pub mod options;
+pub mod partition;
+pub mod pool;
 pub mod processor;
+pub mod profile;
+pub mod progress;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod qif;
+pub mod rate_limit;
+pub mod reconcile;
+#[cfg(feature = "redis")]
+pub mod redis_publisher;
+pub mod rejects;
+pub mod remote;
+pub mod replay;
+pub mod sink;
+pub mod snapshot;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod standing_orders;
+pub mod statements;
+pub mod stats;
+pub mod top;
+pub mod trial_balance;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+pub mod verify;
+pub mod wal;
+pub mod workload;
+#[cfg(feature = "xlsx")]
+pub mod xlsx;
@@ -1,55 +1,2109 @@
+use std::env;
 use std::error::Error;
-use std::fs::File;
-use std::io::{self, BufReader, BufWriter};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::str;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use structopt::StructOpt;
+use signal_hook::{
+    consts::{SIGHUP, SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use tracing_subscriber::{filter::LevelFilter, fmt::writer::BoxMakeWriter, EnvFilter};
 
+#[cfg(unix)]
+use banking_exercise::control::{ControlChannel, ControlCommand};
+#[cfg(feature = "redis")]
+use banking_exercise::redis_publisher::RedisPublisher;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use banking_exercise::uring::UringReader;
 use banking_exercise::{
-    models::transaction::Transaction, options::Options, processor::TransactionProcessor,
+    affinity::{CpuTopology, WorkerPlacement},
+    amounts::AmountStats,
+    autoscale::AutoScaling,
+    bank_profile::BankProfile,
+    chunked::{ChunkedReader, CsvDialect},
+    effective_date::{DatedTransaction, EffectiveDateOrder},
+    failure::{Failure, FailureClass},
+    follow::FollowReader,
+    general_ledger::GeneralLedger,
+    importer::{self, ImportedTransaction, Importer},
+    input::{self, InputFormat, InputOffset},
+    journal::Journal,
+    metadata::MetadataTable,
+    metrics,
+    models::{
+        account::OutputFormat,
+        csv_record::{ExtraColumns, ParserCache, RecordParser},
+        transaction::{self, Source, Transaction},
+    },
+    options::{
+        ApplyOrder, Command, Dedup, ErrorPolicy, ExcessPrecision, GenerateOptions, LogFormat,
+        MergeOptions, Options, OutputSpec, ProcessOptions, ReplayOptions, ReportCommand,
+        ReportInterval, TrialBalanceOptions,
+    },
+    processor::TransactionProcessor,
+    profile::{self, PipelineProfile, Profiler, Stage},
+    progress::ProgressReporter,
+    rate_limit::RateLimiter,
+    reconcile::{self, ExpectedAccount},
+    rejects::{RejectedTransactions, Rejection, RejectsWriter},
+    remote,
+    replay::{self, Replay},
+    sink::{self, AccountFilter, AccountSink, CsvSink, FanOut, Filtered},
+    snapshot::{self, PendingSnapshot},
+    standing_orders::StandingOrders,
+    statements::StatementWriter,
+    stats::{self, RunId, RunStats, RunSummary, WorkerQueueStats},
+    top::TopAccounts,
+    trial_balance::TrialBalance,
+    verify::{self, LedgerTotals},
+    wal,
 };
 
-fn main() -> Result<(), Box<dyn Error>> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
-        .with_writer(io::stderr)
-        .init();
+// Exits with a code that reflects the class of failure, if the run fails.
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err}");
+            ExitCode::from(FailureClass::classify(err.as_ref()).exit_code())
+        }
+    }
+}
+
+fn run() -> Result<(), Box<dyn Error>> {
+    let command = Options::parse().command;
+    init_logging(&command)?;
+    match command {
+        Command::Process(opts) => process(opts, true),
+        Command::Verify(mut opts) => {
+            opts.verify = true;
+            process(opts, false)
+        }
+        Command::Reconcile(opts) => process(opts.into_process(), false),
+        Command::Generate(opts) => generate(opts),
+        Command::Merge(opts) => merge(opts),
+        Command::Replay(opts) => replay(opts),
+        Command::Report(ReportCommand::TrialBalance(opts)) => trial_balance(opts),
+    }
+}
+
+// Logs to stderr, or to the log file, as the options of the command ask. Commands other than those
+// that process transactions only log what RUST_LOG asks for, and errors otherwise.
+fn init_logging(command: &Command) -> Result<(), Box<dyn Error>> {
+    let rust_log = env::var(EnvFilter::DEFAULT_ENV).ok();
+    let opts = match command {
+        Command::Process(opts) | Command::Verify(opts) => opts,
+        Command::Reconcile(opts) => &opts.process,
+        _ => {
+            let filter = env_directives(
+                EnvFilter::default().add_directive(LevelFilter::ERROR.into()),
+                rust_log.as_deref(),
+            )?;
+            tracing_subscriber::fmt()
+                .with_env_filter(filter)
+                .with_writer(io::stderr)
+                .init();
+            return Ok(());
+        }
+    };
+
+    let writer = match &opts.log_file {
+        Some(path) => {
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(io::stderr),
+    };
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(log_filter(opts, rust_log.as_deref())?)
+        .with_ansi(opts.log_file.is_none())
+        .with_writer(writer);
+    match opts.log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    Ok(())
+}
+
+// What to log, as chosen with -q or -v, along with any directives given by RUST_LOG, which override
+// the level chosen for the targets they name, or altogether if they name none.
+fn log_filter(opts: &ProcessOptions, rust_log: Option<&str>) -> Result<EnvFilter, Box<dyn Error>> {
+    let level = match (opts.quiet, opts.verbose) {
+        (true, _) => LevelFilter::OFF,
+        (false, 0) => LevelFilter::ERROR,
+        (false, 1) => LevelFilter::WARN,
+        (false, 2) => LevelFilter::INFO,
+        (false, 3) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let mut filter = EnvFilter::default().add_directive(level.into());
+    if opts.report_interval.is_some() && !opts.quiet {
+        filter = filter.add_directive(format!("{THROUGHPUT_TARGET}=info").parse()?);
+    }
+    env_directives(filter, rust_log)
+}
 
-    let opts = Options::from_args();
+// Adds the directives given by RUST_LOG to the filter, failing on any that aren't valid rather than
+// quietly ignoring them.
+fn env_directives(
+    mut filter: EnvFilter,
+    rust_log: Option<&str>,
+) -> Result<EnvFilter, Box<dyn Error>> {
+    if let Some(directives) = rust_log {
+        for directive in directives
+            .split(',')
+            .filter(|directive| !directive.trim().is_empty())
+        {
+            let directive = directive.parse().map_err(|err| {
+                format!("'{directive}' in RUST_LOG is not a valid directive: {err}")
+            })?;
+            filter = filter.add_directive(directive);
+        }
+    }
+    Ok(filter)
+}
+
+// Processes the transactions file until it's done or SIGINT or SIGTERM asks it to stop. Being asked
+// a second time exits at once.
+fn process(opts: ProcessOptions, default_output: bool) -> Result<(), Box<dyn Error>> {
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        let exit_code = FailureClass::Interrupted.exit_code().into();
+        signal_hook::flag::register_conditional_shutdown(signal, exit_code, stop.clone())?;
+        signal_hook::flag::register(signal, stop.clone())?;
+    }
+    process_until(opts, default_output, stop)
+}
+
+// Processes the transactions file until it's done or `stop` is set. If no outputs were specified,
+// the accounts are written as CSV to stdout only if `default_output` is set.
+fn process_until(
+    mut opts: ProcessOptions,
+    default_output: bool,
+    stop: Arc<AtomicBool>,
+) -> Result<(), Box<dyn Error>> {
+    let started = Instant::now();
+    // A dry run carries on past malformed records, so that every problem with the input is found.
+    if opts.skip_bad_rows || opts.dry_run {
+        opts.error_policy = ErrorPolicy::Lenient;
+    }
+
+    // With a write-ahead log, a run that crashed part way through is carried on as the same run,
+    // by restoring its last snapshot and replaying the transactions it logged since.
+    let recovery = opts
+        .wal_dir
+        .as_deref()
+        .map(|wal_dir| wal::recover(wal_dir, opts.snapshot_dir.as_deref()))
+        .transpose()?
+        .flatten();
+    let run_id = recovery
+        .as_ref()
+        .map_or_else(RunId::generate, |recovery| recovery.run_id);
+
+    // A run may carry on from the accounts of a previous run's snapshot, rather than from none, in
+    // which case it also carries on the order of its transactions.
+    let base = opts
+        .base_snapshot
+        .as_deref()
+        .map(snapshot::load_base)
+        .transpose()?;
+    let starting_order = match &base {
+        Some((manifest, _)) if opts.starting_order == 0 => manifest.next_order,
+        _ => opts.starting_order,
+    };
 
     // Start up our multi-threaded transaction processor, with the specified number of workers. If
     // no worker count was specified, we default to the number of physical cores on the system,
     // accounting for the main thread that is focused on I/O and deserialization. This is an optimum
-    // thread arrangement.
-    let num_workers = opts
-        .num_workers
-        .unwrap_or_else(|| usize::max(num_cpus::get_physical(), 2) - 1);
-    let txn_processor = TransactionProcessor::new(num_workers);
+    // thread arrangement. In deterministic mode, a single worker runs on the main thread instead.
+    let num_workers = if opts.deterministic {
+        1
+    } else {
+        opts.num_workers
+            .unwrap_or_else(|| usize::max(num_cpus::get_physical(), 2) - 1)
+    };
+    // Work out which CSV files of transactions to read. A directory of files is read as though
+    // its files were concatenated together, in the requested order.
+    let is_remote = remote::is_remote(&opts.input_file);
+    let files = if opts.input_file.is_dir() {
+        if opts.follow {
+            return Err("a directory of transactions files cannot be followed".into());
+        }
+        input::list_files(&opts.input_file, &opts.pattern, opts.file_order)?
+    } else {
+        if opts.follow && is_remote {
+            return Err("a remote transactions file cannot be followed".into());
+        }
+        vec![opts.input_file.clone()]
+    };
 
-    // Open up the CSV file of transactions.
-    let file = File::open(opts.input_file)?;
+    // If requested, we'll report progress through the files as we go. The file sizes are used to
+    // estimate how much time remains, unless we're reading from a stream whose size is unknown.
+    let mut progress = if opts.progress {
+        let mut total_bytes = Some(0);
+        for file in &files {
+            let size = if is_remote {
+                remote::size(file)?
+            } else if input::is_stream(file) {
+                total_bytes = None;
+                break;
+            } else {
+                fs::metadata(file)?.len()
+            };
+            total_bytes = total_bytes.map(|total| total + size);
+        }
+        Some(ProgressReporter::new(total_bytes))
+    } else {
+        None
+    };
 
-    // Stream in the transactions from the CSV file, and pass them to our transaction processor.
-    tracing::info!("Starting up transaction processing...");
-    let mut csv_reader = csv::Reader::from_reader(BufReader::new(file));
-    for result in csv_reader.deserialize() {
-        let txn: Transaction = result?;
-        tracing::info!(%txn);
-        txn_processor.process_txn(txn)?;
+    let profiler = opts.timings.then(Profiler::default);
+    let run_stats = Arc::new(RunStats::default());
+    let mut builder = TransactionProcessor::builder(num_workers)
+        .run_id(run_id)
+        .starting_order(starting_order)
+        .partitioner(opts.partitioner.partitioner(num_workers)?)
+        .observer(run_stats.clone());
+    let amount_stats = Arc::new(AmountStats::new(opts.amount_stats.is_some()));
+    builder = builder.observer(amount_stats.clone());
+    if let Some(max_workers) = opts.max_workers {
+        let min_workers = opts.min_workers.unwrap_or(1);
+        if !opts.follow && opts.control_socket.is_none() {
+            return Err(
+                "workers can only be autoscaled when following the input or serving a control socket"
+                    .into(),
+            );
+        }
+        if opts.dedup == Dedup::Bloom {
+            return Err(
+                "workers that detect duplicates with bloom dedup cannot be autoscaled".into(),
+            );
+        }
+        if min_workers > max_workers {
+            return Err("--min-workers cannot be more than --max-workers".into());
+        }
+        let partitioner = opts.partitioner.clone();
+        builder = builder.autoscale(
+            AutoScaling::new(min_workers, max_workers),
+            move |num_workers| partitioner.partitioner(num_workers),
+        );
+    }
+    if opts.worker_placement != WorkerPlacement::Float {
+        // Autoscaled workers are pinned by their index, so every worker there may be is placed.
+        let topology = CpuTopology::detect()?;
+        builder = builder.worker_cpus(
+            opts.worker_placement
+                .cpus_for_workers(&topology, num_workers.max(opts.max_workers.unwrap_or(0))),
+        );
+    }
+    if let Some(profiler) = &profiler {
+        builder = builder.profiler(profiler.clone());
+    }
+    if opts.expected_accounts.is_some() {
+        builder = builder.capacity_hints(opts.expected_accounts, opts.expected_transactions);
+    }
+    if let Some(progress) = &progress {
+        builder = builder.processed_counter(progress.processed_counter());
+    }
+    let metadata = opts
+        .account_metadata
+        .as_deref()
+        .map(MetadataTable::load)
+        .transpose()?
+        .map(Arc::new);
+    let journal = opts
+        .journal
+        .as_deref()
+        .map(|path| Journal::open(path, run_id))
+        .transpose()?
+        .map(|journal| match &metadata {
+            Some(metadata) => journal.with_metadata(metadata.clone()),
+            None => journal,
+        })
+        .map(Arc::new);
+    if let Some(journal) = &journal {
+        builder = builder.observer(journal.clone());
+    }
+    let general_ledger = opts
+        .general_ledger
+        .as_deref()
+        .map(|path| GeneralLedger::create(path, run_id))
+        .transpose()?
+        .map(Arc::new);
+    if let Some(general_ledger) = &general_ledger {
+        builder = builder.observer(general_ledger.clone());
+    }
+    let rejected = opts
+        .dry_run
+        .then(|| Arc::new(RejectedTransactions::default()));
+    if let Some(rejected) = &rejected {
+        builder = builder.observer(rejected.clone());
+    }
+    #[cfg(feature = "redis")]
+    let redis = opts
+        .redis_url
+        .as_deref()
+        .map(RedisPublisher::connect)
+        .transpose()?
+        .map(Arc::new);
+    #[cfg(feature = "redis")]
+    if let Some(redis) = &redis {
+        builder = builder.observer(redis.clone());
+    }
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    if opts.io_uring {
+        return Err(
+            "files can only be read through io_uring by Linux builds with the io-uring feature"
+                .into(),
+        );
+    }
+    #[cfg(not(feature = "redis"))]
+    if opts.redis_url.is_some() {
+        return Err(
+            "balances can only be mirrored into Redis by builds with the redis feature".into(),
+        );
+    }
+
+    // We will write out all the account data to each of the requested outputs. They're opened up
+    // front, as some of them record the transactions of the run as well.
+    let mut sinks = FanOut::default();
+    for output in &outputs(&opts, default_output) {
+        let open = if opts.multi_tenant {
+            sink::open_multi_tenant
+        } else {
+            sink::open
+        };
+        let sink = open(
+            output.format,
+            &output.target,
+            OutputFormat {
+                decimal_places: opts.output_precision.decimal_places(),
+                extended: opts.extended_output,
+                tenant: false,
+            },
+            metadata.clone(),
+            run_id,
+        )?;
+        if let Some(observer) = sink.observer() {
+            builder = builder.observer(observer);
+        }
+        sinks.push(sink);
+    }
+    let mut sinks = Filtered::new(
+        sinks,
+        AccountFilter {
+            only_locked: opts.only_locked,
+            nonzero_only: opts.nonzero_only,
+            clients: opts.clients.clone(),
+        },
+    );
+    let top = opts.top.map(|n| Arc::new(TopAccounts::new(n)));
+    if let Some(top) = &top {
+        builder = builder.observer(top.clone());
+    }
+    let statements = opts
+        .statements_dir
+        .as_deref()
+        .map(StatementWriter::new)
+        .transpose()?
+        .map(|statements| Arc::new(statements.with_format(opts.statement_format.clone())));
+    if let Some(statements) = &statements {
+        builder = builder.observer(statements.clone());
+    }
+    let ledger_totals = opts.verify.then(|| {
+        Arc::new(
+            base.as_ref()
+                .map_or_else(LedgerTotals::default, |(_, accounts)| {
+                    LedgerTotals::opening(accounts)
+                }),
+        )
+    });
+    if let Some(ledger_totals) = &ledger_totals {
+        builder = builder.observer(ledger_totals.clone());
+    }
+    if opts.deterministic {
+        builder = builder.inline();
+    }
+    if opts.global_txn_ids {
+        builder = builder.global_txn_ids();
+    }
+    if let Some(limit) = opts.max_history_per_account {
+        builder = builder.history_limit(limit);
+    }
+    if let Some(window) = opts.dedup_window {
+        builder = builder.dedup_window(window);
+    }
+    if opts.max_rate.is_some() || opts.max_client_rate.is_some() {
+        let mut limiter = RateLimiter::default();
+        if let Some(max_rate) = opts.max_rate {
+            limiter = limiter.with_global_rate(max_rate);
+        }
+        if let Some(max_client_rate) = opts.max_client_rate {
+            limiter = limiter.with_client_rate(max_client_rate);
+        }
+        builder = builder.rate_limiter(limiter);
+    }
+    if opts.dedup == Dedup::Bloom {
+        builder = builder.bloom_dedup(opts.dedup_capacity, opts.dedup_fp_rate);
+    }
+    let assertion_failures = opts.verify.then(|| Arc::new(AtomicU64::new(0)));
+    if let Some(assertion_failures) = &assertion_failures {
+        builder = builder.assertions(assertion_failures.clone());
+    }
+    if let Some((manifest, accounts)) = base {
+        // The snapshot of a recovered run already holds the accounts it carried on from.
+        if recovery
+            .as_ref()
+            .is_none_or(|recovery| recovery.snapshot.is_none())
+        {
+            tracing::info!(
+                accounts = accounts.len(),
+                next_order = manifest.next_order,
+                "Carrying on from the accounts of a base snapshot"
+            );
+            builder = builder.restore(accounts);
+        }
+    }
+    if let Some(recovery) = recovery {
+        tracing::info!(
+            snapshot = ?recovery.snapshot,
+            accounts = recovery.accounts.len(),
+            replayed = recovery.replay.len(),
+            next_order = recovery.next_order,
+            "Recovering a run that didn't complete from its write-ahead log"
+        );
+        builder = builder.recover(recovery);
+    }
+    if let Some(wal_dir) = &opts.wal_dir {
+        builder = builder.wal(wal::open(wal_dir, num_workers, run_id)?);
+    }
+    let txn_processor = builder.build();
+
+    // In follow mode we keep reading the file as it grows until we're asked to stop, and a hangup
+    // asks for an interim snapshot of the accounts to be written. A resident run likewise carries
+    // on until it's asked to stop. Otherwise, being asked to stop interrupts the run: we stop
+    // reading, but the transactions already read are still processed and the accounts are written
+    // out as partial results.
+    let interrupt = (!opts.follow && opts.control_socket.is_none()).then_some(stop.as_ref());
+    let open_input = |path: &Path| -> io::Result<Box<dyn Read>> {
+        if is_remote {
+            return remote::open(path);
+        }
+
+        let file = File::open(path)?;
+        Ok(if opts.follow {
+            Box::new(FollowReader::new(file, stop.clone()))
+        } else {
+            open_file(&opts, path, file)?
+        })
+    };
+    let mut hangups = opts.follow.then(|| Signals::new([SIGHUP])).transpose()?;
+
+    // A dry run reports the problems it finds on stdout, unless it's given a rejects file for them.
+    let mut rejects = opts
+        .rejects_file
+        .as_deref()
+        .map(RejectsWriter::create)
+        .transpose()?
+        .or_else(|| opts.dry_run.then(RejectsWriter::stdout));
+    let mut profile = profiler.as_ref().map(|_| PipelineProfile::default());
+
+    // Only our own CSV files say when their transactions were posted and took effect, by their
+    // timestamp and effective_date columns, which standing orders and effective dates go by.
+    let by_effective_date = opts.apply_order == ApplyOrder::EffectiveDate;
+    if (opts.standing_orders.is_some() || by_effective_date)
+        && (opts.format != InputFormat::Csv || opts.bank_profile.is_some())
+    {
+        return Err(
+            "standing orders and effective dates only apply to CSV input in our own format".into(),
+        );
+    }
+    if by_effective_date
+        && (opts.follow || opts.control_socket.is_some() || opts.checkpoint_every.is_some())
+    {
+        return Err("transactions can only be applied in order of effective date once the whole input has been read, so not when following it, serving a control socket, or checkpointing".into());
+    }
+    let mut standing_orders = opts
+        .standing_orders
+        .as_deref()
+        .map(StandingOrders::load)
+        .transpose()?;
+    tracing::info!(%run_id, "Starting up transaction processing...");
+    let read = thread::scope(|scope| {
+        // A resident run also pushes how far behind its workers are as it goes, and any run may
+        // report its throughput as it goes, until it's done reading, as the summary of the run is
+        // only pushed and logged once it completes.
+        let (reading_tx, reading_rx) = crossbeam_channel::bounded::<()>(0);
+        if let Some(gateway_url) = opts
+            .push_gateway
+            .as_deref()
+            .filter(|_| opts.follow || opts.control_socket.is_some())
+        {
+            let (txn_processor, reading_rx) = (&txn_processor, reading_rx.clone());
+            scope.spawn(move || {
+                push_processor_stats(gateway_url, run_id, txn_processor, reading_rx)
+            });
+        }
+        if let Some(ReportInterval(interval)) = opts.report_interval {
+            let (txn_processor, run_stats) = (&txn_processor, &run_stats);
+            scope.spawn(move || {
+                report_throughput(
+                    interval,
+                    txn_processor,
+                    run_stats,
+                    starting_order,
+                    reading_rx,
+                )
+            });
+        }
+        let hangups_handle = hangups.as_mut().map(|hangups| {
+            let handle = hangups.handle();
+            let (txn_processor, snapshot_dir) = (&txn_processor, opts.snapshot_dir.as_deref());
+            scope.spawn(move || write_interim_snapshots(hangups, txn_processor, snapshot_dir));
+            handle
+        });
+
+        let read = read_transactions(
+            &opts,
+            &files,
+            open_input,
+            &txn_processor,
+            rejects.as_mut(),
+            progress.as_mut(),
+            profile.as_mut(),
+            standing_orders.as_mut(),
+            interrupt,
+        );
+
+        if let Some(handle) = hangups_handle {
+            handle.close();
+        }
+        let read = read?;
+        if read.interrupted {
+            tracing::warn!(
+                records_read = read.records_read,
+                "Interrupted, so only the transactions read so far will be processed"
+            );
+        }
+
+        // A resident run serves its control channel once its input has been read, processing the
+        // files submitted over it against the accounts so far, and only completes once asked to
+        // stop.
+        let read = match &opts.control_socket {
+            Some(socket) => serve_control(
+                &opts,
+                socket,
+                &txn_processor,
+                read,
+                &stop,
+                rejects.as_mut(),
+                profile.as_mut(),
+                standing_orders.as_mut(),
+            )?,
+            None => read,
+        };
+        drop(reading_tx);
+        Ok::<_, Box<dyn Error>>(read)
+    })?;
+    if standing_orders.is_some() {
+        tracing::info!(
+            scheduled = read.scheduled,
+            "Made the transactions of the standing orders that fell due"
+        );
+    }
+
+    // Once all of the transactions have been read, take the final snapshot if one was requested.
+    if let Some(snapshot_dir) = &opts.snapshot_dir {
+        let snapshot = txn_processor.snapshot(&snapshot_dir.join("final"))?;
+        finish_snapshot(Some(snapshot.with_inputs(read.inputs.clone())))?;
     }
 
     // When we've finished passing all transactions to the processor, we'll initiate its shutdown.
-    // The processor will complete all inflight transactions, if any, and then return to us the
-    // latest state of all the accounts that were created during transaction processing.
+    // The processor will complete all inflight transactions, if any, and then hand back to us the
+    // latest state of all the accounts that were created during transaction processing. Each
+    // account is written out as soon as it's handed back, unless we need to hold on to all of them
+    // to check or sort them first.
     tracing::info!("Finished reading transactions, waiting for processing to complete...");
-    let accounts = txn_processor.shutdown()?;
+    let throttled = txn_processor.throttled();
+    if !throttled.is_zero() {
+        tracing::info!(?throttled, "Reading was held up by the rate limits");
+    }
+    let keep_accounts = opts.deterministic || opts.reconcile.is_some() || ledger_totals.is_some();
+    let mut account_stream = txn_processor.shutdown_stream()?;
+    let mut accounts = vec![];
+    let mut num_accounts: u64 = 0;
+    for account in &mut account_stream {
+        let account = account?;
+        num_accounts += 1;
+        if let Some(top) = &top {
+            top.push(&account);
+        }
+        if keep_accounts {
+            accounts.push(account);
+        } else {
+            sinks.write(&account)?;
+        }
+    }
+    let worker_stats = account_stream.worker_stats().to_vec();
     tracing::info!("All transactions processed!");
+    let rejected = rejected.map(|rejected| rejected.take());
+    if let Some(mut rejects) = rejects {
+        if let Some(rejected) = &rejected {
+            for (file, rejection) in rejected {
+                rejects.write(Path::new(file), rejection, None)?;
+            }
+        }
+        rejects.finish()?;
+    }
+    for worker in &worker_stats {
+        tracing::info!(
+            worker = worker.worker_idx,
+            txns_processed = worker.txns_processed,
+            accounts = worker.accounts,
+            rejected = worker.rejected,
+            busy = ?worker.busy,
+            "Worker finished"
+        );
+    }
+    if let Some(skew) = stats::worker_skew(&worker_stats) {
+        tracing::info!(
+            skew = format_args!("{skew:.2}"),
+            "Busiest worker processed {skew:.2}x the mean number of transactions"
+        );
+    }
+
+    if let Some(journal) = &journal {
+        journal.flush()?;
+    }
+    if let Some(statements) = &statements {
+        statements.finish()?;
+    }
+    if let Some(general_ledger) = &general_ledger {
+        general_ledger.flush()?;
+    }
+    #[cfg(feature = "redis")]
+    if let Some(redis) = &redis {
+        redis.finish();
+    }
+
+    if let Some(progress) = &progress {
+        progress.finish(read.bytes_read);
+    }
+
+    if opts.deterministic {
+        accounts.sort_by_key(|account| account.key());
+    }
+    for account in &accounts {
+        sinks.write(account)?;
+    }
+    sinks.finish()?;
+    if let Some(top) = &top {
+        top.write(io::stderr().lock())?;
+    }
 
-    // We now will dump all the account data to stdout.
-    let mut writer = csv::Writer::from_writer(BufWriter::new(io::stdout()));
-    for account in accounts {
-        writer.serialize(&account)?;
+    // Now that the accounts have been written out, there's nothing left to recover, unless the
+    // run was interrupted and can yet be carried on.
+    if let Some(wal_dir) = opts.wal_dir.as_ref().filter(|_| !read.interrupted) {
+        wal::clear(wal_dir)?;
+    }
+
+    let summary = RunSummary {
+        run_id,
+        duration: started.elapsed(),
+        records_read: read.records_read,
+        malformed: read.malformed,
+        applied: run_stats.applied(),
+        rejected: run_stats.rejected(),
+        ignored: run_stats.ignored(),
+        errored: run_stats.errored(),
+        accounts: num_accounts,
+        locked_accounts: run_stats.locked_accounts(),
+        peak_memory_bytes: stats::peak_memory_bytes(),
+        workers: worker_stats,
+        tenants: run_stats.tenants(),
+        amounts: amount_stats.run(),
+        throttled,
+        partial: read.interrupted,
+    };
+    tracing::info!(?summary, "Run complete");
+    for (txn_type, histogram) in [
+        ("deposit", &summary.amounts.deposits),
+        ("withdrawal", &summary.amounts.withdrawals),
+    ] {
+        tracing::info!(
+            txn_type,
+            count = histogram.count(),
+            min = ?histogram.min(),
+            p50 = ?histogram.percentile(0.5),
+            p90 = ?histogram.percentile(0.9),
+            p99 = ?histogram.percentile(0.99),
+            max = ?histogram.max(),
+            "Distribution of {txn_type} amounts"
+        );
+    }
+    if let Some(path) = &opts.amount_stats {
+        amount_stats.write(BufWriter::new(File::create(path)?))?;
+    }
+    if let Some(output) = &opts.txn_stats {
+        stats::write_txn_stats(
+            run_id,
+            &summary.workers,
+            output.format,
+            BufWriter::new(File::create(&output.path)?),
+        )?;
+    }
+
+    if let Some(gateway_url) = &opts.push_gateway {
+        metrics::push_to_gateway(gateway_url, &summary)?;
+    }
+
+    // The results of an interrupted run are only partial, which fails the run without checking
+    // them any further, as they're bound to fall short of what was expected.
+    if read.interrupted {
+        return Err(Failure::new(
+            FailureClass::Interrupted,
+            format!(
+                "interrupted after reading {} records, so the accounts written out are partial",
+                read.records_read
+            ),
+        )
+        .into());
+    }
+
+    // Finally, if we've been asked to reconcile the results against what was expected, then any
+    // differences will fail the run.
+    if let Some(expected_path) = &opts.reconcile {
+        let expected = ExpectedAccount::load(expected_path)?;
+        let mismatches = reconcile::reconcile(&accounts, &expected);
+        for mismatch in &mismatches {
+            eprintln!("{mismatch}");
+        }
+        if !mismatches.is_empty() {
+            return Err(Failure::new(
+                FailureClass::InvariantViolation,
+                format!(
+                    "{} mismatches found while reconciling against {}",
+                    mismatches.len(),
+                    expected_path.display()
+                ),
+            )
+            .into());
+        }
+    }
+
+    if let Some(ledger_totals) = &ledger_totals {
+        let violations = verify::verify(&accounts, &ledger_totals.net_flows());
+        for violation in &violations {
+            eprintln!("{violation}");
+        }
+        if !violations.is_empty() {
+            return Err(Failure::new(
+                FailureClass::InvariantViolation,
+                format!("{} invariant violations found", violations.len()),
+            )
+            .into());
+        }
+    }
+    if let Some(assertion_failures) = &assertion_failures {
+        let failures = assertion_failures.load(Ordering::Relaxed);
+        if failures > 0 {
+            return Err(Failure::new(
+                FailureClass::InvariantViolation,
+                format!("{failures} assertions failed while applying transactions"),
+            )
+            .into());
+        }
+    }
+
+    if let (Some(profiler), Some(profile)) = (profiler, profile) {
+        profiler.merge(&profile);
+        eprint!("{}", profiler.profile());
+    }
+
+    // A dry run fails if it found any problems at all, as the run that posts the input would have.
+    if let Some(rejected) = &rejected {
+        if read.malformed > 0 || !rejected.is_empty() {
+            let class = if read.malformed > 0 {
+                FailureClass::Parse
+            } else {
+                FailureClass::TransactionRejected
+            };
+            return Err(Failure::new(
+                class,
+                format!(
+                    "dry run found {} malformed records and {} transactions that would not be applied",
+                    read.malformed,
+                    rejected.len()
+                ),
+            )
+            .into());
+        }
+    }
+
+    // Transactions that weren't applied only fail the run for the classes we've been asked to.
+    for &class in &opts.fail_on {
+        let count = run_stats.count(class);
+        if count > 0 {
+            return Err(Failure::new(
+                FailureClass::TransactionRejected,
+                format!("{count} transactions were {class}"),
+            )
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+// The outputs the accounts are written to, which by default is CSV to stdout, other than for a dry
+// run, which writes no accounts at all.
+fn outputs(opts: &ProcessOptions, default_output: bool) -> Vec<OutputSpec> {
+    if opts.output.is_empty() {
+        (default_output && !opts.dry_run)
+            .then(OutputSpec::default)
+            .into_iter()
+            .collect()
+    } else {
+        opts.output.clone()
+    }
+}
+
+struct ReadCounts {
+    records_read: u64,
+    malformed: u64,
+    // The transactions made by standing orders as they fell due.
+    scheduled: u64,
+    bytes_read: u64,
+    inputs: Vec<InputOffset>,
+    // Whether reading was interrupted before the end of the input.
+    interrupted: bool,
+}
+
+// The columns of a transactions file without a header row, in order.
+const POSITIONAL_HEADERS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+// Reads the transactions of each of the files in turn, and passes them to the processor. Reading
+// stops early once `interrupt` is set, after the record being read at the time.
+#[allow(clippy::too_many_arguments)]
+fn read_transactions(
+    opts: &ProcessOptions,
+    files: &[PathBuf],
+    open_input: impl Fn(&Path) -> io::Result<Box<dyn Read>>,
+    txn_processor: &TransactionProcessor,
+    mut rejects: Option<&mut RejectsWriter>,
+    mut progress: Option<&mut ProgressReporter>,
+    mut profile: Option<&mut PipelineProfile>,
+    mut standing_orders: Option<&mut StandingOrders>,
+    interrupt: Option<&AtomicBool>,
+) -> Result<ReadCounts, Box<dyn Error>> {
+    let interrupted = || interrupt.is_some_and(|stop| stop.load(Ordering::Relaxed));
+    let mut stopped = false;
+    let mut records_read: u64 = 0;
+    let mut malformed: u64 = 0;
+    let mut scheduled: u64 = 0;
+    let mut held_back =
+        (opts.apply_order == ApplyOrder::EffectiveDate).then(EffectiveDateOrder::default);
+    let mut checkpoint: Option<PendingSnapshot> = None;
+    let mut inputs: Vec<InputOffset> = vec![];
+    let mut bytes_read = 0;
+    let bank_profile = opts
+        .bank_profile
+        .as_deref()
+        .map(BankProfile::load)
+        .transpose()?;
+    // Files with the same headers share a parser, rather than each working out its columns anew.
+    let parsers = Arc::new(ParserCache::default());
+    let importer = importer::for_format(
+        opts.format,
+        bank_profile,
+        opts.sheet.clone(),
+        opts.avro_schema.as_deref(),
+    )?;
+    for path in files {
+        if interrupted() {
+            stopped = true;
+            break;
+        }
+
+        if let Some(importer) = importer.as_deref() {
+            let statement = read_statement(
+                opts,
+                importer,
+                path,
+                &open_input,
+                txn_processor,
+                rejects.as_deref_mut(),
+                profile.as_deref_mut(),
+                interrupt,
+            )?;
+            records_read += statement.records_read;
+            malformed += statement.malformed;
+            bytes_read += statement.bytes_read;
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.record_read(bytes_read);
+            }
+            inputs.push(InputOffset {
+                file: path.clone(),
+                records: statement.records_read,
+                byte_offset: statement.bytes_read,
+                complete: !statement.interrupted,
+            });
+            if statement.interrupted {
+                stopped = true;
+                break;
+            }
+            continue;
+        }
+
+        // Stream in the transactions from the CSV file, or parse them in parallel from the mapped
+        // file, and pass them to our transaction processor. Opening the file is timed as its setup.
+        let mut csv_records = profile::timed(profile.as_deref_mut(), Stage::Setup, || {
+            CsvRecords::open(opts, path, &open_input, &parsers)
+        })?;
+        let parser = csv_records.parser().clone();
+        let headers = parser.headers();
+        let ExtraColumns {
+            timestamp: timestamp_idx,
+            effective_date: effective_date_idx,
+            memo: memo_idx,
+        } = parser.extra_columns();
+        let file = Source::new(path, 0);
+        let mut record = csv::ByteRecord::new();
+        let mut file_records: u64 = 0;
+        while let Some(result) = csv_records.read(&mut record, profile.as_deref_mut())? {
+            records_read += 1;
+            file_records += 1;
+            if let Some(progress) = progress.as_deref_mut() {
+                progress.record_read(bytes_read + csv_records.position());
+            }
+
+            let memo = memo_idx
+                .and_then(|idx| record.get(idx))
+                .and_then(|memo| transaction::memo(&String::from_utf8_lossy(memo)));
+            let result = result
+                .map_err(|err| Rejection::from_csv_error(&err, headers, &record))
+                .and_then(|txn| match opts.excess_precision {
+                    _ if !txn.has_excess_precision() => Ok(txn),
+                    ExcessPrecision::Round => Ok(txn.round_amount()),
+                    ExcessPrecision::Reject => Err(Rejection::excess_precision(headers, &record)),
+                })
+                .and_then(|txn| {
+                    let line = record.position().map(|pos| pos.line());
+                    with_tenant(opts, txn, memo.as_deref(), line)
+                })
+                .and_then(|txn| {
+                    let date = |idx: Option<usize>, column| match idx
+                        .and_then(|idx| record.get(idx))
+                        .filter(|f| !f.is_empty())
+                    {
+                        None => Ok(None),
+                        Some(field) => str::from_utf8(field)
+                            .ok()
+                            .and_then(input::parse_timestamp)
+                            .map(Some)
+                            .ok_or_else(|| Rejection::invalid_date(headers, &record, column)),
+                    };
+                    Ok(DatedTransaction {
+                        txn,
+                        source: file.at_line(record.position().map_or(0, |pos| pos.line())),
+                        memo,
+                        posted_at: date(timestamp_idx, "timestamp")?,
+                        effective_at: date(effective_date_idx, "effective_date")?,
+                    })
+                });
+
+            // A record that cannot be deserialized is either fatal or skipped, depending on the
+            // error policy. Either way, we describe exactly where in the file the problem lies.
+            let dated = match result {
+                Ok(dated) => dated,
+                Err(rejection) => {
+                    reject(opts, path, rejection, Some(&record), rejects.as_deref_mut())?;
+                    malformed += 1;
+                    continue;
+                }
+            };
+            match held_back.as_mut() {
+                Some(held_back) => held_back.push(dated),
+                None => {
+                    let posted_at = dated.posted_at;
+                    scheduled += submit(
+                        txn_processor,
+                        dated,
+                        posted_at,
+                        standing_orders.as_deref_mut(),
+                        profile.as_deref_mut(),
+                    )?
+                }
+            }
+
+            // Periodically checkpoint the state of the accounts, along with how far through the
+            // input we've read. We only wait on the previous checkpoint to be completed when
+            // starting the next one, which by then it almost certainly is.
+            if let (Some(snapshot_dir), Some(checkpoint_every)) =
+                (&opts.snapshot_dir, opts.checkpoint_every)
+            {
+                if records_read.is_multiple_of(checkpoint_every) {
+                    finish_snapshot(checkpoint.take())?;
+                    let dir = snapshot_dir.join(format!("checkpoint-{records_read}"));
+                    let current = InputOffset {
+                        file: path.clone(),
+                        records: file_records,
+                        byte_offset: csv_records.position(),
+                        complete: false,
+                    };
+                    let inputs = inputs.iter().cloned().chain([current]).collect();
+                    checkpoint = Some(txn_processor.snapshot(&dir)?.with_inputs(inputs));
+                }
+            }
+
+            if interrupted() {
+                stopped = true;
+                break;
+            }
+        }
+
+        let byte_offset = csv_records.position();
+        bytes_read += byte_offset;
+        inputs.push(InputOffset {
+            file: path.clone(),
+            records: file_records,
+            byte_offset,
+            complete: !stopped,
+        });
+        if stopped {
+            break;
+        }
+    }
+
+    // Transactions held back to be applied in order of effective date are only applied once the
+    // input has been read, or as much of it as was read before being interrupted.
+    if let Some(held_back) = held_back {
+        tracing::info!(
+            transactions = held_back.len(),
+            "Applying the transactions read in order of effective date"
+        );
+        for (effective_at, dated) in held_back.into_sorted() {
+            scheduled += submit(
+                txn_processor,
+                dated,
+                Some(effective_at),
+                standing_orders.as_deref_mut(),
+                profile.as_deref_mut(),
+            )?;
+        }
+    }
+    finish_snapshot(checkpoint)?;
+
+    Ok(ReadCounts {
+        records_read,
+        malformed,
+        scheduled,
+        bytes_read,
+        inputs,
+        interrupted: stopped,
+    })
+}
+
+// Passes a transaction read from the input to the processor, after the transactions of any standing
+// orders that fell due by the given time, returning how many of those there were.
+fn submit(
+    txn_processor: &TransactionProcessor,
+    dated: DatedTransaction,
+    now: Option<u64>,
+    standing_orders: Option<&mut StandingOrders>,
+    mut profile: Option<&mut PipelineProfile>,
+) -> Result<u64, Box<dyn Error>> {
+    let mut scheduled = 0;
+    if let (Some(standing_orders), Some(now)) = (standing_orders, now) {
+        for (txn, source, due) in standing_orders.advance_to(now) {
+            tracing::info!(account_id = %txn.account_id(), txn_id = %txn.id(), %source, %txn);
+            profile::timed(profile.as_deref_mut(), Stage::Dispatch, || {
+                txn_processor.process_posted_txn_from(txn, source, None, Some(due))
+            })?;
+            scheduled += 1;
+        }
+    }
+
+    let DatedTransaction {
+        txn,
+        source,
+        memo,
+        posted_at,
+        ..
+    } = dated;
+    tracing::info!(account_id = %txn.account_id(), txn_id = %txn.id(), %source, %txn);
+    profile::timed(profile, Stage::Dispatch, || {
+        txn_processor.process_posted_txn_from(txn, source, memo, posted_at)
+    })?;
+    Ok(scheduled)
+}
+
+// The records of a CSV file, either streamed from it and parsed one at a time, or parsed in
+// parallel from the file mapped into memory.
+enum CsvRecords {
+    Stream(csv::Reader<BufReader<Box<dyn Read>>>, Arc<RecordParser>),
+    Chunked(ChunkedReader),
+}
+
+impl CsvRecords {
+    // Memory-maps the file if asked to, unless it's one that can't be mapped, such as a pipe or a
+    // remote file, which are streamed instead.
+    fn open(
+        opts: &ProcessOptions,
+        path: &Path,
+        open_input: impl Fn(&Path) -> io::Result<Box<dyn Read>>,
+        parsers: &Arc<ParserCache>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let dialect = CsvDialect {
+            delimiter: opts.delimiter.0,
+            quote: (!opts.no_quoting).then_some(opts.quote.0),
+            trim: opts.lenient_parsing,
+            has_headers: !opts.no_headers,
+        };
+        let positional = || csv::ByteRecord::from(POSITIONAL_HEADERS.to_vec());
+        if opts.mmap && !remote::is_remote(path) && !input::is_stream(path) {
+            let mut builder = ChunkedReader::builder(dialect)
+                .threads(opts.parse_threads.unwrap_or_else(num_cpus::get_physical))
+                .parsers(parsers.clone());
+            if opts.no_headers {
+                builder = builder.headers(positional());
+            }
+            if opts.lenient_parsing {
+                builder = builder.lenient();
+            }
+            return Ok(Self::Chunked(builder.open(path)?));
+        }
+
+        let mut reader = dialect
+            .reader_builder()
+            .from_reader(BufReader::new(open_input(path)?));
+        let headers = if opts.no_headers {
+            positional()
+        } else {
+            reader.byte_headers()?.clone()
+        };
+        Ok(Self::Stream(
+            reader,
+            parsers.parser(headers, opts.lenient_parsing),
+        ))
+    }
+
+    fn parser(&self) -> &Arc<RecordParser> {
+        match self {
+            Self::Stream(_, parser) => parser,
+            Self::Chunked(reader) => reader.parser(),
+        }
+    }
+
+    // Reads the next record, returning the transaction parsed from it. Reading and deserializing a
+    // streamed record are done as separate steps so that each of them can be timed when profiling
+    // the pipeline, whereas a mapped file's records are parsed ahead of time by other threads, so
+    // that only the time spent waiting on them is.
+    #[allow(clippy::type_complexity)]
+    fn read(
+        &mut self,
+        record: &mut csv::ByteRecord,
+        mut profile: Option<&mut PipelineProfile>,
+    ) -> csv::Result<Option<Result<Transaction, csv::Error>>> {
+        match self {
+            Self::Stream(reader, parser) => {
+                if !profile::timed(profile.as_deref_mut(), Stage::Read, || {
+                    reader.read_byte_record(record)
+                })? {
+                    return Ok(None);
+                }
+                Ok(Some(profile::timed(profile, Stage::Deserialize, || {
+                    parser.parse(record)
+                })))
+            }
+            Self::Chunked(reader) => {
+                profile::timed(profile, Stage::Read, || reader.read_byte_record(record))
+            }
+        }
+    }
+
+    // The byte offset of the end of the last record read.
+    fn position(&self) -> u64 {
+        match self {
+            Self::Stream(reader, _) => reader.position().byte(),
+            Self::Chunked(reader) => reader.position(),
+        }
+    }
+}
+
+// Reads a local file, through io_uring if asked to, unless it's a stream such as a pipe, which is
+// read as usual.
+#[cfg_attr(
+    not(all(target_os = "linux", feature = "io-uring")),
+    allow(unused_variables)
+)]
+fn open_file(opts: &ProcessOptions, path: &Path, file: File) -> io::Result<Box<dyn Read>> {
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    if opts.io_uring && !input::is_stream(path) {
+        return Ok(Box::new(UringReader::new(file)?));
+    }
+    Ok(Box::new(file))
+}
+
+// Serves the control channel of a resident run until it's asked to stop. Submitted files are read
+// with the same options as the run's own input, and are counted as part of it.
+#[cfg(unix)]
+#[allow(clippy::too_many_arguments)]
+fn serve_control(
+    opts: &ProcessOptions,
+    socket: &Path,
+    txn_processor: &TransactionProcessor,
+    mut read: ReadCounts,
+    stop: &AtomicBool,
+    mut rejects: Option<&mut RejectsWriter>,
+    mut profile: Option<&mut PipelineProfile>,
+    mut standing_orders: Option<&mut StandingOrders>,
+) -> Result<ReadCounts, Box<dyn Error>> {
+    let channel = ControlChannel::bind(socket)?;
+    tracing::info!(socket = %socket.display(), "Serving the control channel");
+    let open_input = |path: &Path| -> io::Result<Box<dyn Read>> {
+        if remote::is_remote(path) {
+            return remote::open(path);
+        }
+        open_file(opts, path, File::open(path)?)
+    };
+
+    channel.serve(stop, |command| match command {
+        ControlCommand::Submit(path) => {
+            let submitted = read_transactions(
+                opts,
+                &[path],
+                open_input,
+                txn_processor,
+                rejects.as_deref_mut(),
+                None,
+                profile.as_deref_mut(),
+                standing_orders.as_deref_mut(),
+                None,
+            )
+            .map_err(|err| err.to_string())?;
+            read.records_read += submitted.records_read;
+            read.malformed += submitted.malformed;
+            read.scheduled += submitted.scheduled;
+            read.bytes_read += submitted.bytes_read;
+            read.inputs.extend(submitted.inputs);
+            Ok(format!(
+                "records={} malformed={}",
+                submitted.records_read, submitted.malformed
+            ))
+        }
+
+        ControlCommand::Flush => {
+            txn_processor.flush().map_err(|err| err.to_string())?;
+            Ok(format!("next_order={}", txn_processor.next_order()))
+        }
+
+        ControlCommand::Snapshot(dir) => {
+            let manifest = txn_processor
+                .snapshot(&dir)
+                .and_then(|snapshot| snapshot.with_inputs(read.inputs.clone()).wait())
+                .map_err(|err| err.to_string())?;
+            let accounts: u64 = manifest.shards.iter().map(|shard| shard.accounts).sum();
+            Ok(format!(
+                "accounts={accounts} next_order={}",
+                manifest.next_order
+            ))
+        }
+
+        ControlCommand::Stats => {
+            let stats = txn_processor.stats();
+            let list = |value: fn(&WorkerQueueStats) -> String| {
+                stats
+                    .workers
+                    .iter()
+                    .map(value)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            };
+            Ok(format!(
+                "next_order={} queued={} peak_queued={} busy={} throttled={:.3}",
+                stats.next_order,
+                list(|worker| worker.queued.to_string()),
+                list(|worker| worker.peak_queued.to_string()),
+                list(|worker| format!("{:.3}", worker.busy.as_secs_f64())),
+                stats.throttled.as_secs_f64()
+            ))
+        }
+
+        ControlCommand::Stop => Ok("stopping".to_owned()),
+    })?;
+    Ok(read)
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::too_many_arguments)]
+fn serve_control(
+    _opts: &ProcessOptions,
+    _socket: &Path,
+    _txn_processor: &TransactionProcessor,
+    _read: ReadCounts,
+    _stop: &AtomicBool,
+    _rejects: Option<&mut RejectsWriter>,
+    _profile: Option<&mut PipelineProfile>,
+    _standing_orders: Option<&mut StandingOrders>,
+) -> Result<ReadCounts, Box<dyn Error>> {
+    Err("a control socket can only be served on Unix".into())
+}
+
+// Rejects a record that cannot be turned into a transaction, which either fails the run or is
+// skipped over, depending on the error policy.
+fn reject(
+    opts: &ProcessOptions,
+    path: &Path,
+    rejection: Rejection,
+    raw_record: Option<&csv::ByteRecord>,
+    rejects: Option<&mut RejectsWriter>,
+) -> Result<(), Box<dyn Error>> {
+    if let Some(rejects) = rejects {
+        rejects.write(path, &rejection, raw_record)?;
+    }
+    match opts.error_policy {
+        ErrorPolicy::Strict => Err(rejection.into()),
+        ErrorPolicy::Lenient => {
+            tracing::warn!(
+                file = %path.display(),
+                line = rejection.line,
+                column = rejection.column.as_deref(),
+                raw_field = rejection.raw_field.as_deref(),
+                reason = %rejection.reason,
+                error_code = "malformed_record",
+                "Rejected a malformed transaction record"
+            );
+            Ok(())
+        }
+    }
+}
+
+// Checks that a transaction of a multi-tenant run names its tenant. The tenant of any other run's
+// transactions is ignored, so that they all share the one account space.
+fn with_tenant(
+    opts: &ProcessOptions,
+    txn: Transaction,
+    memo: Option<&str>,
+    line: Option<u64>,
+) -> Result<Transaction, Rejection> {
+    if !opts.multi_tenant {
+        return Ok(txn.with_tenant(None));
+    }
+    match txn.tenant() {
+        Some(_) => Ok(txn),
+        None => Err(Rejection::missing_tenant(memo, line)),
+    }
+}
+
+// Processes the transactions of a bank statement or partner file that needs importing. Unlike one
+// of our own CSV files, it isn't checkpointed part way through, and unless its format can be
+// imported a record at a time, it is imported as a whole before any of its transactions are
+// processed.
+#[allow(clippy::too_many_arguments)]
+fn read_statement(
+    opts: &ProcessOptions,
+    importer: &dyn Importer,
+    path: &Path,
+    open_input: impl Fn(&Path) -> io::Result<Box<dyn Read>>,
+    txn_processor: &TransactionProcessor,
+    mut rejects: Option<&mut RejectsWriter>,
+    mut profile: Option<&mut PipelineProfile>,
+    interrupt: Option<&AtomicBool>,
+) -> Result<ReadCounts, Box<dyn Error>> {
+    // A file that is imported whole is read as the records are opened, and imported as the first is
+    // read, whereas one imported a record at a time is read as each record is.
+    let mut input = CountingReader {
+        inner: open_input(path)?,
+        bytes_read: 0,
+    };
+    let mut records = profile::timed(profile.as_deref_mut(), Stage::Read, || {
+        importer.records(Box::new(&mut input))
+    })?;
+
+    let file = Source::new(path, 0);
+    let mut records_read = 0;
+    let mut malformed = 0;
+    let mut interrupted = false;
+    while let Some(record) = profile::timed(profile.as_deref_mut(), Stage::Deserialize, || {
+        records.next()
+    }) {
+        let record = record?;
+        if interrupt.is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+            interrupted = true;
+            break;
+        }
+        records_read += 1;
+        let result = record.result.and_then(|ImportedTransaction { txn, memo }| {
+            let txn = match opts.excess_precision {
+                _ if !txn.has_excess_precision() => txn,
+                ExcessPrecision::Round => txn.round_amount(),
+                ExcessPrecision::Reject => {
+                    return Err(Rejection::excess_precision_in_statement(
+                        &txn,
+                        memo.as_deref(),
+                        record.line,
+                        importer.amount_column(),
+                    ))
+                }
+            };
+            let txn = with_tenant(opts, txn, memo.as_deref(), Some(record.line))?;
+            Ok((txn, memo))
+        });
+        let (txn, memo) = match result {
+            Ok(imported) => imported,
+            Err(rejection) => {
+                reject(opts, path, rejection, None, rejects.as_deref_mut())?;
+                malformed += 1;
+                continue;
+            }
+        };
+
+        let source = file.at_line(record.line);
+        tracing::info!(account_id = %txn.account_id(), txn_id = %txn.id(), %source, %txn);
+        profile::timed(profile.as_deref_mut(), Stage::Dispatch, || {
+            txn_processor.process_txn_from(txn, source, memo)
+        })?;
+    }
+
+    drop(records);
+
+    Ok(ReadCounts {
+        records_read,
+        malformed,
+        scheduled: 0,
+        bytes_read: input.bytes_read,
+        inputs: vec![],
+        interrupted,
+    })
+}
+
+// Counts the bytes read from a file as it's imported.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
+
+// How often a resident run pushes how far behind its workers are.
+const PROCESSOR_STATS_INTERVAL: Duration = Duration::from_secs(15);
+
+// Pushes how far behind the workers are to the pushgateway once an interval, until the sender of
+// the receiver is dropped. A push that fails is only logged, as the run carries on regardless.
+fn push_processor_stats(
+    gateway_url: &str,
+    run_id: RunId,
+    txn_processor: &TransactionProcessor,
+    stop_rx: crossbeam_channel::Receiver<()>,
+) {
+    while let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+        stop_rx.recv_timeout(PROCESSOR_STATS_INTERVAL)
+    {
+        if let Err(err) = metrics::push_processor_stats(gateway_url, run_id, &txn_processor.stats())
+        {
+            tracing::warn!(
+                error_code = "metrics_push_failed",
+                "Unable to push the processor's stats: {err}"
+            );
+        }
+    }
+}
+
+// The target that throughput reports are logged under, so that they can be logged without
+// everything else at the same level.
+const THROUGHPUT_TARGET: &str = "banking_exercise::throughput";
+
+// Logs how many transactions have been read, processed and rejected at each interval, and how many
+// were processed per second over it, until the stop channel is closed.
+fn report_throughput(
+    interval: Duration,
+    txn_processor: &TransactionProcessor,
+    run_stats: &RunStats,
+    starting_order: u64,
+    stop_rx: crossbeam_channel::Receiver<()>,
+) {
+    let processed =
+        || run_stats.applied() + run_stats.rejected() + run_stats.ignored() + run_stats.errored();
+    let (mut last_processed, mut last_report) = (processed(), Instant::now());
+    while let Err(crossbeam_channel::RecvTimeoutError::Timeout) = stop_rx.recv_timeout(interval) {
+        let (processed, now) = (processed(), Instant::now());
+        let rate = (processed - last_processed) as f64
+            / now
+                .duration_since(last_report)
+                .as_secs_f64()
+                .max(f64::EPSILON);
+        tracing::info!(
+            target: THROUGHPUT_TARGET,
+            read = txn_processor.next_order() - starting_order,
+            processed,
+            rejected = run_stats.rejected(),
+            txns_per_sec = format_args!("{rate:.0}"),
+            "Processed {processed} transactions, {rate:.0} per second"
+        );
+        (last_processed, last_report) = (processed, now);
+    }
+}
+
+// Writes a snapshot of the accounts into the snapshot directory each time a hangup is received,
+// until the signal iterator is closed.
+fn write_interim_snapshots(
+    hangups: &mut Signals,
+    txn_processor: &TransactionProcessor,
+    snapshot_dir: Option<&Path>,
+) {
+    for _ in hangups.forever() {
+        let Some(snapshot_dir) = snapshot_dir else {
+            tracing::warn!("Received a hangup, but there is no snapshot directory to write to");
+            continue;
+        };
+
+        let dir = snapshot_dir.join(format!("interim-{}", txn_processor.next_order()));
+        let result = txn_processor
+            .snapshot(&dir)
+            .map_err(Into::into)
+            .and_then(|snapshot| finish_snapshot(Some(snapshot)));
+        if let Err(err) = result {
+            tracing::error!(
+                dir = %dir.display(),
+                error_code = "snapshot_failed",
+                "Unable to write an interim snapshot: {err}"
+            );
+        }
+    }
+}
+
+// Writes out deposits of the same amount to each client in turn.
+fn generate(opts: GenerateOptions) -> Result<(), Box<dyn Error>> {
+    let mut writer = BufWriter::new(io::stdout().lock());
+    writeln!(writer, "type,client,tx,amount")?;
+    for txn_id in 1..=opts.transactions {
+        let client = (txn_id - 1) % u64::from(opts.clients) + 1;
+        writeln!(writer, "deposit,{client},{txn_id},{}", opts.amount)?;
     }
     writer.flush()?;
+    Ok(())
+}
 
+fn merge(opts: MergeOptions) -> Result<(), Box<dyn Error>> {
+    let manifest = snapshot::merge(&opts.snapshot_dirs, &opts.output_dir)?;
+    let accounts: u64 = manifest.shards.iter().map(|shard| shard.accounts).sum();
+    eprintln!(
+        "Merged {} snapshots into {}, with {accounts} accounts",
+        opts.snapshot_dirs.len(),
+        opts.output_dir.display()
+    );
     Ok(())
 }
+
+// Reconstructs the accounts from the journal, and writes them out as CSV to stdout even if they
+// diverged from it, so that they can be compared against the accounts the run wrote out.
+fn replay(opts: ReplayOptions) -> Result<(), Box<dyn Error>> {
+    let mut replay = Replay::default();
+    if let Some(run_id) = opts.run_id {
+        replay = replay.with_run_id(run_id);
+    }
+    let journal = BufReader::new(File::open(&opts.journal)?);
+    let replay = replay::replay(journal, replay)?;
+    if replay.entries() == 0 {
+        return Err(match opts.run_id {
+            Some(run_id) => format!("run {run_id} has no entries in {}", opts.journal.display()),
+            None => format!("{} has no entries", opts.journal.display()),
+        }
+        .into());
+    }
+
+    for divergence in replay.divergences() {
+        eprintln!("{divergence}");
+    }
+    eprintln!(
+        "Replayed {} journal entries of {} runs, skipping {} journaled again",
+        replay.entries(),
+        replay.runs(),
+        replay.duplicates()
+    );
+    let divergences = replay.divergences().len();
+
+    let format = OutputFormat {
+        decimal_places: opts.output_precision.decimal_places(),
+        extended: false,
+        tenant: false,
+    };
+    let mut sink = CsvSink::new(BufWriter::new(io::stdout()), format);
+    for account in replay.into_accounts() {
+        sink.write(&account)?;
+    }
+    sink.finish()?;
+
+    if divergences > 0 {
+        return Err(Failure::new(
+            FailureClass::InvariantViolation,
+            format!(
+                "{divergences} accounts diverged from {} when it was replayed",
+                opts.journal.display()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Totals the general ledger export, and writes the trial balance out as CSV to stdout even if it
+// doesn't balance, so that the sub-ledgers that are out can be looked into.
+fn trial_balance(opts: TrialBalanceOptions) -> Result<(), Box<dyn Error>> {
+    let general_ledger = BufReader::new(File::open(&opts.general_ledger)?);
+    let trial_balance = TrialBalance::read(general_ledger)?;
+
+    for reference in trial_balance.unbalanced_references() {
+        eprintln!("The postings of {reference} don't balance");
+    }
+    eprintln!(
+        "Totalled {} postings, with debits of {} and credits of {}",
+        trial_balance.postings(),
+        trial_balance.debits().normalize(),
+        trial_balance.credits().normalize()
+    );
+    trial_balance.write(BufWriter::new(io::stdout()))?;
+
+    if !trial_balance.is_balanced() {
+        return Err(Failure::new(
+            FailureClass::InvariantViolation,
+            format!(
+                "the debits of {} don't equal its credits",
+                opts.general_ledger.display()
+            ),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn finish_snapshot(snapshot: Option<PendingSnapshot>) -> Result<(), Box<dyn Error>> {
+    if let Some(snapshot) = snapshot {
+        let dir = snapshot.dir().to_path_buf();
+        let manifest = snapshot.wait()?;
+        tracing::info!(
+            dir = %dir.display(),
+            shards = manifest.shards.len(),
+            "Snapshot complete"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use structopt::StructOpt;
+    use uuid::Uuid;
+
+    #[test]
+    fn log_filter_levels() -> Result<(), Box<dyn Error>> {
+        use tracing::Level;
+        use tracing_subscriber::prelude::*;
+
+        let enabled = |args: &[&str], rust_log: Option<&str>| {
+            let input = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/test1.csv");
+            let args = ["process", input].into_iter().chain(args.iter().copied());
+            let opts = ProcessOptions::from_iter_safe(args)?;
+            let subscriber = tracing_subscriber::registry().with(log_filter(&opts, rust_log)?);
+            let levels = tracing::subscriber::with_default(subscriber, || {
+                [
+                    tracing::enabled!(target: "banking_exercise::processor", Level::DEBUG),
+                    tracing::enabled!(target: "banking_exercise::processor", Level::ERROR),
+                    tracing::enabled!(target: "banking_exercise", Level::WARN),
+                    tracing::enabled!(target: "banking_exercise", Level::ERROR),
+                ]
+            });
+            Ok::<_, Box<dyn Error>>(levels)
+        };
+
+        assert_eq!(enabled(&[], None)?, [false, true, false, true]);
+        assert_eq!(enabled(&["-v"], None)?, [false, true, true, true]);
+        // RUST_LOG overrides the level chosen for the targets it names, and only those.
+        let rust_log = Some("banking_exercise::processor=debug");
+        assert_eq!(enabled(&["-q"], rust_log)?, [true, true, false, false]);
+        assert_eq!(enabled(&["-vv"], rust_log)?, [true, true, true, true]);
+        assert_eq!(
+            enabled(&["-vvvv"], Some("banking_exercise::processor=off"))?,
+            [false, false, true, true]
+        );
+        assert!(enabled(&[], Some("banking_exercise=loud")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reports_throughput() -> Result<(), Box<dyn Error>> {
+        use banking_exercise::models::transaction::TransactionType;
+
+        let run_stats = Arc::new(RunStats::default());
+        let txn_processor = TransactionProcessor::builder(1)
+            .starting_order(100)
+            .observer(run_stats.clone())
+            .build();
+        let amount = "10".parse()?;
+        for (txn_id, txn_type) in [
+            (1, TransactionType::Deposit { amount }),
+            (2, TransactionType::Withdrawal { amount }),
+            (3, TransactionType::Withdrawal { amount }),
+        ] {
+            txn_processor.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))?;
+        }
+        txn_processor.flush()?;
+
+        let log_file = env::temp_dir().join(format!("throughput-{}.log", Uuid::new_v4()));
+        let subscriber = tracing_subscriber::fmt()
+            .without_time()
+            .with_ansi(false)
+            .with_writer(Mutex::new(File::create(&log_file)?))
+            .finish();
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(stop_tx);
+        });
+        tracing::subscriber::with_default(subscriber, || {
+            report_throughput(
+                Duration::from_millis(10),
+                &txn_processor,
+                &run_stats,
+                100,
+                stop_rx,
+            )
+        });
+        stopper.join().expect("stopper panicked");
+        txn_processor.shutdown()?;
+
+        let logged = fs::read_to_string(&log_file)?;
+        let report = logged.lines().next().expect("no throughput was reported");
+        assert!(
+            report.contains("Processed 3 transactions")
+                && report.contains("read=3 processed=3 rejected=1"),
+            "{logged}"
+        );
+
+        fs::remove_file(log_file)?;
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_reports_rejects() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("dry-run-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let input = dir.join("txns.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             dispute,1,9,\n\
+             withdrawal,1,2,500\n",
+        )?;
+        let rejects = dir.join("rejects.csv");
+        let opts = ProcessOptions::from_iter_safe([
+            "process".as_ref(),
+            input.as_os_str(),
+            "--dry-run".as_ref(),
+            "--deterministic".as_ref(),
+            "--rejects-file".as_ref(),
+            rejects.as_os_str(),
+        ])?;
+        assert!(outputs(&opts, true).is_empty(), "no accounts are written");
+
+        let err = process_until(opts, true, Arc::default()).unwrap_err();
+        assert_eq!(
+            FailureClass::classify(err.as_ref()),
+            FailureClass::TransactionRejected
+        );
+        let rejects = fs::read_to_string(&rejects)?;
+        let lines: Vec<_> = rejects.lines().skip(1).collect();
+        assert_eq!(lines.len(), 2, "{rejects}");
+        assert!(
+            lines[0]
+                .ends_with(",3,,,The account with ID 1 had no past transaction with the ID 9,,"),
+            "{rejects}"
+        );
+        assert!(
+            lines[1].contains(",4,,,\"The account with ID 1 has insufficient funds"),
+            "{rejects}"
+        );
+        assert_eq!(
+            fs::read_dir(&dir)?.count(),
+            2,
+            "only the rejects are written"
+        );
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn skips_bad_rows_into_rejects_file() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("skip-bad-rows-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let input = dir.join("txns.csv");
+        fs::write(
+            &input,
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             deposit,2,2,ten\n\
+             deposit,1,3,5\n",
+        )?;
+        let (rejects, accounts) = (dir.join("rejects.csv"), dir.join("accounts.csv"));
+        let output = format!("csv:{}", accounts.display());
+        let args = |skip_bad_rows: bool| {
+            let mut args = vec![
+                "process".as_ref(),
+                input.as_os_str(),
+                "--deterministic".as_ref(),
+                "--rejects-file".as_ref(),
+                rejects.as_os_str(),
+                "--output".as_ref(),
+                output.as_ref(),
+            ];
+            if skip_bad_rows {
+                args.push("--skip-bad-rows".as_ref());
+            }
+            ProcessOptions::from_iter_safe(args)
+        };
+
+        // Without it, the first bad row fails the run, though it's still written to the rejects.
+        let err = process_until(args(false)?, true, Arc::default()).unwrap_err();
+        assert_eq!(FailureClass::classify(err.as_ref()), FailureClass::Parse);
+        assert_eq!(fs::read_to_string(&rejects)?.lines().count(), 2);
+
+        process_until(args(true)?, true, Arc::default())?;
+        let rejects = fs::read_to_string(&rejects)?;
+        let lines: Vec<_> = rejects.lines().collect();
+        assert_eq!(lines.len(), 2, "{rejects}");
+        assert_eq!(
+            lines[0],
+            "file,line,column,raw_field,reason,memo,raw_record"
+        );
+        assert!(
+            lines[1].starts_with(&format!("{},3,amount,ten,", input.display()))
+                && lines[1].ends_with(",\"deposit,2,2,ten\""),
+            "{rejects}"
+        );
+        let accounts = fs::read_to_string(&accounts)?;
+        let accounts: Vec<_> = accounts.lines().skip(1).collect();
+        assert_eq!(accounts, ["1,105.0000,0.0000,105.0000,false"]);
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reads_other_csv_dialects() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("dialects-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let input = dir.join("txns.csv");
+        let accounts = dir.join("accounts.csv");
+        let output = format!("csv:{}", accounts.display());
+        let process = |records: &str, dialect: &[&str]| {
+            fs::write(&input, records)?;
+            let args = [
+                "process",
+                input.to_str().ok_or("temp dir isn't UTF-8")?,
+                "--deterministic",
+                "--output",
+                &output,
+                "--delimiter",
+                ";",
+                "--no-headers",
+            ];
+            let opts = ProcessOptions::from_iter_safe(args.iter().chain(dialect))?;
+            process_until(opts, true, Arc::default())?;
+            Ok::<_, Box<dyn Error>>(fs::read_to_string(&accounts)?)
+        };
+
+        // Fields may be quoted with the given quote character.
+        let written = process(
+            "'deposit';1;1;100\nwithdrawal;1;2;'40'\n",
+            &["--quote", "'"],
+        )?;
+        assert_eq!(
+            written.lines().nth(1),
+            Some("1,60.0000,0.0000,60.0000,false")
+        );
+
+        // Or not at all, in which case quotes are part of the field they're in, so the first
+        // transaction is of an unknown type.
+        let written = process("\"deposit\";2;3;5\ndeposit;2;4;7\n", &["--no-quoting"])?;
+        assert_eq!(written.lines().nth(1), Some("2,7.0000,0.0000,7.0000,false"));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reports_where_txns_were_read() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("sources-{}", Uuid::new_v4()));
+        let input = dir.join("input");
+        fs::create_dir_all(&input)?;
+        let (first, second) = (input.join("1.csv"), input.join("2.csv"));
+        fs::write(
+            &first,
+            "type,client,tx,amount\n\
+             deposit,1,1,100\n\
+             withdrawal,1,2,500\n",
+        )?;
+        fs::write(&second, "type,client,tx,amount\ndeposit,2,3,5\n")?;
+        let (journal, log_file) = (dir.join("journal.csv"), dir.join("run.log"));
+        let opts = ProcessOptions::from_iter_safe([
+            "process".as_ref(),
+            input.as_os_str(),
+            "--deterministic".as_ref(),
+            "--journal".as_ref(),
+            journal.as_os_str(),
+            "--output".as_ref(),
+            format!("csv:{}", dir.join("accounts.csv").display()).as_ref(),
+        ])?;
+        let subscriber = tracing_subscriber::fmt()
+            .without_time()
+            .with_ansi(false)
+            .with_writer(Mutex::new(File::create(&log_file)?))
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            process_until(opts, true, Arc::default())
+        })?;
+
+        // Each transaction is logged and journaled with the file and line it was read from.
+        let logged = fs::read_to_string(&log_file)?;
+        let journaled = fs::read_to_string(&journal)?;
+        for (file, line) in [(&first, 2), (&first, 3), (&second, 2)] {
+            let file = file.display();
+            assert!(
+                logged.contains(&format!("source={file}:{line}")),
+                "{logged}"
+            );
+            assert!(
+                journaled.contains(&format!(",{file},{line},")),
+                "{journaled}"
+            );
+        }
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    // The input is a named pipe, so that the run can be stopped while it's waiting on the record
+    // after the first. Whether it stops before or after reading the second, it never reads the
+    // third.
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn interrupted_run_writes_partial_accounts() -> Result<(), Box<dyn Error>> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = env::temp_dir().join(format!("interrupted-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let input = dir.join("txns.csv");
+        let fifo = CString::new(input.as_os_str().as_bytes())?;
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+        let accounts = dir.join("accounts.csv");
+        let output = format!("csv:{}", accounts.display());
+        let opts = ProcessOptions::from_iter_safe([
+            "process".as_ref(),
+            input.as_os_str(),
+            "--deterministic".as_ref(),
+            "--output".as_ref(),
+            output.as_ref(),
+        ])?;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (input, stop) = (input.clone(), stop.clone());
+            thread::spawn(move || -> io::Result<()> {
+                let mut pipe = fs::OpenOptions::new().write(true).open(input)?;
+                pipe.write_all(b"type,client,tx,amount\ndeposit,1,1,100\n")?;
+                thread::sleep(Duration::from_millis(100));
+                stop.store(true, Ordering::Relaxed);
+                // The run may already have stopped reading, and closed the pipe.
+                let _ = pipe.write_all(b"deposit,2,2,100\ndeposit,3,3,100\n");
+                Ok(())
+            })
+        };
+        let err = process_until(opts, true, stop).unwrap_err();
+        writer.join().expect("writer panicked")?;
+        assert_eq!(
+            FailureClass::classify(err.as_ref()),
+            FailureClass::Interrupted
+        );
+        assert!(err
+            .to_string()
+            .ends_with("the accounts written out are partial"));
+        let accounts = fs::read_to_string(&accounts)?;
+        let clients: Vec<_> = accounts
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split(',').next())
+            .collect();
+        assert_eq!(clients.first(), Some(&"1"), "{accounts}");
+        assert!(!clients.contains(&"3"), "{accounts}");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn reconciles_against_expected_accounts() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("reconcile-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/test1.csv");
+        let reconcile = |expected: &str| {
+            let path = dir.join("expected.csv");
+            fs::write(&path, expected)?;
+            let command = Options::from_iter_safe([
+                "banking-exercise".as_ref(),
+                "reconcile".as_ref(),
+                input.as_ref(),
+                path.as_os_str(),
+            ])?
+            .command;
+            let Command::Reconcile(opts) = command else {
+                unreachable!()
+            };
+            let opts = opts.into_process();
+            assert!(outputs(&opts, false).is_empty(), "no accounts are written");
+            process_until(opts, false, Arc::default())
+        };
+
+        reconcile(
+            "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             2,2,0,2,false\n",
+        )?;
+        let err = reconcile(
+            "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             2,3,0,3,false\n",
+        )
+        .unwrap_err();
+        assert_eq!(
+            FailureClass::classify(err.as_ref()),
+            FailureClass::InvariantViolation
+        );
+        // Both the available and total funds of client 2 differ.
+        assert!(err.to_string().starts_with("2 mismatches found"), "{err}");
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::models::account::AccountId;
+
+// Descriptive details of an account that aren't derived from its transactions, which make reports
+// easier to consume than a bare client ID.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountMetadata {
+    pub name: Option<String>,
+    // The date the account was opened, as given in the metadata file, e.g. `2021-06-30`.
+    pub opened_at: Option<String>,
+    pub region: Option<String>,
+}
+
+impl AccountMetadata {
+    // The metadata of an account that has none, whose fields are all empty.
+    pub const EMPTY: Self = Self {
+        name: None,
+        opened_at: None,
+        region: None,
+    };
+}
+
+// The metadata of every account listed in a metadata file.
+#[derive(Clone, Debug, Default)]
+pub struct MetadataTable {
+    accounts: HashMap<AccountId, AccountMetadata>,
+}
+
+// A row of a metadata file, which lists the metadata of one account per row.
+#[derive(Deserialize)]
+struct MetadataRecord {
+    client: AccountId,
+    name: Option<String>,
+    opened_at: Option<String>,
+    region: Option<String>,
+}
+
+impl MetadataTable {
+    // Loads a CSV file with the columns `client`, `name`, `opened_at`, and `region`, any of which
+    // other than `client` may be left empty. Accounts without a row simply have no metadata.
+    pub fn load(path: &Path) -> csv::Result<Self> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .map(|record| {
+                let record: MetadataRecord = record?;
+                let metadata = AccountMetadata {
+                    name: record.name,
+                    opened_at: record.opened_at,
+                    region: record.region,
+                };
+                Ok((record.client, metadata))
+            })
+            .collect::<csv::Result<_>>()
+            .map(|accounts| Self { accounts })
+    }
+
+    // The metadata of the given account, which is empty if it isn't listed.
+    pub fn get(&self, account_id: AccountId) -> &AccountMetadata {
+        self.accounts
+            .get(&account_id)
+            .unwrap_or(&AccountMetadata::EMPTY)
+    }
+}
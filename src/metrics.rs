@@ -0,0 +1,289 @@
+use std::fmt::Write;
+
+use snafu::{ResultExt, Whatever};
+
+use crate::amounts::{self, AmountHistogram};
+
+use crate::stats::{
+    self, ProcessorStats, RunId, RunSummary, TenantStats, WorkerQueueStats, WorkerStats,
+};
+
+const JOB_NAME: &str = "banking_exercise";
+
+// Renders the run summary in the Prometheus text exposition format.
+pub fn render(summary: &RunSummary) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(&str, f64)]| {
+        write_gauge(&mut out, name, help, samples)
+    };
+
+    gauge(
+        "banking_run_started_timestamp_seconds",
+        "Time at which the run started, in seconds since the Unix epoch.",
+        &[("", summary.run_id.started_at as f64)],
+    );
+    gauge(
+        "banking_run_duration_seconds",
+        "Wall clock duration of the run.",
+        &[("", summary.duration.as_secs_f64())],
+    );
+    gauge(
+        "banking_records_read",
+        "Number of records read from the input.",
+        &[("", summary.records_read as f64)],
+    );
+    gauge(
+        "banking_transactions",
+        "Number of transactions by outcome.",
+        &[
+            ("{outcome=\"applied\"}", summary.applied as f64),
+            ("{outcome=\"rejected\"}", summary.rejected as f64),
+            ("{outcome=\"ignored\"}", summary.ignored as f64),
+            ("{outcome=\"errored\"}", summary.errored as f64),
+            ("{outcome=\"malformed\"}", summary.malformed as f64),
+        ],
+    );
+    gauge(
+        "banking_run_partial",
+        "Whether the run was interrupted before reading all of its input, leaving its results partial.",
+        &[("", f64::from(u8::from(summary.partial)))],
+    );
+    gauge(
+        "banking_throttled_seconds",
+        "Time reading the input was held up by the rate limits.",
+        &[("", summary.throttled.as_secs_f64())],
+    );
+    gauge(
+        "banking_accounts",
+        "Number of accounts at the end of the run.",
+        &[("", summary.accounts as f64)],
+    );
+    gauge(
+        "banking_locked_accounts",
+        "Number of accounts locked during the run.",
+        &[("", summary.locked_accounts as f64)],
+    );
+    // Each worker's stats are labelled with its index, to show how evenly the work was spread.
+    let worker_labels: Vec<_> = summary
+        .workers
+        .iter()
+        .map(|worker| format!("{{worker=\"{}\"}}", worker.worker_idx))
+        .collect();
+    let worker_samples = |value: fn(&WorkerStats) -> f64| -> Vec<(&str, f64)> {
+        worker_labels
+            .iter()
+            .map(String::as_str)
+            .zip(summary.workers.iter().map(value))
+            .collect()
+    };
+    gauge(
+        "banking_worker_transactions",
+        "Number of transactions processed by each worker.",
+        &worker_samples(|worker| worker.txns_processed as f64),
+    );
+    gauge(
+        "banking_worker_accounts",
+        "Number of accounts owned by each worker.",
+        &worker_samples(|worker| worker.accounts as f64),
+    );
+    gauge(
+        "banking_worker_rejected_transactions",
+        "Number of transactions each worker did not apply.",
+        &worker_samples(|worker| worker.rejected as f64),
+    );
+    gauge(
+        "banking_worker_busy_seconds",
+        "Time each worker spent applying transactions.",
+        &worker_samples(|worker| worker.busy.as_secs_f64()),
+    );
+    gauge(
+        "banking_worker_peak_queue_depth",
+        "Most messages queued for each worker at once.",
+        &worker_samples(|worker| worker.peak_queued as f64),
+    );
+    if let Some(skew) = stats::worker_skew(&summary.workers) {
+        gauge(
+            "banking_worker_skew_ratio",
+            "Transactions processed by the busiest worker relative to the mean.",
+            &[("", skew)],
+        );
+    }
+    // In a multi-tenant run, each tenant's stats are labelled with its name, so that every tenant can
+    // be monitored on its own. Tenant names need no escaping, as they're only made up of letters,
+    // digits, '-' and '_'.
+    if !summary.tenants.is_empty() {
+        let tenant_samples = |value: fn(&TenantStats) -> f64| -> Vec<(String, f64)> {
+            summary
+                .tenants
+                .iter()
+                .map(|tenant| (format!("{{tenant=\"{}\"}}", tenant.tenant), value(tenant)))
+                .collect()
+        };
+        let transactions: Vec<_> = summary
+            .tenants
+            .iter()
+            .flat_map(|tenant| {
+                [
+                    ("applied", tenant.applied),
+                    ("rejected", tenant.rejected),
+                    ("ignored", tenant.ignored),
+                    ("errored", tenant.errored),
+                ]
+                .map(|(outcome, count)| {
+                    (
+                        format!("{{tenant=\"{}\",outcome=\"{outcome}\"}}", tenant.tenant),
+                        count as f64,
+                    )
+                })
+            })
+            .collect();
+        gauge(
+            "banking_tenant_transactions",
+            "Number of transactions of each tenant by outcome.",
+            &borrowed(&transactions),
+        );
+        gauge(
+            "banking_tenant_accounts",
+            "Number of accounts of each tenant at the end of the run.",
+            &borrowed(&tenant_samples(|tenant| tenant.accounts as f64)),
+        );
+        gauge(
+            "banking_tenant_locked_accounts",
+            "Number of accounts of each tenant locked during the run.",
+            &borrowed(&tenant_samples(|tenant| tenant.locked_accounts as f64)),
+        );
+    }
+    if let Some(peak_memory_bytes) = summary.peak_memory_bytes {
+        gauge(
+            "banking_peak_memory_bytes",
+            "Peak resident memory of the process.",
+            &[("", peak_memory_bytes as f64)],
+        );
+    }
+    write_amount_histogram(
+        &mut out,
+        "banking_deposit_amount",
+        "Amounts of the deposits handed to the workers.",
+        &summary.amounts.deposits,
+    );
+    write_amount_histogram(
+        &mut out,
+        "banking_withdrawal_amount",
+        "Amounts of the withdrawals handed to the workers.",
+        &summary.amounts.withdrawals,
+    );
+
+    out
+}
+
+// Renders how far behind the workers of a running processor are in the Prometheus text exposition
+// format, so that a run that stalls shows where its transactions are piling up.
+pub fn render_processor(stats: &ProcessorStats) -> String {
+    let mut out = String::new();
+    let mut gauge = |name: &str, help: &str, samples: &[(&str, f64)]| {
+        write_gauge(&mut out, name, help, samples)
+    };
+
+    gauge(
+        "banking_next_order",
+        "Order that will be assigned to the next transaction submitted.",
+        &[("", stats.next_order as f64)],
+    );
+    gauge(
+        "banking_throttled_seconds",
+        "Time reading the input was held up by the rate limits.",
+        &[("", stats.throttled.as_secs_f64())],
+    );
+    gauge(
+        "banking_workers",
+        "Number of workers processing transactions.",
+        &[("", stats.workers.len() as f64)],
+    );
+    let worker_samples = |value: fn(&WorkerQueueStats) -> f64| -> Vec<(String, f64)> {
+        stats
+            .workers
+            .iter()
+            .map(|worker| {
+                (
+                    format!("{{worker=\"{}\"}}", worker.worker_idx),
+                    value(worker),
+                )
+            })
+            .collect()
+    };
+    gauge(
+        "banking_worker_queue_depth",
+        "Number of messages queued for each worker.",
+        &borrowed(&worker_samples(|worker| worker.queued as f64)),
+    );
+    gauge(
+        "banking_worker_peak_queue_depth",
+        "Most messages queued for each worker at once.",
+        &borrowed(&worker_samples(|worker| worker.peak_queued as f64)),
+    );
+    gauge(
+        "banking_worker_busy_seconds",
+        "Time each worker spent applying transactions.",
+        &borrowed(&worker_samples(|worker| worker.busy.as_secs_f64())),
+    );
+
+    out
+}
+
+fn write_gauge(out: &mut String, name: &str, help: &str, samples: &[(&str, f64)]) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} gauge");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+fn write_amount_histogram(out: &mut String, name: &str, help: &str, histogram: &AmountHistogram) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} histogram");
+    for (bucket, count) in histogram.cumulative_counts().enumerate() {
+        let bound = if bucket < amounts::NUM_BOUNDS {
+            amounts::bound(bucket).to_string()
+        } else {
+            "+Inf".to_owned()
+        };
+        let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}");
+    }
+    let _ = writeln!(out, "{name}_sum {}", histogram.sum());
+    let _ = writeln!(out, "{name}_count {}", histogram.count());
+}
+
+fn borrowed(samples: &[(String, f64)]) -> Vec<(&str, f64)> {
+    samples
+        .iter()
+        .map(|(labels, value)| (labels.as_str(), *value))
+        .collect()
+}
+
+// Pushes the run summary to a Prometheus pushgateway. Metrics are grouped by run ID, which is
+// attached to every metric as a `run_id` label, so runs don't replace each other's metrics.
+pub fn push_to_gateway(gateway_url: &str, summary: &RunSummary) -> Result<(), Whatever> {
+    push(gateway_url, summary.run_id, &render(summary))
+}
+
+// Pushes how far behind the workers of a running processor are to a Prometheus pushgateway, in the
+// same group as the run's summary, which replaces them once the run completes.
+pub fn push_processor_stats(
+    gateway_url: &str,
+    run_id: RunId,
+    stats: &ProcessorStats,
+) -> Result<(), Whatever> {
+    push(gateway_url, run_id, &render_processor(stats))
+}
+
+fn push(gateway_url: &str, run_id: RunId, metrics: &str) -> Result<(), Whatever> {
+    let url = format!(
+        "{}/metrics/job/{JOB_NAME}/run_id/{run_id}",
+        gateway_url.trim_end_matches('/'),
+    );
+    ureq::put(&url)
+        .set("Content-Type", "text/plain; version=0.0.4")
+        .send_string(metrics)
+        .with_whatever_context(|_| format!("unable to push metrics to {url}"))?;
+    Ok(())
+}
@@ -1,2 +1,4 @@
 pub mod account;
+pub mod csv_record;
+pub mod handler;
 pub mod transaction;
@@ -1,52 +1,186 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
 
-use derive_more::{Display, From, Into};
-use rust_decimal::Decimal;
+use derive_more::Display;
+use rust_decimal::{Decimal, RoundingStrategy};
 use serde::{
+    de,
     ser::{self, SerializeStruct},
-    Deserialize, Serialize,
+    Deserialize, Deserializer, Serialize,
 };
 use snafu::{OptionExt, Snafu};
 
-use crate::models::transaction::{Transaction, TransactionId, TransactionType};
+use crate::hash::{FastHashMap, FastHashSet};
+use crate::ledger::{Entry, Ledger, SubLedger};
+use crate::metadata::AccountMetadata;
+use crate::models::{
+    handler::TransactionHandlers,
+    transaction::{self, Transaction, TransactionId, TransactionType},
+};
 
 #[derive(Clone, Debug)]
 pub struct Account {
     id: AccountId,
-    available: Decimal,
-    held: Decimal,
+    // The tenant whose account space the account belongs to, in a multi-tenant run.
+    tenant: Option<Tenant>,
+    // The account's funds, which only move by posting balanced entries between its sub-ledgers,
+    // and the entries posted by the last transaction applied to it.
+    ledger: Ledger,
+    posted: Vec<Entry>,
     locked: bool,
-    txn_history: HashMap<TransactionId, Transaction>,
-    disputed_txns: HashMap<TransactionId, Decimal>,
+    // Past deposits and withdrawals by ID. Only their types are kept, as their IDs are the keys and
+    // their account is this one.
+    txn_history: FastHashMap<TransactionId, TransactionType>,
+    disputed_txns: FastHashMap<TransactionId, Decimal>,
+    // When the history is limited, the order in which transactions were recorded in it, and the
+    // IDs of those most recently evicted from it to make room for more, in the order they were
+    // evicted. Only as many evicted IDs are kept as the limit, so that they don't grow without
+    // bound either, and none at all if duplicates of them are detected some other way.
+    history_limit: Option<usize>,
+    keep_evicted_ids: bool,
+    history_order: VecDeque<TransactionId>,
+    evicted_txns: FastHashSet<TransactionId>,
+    evicted_order: VecDeque<TransactionId>,
+    // When duplicates are only detected within a window, the IDs of the transactions recorded
+    // within it in the order they were recorded, along with when they were posted if known, and
+    // the number of transactions forgotten since for falling outside of it, or for having been
+    // evicted from the history too long ago.
+    dedup_window: Option<DedupWindow>,
+    window_order: VecDeque<(TransactionId, Option<u64>)>,
+    forgotten_txns: u64,
+    txns_applied: u64,
+    lifetime_deposits: Decimal,
+    lifetime_withdrawals: Decimal,
+    last_order: Option<u64>,
+    last_applied_at: Option<u64>,
 }
 
 impl Account {
     pub fn new(id: AccountId) -> Self {
-        let available = Default::default();
-        let held = Default::default();
+        let ledger = Default::default();
         let locked = false;
         let txn_history = Default::default();
         let disputed_txns = Default::default();
+        let history_limit = None;
+        let keep_evicted_ids = true;
+        let history_order = Default::default();
+        let evicted_txns = Default::default();
+        let evicted_order = Default::default();
+        let dedup_window = None;
+        let window_order = Default::default();
+        let forgotten_txns = 0;
+        let txns_applied = 0;
+        let lifetime_deposits = Default::default();
+        let lifetime_withdrawals = Default::default();
+        let last_order = None;
+        let last_applied_at = None;
 
         Self {
             id,
-            available,
-            held,
+            tenant: None,
+            ledger,
+            posted: vec![],
             locked,
             txn_history,
             disputed_txns,
+            history_limit,
+            keep_evicted_ids,
+            history_order,
+            evicted_txns,
+            evicted_order,
+            dedup_window,
+            window_order,
+            forgotten_txns,
+            txns_applied,
+            lifetime_deposits,
+            lifetime_withdrawals,
+            last_order,
+            last_applied_at,
         }
     }
+
+    // Limits the number of past transactions held on to in case of future disputes. Beyond the
+    // limit, the least recently recorded transactions that aren't in dispute are evicted, and can
+    // no longer be disputed. Only their IDs are kept, so that duplicates are still detected, and
+    // only for as many transactions again as the limit. Those evicted before that are forgotten
+    // entirely, as though they had fallen outside of a dedup window, so a duplicate of one is
+    // applied as though it were new. A Bloom filter (`--dedup bloom`) still catches those.
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = Some(limit);
+        self
+    }
+
+    // Forgets transactions as soon as they're evicted from the history, keeping none of their IDs,
+    // for when duplicates of them are detected some other way, such as by a Bloom filter. A
+    // dispute of an evicted transaction is then ignored as not found, rather than errored.
+    pub fn without_evicted_ids(mut self) -> Self {
+        self.keep_evicted_ids = false;
+        self
+    }
+
+    // Only detects duplicate deposits and withdrawals within the given window, rather than for as
+    // long as the account is held. Transactions that fall outside of it are forgotten entirely,
+    // unless they're in dispute: they can no longer be disputed, and a later transaction reusing
+    // their ID is applied as a new one. This bounds the memory an account holds on to, which is
+    // otherwise dominated by the IDs of its past deposits.
+    pub fn with_dedup_window(mut self, window: DedupWindow) -> Self {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    // Makes room in the history up front for the given number of transactions, so that it isn't
+    // grown over and over again as they're applied.
+    pub fn with_history_capacity(mut self, capacity: usize) -> Self {
+        self.txn_history.reserve(capacity);
+        self
+    }
+
+    // Places the account in the given tenant's account space.
+    pub fn with_tenant(mut self, tenant: Option<Tenant>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
     pub fn id(&self) -> AccountId {
         self.id
     }
 
+    pub fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    // Identifies the account among those of every tenant.
+    pub fn key(&self) -> AccountKey {
+        (self.tenant, self.id)
+    }
+
     pub fn available(&self) -> Decimal {
-        self.available
+        self.ledger.balance(SubLedger::Available)
     }
 
     pub fn held(&self) -> Decimal {
-        self.held
+        self.ledger.balance(SubLedger::Held)
+    }
+
+    // The funds reversed out of the account by chargebacks.
+    pub fn charged_back(&self) -> Decimal {
+        self.ledger.balance(SubLedger::ChargebackExpense)
+    }
+
+    pub fn ledger(&self) -> &Ledger {
+        &self.ledger
+    }
+
+    // The entries posted by the last transaction applied to the account, in the order they were
+    // posted.
+    pub fn last_entries(&self) -> &[Entry] {
+        &self.posted
+    }
+
+    fn post(&mut self, entry: Entry) {
+        self.ledger.post(entry);
+        self.posted.push(entry);
     }
 
     pub fn total(&self) -> Decimal {
@@ -57,7 +191,172 @@ impl Account {
         self.locked
     }
 
+    // The number of transactions of any type that have been successfully applied to the account.
+    pub fn transactions_applied(&self) -> u64 {
+        self.txns_applied
+    }
+
+    pub fn open_disputes(&self) -> usize {
+        self.disputed_txns.len()
+    }
+
+    // The amount on hold for the given transaction, if it is currently in dispute.
+    pub fn disputed_amount(&self, txn_id: TransactionId) -> Option<Decimal> {
+        self.disputed_txns.get(&txn_id).copied()
+    }
+
+    // The amount on hold across every transaction currently in dispute.
+    pub fn disputed_total(&self) -> Decimal {
+        self.disputed_txns.values().sum()
+    }
+
+    // The number of past transactions held on to in case of future disputes.
+    pub fn recorded_txns(&self) -> usize {
+        self.txn_history.len()
+    }
+
+    // The number of past transactions evicted from the history to stay within its limit, whose IDs
+    // are still kept to detect duplicates of them.
+    pub fn evicted_txns(&self) -> usize {
+        self.evicted_txns.len()
+    }
+
+    pub fn history_limit(&self) -> Option<usize> {
+        self.history_limit
+    }
+
+    pub fn dedup_window(&self) -> Option<DedupWindow> {
+        self.dedup_window
+    }
+
+    // The number of past transactions forgotten for falling outside of the dedup window, or for
+    // having been evicted from the history too long ago.
+    pub fn forgotten_txns(&self) -> u64 {
+        self.forgotten_txns
+    }
+
+    // Whether the given transaction has been applied to the account, even if it has since been
+    // evicted from the history.
+    pub fn has_applied(&self, txn_id: TransactionId) -> bool {
+        self.txn_history.contains_key(&txn_id) || self.evicted_txns.contains(&txn_id)
+    }
+
+    pub fn lifetime_deposits(&self) -> Decimal {
+        self.lifetime_deposits
+    }
+
+    pub fn lifetime_withdrawals(&self) -> Decimal {
+        self.lifetime_withdrawals
+    }
+
+    // The order of the last transaction applied to the account, if known.
+    pub fn last_order(&self) -> Option<u64> {
+        self.last_order
+    }
+
+    // When the last transaction was applied to the account, in milliseconds since the Unix epoch,
+    // if known.
+    pub fn last_applied_at(&self) -> Option<u64> {
+        self.last_applied_at
+    }
+
+    // Records the order of the last transaction applied to the account, and when it was applied.
+    // An account has no notion of either itself, so this is up to whoever is applying them.
+    //
+    // This is also what ages transactions out of a dedup window of time, by when they were posted
+    // rather than when they happened to be applied, so that how far apart duplicates are is judged
+    // the same however fast the input is processed. A transaction without a time of its own is
+    // taken to have been posted with the last one that had one, so it doesn't move time on.
+    pub fn record_applied(&mut self, order: u64, applied_at: u64, posted_at: Option<u64>) {
+        self.last_order = Some(order);
+        self.last_applied_at = Some(applied_at);
+
+        let Some(posted_at) = posted_at.or_else(|| {
+            self.window_order
+                .iter()
+                .rev()
+                .find_map(|(_, recorded_at)| *recorded_at)
+        }) else {
+            return;
+        };
+        for (_, recorded_at) in self.window_order.iter_mut().rev() {
+            if recorded_at.is_some() {
+                break;
+            }
+            *recorded_at = Some(posted_at);
+        }
+        self.forget_outside_window(Some(posted_at));
+    }
+
+    // Looks up a past Deposit or Withdrawal transaction that was applied to this account.
+    pub fn past_txn(&self, txn_id: TransactionId) -> Option<Transaction> {
+        self.txn_history
+            .get(&txn_id)
+            .map(|txn_type| Transaction::new(txn_id, self.id, txn_type.clone()))
+    }
+
+    // Adds funds to the account's available balance.
+    pub fn credit(&mut self, amount: Decimal) {
+        self.post(Entry::new(
+            SubLedger::Suspense,
+            SubLedger::Available,
+            amount,
+        ));
+    }
+
+    // Removes funds from the account's available balance, provided there are enough available.
+    pub fn debit(&mut self, amount: Decimal) -> Result<(), TransactionError> {
+        snafu::ensure!(
+            self.available() >= amount,
+            InsufficientFundsSnafu {
+                id: self.id,
+                available: self.available(),
+                needed: amount
+            }
+        );
+
+        self.post(Entry::new(
+            SubLedger::Available,
+            SubLedger::Suspense,
+            amount,
+        ));
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    // A view of the account that serializes it in the given format.
+    pub fn output(&self, format: OutputFormat) -> AccountOutput<'_> {
+        AccountOutput {
+            account: self,
+            format,
+            metadata: None,
+        }
+    }
+
+    // A view of the account that serializes its balances rounded to exactly `decimal_places`
+    // decimal places, with midpoints rounded away from zero.
+    pub fn rounded(&self, decimal_places: u32) -> AccountOutput<'_> {
+        self.output(OutputFormat {
+            decimal_places: Some(decimal_places),
+            extended: false,
+            tenant: false,
+        })
+    }
+
     pub fn process_txn(&mut self, txn: Transaction) -> Result<(), TransactionError> {
+        self.process_txn_with_handlers(txn, &TransactionHandlers::default())
+    }
+
+    // Processes a transaction, delegating any custom transaction types to their registered
+    // handler.
+    pub fn process_txn_with_handlers(
+        &mut self,
+        txn: Transaction,
+        handlers: &TransactionHandlers,
+    ) -> Result<(), TransactionError> {
         use TransactionType::*;
 
         let span = tracing::debug_span!(
@@ -67,11 +366,12 @@ impl Account {
             txn_type = %txn.txn_type(),
         );
         let _enter = span.enter();
+        self.posted.clear();
 
         // If the provided transaction is not intended for our account, then we should not process
         // it.
         snafu::ensure!(
-            self.id == txn.account_id(),
+            self.key() == txn.account_key(),
             WrongAccountSnafu {
                 id: self.id,
                 intended_account: txn.account_id(),
@@ -83,8 +383,8 @@ impl Account {
         snafu::ensure!(!self.locked, AccountLockedSnafu { id: self.id });
 
         tracing::debug!(
-            available = %self.available,
-            held = %self.held,
+            available = %self.available(),
+            held = %self.held(),
             total = %self.total(),
             locked = self.locked,
             "preparing to process transaction..."
@@ -95,24 +395,30 @@ impl Account {
             Deposit { amount } => {
                 // For a Deposit, it is not expected to have already seen this transaction ID.
                 snafu::ensure!(
-                    !self.txn_history.contains_key(&txn.id()),
+                    !self.has_applied(txn.id()),
                     TransactionAlreadyProcessedSnafu {
                         id: self.id,
                         txn_id: txn.id(),
                     },
                 );
 
-                // Deposits will increase the available funds for the account.
-                self.available += amount;
+                // Deposits will increase the available funds for the account, as they arrive from
+                // outside of it.
+                self.post(Entry::new(
+                    SubLedger::Suspense,
+                    SubLedger::Available,
+                    amount,
+                ));
+                self.lifetime_deposits += amount;
 
                 // Store the transaction in case of future disputes.
-                self.txn_history.insert(txn.id(), txn);
+                self.record_txn(txn);
             }
 
             Withdrawal { amount } => {
                 // For a Withdrawal, it is not expected to have already seen this transaction ID.
                 snafu::ensure!(
-                    !self.txn_history.contains_key(&txn.id()),
+                    !self.has_applied(txn.id()),
                     TransactionAlreadyProcessedSnafu {
                         id: self.id,
                         txn_id: txn.id(),
@@ -122,18 +428,23 @@ impl Account {
                 // Withdrawals will decrease the available funds for the account. However, if there
                 // are not enough available funds, the transaction will fail.
                 snafu::ensure!(
-                    self.available >= amount,
+                    self.available() >= amount,
                     InsufficientFundsSnafu {
                         id: self.id,
-                        available: self.available,
+                        available: self.available(),
                         needed: amount
                     }
                 );
 
-                self.available -= amount;
+                self.post(Entry::new(
+                    SubLedger::Available,
+                    SubLedger::Suspense,
+                    amount,
+                ));
+                self.lifetime_withdrawals += amount;
 
                 // Store the transaction in case of future disputes.
-                self.txn_history.insert(txn.id(), txn);
+                self.record_txn(txn);
             }
 
             Dispute => {
@@ -171,22 +482,31 @@ impl Account {
                     }
                 );
 
+                // A transaction that has been evicted from our history can no longer be disputed.
+                snafu::ensure!(
+                    !self.evicted_txns.contains(&txn.id()),
+                    HistoryEvictedSnafu {
+                        id: self.id,
+                        txn_id: txn.id()
+                    }
+                );
+
                 // Attempt to lookup this transaction in our history of Deposits and Withdrawals.
-                let past_txn =
+                let past_txn_type =
                     self.txn_history
                         .get(&txn.id())
+                        .cloned()
                         .context(TransactionNotFoundSnafu {
                             id: self.id,
                             txn_id: txn.id(),
                         })?;
 
-                match past_txn.txn_type() {
+                match past_txn_type {
                     Deposit { amount } | Withdrawal { amount } => {
                         // For disputing a transaction, we'll take the funds from the account's
                         // available funds and put them on hold.
-                        self.available -= amount;
-                        self.held += amount;
-                        self.disputed_txns.insert(past_txn.id(), amount);
+                        self.post(Entry::new(SubLedger::Available, SubLedger::Held, amount));
+                        self.disputed_txns.insert(txn.id(), amount);
                     }
 
                     _ => (),
@@ -205,8 +525,11 @@ impl Account {
 
                 // For resolving a dispute, we'll restore funds to an account's
                 // available balance.
-                self.available += disputed_amount;
-                self.held -= disputed_amount;
+                self.post(Entry::new(
+                    SubLedger::Held,
+                    SubLedger::Available,
+                    disputed_amount,
+                ));
             }
 
             Chargeback => {
@@ -221,24 +544,151 @@ impl Account {
 
                 // For finalizing a dispute via a chargeback, we'll remove the disputed funds on
                 // hold in the account.
-                self.held -= disputed_amount;
+                self.post(Entry::new(
+                    SubLedger::Held,
+                    SubLedger::ChargebackExpense,
+                    disputed_amount,
+                ));
                 self.locked = true;
             }
+
+            Custom { name, .. } => {
+                // Transaction types that we don't natively understand are handed off to the
+                // handler registered for them. Without one, we have no idea how to apply it.
+                let handler = handlers.get(&name).context(UnknownTransactionTypeSnafu {
+                    id: self.id,
+                    txn_id: txn.id(),
+                    txn_type: &*name,
+                })?;
+                handler.apply(self, &txn)?;
+            }
         }
 
         // Note: For this exercise, only transactions that are Deposits or Withdrawals are recorded
-        // for future reference. However, for audit purposes it would be good practice to record all
-        // transaction types and whether or not they were successfully committed.
+        // for future reference. For audit purposes, all transaction types and whether or not they
+        // were successfully committed can be recorded by the `Journal` observer.
+
+        self.txns_applied += 1;
 
         tracing::debug!(
-            available = %self.available,
-            held = %self.held,
+            available = %self.available(),
+            held = %self.held(),
             total = %self.total(),
             locked = self.locked,
             "transaction successfully applied"
         );
         Ok(())
     }
+
+    // Records a transaction in the history, evicting the least recently recorded transactions that
+    // aren't in dispute if that takes the history beyond its limit. Transactions in dispute are
+    // treated as though they were recorded anew.
+    fn record_txn(&mut self, txn: Transaction) {
+        self.txn_history.insert(txn.id(), txn.txn_type());
+        self.record_in_window(txn.id());
+        let Some(limit) = self.history_limit else {
+            return;
+        };
+
+        self.history_order.push_back(txn.id());
+        let mut candidates = self.history_order.len();
+        while self.txn_history.len() > limit && candidates > 0 {
+            candidates -= 1;
+            let Some(txn_id) = self.history_order.pop_front() else {
+                break;
+            };
+            if self.disputed_txns.contains_key(&txn_id) {
+                self.history_order.push_back(txn_id);
+                continue;
+            }
+
+            // Transactions that have since been forgotten are no longer in the history at all.
+            if self.txn_history.remove(&txn_id).is_some() {
+                self.evict(txn_id, limit);
+            }
+        }
+    }
+
+    // Keeps the ID of a transaction evicted from the history, forgetting the least recently
+    // evicted once more than the limit are kept.
+    fn evict(&mut self, txn_id: TransactionId, limit: usize) {
+        if !self.keep_evicted_ids {
+            self.forgotten_txns += 1;
+            return;
+        }
+        self.evicted_txns.insert(txn_id);
+        self.evicted_order.push_back(txn_id);
+        while self.evicted_txns.len() > limit {
+            let Some(txn_id) = self.evicted_order.pop_front() else {
+                break;
+            };
+            // Transactions forgotten for falling outside of the dedup window are already gone.
+            if self.evicted_txns.remove(&txn_id) {
+                self.forgotten_txns += 1;
+            }
+        }
+
+        // Likewise, those forgotten are left in the order of eviction until they'd be dropped, so
+        // clear them out should they come to outnumber those still kept.
+        if self.evicted_order.len() > 2 * self.evicted_txns.len().max(1) {
+            let evicted_txns = &self.evicted_txns;
+            self.evicted_order
+                .retain(|txn_id| evicted_txns.contains(txn_id));
+        }
+    }
+
+    // Records a transaction in the dedup window, forgetting those that no longer fit in it.
+    fn record_in_window(&mut self, txn_id: TransactionId) {
+        if self.dedup_window.is_none() {
+            return;
+        }
+        self.window_order.push_back((txn_id, None));
+        self.forget_outside_window(None);
+
+        // Forgotten transactions are left in the order of the history until they'd be evicted,
+        // so clear them out should they come to outnumber those still in it.
+        if self.history_order.len() > 2 * self.txn_history.len() {
+            let txn_history = &self.txn_history;
+            self.history_order
+                .retain(|txn_id| txn_history.contains_key(txn_id));
+        }
+    }
+
+    // Forgets the least recently recorded transactions that fall outside of the dedup window as of
+    // `now`, if known. Transactions in dispute are treated as though they were recorded anew.
+    fn forget_outside_window(&mut self, now: Option<u64>) {
+        let Some(window) = self.dedup_window else {
+            return;
+        };
+
+        let mut candidates = self.window_order.len();
+        while candidates > 0 {
+            let Some(&(txn_id, recorded_at)) = self.window_order.front() else {
+                break;
+            };
+            let outside = match window {
+                DedupWindow::Transactions(count) => self.window_order.len() > count,
+                DedupWindow::Millis(millis) => match (recorded_at, now) {
+                    (Some(recorded_at), Some(now)) => now.saturating_sub(recorded_at) > millis,
+                    _ => false,
+                },
+            };
+            if !outside {
+                break;
+            }
+
+            candidates -= 1;
+            self.window_order.pop_front();
+            if self.disputed_txns.contains_key(&txn_id) {
+                self.window_order.push_back((txn_id, now.or(recorded_at)));
+                continue;
+            }
+
+            self.txn_history.remove(&txn_id);
+            self.evicted_txns.remove(&txn_id);
+            self.forgotten_txns += 1;
+        }
+    }
 }
 
 impl ser::Serialize for Account {
@@ -246,40 +696,478 @@ impl ser::Serialize for Account {
     where
         S: ser::Serializer,
     {
-        let mut s = serializer.serialize_struct("Account", 5)?;
-        s.serialize_field("client", &self.id())?;
-        s.serialize_field("available", &self.available())?;
-        s.serialize_field("held", &self.held())?;
-        s.serialize_field("total", &self.total())?;
-        s.serialize_field("locked", &self.locked())?;
+        self.output(OutputFormat::default()).serialize(serializer)
+    }
+}
+
+// Accounts are deserialized from their complete state, as given by `Account::to_parts`, rather
+// than from the output they are serialized to, which leaves out their history.
+impl<'de> Deserialize<'de> for Account {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Account::from_parts(AccountParts::deserialize(deserializer)?).map_err(de::Error::custom)
+    }
+}
+
+// The version of `AccountParts` written by this release. Older versions are upgraded as they are
+// read, and newer ones are refused.
+pub const ACCOUNT_PARTS_VERSION: u32 = 1;
+
+// The complete state of an account, in a stable representation for persisting the account and
+// restoring it later, e.g. in snapshots and checkpoints.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AccountParts {
+    pub version: u32,
+    pub client: AccountId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<Tenant>,
+    pub available: Decimal,
+    pub held: Decimal,
+    // The funds reversed out of the account by chargebacks, which older states don't have.
+    #[serde(default, skip_serializing_if = "Decimal::is_zero")]
+    pub chargebacks: Decimal,
+    pub locked: bool,
+    // The past transactions that may yet be disputed, in the order they were recorded.
+    pub history: Vec<Transaction>,
+    pub disputes: Vec<DisputeParts>,
+    pub history_limit: Option<usize>,
+    // The IDs kept of transactions evicted from the history, in the order they were evicted.
+    pub evicted: Vec<TransactionId>,
+    // The transactions within the dedup window, in the order they were recorded. Older states
+    // have neither.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dedup_window: Option<DedupWindow>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub window: Vec<WindowParts>,
+    #[serde(default)]
+    pub forgotten: u64,
+    pub transactions_applied: u64,
+    pub lifetime_deposits: Decimal,
+    pub lifetime_withdrawals: Decimal,
+    pub last_order: Option<u64>,
+    pub last_applied_at: Option<u64>,
+}
+
+// A transaction within the account's dedup window, and when it was posted if known. The time is
+// named for when it was applied, which windows went by before.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct WindowParts {
+    pub tx: TransactionId,
+    pub applied_at: Option<u64>,
+}
+
+// A transaction in the account's history that is currently in dispute, and the amount held for it.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct DisputeParts {
+    pub tx: TransactionId,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Snafu)]
+pub enum AccountPartsError {
+    #[snafu(display("Account state version {version} is not supported; the latest supported version is {ACCOUNT_PARTS_VERSION}"))]
+    UnsupportedVersion { version: u32 },
+
+    #[snafu(display("The account with ID {id} has transaction ID {txn_id} in its history, which is for account {owner}"))]
+    ForeignTransaction {
+        id: AccountId,
+        txn_id: TransactionId,
+        owner: AccountId,
+    },
+
+    #[snafu(display("The account with ID {id} has transaction ID {txn_id} in dispute, which is not in its history"))]
+    DisputeNotInHistory {
+        id: AccountId,
+        txn_id: TransactionId,
+    },
+}
+
+impl Account {
+    // Breaks the account down into its complete state. See `AccountParts`.
+    pub fn to_parts(&self) -> AccountParts {
+        // The order transactions were recorded in is only kept track of when the history is
+        // limited. Otherwise, they are given in order of ID, so that the parts are stable.
+        let history = if self.history_order.is_empty() {
+            let mut history: Vec<_> = self
+                .txn_history
+                .keys()
+                .filter_map(|&txn_id| self.past_txn(txn_id))
+                .collect();
+            history.sort_by_key(|txn| txn.id());
+            history
+        } else {
+            self.history_order
+                .iter()
+                .filter_map(|&txn_id| self.past_txn(txn_id))
+                .collect()
+        };
+        let mut disputes: Vec<_> = self
+            .disputed_txns
+            .iter()
+            .map(|(&tx, &amount)| DisputeParts { tx, amount })
+            .collect();
+        disputes.sort_by_key(|dispute| dispute.tx);
+        let evicted = self
+            .evicted_order
+            .iter()
+            .filter(|txn_id| self.evicted_txns.contains(*txn_id))
+            .copied()
+            .collect();
+
+        AccountParts {
+            version: ACCOUNT_PARTS_VERSION,
+            client: self.id,
+            tenant: self.tenant,
+            available: self.available(),
+            held: self.held(),
+            chargebacks: self.charged_back(),
+            locked: self.locked,
+            history,
+            disputes,
+            history_limit: self.history_limit,
+            evicted,
+            dedup_window: self.dedup_window,
+            window: self
+                .window_order
+                .iter()
+                .map(|&(tx, applied_at)| WindowParts { tx, applied_at })
+                .collect(),
+            forgotten: self.forgotten_txns,
+            transactions_applied: self.txns_applied,
+            lifetime_deposits: self.lifetime_deposits,
+            lifetime_withdrawals: self.lifetime_withdrawals,
+            last_order: self.last_order,
+            last_applied_at: self.last_applied_at,
+        }
+    }
+
+    // Restores an account from its complete state, as given by `to_parts`.
+    pub fn from_parts(parts: AccountParts) -> Result<Self, AccountPartsError> {
+        snafu::ensure!(
+            parts.version <= ACCOUNT_PARTS_VERSION,
+            UnsupportedVersionSnafu {
+                version: parts.version
+            }
+        );
+
+        let id = parts.client;
+        if let Some(txn) = parts.history.iter().find(|txn| txn.account_id() != id) {
+            return ForeignTransactionSnafu {
+                id,
+                txn_id: txn.id(),
+                owner: txn.account_id(),
+            }
+            .fail();
+        }
+        let txn_history: FastHashMap<_, _> = parts
+            .history
+            .iter()
+            .map(|txn| (txn.id(), txn.txn_type()))
+            .collect();
+        if let Some(dispute) = parts
+            .disputes
+            .iter()
+            .find(|dispute| !txn_history.contains_key(&dispute.tx))
+        {
+            return DisputeNotInHistorySnafu {
+                id,
+                txn_id: dispute.tx,
+            }
+            .fail();
+        }
+
+        let history_order = match parts.history_limit {
+            Some(_) => parts.history.iter().map(|txn| txn.id()).collect(),
+            None => VecDeque::new(),
+        };
+
+        Ok(Self {
+            id,
+            tenant: parts.tenant,
+            ledger: Ledger::from_balances(parts.available, parts.held, parts.chargebacks),
+            posted: vec![],
+            locked: parts.locked,
+            txn_history,
+            disputed_txns: parts
+                .disputes
+                .into_iter()
+                .map(|dispute| (dispute.tx, dispute.amount))
+                .collect(),
+            history_limit: parts.history_limit,
+            keep_evicted_ids: true,
+            history_order,
+            evicted_txns: parts.evicted.iter().copied().collect(),
+            evicted_order: parts.evicted.into_iter().collect(),
+            dedup_window: parts.dedup_window,
+            window_order: parts
+                .window
+                .into_iter()
+                .map(|window| (window.tx, window.applied_at))
+                .collect(),
+            forgotten_txns: parts.forgotten,
+            txns_applied: parts.transactions_applied,
+            lifetime_deposits: parts.lifetime_deposits,
+            lifetime_withdrawals: parts.lifetime_withdrawals,
+            last_order: parts.last_order,
+            last_applied_at: parts.last_applied_at,
+        })
+    }
+}
+
+// Controls how an account is rendered in the output of a run.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct OutputFormat {
+    // Rounds amounts to exactly this many decimal places, with midpoints rounded away from zero.
+    pub decimal_places: Option<u32>,
+    // Includes the account's activity alongside its balances.
+    pub extended: bool,
+    // Leads with the tenant the account belongs to, for the accounts of many tenants written
+    // together.
+    pub tenant: bool,
+}
+
+pub struct AccountOutput<'a> {
+    account: &'a Account,
+    format: OutputFormat,
+    metadata: Option<&'a AccountMetadata>,
+}
+
+impl<'a> AccountOutput<'a> {
+    // Includes the account's metadata after the rest of its fields.
+    pub fn with_metadata(mut self, metadata: &'a AccountMetadata) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+
+    fn round(&self, amount: Decimal) -> Decimal {
+        let Some(decimal_places) = self.format.decimal_places else {
+            return amount;
+        };
+
+        let mut amount =
+            amount.round_dp_with_strategy(decimal_places, RoundingStrategy::MidpointAwayFromZero);
+        // Pad out any amounts with fewer decimal places than requested.
+        amount.rescale(decimal_places);
+        amount
+    }
+}
+
+impl ser::Serialize for AccountOutput<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let account = self.account;
+        let len = if self.format.extended { 11 } else { 5 }
+            + if self.metadata.is_some() { 3 } else { 0 }
+            + usize::from(self.format.tenant);
+        let mut s = serializer.serialize_struct("Account", len)?;
+        if self.format.tenant {
+            s.serialize_field("tenant", &account.tenant())?;
+        }
+        s.serialize_field("client", &account.id())?;
+        s.serialize_field("available", &self.round(account.available()))?;
+        s.serialize_field("held", &self.round(account.held()))?;
+        s.serialize_field("total", &self.round(account.total()))?;
+        s.serialize_field("locked", &account.locked())?;
+        if self.format.extended {
+            s.serialize_field("transactions_applied", &account.transactions_applied())?;
+            s.serialize_field("open_disputes", &account.open_disputes())?;
+            s.serialize_field(
+                "lifetime_deposits",
+                &self.round(account.lifetime_deposits()),
+            )?;
+            s.serialize_field(
+                "lifetime_withdrawals",
+                &self.round(account.lifetime_withdrawals()),
+            )?;
+            s.serialize_field("last_order", &account.last_order())?;
+            s.serialize_field("last_applied_at", &account.last_applied_at())?;
+        }
+        if let Some(metadata) = self.metadata {
+            s.serialize_field("name", &metadata.name)?;
+            s.serialize_field("opened_at", &metadata.opened_at)?;
+            s.serialize_field("region", &metadata.region)?;
+        }
         s.end()
     }
 }
 
-#[derive(
-    Clone,
-    Copy,
-    Debug,
-    Deserialize,
-    Display,
-    Eq,
-    From,
-    Hash,
-    Into,
-    PartialEq,
-    PartialOrd,
-    Ord,
-    Serialize,
-)]
-#[display(fmt = "{_0}")]
+// Identifies a client's account. Most feeds identify clients by number, but some use alphanumeric
+// identifiers such as IBANs instead, which are interned so that account IDs remain cheap to copy.
+// A numeric ID and an alphanumeric one are never the same account, even if they look alike.
+#[derive(Clone, Copy, Debug, Display, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[serde(untagged)]
+pub enum AccountId {
+    #[display(fmt = "{_0}")]
+    Number(RawAccountId),
+    #[display(fmt = "{_0}")]
+    Name(&'static str),
+}
+
+impl AccountId {
+    // Creates an alphanumeric account ID. IDs made up only of digits are numeric IDs, and must fit
+    // within `RawAccountId`.
+    pub fn parse(id: &str) -> Result<Self, String> {
+        if id.is_empty() {
+            return Err("an account ID cannot be empty".to_string());
+        }
+        if id.bytes().all(|b| b.is_ascii_digit()) {
+            return id
+                .parse()
+                .map(Self::Number)
+                .map_err(|_| format!("the account ID {id} is too large"));
+        }
+        if !id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(format!(
+                "the account ID '{id}' may only contain letters, digits, '-' and '_'"
+            ));
+        }
+        Ok(Self::Name(transaction::intern(id)))
+    }
+
+    // The account's number, unless it is identified by an alphanumeric ID.
+    pub fn number(&self) -> Option<RawAccountId> {
+        match self {
+            Self::Number(number) => Some(*number),
+            Self::Name(_) => None,
+        }
+    }
+}
+
+impl From<RawAccountId> for AccountId {
+    fn from(number: RawAccountId) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl<'de> Deserialize<'de> for AccountId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct AccountIdVisitor;
+
+        impl de::Visitor<'_> for AccountIdVisitor {
+            type Value = AccountId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a numeric or alphanumeric account ID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                RawAccountId::try_from(v)
+                    .map(AccountId::Number)
+                    .map_err(|_| E::custom(format!("the account ID {v} is too large")))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+                    .and_then(|v| self.visit_u64(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                AccountId::parse(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(AccountIdVisitor)
+    }
+}
+
+// Identifies the account space of one of the tenants of a multi-tenant run, each of which is kept
+// fully isolated from the others, e.g. a partner in a sandbox of its own. Tenant names are used in
+// the names of files, so are limited to letters, digits, '-' and '_', and are interned so that
+// transactions remain cheap to copy.
+#[derive(Clone, Copy, Debug, Display, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
 #[serde(transparent)]
-pub struct AccountId(u16);
+pub struct Tenant(&'static str);
+
+impl Tenant {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        if name.is_empty() {
+            return Err("a tenant cannot be empty".to_string());
+        }
+        if !name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        {
+            return Err(format!(
+                "the tenant '{name}' may only contain letters, digits, '-' and '_'"
+            ));
+        }
+        Ok(Self(transaction::intern(name)))
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl FromStr for Tenant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl<'de> Deserialize<'de> for Tenant {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TenantVisitor;
+
+        impl de::Visitor<'_> for TenantVisitor {
+            type Value = Tenant;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "the name of a tenant")
+            }
+
+            // Tenants named only with digits are read as numbers by formats that infer types.
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                self.visit_str(&v.to_string())
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                Tenant::parse(v).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(TenantVisitor)
+    }
+}
+
+// Identifies an account among those of every tenant. Accounts that belong to no tenant are those
+// of a run that isn't multi-tenant.
+pub type AccountKey = (Option<Tenant>, AccountId);
+
+// The integer type that client IDs are represented by, which is widened from 16 to 32 bits by the
+// `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type RawAccountId = u16;
+#[cfg(feature = "wide-ids")]
+pub type RawAccountId = u32;
 
 #[derive(Debug, Snafu)]
 pub enum TransactionError {
     #[snafu(display("The account with ID {id} is currently locked"))]
     AccountLocked { id: AccountId },
 
+    #[snafu(display("The account with ID {id} can no longer dispute transaction ID {txn_id} as it has been evicted from the account's history"))]
+    HistoryEvicted {
+        id: AccountId,
+        txn_id: TransactionId,
+    },
+
     #[snafu(display("The account with ID {id} has insufficient funds; funds available: {available}, funds needed: {needed}"))]
     InsufficientFunds {
         id: AccountId,
@@ -299,6 +1187,19 @@ pub enum TransactionError {
         txn_id: TransactionId,
     },
 
+    #[snafu(display("The account with ID {id} cannot use transaction ID {txn_id} as it has already been used by the account with ID {owner}"))]
+    TransactionIdInUse {
+        id: AccountId,
+        txn_id: TransactionId,
+        owner: AccountId,
+    },
+
+    #[snafu(display("The account with ID {id} turned away transaction ID {txn_id} as the account's transactions are arriving faster than its rate limit"))]
+    RateLimited {
+        id: AccountId,
+        txn_id: TransactionId,
+    },
+
     #[snafu(display("The account with ID {id} had no past transaction with the ID {txn_id}"))]
     TransactionNotFound {
         id: AccountId,
@@ -313,6 +1214,13 @@ pub enum TransactionError {
         txn_id: TransactionId,
     },
 
+    #[snafu(display("The account with ID {id} could not process the transaction ID {txn_id} as there is no handler for transactions of type '{txn_type}'"))]
+    UnknownTransactionType {
+        id: AccountId,
+        txn_id: TransactionId,
+        txn_type: String,
+    },
+
     #[snafu(display("The account with ID {id} could not process the transaction ID {txn_id} as it is intended for account {intended_account}"))]
     WrongAccount {
         id: AccountId,
@@ -321,11 +1229,134 @@ pub enum TransactionError {
     },
 }
 
+impl TransactionError {
+    // A stable, machine-readable identifier for the kind of error, suitable for structured logs and
+    // reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::AccountLocked { .. } => "account_locked",
+            Self::HistoryEvicted { .. } => "history_evicted",
+            Self::InsufficientFunds { .. } => "insufficient_funds",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::TransactionAlreadyInDispute { .. } => "transaction_already_in_dispute",
+            Self::TransactionAlreadyProcessed { .. } => "transaction_already_processed",
+            Self::TransactionIdInUse { .. } => "transaction_id_in_use",
+            Self::TransactionNotFound { .. } => "transaction_not_found",
+            Self::TransactionNotInDispute { .. } => "transaction_not_in_dispute",
+            Self::UnknownTransactionType { .. } => "unknown_transaction_type",
+            Self::WrongAccount { .. } => "wrong_account",
+        }
+    }
+
+    // How the transaction that caused the error should be treated when reporting on a run.
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            Self::AccountLocked { .. }
+            | Self::InsufficientFunds { .. }
+            | Self::RateLimited { .. }
+            | Self::TransactionIdInUse { .. } => ErrorClass::Rejected,
+            Self::TransactionAlreadyInDispute { .. }
+            | Self::TransactionAlreadyProcessed { .. }
+            | Self::TransactionNotFound { .. }
+            | Self::TransactionNotInDispute { .. } => ErrorClass::Ignored,
+            Self::HistoryEvicted { .. }
+            | Self::UnknownTransactionType { .. }
+            | Self::WrongAccount { .. } => ErrorClass::Errored,
+        }
+    }
+}
+
+// The classes of outcome for a transaction that was not applied to an account.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorClass {
+    // The transaction broke one of the account's business rules, e.g. a withdrawal with
+    // insufficient funds.
+    Rejected,
+
+    // The transaction was redundant or referred to something that does not exist, e.g. a duplicate
+    // deposit or a dispute of an unknown transaction. The account is unaffected.
+    Ignored,
+
+    // The transaction could not be processed due to a problem on our side, e.g. it was routed to
+    // the wrong account or there is no handler for its type.
+    Errored,
+}
+
+impl ErrorClass {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Rejected => "rejected",
+            Self::Ignored => "ignored",
+            Self::Errored => "errored",
+        }
+    }
+}
+
+impl fmt::Display for ErrorClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ErrorClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rejected" => Ok(Self::Rejected),
+            "ignored" => Ok(Self::Ignored),
+            "errored" => Ok(Self::Errored),
+            _ => Err(format!("'{s}' is not a valid error class.")),
+        }
+    }
+}
+
+// How far back an account looks for duplicate deposits and withdrawals. See
+// `Account::with_dedup_window`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupWindow {
+    // The last this many transactions recorded.
+    Transactions(usize),
+    // The transactions posted within this many milliseconds of the latest one applied.
+    Millis(u64),
+}
+
+// Windows are given as a number of transactions, e.g. `100000`, or as a span of time with a unit of
+// seconds, minutes, hours or days, e.g. `90s`, `30m`, `24h` or `7d`.
+impl FromStr for DedupWindow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not a valid dedup window.");
+        if let Ok(count) = s.parse::<usize>() {
+            return match count {
+                0 => Err(invalid()),
+                count => Ok(Self::Transactions(count)),
+            };
+        }
+
+        let unit = match s.chars().last() {
+            Some('s') => 1_000,
+            Some('m') => 60_000,
+            Some('h') => 3_600_000,
+            Some('d') => 86_400_000,
+            _ => return Err(invalid()),
+        };
+        match s[..s.len() - 1].parse::<u64>() {
+            Ok(span) if span > 0 => span.checked_mul(unit).map(Self::Millis).ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::models::transaction::RawTransactionId;
     use std::error::Error;
     use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
 
     static NEXT_TXN_ID: AtomicU32 = AtomicU32::new(1);
 
@@ -334,7 +1365,7 @@ mod tests {
     }
 
     fn next_txn_id() -> TransactionId {
-        NEXT_TXN_ID.fetch_add(1, Ordering::SeqCst).into()
+        RawTransactionId::from(NEXT_TXN_ID.fetch_add(1, Ordering::SeqCst)).into()
     }
 
     #[test]
@@ -367,13 +1398,13 @@ mod tests {
         assert_eq!(account.total(), Decimal::ZERO);
 
         let one_hundred = Decimal::new(100, 0);
-        account.available = one_hundred;
+        account.ledger = Ledger::from_balances(one_hundred, Decimal::ZERO, Decimal::ZERO);
         assert_eq!(account.available(), one_hundred);
         assert_eq!(account.held(), Decimal::ZERO);
         assert_eq!(account.total(), one_hundred);
 
         let fifty = Decimal::new(50, 0);
-        account.held = fifty;
+        account.ledger = Ledger::from_balances(one_hundred, fifty, Decimal::ZERO);
         assert_eq!(account.available(), one_hundred);
         assert_eq!(account.held(), fifty);
         assert_eq!(account.total(), one_hundred + fifty);
@@ -388,7 +1419,7 @@ mod tests {
             account.id(),
             TransactionType::Deposit { amount },
         );
-        account.process_txn(txn)?;
+        account.process_txn(txn.clone())?;
 
         assert!(
             account.available() == amount && account.held() == Decimal::ZERO,
@@ -476,7 +1507,7 @@ mod tests {
             account.id(),
             TransactionType::Deposit { amount },
         );
-        account.process_txn(txn)?;
+        account.process_txn(txn.clone())?;
 
         assert!(
             account.available() == amount && account.held() == Decimal::ZERO,
@@ -486,14 +1517,14 @@ mod tests {
         let txn = Transaction::new(txn.id(), account.id(), TransactionType::Resolve);
         assert!(
             matches!(
-                account.process_txn(txn),
+                account.process_txn(txn.clone()),
                 Err(TransactionError::TransactionNotInDispute { .. })
             ),
             "transaction that is not in dispute cannot be resolved"
         );
 
         let txn = Transaction::new(txn.id(), account.id(), TransactionType::Dispute);
-        account.process_txn(txn)?;
+        account.process_txn(txn.clone())?;
 
         assert!(
             account.available() == Decimal::ZERO && account.held() == amount,
@@ -503,7 +1534,7 @@ mod tests {
         let txn = Transaction::new(txn.id(), account.id(), TransactionType::Dispute);
         assert!(
             matches!(
-                account.process_txn(txn),
+                account.process_txn(txn.clone()),
                 Err(TransactionError::TransactionAlreadyInDispute { .. })
             ),
             "transaction cannot be put into dispute more than once"
@@ -529,7 +1560,7 @@ mod tests {
             account.id(),
             TransactionType::Deposit { amount },
         );
-        account.process_txn(txn)?;
+        account.process_txn(txn.clone())?;
 
         assert!(
             account.available() == amount && account.held() == Decimal::ZERO,
@@ -537,7 +1568,7 @@ mod tests {
         );
 
         let txn = Transaction::new(txn.id(), account.id(), TransactionType::Dispute);
-        account.process_txn(txn)?;
+        account.process_txn(txn.clone())?;
 
         assert!(
             account.available() == Decimal::ZERO && account.held() == amount,
@@ -551,6 +1582,10 @@ mod tests {
             account.total() == Decimal::ZERO && account.locked(),
             "account should have 0 units available and be locked after a chargeback"
         );
+        assert!(
+            account.charged_back() == amount && account.ledger().is_balanced(),
+            "the funds charged back should be posted to the chargeback expense sub-ledger"
+        );
 
         let txn = Transaction::new(
             next_txn_id(),
@@ -567,4 +1602,292 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn custom_transaction_type() -> Result<(), Box<dyn Error>> {
+        let amount = "25".parse()?;
+        let mut account = get_account();
+        let txn = Transaction::new(
+            next_txn_id(),
+            account.id(),
+            TransactionType::custom("bonus", Some(amount)),
+        );
+
+        assert!(
+            matches!(
+                account.process_txn(txn.clone()),
+                Err(TransactionError::UnknownTransactionType { .. })
+            ),
+            "custom transaction types cannot be processed without a handler"
+        );
+
+        let mut handlers = TransactionHandlers::default();
+        handlers.register(
+            "bonus",
+            Arc::new(|account: &mut Account, txn: &Transaction| {
+                if let TransactionType::Custom {
+                    amount: Some(amount),
+                    ..
+                } = txn.txn_type()
+                {
+                    account.credit(amount);
+                }
+                Ok(())
+            }),
+        );
+        account.process_txn_with_handlers(txn, &handlers)?;
+
+        assert_eq!(
+            account.available(),
+            amount,
+            "the registered handler should have credited the bonus"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn history_limit() -> Result<(), Box<dyn Error>> {
+        let amount = "100".parse()?;
+        let mut account = get_account().with_history_limit(2);
+        let mut process = |txn_id: RawTransactionId, txn_type| {
+            account.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))
+        };
+
+        process(1, TransactionType::Deposit { amount })?;
+        process(2, TransactionType::Deposit { amount })?;
+        process(1, TransactionType::Dispute)?;
+        process(3, TransactionType::Deposit { amount })?;
+
+        assert!(
+            matches!(
+                process(2, TransactionType::Dispute),
+                Err(TransactionError::HistoryEvicted { .. })
+            ),
+            "the oldest transaction not in dispute should have been evicted"
+        );
+        assert!(
+            matches!(
+                process(2, TransactionType::Deposit { amount }),
+                Err(TransactionError::TransactionAlreadyProcessed { .. })
+            ),
+            "evicted transactions should still be detected as duplicates"
+        );
+
+        process(1, TransactionType::Resolve)?;
+        process(4, TransactionType::Deposit { amount })?;
+        assert!(matches!(
+            process(3, TransactionType::Dispute),
+            Err(TransactionError::HistoryEvicted { .. })
+        ));
+        process(1, TransactionType::Dispute)?;
+
+        assert_eq!(account.recorded_txns(), 2);
+        assert_eq!(account.evicted_txns(), 2);
+
+        // Only as many evicted IDs are kept as the limit, and those evicted before are forgotten.
+        let mut process = |txn_id: RawTransactionId, txn_type| {
+            account.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))
+        };
+        process(5, TransactionType::Deposit { amount })?;
+        process(2, TransactionType::Deposit { amount })?;
+        assert!(
+            matches!(
+                process(4, TransactionType::Deposit { amount }),
+                Err(TransactionError::TransactionAlreadyProcessed { .. })
+            ),
+            "recently evicted transactions should still be detected as duplicates"
+        );
+        assert_eq!(
+            (
+                account.recorded_txns(),
+                account.evicted_txns(),
+                account.forgotten_txns()
+            ),
+            (2, 2, 2)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn dedup_window() -> Result<(), Box<dyn Error>> {
+        let amount = "100".parse()?;
+        let mut account = get_account()
+            .with_history_limit(1)
+            .with_dedup_window("2".parse()?);
+        let mut process = |txn_id: RawTransactionId, txn_type| {
+            account.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))
+        };
+
+        process(1, TransactionType::Deposit { amount })?;
+        process(2, TransactionType::Deposit { amount })?;
+        assert!(
+            matches!(
+                process(1, TransactionType::Deposit { amount }),
+                Err(TransactionError::TransactionAlreadyProcessed { .. })
+            ),
+            "evicted transactions within the window should still be detected as duplicates"
+        );
+        process(3, TransactionType::Deposit { amount })?;
+        process(1, TransactionType::Deposit { amount })?;
+        assert_eq!(account.forgotten_txns(), 2);
+        assert_eq!((account.recorded_txns(), account.evicted_txns()), (1, 1));
+
+        let mut account = get_account().with_dedup_window("1m".parse()?);
+        let mut order = 0;
+        let mut apply = |txn_id: RawTransactionId, txn_type, posted_at| {
+            account.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))?;
+            order += 1;
+            // However long ago they were applied, transactions are aged by when they were posted.
+            account.record_applied(order, 1_700_000_000_000, posted_at);
+            Ok::<_, TransactionError>(account.recorded_txns())
+        };
+        apply(1, TransactionType::Deposit { amount }, Some(0))?;
+        apply(1, TransactionType::Dispute, Some(0))?;
+        apply(2, TransactionType::Deposit { amount }, Some(30_000))?;
+        assert_eq!(
+            apply(3, TransactionType::Deposit { amount }, Some(80_000))?,
+            3,
+            "transactions in dispute should be kept beyond the window"
+        );
+        assert_eq!(
+            apply(5, TransactionType::Deposit { amount }, None)?,
+            4,
+            "transactions without a time of their own shouldn't move time on"
+        );
+        assert_eq!(
+            apply(4, TransactionType::Deposit { amount }, Some(200_000))?,
+            2,
+            "transactions posted over a minute before should be forgotten"
+        );
+
+        assert!("0".parse::<DedupWindow>().is_err());
+        assert!("7w".parse::<DedupWindow>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn account_ids() -> Result<(), Box<dyn Error>> {
+        assert_eq!(AccountId::parse("42")?, AccountId::Number(42));
+        assert_eq!(
+            AccountId::parse("GB82WEST12345698765432")?,
+            AccountId::Name("GB82WEST12345698765432")
+        );
+        assert!(AccountId::parse("").is_err());
+        assert!(AccountId::parse("GB82 WEST").is_err());
+        assert!(AccountId::parse("99999999999999999999").is_err());
+
+        let ids: Vec<AccountId> = serde_json::from_str(r#"[7, "7", "DE89-3704"]"#)?;
+        assert_eq!(
+            ids,
+            [
+                AccountId::Number(7),
+                AccountId::Number(7),
+                AccountId::Name("DE89-3704")
+            ]
+        );
+        assert_eq!(serde_json::to_string(&ids)?, r#"[7,7,"DE89-3704"]"#);
+
+        Ok(())
+    }
+
+    #[test]
+    fn parts_round_trip() -> Result<(), Box<dyn Error>> {
+        let amount = "100".parse()?;
+        let mut account = get_account().with_history_limit(4);
+        let mut process = |txn_id: RawTransactionId, txn_type| {
+            account.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))
+        };
+        process(1, TransactionType::Deposit { amount })?;
+        process(
+            2,
+            TransactionType::Withdrawal {
+                amount: "25".parse()?,
+            },
+        )?;
+        process(3, TransactionType::Deposit { amount })?;
+        process(3, TransactionType::Dispute)?;
+        process(4, TransactionType::Deposit { amount })?;
+        process(1, TransactionType::Dispute)?;
+        process(1, TransactionType::Chargeback)?;
+        account.record_applied(7, 1_700_000_000_000, None);
+
+        let json = serde_json::to_string(&account.to_parts())?;
+        let restored: Account = serde_json::from_str(&json)?;
+        assert_eq!(serde_json::to_string(&restored.to_parts())?, json);
+        assert_eq!(restored.held(), amount);
+        assert!(restored.locked());
+        assert_eq!(restored.disputed_amount(3.into()), Some(amount));
+        assert!(restored.has_applied(2.into()));
+
+        let mut parts = account.to_parts();
+        parts.version = ACCOUNT_PARTS_VERSION + 1;
+        assert!(matches!(
+            Account::from_parts(parts),
+            Err(AccountPartsError::UnsupportedVersion { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rounded() -> Result<(), Box<dyn Error>> {
+        let mut account = get_account();
+        account.credit("1.23456".parse()?);
+        account.credit("0.00004".parse()?);
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(account.rounded(2))?;
+        writer.serialize(account.rounded(4))?;
+        writer.serialize(account.rounded(6))?;
+        assert_eq!(
+            String::from_utf8(writer.into_inner()?)?,
+            "client,available,held,total,locked\n\
+             1,1.23,0.00,1.23,false\n\
+             1,1.2346,0.0000,1.2346,false\n\
+             1,1.234600,0.000000,1.234600,false\n",
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn extended_output() -> Result<(), Box<dyn Error>> {
+        let mut account = get_account();
+        let deposit_id = next_txn_id();
+        let txns = [
+            (
+                deposit_id,
+                TransactionType::Deposit {
+                    amount: "10".parse()?,
+                },
+            ),
+            (
+                next_txn_id(),
+                TransactionType::Withdrawal {
+                    amount: "2.5".parse()?,
+                },
+            ),
+            (deposit_id, TransactionType::Dispute),
+        ];
+        for (order, (txn_id, txn_type)) in txns.into_iter().enumerate() {
+            account.process_txn(Transaction::new(txn_id, account.id(), txn_type))?;
+            account.record_applied(order as u64, 1_000 + order as u64, None);
+        }
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        writer.serialize(account.output(OutputFormat {
+            decimal_places: Some(1),
+            extended: true,
+            tenant: false,
+        }))?;
+        assert_eq!(
+            String::from_utf8(writer.into_inner()?)?,
+            "client,available,held,total,locked,transactions_applied,open_disputes,lifetime_deposits,lifetime_withdrawals,last_order,last_applied_at\n\
+             1,-2.5,10.0,7.5,false,3,1,10.0,2.5,2,1002\n",
+        );
+
+        Ok(())
+    }
 }
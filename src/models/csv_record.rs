@@ -0,0 +1,279 @@
+use std::str;
+use std::sync::{Arc, Mutex};
+
+use rust_decimal::Decimal;
+
+use crate::models::{
+    account::{AccountId, RawAccountId},
+    transaction::{
+        LenientTransaction, RawTransactionId, Transaction, TransactionId, TransactionType,
+    },
+};
+
+// The most significant digits a fractional amount may have to be parsed by the fast path. Through
+// serde, the CSV reader reads such amounts as floats first, which hold up to 15 significant digits
+// exactly, so only those amounts are sure to be read the same either way.
+const MAX_FRACTIONAL_DIGITS: usize = 15;
+
+// The most digits a whole amount may have to be parsed by the fast path, as the CSV reader reads
+// those that fit within a u64 as integers.
+const MAX_WHOLE_DIGITS: usize = 19;
+
+// Where each of the columns of our own CSV format are within a file's records, for parsing them
+// into transactions without going through serde. Deserializing a transaction through serde buffers
+// each of its fields, as the transaction type is flattened into it, which makes up most of the cost
+// of reading a record.
+//
+// Only the records of the most common shape are parsed this way: native transaction types, numeric
+// clients and transaction IDs, plain amounts, and no tenant. Everything else is left to
+// serde, which also reports why a record is malformed, so either way a record is read exactly the
+// same.
+#[derive(Clone, Copy, Debug)]
+pub struct CsvLayout {
+    txn_type: usize,
+    client: usize,
+    tx: usize,
+    amount: Option<usize>,
+    tenant: Option<usize>,
+}
+
+impl CsvLayout {
+    // Finds the columns within the headers of a file, if it has those of a transaction and has each
+    // of them only once.
+    pub fn from_headers(headers: &csv::ByteRecord) -> Option<Self> {
+        let column = |name: &[u8]| -> Result<Option<usize>, ()> {
+            let mut found = headers.iter().enumerate().filter(|(_, h)| *h == name);
+            match (found.next(), found.next()) {
+                (Some((idx, _)), None) => Ok(Some(idx)),
+                (None, _) => Ok(None),
+                (Some(_), Some(_)) => Err(()),
+            }
+        };
+        Some(Self {
+            txn_type: column(b"type").ok()??,
+            client: column(b"client").ok()??,
+            tx: column(b"tx").ok()??,
+            amount: column(b"amount").ok()?,
+            tenant: column(b"tenant").ok()?,
+        })
+    }
+
+    // Parses the record into a transaction, or returns `None` if it isn't of the shape the fast
+    // path handles, in which case it should be deserialized as usual.
+    pub fn parse(&self, record: &csv::ByteRecord) -> Option<Transaction> {
+        let is_empty = |idx: Option<usize>| idx.is_none_or(|idx| record.get(idx) == Some(b""));
+        if !is_empty(self.tenant) {
+            return None;
+        }
+
+        let amount = match self.amount.map(|idx| record.get(idx)) {
+            None | Some(Some(b"")) => None,
+            Some(Some(amount)) => Some(parse_amount(amount)?),
+            Some(None) => return None,
+        };
+        let name = record.get(self.txn_type)?;
+        let txn_type = match name {
+            b"deposit" | b"withdrawal" | b"dispute" | b"resolve" | b"chargeback" => {
+                TransactionType::from_name(str::from_utf8(name).ok()?, amount).ok()?
+            }
+            _ => return None,
+        };
+        let client: RawAccountId = parse_digits(record.get(self.client)?)?.try_into().ok()?;
+        // Transaction IDs are already 64-bit in builds with the wide-ids feature.
+        #[allow(clippy::useless_conversion)]
+        let tx: RawTransactionId = parse_digits(record.get(self.tx)?)?.try_into().ok()?;
+        Some(Transaction::new(
+            TransactionId::Number(tx),
+            AccountId::Number(client),
+            txn_type,
+        ))
+    }
+}
+
+// Where the columns read alongside each transaction, rather than as part of it, are within a file's
+// records.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExtraColumns {
+    pub timestamp: Option<usize>,
+    pub effective_date: Option<usize>,
+    pub memo: Option<usize>,
+}
+
+impl ExtraColumns {
+    fn from_headers(headers: &csv::ByteRecord) -> Self {
+        let column = |name: &[u8]| headers.iter().position(|h| h == name);
+        Self {
+            timestamp: column(b"timestamp"),
+            effective_date: column(b"effective_date"),
+            memo: column(b"memo"),
+        }
+    }
+}
+
+// Parses the records of a file into transactions, given its headers. Records are parsed directly
+// where the layout of the file allows, and deserialized through serde otherwise.
+#[derive(Clone, Debug)]
+pub struct RecordParser {
+    headers: csv::ByteRecord,
+    layout: Option<CsvLayout>,
+    extra_columns: ExtraColumns,
+    lenient: bool,
+}
+
+impl RecordParser {
+    pub fn new(headers: csv::ByteRecord, lenient: bool) -> Self {
+        Self {
+            layout: CsvLayout::from_headers(&headers),
+            extra_columns: ExtraColumns::from_headers(&headers),
+            headers,
+            lenient,
+        }
+    }
+
+    pub fn headers(&self) -> &csv::ByteRecord {
+        &self.headers
+    }
+
+    pub fn extra_columns(&self) -> ExtraColumns {
+        self.extra_columns
+    }
+
+    pub fn parse(&self, record: &csv::ByteRecord) -> Result<Transaction, csv::Error> {
+        if self.lenient {
+            return record
+                .deserialize::<LenientTransaction>(Some(&self.headers))
+                .map(Transaction::from);
+        }
+        self.layout
+            .and_then(|layout| layout.parse(record))
+            .map_or_else(|| record.deserialize(Some(&self.headers)), Ok)
+    }
+}
+
+// The parsers of the files read so far, by their headers. A run over many files tends to read files
+// of only a few shapes, so a file with the same headers as one before it is parsed as that one was,
+// rather than having its columns worked out again.
+#[derive(Debug, Default)]
+pub struct ParserCache {
+    parsers: Mutex<Vec<Arc<RecordParser>>>,
+}
+
+impl ParserCache {
+    pub fn parser(&self, headers: csv::ByteRecord, lenient: bool) -> Arc<RecordParser> {
+        let mut parsers = self.parsers.lock().expect("parser cache lock poisoned");
+        if let Some(parser) = parsers
+            .iter()
+            .find(|parser| parser.headers == headers && parser.lenient == lenient)
+        {
+            return parser.clone();
+        }
+        let parser = Arc::new(RecordParser::new(headers, lenient));
+        parsers.push(parser.clone());
+        parser
+    }
+}
+
+// Parses a number made up only of digits.
+fn parse_digits(field: &[u8]) -> Option<u64> {
+    if field.is_empty() || field.len() > MAX_WHOLE_DIGITS {
+        return None;
+    }
+    field.iter().try_fold(0u64, |number, &b| {
+        b.is_ascii_digit()
+            .then(|| number * 10 + u64::from(b - b'0'))
+    })
+}
+
+// Parses an amount of digits with an optional fractional part, normalized as the amount would be
+// after being read as a float.
+fn parse_amount(field: &[u8]) -> Option<Decimal> {
+    let (whole, fraction) = match field.iter().position(|&b| b == b'.') {
+        Some(idx) => (&field[..idx], Some(&field[idx + 1..])),
+        None => (field, None),
+    };
+    let Some(fraction) = fraction else {
+        return parse_digits(whole).map(Decimal::from);
+    };
+
+    if whole.is_empty() || fraction.is_empty() {
+        return None;
+    }
+    if !whole.iter().chain(fraction).all(u8::is_ascii_digit) {
+        return None;
+    }
+    let significant = whole
+        .iter()
+        .chain(fraction)
+        .skip_while(|&&b| b == b'0')
+        .count();
+    if significant > MAX_FRACTIONAL_DIGITS {
+        return None;
+    }
+    str::from_utf8(field)
+        .ok()?
+        .parse::<Decimal>()
+        .ok()
+        .map(|amount| amount.normalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_records_as_serde_does() {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,1.50,\n\
+                     withdrawal,007,2,10.0,\n\
+                     deposit,2,3,12,\n\
+                     dispute,1,1,,\n\
+                     resolve,1,1,,\n\
+                     chargeback,1,1,,\n\
+                     deposit,1,4,0.00012,\n\
+                     deposit,1,5,1234567.123456789123,\n\
+                     deposit,1,6,-1.5,\n\
+                     deposit,1,7,.5,\n\
+                     Deposit,1,8,1.5,\n\
+                     deposit,1,9,1.5,payroll\n\
+                     deposit,ab-1,10,1.5,\n\
+                     deposit,1,0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d,1.5,\n\
+                     deposit,1,11,,\n\
+                     deposit,99999999999,12,1.5,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let headers = reader.byte_headers().expect("headers").clone();
+        let layout = CsvLayout::from_headers(&headers).expect("the headers of a transaction");
+
+        let mut parsed = 0;
+        for record in reader.byte_records() {
+            let record = record.expect("a record");
+            let expected = record
+                .deserialize::<Transaction>(Some(&headers))
+                .map(|txn| txn.to_string())
+                .map_err(|err| err.to_string());
+            if let Some(txn) = layout.parse(&record) {
+                parsed += 1;
+                assert_eq!(Ok(txn.to_string()), expected, "{record:?}");
+            }
+        }
+        assert_eq!(
+            parsed, 8,
+            "only records of the common shape take the fast path"
+        );
+
+        let duplicated = csv::ByteRecord::from(vec!["type", "client", "tx", "tx", "amount"]);
+        assert!(CsvLayout::from_headers(&duplicated).is_none());
+    }
+
+    #[test]
+    fn caches_parsers_by_headers() {
+        let cache = ParserCache::default();
+        let headers = csv::ByteRecord::from(vec!["type", "client", "tx", "amount", "memo"]);
+        let parser = cache.parser(headers.clone(), false);
+        assert_eq!(parser.extra_columns().memo, Some(4));
+        assert!(Arc::ptr_eq(&parser, &cache.parser(headers.clone(), false)));
+        assert!(!Arc::ptr_eq(&parser, &cache.parser(headers, true)));
+
+        let reordered = csv::ByteRecord::from(vec!["memo", "type", "client", "tx", "amount"]);
+        assert_eq!(cache.parser(reordered, false).extra_columns().memo, Some(0));
+    }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::models::{
+    account::{Account, TransactionError},
+    transaction::Transaction,
+};
+
+// Applies transactions of a custom type to an account. Handlers are given mutable access to the
+// account so that they can adjust its balances via `Account::credit`, `Account::debit`, and
+// `Account::lock`. The account's locked status and ownership of the transaction have already been
+// verified by the time a handler is invoked.
+pub trait TransactionHandler: Send + Sync {
+    fn apply(&self, account: &mut Account, txn: &Transaction) -> Result<(), TransactionError>;
+}
+
+impl<F> TransactionHandler for F
+where
+    F: Fn(&mut Account, &Transaction) -> Result<(), TransactionError> + Send + Sync,
+{
+    fn apply(&self, account: &mut Account, txn: &Transaction) -> Result<(), TransactionError> {
+        self(account, txn)
+    }
+}
+
+// The set of handlers for custom transaction types, keyed by the name of the type as it appears in
+// the input.
+#[derive(Clone, Default)]
+pub struct TransactionHandlers {
+    handlers: HashMap<String, Arc<dyn TransactionHandler>>,
+}
+
+impl TransactionHandlers {
+    pub fn register(&mut self, name: impl Into<String>, handler: Arc<dyn TransactionHandler>) {
+        self.handlers.insert(name.into(), handler);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn TransactionHandler> {
+        self.handlers.get(name).map(|handler| handler.as_ref())
+    }
+}
@@ -1,10 +1,22 @@
-use derive_more::{Constructor, Display, From, Into};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex, OnceLock};
+
+use derive_more::Display;
 use rust_decimal::Decimal;
-use serde::Deserialize;
+use serde::{
+    de,
+    ser::{self, SerializeStruct},
+    Deserialize, Deserializer, Serialize,
+};
+use snafu::Snafu;
+use uuid::Uuid;
 
-use crate::models::account::AccountId;
+use crate::models::account::{AccountId, AccountKey, Tenant};
 
-#[derive(Clone, Constructor, Copy, Debug, Deserialize, Display)]
+#[derive(Clone, Debug, Deserialize, Display, Serialize)]
 #[display(fmt = "ID: {id}, Account ID: {account_id}, Type: {txn_type}")]
 pub struct Transaction {
     #[serde(rename = "tx")]
@@ -15,9 +27,31 @@ pub struct Transaction {
 
     #[serde(flatten)]
     txn_type: TransactionType,
+
+    // The tenant whose account space the transaction is for, in a multi-tenant run.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_tenant",
+        skip_serializing_if = "Option::is_none"
+    )]
+    tenant: Option<Tenant>,
 }
 
 impl Transaction {
+    pub fn new(id: TransactionId, account_id: AccountId, txn_type: TransactionType) -> Self {
+        Self {
+            id,
+            account_id,
+            txn_type,
+            tenant: None,
+        }
+    }
+
+    pub fn with_tenant(mut self, tenant: Option<Tenant>) -> Self {
+        self.tenant = tenant;
+        self
+    }
+
     pub fn id(&self) -> TransactionId {
         self.id
     }
@@ -26,20 +60,294 @@ impl Transaction {
         self.account_id
     }
 
+    pub fn tenant(&self) -> Option<Tenant> {
+        self.tenant
+    }
+
+    // Identifies the transaction's account among those of every tenant.
+    pub fn account_key(&self) -> AccountKey {
+        (self.tenant, self.account_id)
+    }
+
     pub fn txn_type(&self) -> TransactionType {
+        self.txn_type.clone()
+    }
+
+    // The name of the transaction's type, as it appears in the input.
+    pub fn type_name(&self) -> &str {
+        self.txn_type.name()
+    }
+
+    // Whether the transaction's amount has more significant decimal places than the input format
+    // allows.
+    pub fn has_excess_precision(&self) -> bool {
         self.txn_type
+            .amount()
+            .is_some_and(|amount| amount.normalize().scale() > AMOUNT_DECIMAL_PLACES)
+    }
+
+    // Rounds the transaction's amount to the decimal places the input format allows, using banker's
+    // rounding.
+    pub fn round_amount(mut self) -> Self {
+        self.txn_type = match self.txn_type {
+            TransactionType::Deposit { amount } => TransactionType::Deposit {
+                amount: amount.round_dp(AMOUNT_DECIMAL_PLACES),
+            },
+            TransactionType::Withdrawal { amount } => TransactionType::Withdrawal {
+                amount: amount.round_dp(AMOUNT_DECIMAL_PLACES),
+            },
+            TransactionType::Custom { name, amount } => TransactionType::Custom {
+                name,
+                amount: amount.map(|amount| amount.round_dp(AMOUNT_DECIMAL_PLACES)),
+            },
+            txn_type => txn_type,
+        };
+        self
+    }
+}
+
+// A transaction read from a less than perfectly formatted record, whose type may be written in any
+// case, e.g. `Deposit` or `DEPOSIT`.
+#[derive(Deserialize)]
+pub struct LenientTransaction {
+    #[serde(rename = "tx")]
+    id: TransactionId,
+
+    #[serde(rename = "client")]
+    account_id: AccountId,
+
+    #[serde(flatten, deserialize_with = "deserialize_lenient_type")]
+    txn_type: TransactionType,
+
+    #[serde(default, deserialize_with = "deserialize_tenant")]
+    tenant: Option<Tenant>,
+}
+
+impl From<LenientTransaction> for Transaction {
+    fn from(txn: LenientTransaction) -> Self {
+        Self {
+            id: txn.id,
+            account_id: txn.account_id,
+            txn_type: txn.txn_type,
+            tenant: txn.tenant,
+        }
+    }
+}
+
+// Where in the input a transaction was read from. File names are interned, so that transactions
+// can remain cheap to copy. A file's name need only be interned once, with the source of each of
+// its records made from it by `at_line`.
+#[derive(Clone, Copy, Debug, Display, Eq, PartialEq)]
+#[display(fmt = "{file}:{line}")]
+pub struct Source {
+    file: &'static str,
+    line: u64,
+}
+
+impl Source {
+    pub fn new(file: &Path, line: u64) -> Self {
+        Self {
+            file: intern(&file.to_string_lossy()),
+            line,
+        }
+    }
+
+    // The given line of the same file.
+    pub fn at_line(self, line: u64) -> Self {
+        Self { line, ..self }
+    }
+
+    pub fn file(&self) -> &'static str {
+        self.file
+    }
+
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+}
+
+// A transaction along with its position in the overall sequence of transactions submitted for
+// processing, and where it was read from if it came from an input file.
+#[derive(Clone, Debug, Display)]
+#[display(fmt = "Order: {order}, {txn}")]
+pub struct OrderedTransaction {
+    order: u64,
+    txn: Transaction,
+    source: Option<Source>,
+    // When the transaction was posted, in milliseconds since the Unix epoch, if the input says.
+    posted_at: Option<u64>,
+    // Free-text context about the transaction, which isn't used in processing but is carried
+    // through to the reports. It's held here rather than on the transaction, so that the
+    // transaction remains cheap to copy.
+    memo: Option<Arc<str>>,
+    // Whether the transaction arrived faster than its client's rate limit, in which case it's
+    // turned away by the worker that would have applied it.
+    rate_limited: bool,
+}
+
+impl OrderedTransaction {
+    pub fn new(order: u64, txn: Transaction) -> Self {
+        Self {
+            order,
+            txn,
+            source: None,
+            posted_at: None,
+            memo: None,
+            rate_limited: false,
+        }
+    }
+
+    pub fn with_source(mut self, source: Source) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn with_posted_at(mut self, posted_at: Option<u64>) -> Self {
+        self.posted_at = posted_at;
+        self
+    }
+
+    pub fn with_memo(mut self, memo: Option<Arc<str>>) -> Self {
+        self.memo = memo;
+        self
+    }
+
+    pub fn with_rate_limited(mut self, rate_limited: bool) -> Self {
+        self.rate_limited = rate_limited;
+        self
+    }
+
+    pub fn order(&self) -> u64 {
+        self.order
+    }
+
+    pub fn txn(&self) -> &Transaction {
+        &self.txn
+    }
+
+    pub fn source(&self) -> Option<Source> {
+        self.source
+    }
+
+    pub fn posted_at(&self) -> Option<u64> {
+        self.posted_at
+    }
+
+    pub fn memo(&self) -> Option<&str> {
+        self.memo.as_deref()
+    }
+
+    pub fn rate_limited(&self) -> bool {
+        self.rate_limited
+    }
+
+    // The memo, for holding on to without copying it.
+    pub fn shared_memo(&self) -> Option<&Arc<str>> {
+        self.memo.as_ref()
+    }
+}
+
+// The memo of a transaction as it's written in the input, where an empty memo is the same as no
+// memo at all.
+pub fn memo(text: &str) -> Option<Arc<str>> {
+    (!text.is_empty()).then(|| text.into())
+}
+
+// Identifies a transaction. Most feeds number their transactions, but some use UUIDs instead,
+// e.g. event-sourced systems that generate time-ordered UUIDv7s. UUIDs are held as their 16 raw
+// bytes rather than as text.
+#[derive(Clone, Copy, Debug, Display, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[serde(untagged)]
+pub enum TransactionId {
+    #[display(fmt = "{_0}")]
+    Number(RawTransactionId),
+    #[display(fmt = "{_0}")]
+    Uuid(Uuid),
+}
+
+impl TransactionId {
+    // The namespace of the UUIDs derived from references that are neither numeric nor UUIDs
+    // themselves.
+    const REFERENCE_NAMESPACE: Uuid = Uuid::from_u128(0x5f1c_9a0e_3b7d_4e62_8c41_d2a6_f0b3_9e17);
+
+    // Identifies a transaction by a reference from a bank statement. References are free-form, so
+    // those that aren't already numeric or UUIDs are mapped to UUIDs derived from them, which
+    // identify the same transaction every time it is imported.
+    pub fn from_reference(reference: &str) -> Self {
+        if !reference.is_empty() && reference.bytes().all(|b| b.is_ascii_digit()) {
+            if let Ok(number) = reference.parse() {
+                return Self::Number(number);
+            }
+        }
+        Uuid::parse_str(reference)
+            .unwrap_or_else(|_| Uuid::new_v5(&Self::REFERENCE_NAMESPACE, reference.as_bytes()))
+            .into()
+    }
+}
+
+impl From<RawTransactionId> for TransactionId {
+    fn from(number: RawTransactionId) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl From<Uuid> for TransactionId {
+    fn from(uuid: Uuid) -> Self {
+        Self::Uuid(uuid)
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct TransactionIdVisitor;
+
+        impl de::Visitor<'_> for TransactionIdVisitor {
+            type Value = TransactionId;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a numeric or UUID transaction ID")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+                RawTransactionId::try_from(v)
+                    .map(TransactionId::Number)
+                    .map_err(|_| E::custom(format!("the transaction ID {v} is too large")))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+                u64::try_from(v)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+                    .and_then(|v| self.visit_u64(v))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+                if !v.is_empty() && v.bytes().all(|b| b.is_ascii_digit()) {
+                    return v
+                        .parse()
+                        .map(TransactionId::Number)
+                        .map_err(|_| E::custom(format!("the transaction ID {v} is too large")));
+                }
+                Uuid::parse_str(v)
+                    .map(TransactionId::Uuid)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+            }
+        }
+
+        deserializer.deserialize_any(TransactionIdVisitor)
     }
 }
 
-#[derive(
-    Clone, Copy, Debug, Deserialize, Display, Eq, From, Hash, Into, PartialEq, PartialOrd, Ord,
-)]
-#[display(fmt = "{_0}")]
-#[serde(transparent)]
-pub struct TransactionId(u32);
+// The integer type that transaction IDs are represented by, which is widened from 32 to 64 bits by
+// the `wide-ids` feature.
+#[cfg(not(feature = "wide-ids"))]
+pub type RawTransactionId = u32;
+#[cfg(feature = "wide-ids")]
+pub type RawTransactionId = u64;
 
-#[derive(Clone, Copy, Debug, Deserialize, Display)]
-#[serde(rename_all = "lowercase", tag = "type")]
+#[derive(Clone, Debug, Display)]
 pub enum TransactionType {
     #[display(fmt = "Deposit {amount}")]
     Deposit { amount: Decimal },
@@ -51,4 +359,370 @@ pub enum TransactionType {
     Resolve,
     #[display(fmt = "Chargeback")]
     Chargeback,
+    // Any transaction type we don't natively understand. These are applied by a `TransactionHandler`
+    // registered for the type's name, if there is one. The name comes from the input, so it's owned
+    // by the transactions of the type rather than interned, which would keep every name ever read.
+    #[display(fmt = "{name}")]
+    Custom {
+        name: Arc<str>,
+        amount: Option<Decimal>,
+    },
+}
+
+impl TransactionType {
+    // The transaction type of the given name, with the given amount if it is one that has one.
+    pub fn from_name(name: &str, amount: Option<Decimal>) -> Result<Self, String> {
+        let required_amount = || {
+            let amount =
+                amount.ok_or_else(|| format!("a {name} transaction requires an amount"))?;
+            validate_amount(amount).map_err(|err| err.to_string())
+        };
+
+        Ok(match name {
+            "deposit" => Self::Deposit {
+                amount: required_amount()?,
+            },
+            "withdrawal" => Self::Withdrawal {
+                amount: required_amount()?,
+            },
+            "dispute" => Self::Dispute,
+            "resolve" => Self::Resolve,
+            "chargeback" => Self::Chargeback,
+            name => Self::custom(name, amount),
+        })
+    }
+
+    // The name of the transaction type as it appears in the input.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Custom { name, .. } => name,
+            _ => self.static_name(),
+        }
+    }
+
+    // The name of the transaction type, which is only copied for a custom type.
+    pub fn owned_name(&self) -> Cow<'static, str> {
+        match self {
+            Self::Custom { name, .. } => Cow::Owned(name.to_string()),
+            _ => Cow::Borrowed(self.static_name()),
+        }
+    }
+
+    fn static_name(&self) -> &'static str {
+        match self {
+            Self::Deposit { .. } => "deposit",
+            Self::Withdrawal { .. } => "withdrawal",
+            Self::Dispute => "dispute",
+            Self::Resolve => "resolve",
+            Self::Chargeback => "chargeback",
+            Self::Custom { .. } => "custom",
+        }
+    }
+
+    pub fn amount(&self) -> Option<Decimal> {
+        match self {
+            Self::Deposit { amount } | Self::Withdrawal { amount } => Some(*amount),
+            Self::Custom { amount, .. } => *amount,
+            Self::Dispute | Self::Resolve | Self::Chargeback => None,
+        }
+    }
+
+    // Creates a custom transaction type.
+    pub fn custom(name: &str, amount: Option<Decimal>) -> Self {
+        Self::Custom {
+            name: name.into(),
+            amount,
+        }
+    }
+}
+
+// Transaction types are serialized in the same shape they are read from the input, so that a
+// serialized transaction can be read back in.
+impl Serialize for TransactionType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: ser::Serializer,
+    {
+        let mut s = serializer.serialize_struct("TransactionType", 2)?;
+        s.serialize_field("type", self.name())?;
+        s.serialize_field("amount", &self.amount())?;
+        s.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        RawTransactionType::deserialize(deserializer)?
+            .try_into()
+            .map_err(de::Error::custom)
+    }
+}
+
+// The most decimal places an amount may be given to in the input.
+pub const AMOUNT_DECIMAL_PLACES: u32 = 4;
+
+// The largest amount we'll accept in a single transaction. Anything larger is far more likely to be
+// a corrupt record than a genuine transaction.
+pub const MAX_AMOUNT: u64 = 1_000_000_000_000_000;
+
+#[derive(Debug, Snafu)]
+pub enum AmountError {
+    #[snafu(display("The amount {amount} is negative"))]
+    NegativeAmount { amount: Decimal },
+
+    #[snafu(display("The amount {amount} exceeds the largest accepted amount of {MAX_AMOUNT}"))]
+    AmountTooLarge { amount: Decimal },
+}
+
+// Checks that an amount given for a deposit or withdrawal is one that could plausibly be genuine.
+pub fn validate_amount(amount: Decimal) -> Result<Decimal, AmountError> {
+    snafu::ensure!(amount >= Decimal::ZERO, NegativeAmountSnafu { amount });
+    snafu::ensure!(amount <= MAX_AMOUNT.into(), AmountTooLargeSnafu { amount });
+    Ok(amount)
+}
+
+// The shape of a transaction type as it appears in the input: the name of the type, and an amount
+// that may or may not be present depending on the type.
+#[derive(Deserialize)]
+struct RawTransactionType {
+    #[serde(rename = "type")]
+    name: String,
+
+    #[serde(default, deserialize_with = "deserialize_amount")]
+    amount: Option<Decimal>,
+}
+
+impl TryFrom<RawTransactionType> for TransactionType {
+    type Error = String;
+
+    fn try_from(raw: RawTransactionType) -> Result<Self, Self::Error> {
+        Self::from_name(&raw.name, raw.amount)
+    }
+}
+
+fn deserialize_lenient_type<'de, D>(deserializer: D) -> Result<TransactionType, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut raw = RawTransactionType::deserialize(deserializer)?;
+    raw.name.make_ascii_lowercase();
+    raw.try_into().map_err(de::Error::custom)
+}
+
+// Amounts are optional for some transaction types, in which case the field is left empty.
+fn deserialize_amount<'de, D>(deserializer: D) -> Result<Option<Decimal>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct AmountVisitor;
+
+    impl<'de> de::Visitor<'de> for AmountVisitor {
+        type Value = Option<Decimal>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an optional Decimal amount")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_any(self)
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            if v.is_empty() {
+                Ok(None)
+            } else {
+                v.parse()
+                    .map(Some)
+                    .map_err(|_| E::invalid_value(de::Unexpected::Str(v), &"a Decimal amount"))
+            }
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<Self::Value, E> {
+            self.visit_str(&v.to_string())
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<Self::Value, E> {
+            Ok(Some(v.into()))
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            Ok(Some(v.into()))
+        }
+    }
+
+    deserializer.deserialize_any(AmountVisitor)
+}
+
+// An empty tenant is the same as no tenant at all.
+fn deserialize_tenant<'de, D>(deserializer: D) -> Result<Option<Tenant>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct TenantVisitor;
+
+    impl<'de> de::Visitor<'de> for TenantVisitor {
+        type Value = Option<Tenant>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "an optional tenant")
+        }
+
+        fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+            Ok(None)
+        }
+
+        fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<Self::Value, D::Error> {
+            d.deserialize_any(self)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<Self::Value, E> {
+            self.visit_str(&v.to_string())
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+            if v.is_empty() {
+                return Ok(None);
+            }
+            Tenant::parse(v).map(Some).map_err(E::custom)
+        }
+    }
+
+    deserializer.deserialize_any(TenantVisitor)
+}
+
+pub(crate) fn intern(name: &str) -> &'static str {
+    static NAMES: OnceLock<Mutex<HashSet<&'static str>>> = OnceLock::new();
+
+    let mut names = NAMES
+        .get_or_init(Default::default)
+        .lock()
+        .expect("interned names lock poisoned");
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn lenient_transaction_types() -> Result<(), Box<dyn Error>> {
+        let input = "type,client,tx,amount\nDeposit,1,1,1.5\nDISPUTE,1,1,\n";
+        let txns = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<LenientTransaction>()
+            .map(|txn| txn.map(|txn| Transaction::from(txn).txn_type().to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(txns, ["Deposit 1.5", "Dispute"]);
+
+        assert!(
+            csv::Reader::from_reader(input.as_bytes())
+                .deserialize::<Transaction>()
+                .all(|txn| matches!(
+                    txn.map(|txn| txn.txn_type()),
+                    Ok(TransactionType::Custom { .. })
+                )),
+            "unless parsed leniently, transaction types in the wrong case are custom types"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn uuid_transaction_ids() -> Result<(), Box<dyn Error>> {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d,1.5\n\
+                     dispute,1,0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d,\n\
+                     deposit,1,42,1\n\
+                     deposit,1,not-a-uuid,1\n";
+        let ids: Vec<_> = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|txn| txn.ok().map(|txn| txn.id()))
+            .collect();
+
+        let uuid = Uuid::parse_str("0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d")?;
+        assert_eq!(
+            ids,
+            [
+                Some(TransactionId::Uuid(uuid)),
+                Some(TransactionId::Uuid(uuid)),
+                Some(TransactionId::Number(42)),
+                None
+            ]
+        );
+        assert_eq!(
+            TransactionId::Uuid(uuid).to_string(),
+            "0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn excess_precision() -> Result<(), Box<dyn Error>> {
+        let deposit = |amount: &str| -> Result<Transaction, Box<dyn Error>> {
+            Ok(Transaction::new(
+                1.into(),
+                1.into(),
+                TransactionType::Deposit {
+                    amount: amount.parse()?,
+                },
+            ))
+        };
+
+        assert!(!deposit("1.2500000")?.has_excess_precision());
+        assert!(deposit("1.00005")?.has_excess_precision());
+        assert_eq!(
+            deposit("1.00005")?.round_amount().txn_type().amount(),
+            Some("1.0000".parse()?),
+            "amounts should be rounded to the nearest even digit"
+        );
+        assert_eq!(
+            deposit("1.00015")?.round_amount().txn_type().amount(),
+            Some("1.0002".parse()?)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_amounts() -> Result<(), Box<dyn Error>> {
+        let input = "type,client,tx,amount\ndeposit,1,1,-1.5\nwithdrawal,1,2,1000000000000001\ndeposit,1,3,1000000000000000\n";
+        let results = csv::Reader::from_reader(input.as_bytes())
+            .deserialize::<Transaction>()
+            .map(|txn| match txn {
+                Ok(txn) => txn.txn_type().to_string(),
+                Err(err) => err.to_string(),
+            })
+            .collect::<Vec<_>>();
+
+        assert!(results[0].ends_with("The amount -1.5 is negative"));
+        assert!(results[1].ends_with(
+            "The amount 1000000000000001 exceeds the largest accepted amount of 1000000000000000"
+        ));
+        assert_eq!(results[2], "Deposit 1000000000000000");
+
+        Ok(())
+    }
 }
@@ -0,0 +1,254 @@
+use std::fmt::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+
+use crate::calendar::civil_from_days;
+use crate::models::account::Account;
+use crate::statements::StatementLine;
+
+// The longest a reference may be in a `:61:` statement line.
+const MAX_REFERENCE_LEN: usize = 16;
+
+// The longest each line of a `:86:` information field may be, and how many lines it may have.
+const INFORMATION_LINE_LEN: usize = 65;
+const INFORMATION_LINES: usize = 6;
+
+// Renders the activity of an account as an MT940 customer statement. The statement's balances
+// are of the account's total funds, so the only entries listed are those that changed them;
+// disputes and resolutions only move funds between available and held, which is reflected in the
+// closing available balance instead.
+//
+// Accounts don't record what they held before the run, so the opening balance is worked back from
+// the first transaction in the statement. Should that be a custom transaction type, whose effect
+// isn't known, the account is assumed to have been opened by it.
+pub(crate) fn render(account: &Account, lines: &[StatementLine], currency: &str) -> String {
+    let mut entries = Entries::default();
+    let mut body = String::new();
+    entries.render(lines, &mut body);
+    format!(
+        "{}{body}{}",
+        entries.header(account, currency),
+        entries.trailer(account, currency)
+    )
+}
+
+// The entries of a statement, rendered a few lines at a time as the account's transactions are
+// applied, so that the lines needn't all be held until the statement is complete. The header and
+// trailer are rendered around them once it is, as they depend on the first and last lines.
+#[derive(Debug, Default)]
+pub(crate) struct Entries {
+    // When the statement opened and the account's total funds then, once there's a line.
+    opening: Option<(Option<u64>, Decimal)>,
+    previous_total: Decimal,
+    closed_at: Option<u64>,
+}
+
+impl Entries {
+    // Renders the entries of the given lines, following on from those rendered before.
+    pub(crate) fn render(&mut self, lines: &[StatementLine], out: &mut String) {
+        let now = now();
+        for line in lines {
+            let previous_total = match self.opening {
+                Some(_) => self.previous_total,
+                None => {
+                    let opening_total = opening_total(line);
+                    self.opening = Some((line.applied_at, opening_total));
+                    opening_total
+                }
+            };
+            self.previous_total = line.total;
+            self.closed_at = line.applied_at;
+            let movement = line.total - previous_total;
+            if movement.is_zero() {
+                continue;
+            }
+
+            // A chargeback reverses the credit of the deposit it was raised against.
+            let mark = match (line.txn_type.as_ref(), movement.is_sign_negative()) {
+                ("chargeback", _) => "RC",
+                (_, true) => "D",
+                (_, false) => "C",
+            };
+            let tx = line.tx.to_string();
+            let reference = if tx.len() <= MAX_REFERENCE_LEN {
+                tx.as_str()
+            } else {
+                "NONREF"
+            };
+            // Writing to a string can't fail, so neither can any of the writes below.
+            let _ = writeln!(
+                out,
+                ":61:{}{mark}{}NMSC{reference}",
+                date(line.applied_at.unwrap_or(now)),
+                amount(movement.abs()),
+            );
+
+            let mut information = format!("{} {tx}", line.txn_type);
+            if let Some(memo) = &line.memo {
+                information = format!("{information} {memo}");
+            }
+            let _ = writeln!(out, ":86:{}", wrap_information(&information));
+        }
+    }
+
+    // The statement's fields ahead of its entries, with its opening balance.
+    pub(crate) fn header(&self, account: &Account, currency: &str) -> String {
+        let now = now();
+        let (opened_at, opening_total) = self.opening.unwrap_or((None, account.total()));
+        let mut header = String::new();
+        let _ = writeln!(header, ":20:STMT{}", date(self.closed_at.unwrap_or(now)));
+        let _ = writeln!(header, ":25:{}", account.id());
+        let _ = writeln!(header, ":28C:1/1");
+        let _ = writeln!(
+            header,
+            ":60F:{}",
+            balance(opening_total, opened_at.unwrap_or(now), currency)
+        );
+        header
+    }
+
+    // The statement's fields after its entries, with its closing balances.
+    pub(crate) fn trailer(&self, account: &Account, currency: &str) -> String {
+        let closed_at = self.closed_at.unwrap_or_else(now);
+        let mut trailer = String::new();
+        let _ = writeln!(
+            trailer,
+            ":62F:{}",
+            balance(account.total(), closed_at, currency)
+        );
+        let _ = writeln!(
+            trailer,
+            ":64:{}",
+            balance(account.available(), closed_at, currency)
+        );
+        trailer.push_str("-\n");
+        trailer
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+// What the account's total funds were before the given transaction was applied.
+fn opening_total(first: &StatementLine) -> Decimal {
+    let amount = first.amount.unwrap_or_default();
+    match first.txn_type.as_ref() {
+        "deposit" => first.total - amount,
+        "withdrawal" | "chargeback" => first.total + amount,
+        "dispute" | "resolve" => first.total,
+        _ => Decimal::ZERO,
+    }
+}
+
+// A balance field, e.g. `C240102EUR1500,00`.
+fn balance(amount: Decimal, at: u64, currency: &str) -> String {
+    let mark = if amount.is_sign_negative() { "D" } else { "C" };
+    format!("{mark}{}{currency}{}", date(at), self::amount(amount.abs()))
+}
+
+// Amounts are written with a decimal comma, which must be present even if there are no decimals.
+fn amount(amount: Decimal) -> String {
+    let amount = amount.normalize().to_string().replace('.', ",");
+    if amount.contains(',') {
+        amount
+    } else {
+        format!("{amount},")
+    }
+}
+
+// The date of the given time in milliseconds since the Unix epoch, as YYMMDD.
+fn date(millis: u64) -> String {
+    let days = (millis / 86_400_000) as i64;
+    let (year, month, day) = civil_from_days(days);
+    format!("{:02}{month:02}{day:02}", year % 100)
+}
+
+// Splits the information about an entry over as many lines as it needs, up to the most allowed,
+// truncating whatever doesn't fit.
+fn wrap_information(information: &str) -> String {
+    information
+        .chars()
+        .collect::<Vec<_>>()
+        .chunks(INFORMATION_LINE_LEN)
+        .take(INFORMATION_LINES)
+        .map(|chunk| chunk.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::models::{
+        account::RawAccountId,
+        transaction::{RawTransactionId, Transaction, TransactionType},
+    };
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(date(1_704_153_600_000), "240102");
+    }
+
+    #[test]
+    fn renders_statements() -> Result<(), Box<dyn Error>> {
+        let id: RawAccountId = 7;
+        let mut account = Account::new(id.into());
+        let txns = [
+            (1, TransactionType::Deposit { amount: 100.into() }),
+            (
+                2,
+                TransactionType::Withdrawal {
+                    amount: Decimal::new(2550, 2),
+                },
+            ),
+            (1, TransactionType::Dispute),
+            (1, TransactionType::Chargeback),
+        ];
+
+        let mut lines = vec![];
+        for (txn_id, txn_type) in txns {
+            let txn_id: RawTransactionId = txn_id;
+            let txn = Transaction::new(txn_id.into(), id.into(), txn_type.clone());
+            account.process_txn(txn.clone())?;
+            let amount = txn_type
+                .amount()
+                .or_else(|| account.past_txn(txn.id())?.txn_type().amount());
+            lines.push(StatementLine {
+                tx: txn.id(),
+                txn_type: txn_type.owned_name(),
+                amount,
+                available: account.available(),
+                held: account.held(),
+                total: account.total(),
+                locked: account.locked(),
+                memo: (txn_id == 2).then(|| "Rent".into()),
+                applied_at: Some(1_704_153_600_000),
+            });
+        }
+
+        let statement = render(&account, &lines, "EUR");
+        assert_eq!(
+            statement,
+            ":20:STMT240102\n\
+             :25:7\n\
+             :28C:1/1\n\
+             :60F:C240102EUR0,\n\
+             :61:240102C100,NMSC1\n\
+             :86:deposit 1\n\
+             :61:240102D25,5NMSC2\n\
+             :86:withdrawal 2 Rent\n\
+             :61:240102RC100,NMSC1\n\
+             :86:chargeback 1\n\
+             :62F:D240102EUR25,5\n\
+             :64:D240102EUR25,5\n\
+             -\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+use crate::models::{
+    account::{Account, TransactionError},
+    transaction::OrderedTransaction,
+};
+
+// Observers are notified by the worker threads of a `TransactionProcessor` as transactions are
+// processed. Callbacks are invoked on the worker thread that owns the account, in the order the
+// transactions for that account were processed, so implementations must be thread-safe and
+// should avoid blocking for long periods of time as they stall the worker.
+pub trait EventObserver: Send + Sync {
+    // Called after a transaction has been successfully applied to an account.
+    fn on_applied(&self, _txn: &OrderedTransaction, _account: &Account) {}
+
+    // Called when an account refused to apply a transaction.
+    fn on_rejected(&self, _txn: &OrderedTransaction, _account: &Account, _err: &TransactionError) {}
+
+    // Called when an account becomes locked as a result of applying a transaction.
+    fn on_account_locked(&self, _account: &Account) {}
+
+    // Called once by each worker when it shuts down, with the final state of its accounts.
+    fn on_shutdown(&self, _accounts: &[Account]) {}
+}
@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+
+use crate::importer::{ImportedTransaction, StatementRecord};
+use crate::models::{
+    account::AccountId,
+    transaction::{self, Transaction, TransactionId, TransactionType},
+};
+use crate::rejects::Rejection;
+
+// Reads the transactions from an OFX bank statement, of either the SGML (1.x) or XML (2.x) flavour.
+// Credits become deposits and debits withdrawals, to the account whose `<ACCTID>` most recently
+// precedes them, and each is identified by its `<FITID>`. Any other details of the statement are
+// ignored, other than the memo or payee of each transaction, which is carried through as its memo.
+pub fn parse(text: &str) -> Vec<StatementRecord> {
+    let mut records = vec![];
+    let mut account: Option<String> = None;
+    // The fields of the `<STMTTRN>` aggregate being read, if any, and the line it started on.
+    let mut txn: Option<(u64, HashMap<&str, String>)> = None;
+    for element in Elements::new(text) {
+        match (element.tag, &mut txn) {
+            ("STMTTRN", _) => txn = Some((element.line, HashMap::new())),
+            ("/STMTTRN", Some(_)) => {
+                let (line, fields) = txn.take().expect("transaction is being read");
+                records.push(StatementRecord {
+                    line,
+                    result: to_transaction(account.as_deref(), &fields, line),
+                });
+            }
+            ("ACCTID", None) => account = Some(element.value),
+            (tag, Some((_, fields))) if !tag.starts_with('/') => {
+                fields.insert(tag, element.value);
+            }
+            _ => {}
+        }
+    }
+    records
+}
+
+fn to_transaction(
+    account: Option<&str>,
+    fields: &HashMap<&str, String>,
+    line: u64,
+) -> Result<ImportedTransaction, Rejection> {
+    let reject = |column: &str, reason: String| Rejection {
+        line: Some(line),
+        column: Some(column.to_owned()),
+        raw_field: fields.get(column).cloned(),
+        reason,
+        memo: memo(fields).map(str::to_owned),
+    };
+
+    let account = account.ok_or_else(|| {
+        reject(
+            "ACCTID",
+            "The transaction precedes any account ID in the statement".to_owned(),
+        )
+    })?;
+    let account_id = AccountId::parse(account).map_err(|err| reject("ACCTID", err))?;
+
+    let id = match fields.get("FITID") {
+        Some(fitid) if !fitid.is_empty() => TransactionId::from_reference(fitid),
+        _ => return Err(reject("FITID", "The transaction has no ID".to_owned())),
+    };
+
+    let amount = fields
+        .get("TRNAMT")
+        .ok_or_else(|| reject("TRNAMT", "The transaction has no amount".to_owned()))?;
+    // Some banks write amounts with a decimal comma.
+    let amount: Decimal = amount
+        .replace(',', ".")
+        .parse()
+        .map_err(|err| reject("TRNAMT", format!("The amount is not a number: {err}")))?;
+    let txn_type = if amount.is_sign_negative() {
+        TransactionType::Withdrawal { amount: -amount }
+    } else {
+        TransactionType::Deposit { amount }
+    };
+    if let Some(amount) = txn_type.amount() {
+        transaction::validate_amount(amount).map_err(|err| reject("TRNAMT", err.to_string()))?;
+    }
+
+    Ok(ImportedTransaction {
+        txn: Transaction::new(id, account_id, txn_type),
+        memo: memo(fields).and_then(transaction::memo),
+    })
+}
+
+fn memo<'a>(fields: &'a HashMap<&str, String>) -> Option<&'a str> {
+    ["MEMO", "NAME"]
+        .iter()
+        .filter_map(|tag| fields.get(tag))
+        .map(String::as_str)
+        .find(|memo| !memo.is_empty())
+}
+
+// An element of an OFX document: a tag, and the text that follows it up to the next tag. Closing
+// tags are included, with their leading `/`.
+struct Element<'a> {
+    line: u64,
+    tag: &'a str,
+    value: String,
+}
+
+// Splits an OFX document into its elements. SGML flavoured OFX leaves the elements that hold a
+// value unclosed, e.g. `<TRNAMT>-20.00`, so rather than parse the document as a tree we take each
+// value to be whatever text follows its tag.
+struct Elements<'a> {
+    rest: &'a str,
+    line: u64,
+}
+
+impl<'a> Elements<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            rest: text,
+            line: 1,
+        }
+    }
+
+    // Moves past the given number of bytes, keeping track of the line we're on.
+    fn advance(&mut self, len: usize) -> &'a str {
+        let (skipped, rest) = self.rest.split_at(len);
+        self.line += skipped.matches('\n').count() as u64;
+        self.rest = rest;
+        skipped
+    }
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = Element<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Anything before the first tag is the SGML flavour's header, which we don't need.
+            let start = self.rest.find('<')?;
+            self.advance(start + 1);
+            let line = self.line;
+            let end = self.rest.find('>')?;
+            let tag = self.advance(end).trim();
+            self.advance(1);
+            let value_end = self.rest.find('<').unwrap_or(self.rest.len());
+            let value = self.advance(value_end).trim();
+
+            // Skip the XML flavour's declarations and any comments.
+            if tag.starts_with('?') || tag.starts_with('!') {
+                continue;
+            }
+            return Some(Element {
+                line,
+                tag,
+                value: decode_entities(value),
+            });
+        }
+    }
+}
+
+fn decode_entities(value: &str) -> String {
+    if !value.contains('&') {
+        return value.to_owned();
+    }
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use uuid::Uuid;
+
+    #[test]
+    fn reads_sgml_statements() -> Result<(), Box<dyn Error>> {
+        let statement = "OFXHEADER:100\n\
+                         DATA:OFXSGML\n\
+                         \n\
+                         <OFX>\n\
+                         <BANKMSGSRSV1><STMTTRNRS><STMTRS>\n\
+                         <BANKACCTFROM><BANKID>121000248<ACCTID>GB33BUKB20201555555555<ACCTTYPE>CHECKING</BANKACCTFROM>\n\
+                         <BANKTRANLIST>\n\
+                         <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240102<TRNAMT>1500.00<FITID>1001<NAME>ACME PAYROLL</STMTTRN>\n\
+                         <STMTTRN>\n\
+                         <TRNTYPE>DEBIT\n\
+                         <DTPOSTED>20240103\n\
+                         <TRNAMT>-42,50\n\
+                         <FITID>AB-77/2024\n\
+                         <NAME>Corner Shop\n\
+                         <MEMO>Groceries &amp; sundries\n\
+                         </STMTTRN>\n\
+                         <STMTTRN><TRNTYPE>DEBIT<TRNAMT>lots<FITID>1003</STMTTRN>\n\
+                         </BANKTRANLIST>\n\
+                         </STMTRS></STMTTRNRS></BANKMSGSRSV1>\n\
+                         </OFX>\n";
+        let records = parse(statement);
+        assert_eq!(records.len(), 3);
+
+        let account_id = AccountId::parse("GB33BUKB20201555555555")?;
+        let credit = records[0].result.clone()?;
+        assert_eq!(records[0].line, 8);
+        assert_eq!(credit.txn.id(), TransactionId::Number(1001));
+        assert_eq!(credit.txn.account_id(), account_id);
+        assert_eq!(credit.txn.txn_type().amount(), Some(Decimal::new(1500, 0)));
+        assert_eq!(credit.txn.txn_type().name(), "deposit");
+        assert_eq!(credit.memo.as_deref(), Some("ACME PAYROLL"));
+
+        let debit = records[1].result.clone()?;
+        assert_eq!(records[1].line, 9);
+        assert_eq!(
+            debit.txn.id(),
+            TransactionId::from_reference("AB-77/2024"),
+            "free-form IDs should be mapped to UUIDs"
+        );
+        assert_eq!(debit.txn.txn_type().name(), "withdrawal");
+        assert_eq!(debit.txn.txn_type().amount(), Some(Decimal::new(4250, 2)));
+        assert_eq!(debit.memo.as_deref(), Some("Groceries & sundries"));
+
+        let rejection = records[2].result.as_ref().unwrap_err();
+        assert_eq!(rejection.line, Some(17));
+        assert_eq!(rejection.column.as_deref(), Some("TRNAMT"));
+        assert_eq!(rejection.raw_field.as_deref(), Some("lots"));
+        Ok(())
+    }
+
+    #[test]
+    fn reads_xml_statements() -> Result<(), Box<dyn Error>> {
+        let statement = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX>
+  <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+    <CCACCTFROM><ACCTID>4111</ACCTID></CCACCTFROM>
+    <BANKTRANLIST>
+      <STMTTRN>
+        <TRNTYPE>DEBIT</TRNTYPE>
+        <TRNAMT>-9.99</TRNAMT>
+        <FITID>0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d</FITID>
+        <MEMO></MEMO>
+      </STMTTRN>
+    </BANKTRANLIST>
+  </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>
+"#;
+        let records = parse(statement);
+        assert_eq!(records.len(), 1);
+
+        let txn = records[0].result.clone()?;
+        assert_eq!(records[0].line, 7);
+        assert_eq!(
+            txn.txn.id(),
+            TransactionId::Uuid(Uuid::parse_str("0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d")?)
+        );
+        assert_eq!(txn.txn.account_id(), AccountId::Number(4111));
+        assert_eq!(txn.txn.txn_type().amount(), Some(Decimal::new(999, 2)));
+        assert_eq!(txn.memo.as_deref(), None);
+        Ok(())
+    }
+}
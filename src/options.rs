@@ -1,36 +1,1065 @@
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use structopt::StructOpt;
+use glob::Pattern;
+use rust_decimal::Decimal;
+use structopt::{clap, StructOpt};
+use uuid::Uuid;
+
+use crate::affinity::WorkerPlacement;
+use crate::config::{self, Config, ConfigError};
+use crate::input::{self, FileOrder, InputFormat};
+
+use crate::models::account::{DedupWindow, ErrorClass};
+use crate::partition::PartitionStrategy;
+use crate::remote;
+use crate::sink::{ClientList, SinkFormat, SinkTarget};
+use crate::statements::StatementFormat;
+use crate::stats::TxnStatsOutput;
+
+#[derive(Debug, StructOpt)]
+pub struct Options {
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+impl Options {
+    // Parses the command line arguments. If no subcommand is given then the `process` subcommand
+    // is assumed, so that `banking-exercise <TRANSACTIONS_FILE>` continues to work as it always
+    // has.
+    pub fn parse() -> Self {
+        let mut args: Vec<OsString> = env::args_os().collect();
+        let has_subcommand = args.get(1).and_then(|arg| arg.to_str()).is_some_and(|arg| {
+            SUBCOMMANDS.contains(&arg) || matches!(arg, "-h" | "--help" | "-V" | "--version")
+        });
+        if args.len() > 1 && !has_subcommand {
+            args.insert(1, "process".into());
+        }
+        let from_env = match with_config(&mut args, env::vars_os().collect()) {
+            Ok(from_env) => from_env,
+            Err(err) => {
+                clap::Error::with_description(&err.to_string(), clap::ErrorKind::InvalidValue)
+                    .exit()
+            }
+        };
+        match Self::from_iter_safe(&args) {
+            Ok(opts) => opts,
+            Err(err) => unknown_env_var(&err, &from_env)
+                .map_or(err, |var| {
+                    clap::Error::with_description(
+                        &format!("The environment variable {var} doesn't stand for any option"),
+                        clap::ErrorKind::UnknownArgument,
+                    )
+                })
+                .exit(),
+        }
+    }
+}
+
+// Adds the options set in the configuration file given to `--config`, or else by `BANKING_CONFIG`,
+// and by `BANKING_*` environment variables to the arguments of the `process`, `verify` or
+// `reconcile` subcommand. Options given on the command line take precedence over those in the
+// file, which take precedence over those in the environment, as given by `vars`. They're added
+// ahead of the rest of the arguments, as though they'd been given first. Returns the options that
+// were given by the environment.
+fn with_config(
+    args: &mut Vec<OsString>,
+    vars: Vec<(OsString, OsString)>,
+) -> Result<HashSet<String>, ConfigError> {
+    if !matches!(
+        args.get(1).and_then(|arg| arg.to_str()),
+        Some("process" | "verify" | "reconcile")
+    ) {
+        return Ok(HashSet::new());
+    }
+    let given = given_options(&args[2..]);
+    let config = given.iter().enumerate().find_map(|(idx, arg)| match *arg {
+        "--config" => given.get(idx + 1).map(PathBuf::from),
+        arg => arg.strip_prefix("--config=").map(PathBuf::from),
+    });
+    let mut given: HashSet<String> = given
+        .iter()
+        .flat_map(|arg| option_names(arg))
+        .map(str::to_owned)
+        .collect();
+    with_conflicting(&mut given);
+
+    let mut layered = vec![];
+    let config_var = config::env_var("config");
+    if let Some(config) = config.or_else(|| {
+        vars.iter()
+            .find(|(var, _)| *var == *config_var)
+            .map(|(_, path)| PathBuf::from(path))
+    }) {
+        let config = Config::load(&config)?;
+        layered.extend(config.args(&given));
+        given.extend(config.options().map(str::to_owned));
+        with_conflicting(&mut given);
+    }
+    let env = Config::from_env(vars);
+    layered.extend(env.args(&given));
+    args.splice(2..2, layered);
+    Ok(env
+        .options()
+        .filter(|option| !given.contains(*option))
+        .map(str::to_owned)
+        .collect())
+}
+
+// The environment variable that gave the option the arguments were rejected for not knowing, if it
+// was given by one.
+fn unknown_env_var(err: &clap::Error, from_env: &HashSet<String>) -> Option<String> {
+    if err.kind != clap::ErrorKind::UnknownArgument {
+        return None;
+    }
+    let option = *option_names(err.info.as_ref()?.first()?).first()?;
+    from_env.contains(option).then(|| config::env_var(option))
+}
+
+// The arguments that may be options, as those given after `--` are taken to be positional.
+fn given_options(args: &[OsString]) -> Vec<&str> {
+    args.iter()
+        .filter_map(|arg| arg.to_str())
+        .take_while(|arg| *arg != "--")
+        .collect()
+}
+
+// The short options of the subcommands that process transactions, the long options they stand for,
+// and whether they take a value.
+const SHORT_OPTIONS: &[(char, &str, bool)] = &[
+    ('w', "num-workers", true),
+    ('v', "verbose", false),
+    ('q', "quiet", false),
+];
+
+// Options that conflict with one another, so that giving any of them in one layer overrides each
+// of them in the layers below it.
+const CONFLICTING_OPTIONS: &[&[&str]] = &[&["verbose", "quiet"]];
+
+// The long names of the options given by an argument, of which a cluster of short options, such as
+// -vv or -qw4, may give more than one.
+fn option_names(arg: &str) -> Vec<&str> {
+    if let Some(option) = arg.strip_prefix("--") {
+        return vec![option.split_once('=').map_or(option, |(option, _)| option)];
+    }
+    let mut names = vec![];
+    for short in arg.strip_prefix('-').unwrap_or_default().chars() {
+        let Some(&(_, name, takes_value)) =
+            SHORT_OPTIONS.iter().find(|(option, ..)| *option == short)
+        else {
+            break;
+        };
+        names.push(name);
+        // The rest of the argument is the option's value.
+        if takes_value {
+            break;
+        }
+    }
+    names
+}
+
+// Adds the options that conflict with those given to them, as they're given too in effect.
+fn with_conflicting(given: &mut HashSet<String>) {
+    for options in CONFLICTING_OPTIONS {
+        if options.iter().any(|option| given.contains(*option)) {
+            given.extend(options.iter().map(|option| (*option).to_owned()));
+        }
+    }
+}
+
+const SUBCOMMANDS: &[&str] = &[
+    "process",
+    "verify",
+    "reconcile",
+    "generate",
+    "merge",
+    "replay",
+    "report",
+    "help",
+];
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    #[structopt(
+        about = "Process a file of transactions, and write out the resulting accounts. This is the default when no subcommand is given."
+    )]
+    Process(ProcessOptions),
+
+    #[structopt(
+        about = "Process a file of transactions, and check that the resulting accounts satisfy the ledger's invariants. The accounts are only written out if outputs are given."
+    )]
+    Verify(ProcessOptions),
+
+    #[structopt(
+        about = "Process a file of transactions, and compare the resulting accounts against those expected, printing any mismatches and failing if there are any. Shorthand for process --reconcile, whose accounts are only written out if outputs are given."
+    )]
+    Reconcile(ReconcileOptions),
+
+    #[structopt(about = "Generate a file of transactions in CSV format, for load testing.")]
+    Generate(GenerateOptions),
+
+    #[structopt(
+        about = "Merge snapshots of disjoint sets of accounts, such as those from runs over separate account ranges, into a single snapshot."
+    )]
+    Merge(MergeOptions),
+
+    #[structopt(
+        about = "Reconstruct the accounts of a run purely from its audit journal, checking that replaying each transaction leaves the account with the balances the journal recorded, and write them out as CSV."
+    )]
+    Replay(ReplayOptions),
+
+    #[structopt(about = "Report on the books kept by a run, such as for month-end close checks.")]
+    Report(ReportCommand),
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ReportCommand {
+    #[structopt(
+        about = "Total the postings of a general ledger export by sub-ledger, and write them out as CSV, checking that debits equal credits both overall and for each transaction."
+    )]
+    TrialBalance(TrialBalanceOptions),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ProcessOptions {
+    #[structopt(
+        name = "TRANSACTIONS_FILE",
+        parse(from_os_str),
+        help = "Path to a file containing transactions in CSV format, or to a directory of such files to be processed in turn as one stream. May also be the URL of an object in S3 (s3://), GCS (gs://), or Azure (az://), or of a file served over HTTP(S), which is streamed without being downloaded first; credentials for object stores are taken from the usual AWS_*, GOOGLE_*, or AZURE_* environment variables, and interrupted HTTP(S) downloads are resumed, unless the file has changed since, by its ETag or Last-Modified date. Named pipes and process substitutions such as <(zcat txns.csv.gz) are also accepted.",
+        validator(is_input_location)
+    )]
+    pub input_file: PathBuf,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Read options from the given TOML file, with a setting for each named after its long option, e.g. num-workers = 8, output = [\"csv:accounts.csv\", \"json:accounts.jsonl\"], or deterministic = true. Options given on the command line take precedence over those in the file, though a flag set in the file can't be unset, and those in the file take precedence over BANKING_* environment variables, such as BANKING_NUM_WORKERS. The file may also be given by BANKING_CONFIG."
+    )]
+    pub config: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "*.csv",
+        help = "When the transactions file is a directory, only the files within it whose names match this glob pattern are processed."
+    )]
+    pub pattern: Pattern,
+
+    #[structopt(
+        long,
+        default_value = "lexicographic",
+        possible_values = &["lexicographic", "mtime"],
+        help = "When the transactions file is a directory, the order in which its files are processed as one stream: by name, or by modification time."
+    )]
+    pub file_order: FileOrder,
+
+    #[structopt(
+        long,
+        default_value = "csv",
+        possible_values = &["csv", "ofx", "camt053", "qif", "xlsx", "avro", "protobuf", "arrow"],
+        help = "The format of the transactions files: CSV, or OFX, ISO 20022 camt.053, or QIF bank statements whose credits and debits are processed as deposits and withdrawals to the statement's account, or Excel workbooks with the same columns as CSV files (only in builds with the xlsx feature), or Avro container files whose records have the same fields as CSV files (only in builds with the avro feature), or streams of length-delimited protobuf messages of the Transaction type in proto/banking.proto (only in builds with the protobuf feature), or Arrow IPC (Feather) files or streams with the same columns as CSV files (only in builds with the arrow feature). The options for parsing CSV don't apply to the other formats, and a directory of them needs a --pattern such as '*.ofx' or '*.avro'. Rejected Avro records, protobuf messages, and Arrow rows are reported by their number within the file in place of a line number."
+    )]
+    pub format: InputFormat,
+
+    #[structopt(
+        long,
+        help = "The sheet of each Excel workbook to read transactions from. Defaults to the first sheet."
+    )]
+    pub sheet: Option<String>,
+
+    #[structopt(
+        long,
+        name = "SCHEMA_AVSC",
+        parse(from_os_str),
+        help = "Resolve the records of Avro files against the given schema, rather than read them with the schema embedded in each file. Files whose schema is incompatible with it have their records rejected."
+    )]
+    pub avro_schema: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        name = "PROFILE_TOML",
+        parse(from_os_str),
+        help = "Map the columns of CSV, QIF, or Excel files shaped differently to our own onto transactions, as described by the given TOML bank profile: which columns hold the client, transaction ID, type, amount (or separate debit and credit amounts), memo, and date, along with the delimiter, the decimal separator, the sign convention of amounts, the date format, and the names of transaction types."
+    )]
+    pub bank_profile: Option<PathBuf>,
+
+    #[structopt(
+        short = "w",
+        long,
+        help = "Number of transaction processing worker threads. Defaults to an optimum number based on the number of physical cores on the system.",
+        validator(is_greater_than_zero)
+    )]
+    pub num_workers: Option<usize>,
+
+    #[structopt(
+        long,
+        conflicts_with = "num-workers",
+        help = "Process every transaction on the main thread, strictly in the order they are read, and write out the accounts in order of client ID. This makes the results entirely deterministic, for golden-file regression testing and debugging."
+    )]
+    pub deterministic: bool,
+
+    #[structopt(
+        long,
+        alias = "profile-pipeline",
+        help = "Time each transaction through the read, deserialize, dispatch, queue wait, and apply stages, and print a per-stage breakdown of their latencies, and of the share of the time spent in each, to stderr when finished. Files that are imported whole, such as bank statements, are read and deserialized in one go, and are timed as such. The setup of each CSV file, from opening it to working out how to parse its columns, is timed once per file."
+    )]
+    pub timings: bool,
+
+    #[structopt(
+        long,
+        name = "STRATEGY",
+        default_value = "modulo",
+        help = "How accounts are shared out between the workers: 'modulo' assigns them in turn by ID, 'consistent-hash' by the hash of their ID, and 'least-loaded' to whichever worker has been sent the fewest transactions when an account is first seen. Ranges of IDs may also be mapped to specific workers with e.g. 'ranges:1-1000=0,1001-2000=1', with any other accounts assigned by modulo."
+    )]
+    pub partitioner: PartitionStrategy,
+
+    #[structopt(
+        long,
+        name = "PLACEMENT",
+        default_value = "float",
+        help = "Where the workers run: 'float' leaves them to the scheduler, 'cores' pins each to a CPU of its own, 'numa' pins each to the CPUs of a NUMA node, sharing the workers out across the nodes, and 'auto' does so on machines with more than one node. Pinned workers allocate their accounts on their own node. Only on Linux."
+    )]
+    pub worker_placement: WorkerPlacement,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["deterministic", "wal-dir"],
+        help = "Scale the workers of a run that follows its input or serves a control socket to their load, up to this many. A worker is added while their queues back up or they're kept busy, and retired while they're mostly idle, with their accounts handed over to the workers that then own them. Can't be used with bloom dedup, as each worker's filter would be lost.",
+        validator(is_greater_than_zero)
+    )]
+    pub max_workers: Option<usize>,
+
+    #[structopt(
+        long,
+        requires = "max-workers",
+        help = "The fewest workers autoscaling may retire down to. Defaults to one.",
+        validator(is_greater_than_zero)
+    )]
+    pub min_workers: Option<usize>,
+
+    #[structopt(
+        long,
+        help = "Display a progress bar on stderr with the number of transactions read and processed, the current throughput, and an estimated time remaining."
+    )]
+    pub progress: bool,
+
+    #[structopt(
+        long,
+        default_value = "strict",
+        possible_values = &["strict", "lenient"],
+        help = "How to handle malformed transaction records, including those with negative or implausibly large amounts. A strict policy aborts the run on the first malformed record, while a lenient policy logs the rejected record and continues."
+    )]
+    pub error_policy: ErrorPolicy,
+
+    #[structopt(
+        long,
+        default_value = "reject",
+        possible_values = &["reject", "round"],
+        help = "How to handle amounts with more than four decimal places. They are either rejected as malformed records, subject to the error policy, or rounded to four decimal places using banker's rounding."
+    )]
+    pub excess_precision: ExcessPrecision,
+
+    #[structopt(
+        long,
+        help = "Skip records that cannot be parsed rather than aborting the run. Shorthand for --error-policy lenient."
+    )]
+    pub skip_bad_rows: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["FORMAT[:PATH]", "journal", "general-ledger", "snapshot-dir", "wal-dir", "statements-dir", "REDIS_URL", "URL"],
+        help = "Validate every record without posting anything, to pre-flight a file before the run that posts it. Every record is parsed, and the transactions are applied to accounts held only in memory, so that those that would not be applied, such as disputes of transactions that don't exist or withdrawals without the funds, are caught as well. Every problem found is reported as CSV on stdout, or to the rejects file if one is given, in the same columns, and the run fails if there are any. No accounts are written out."
+    )]
+    pub dry_run: bool,
+
+    #[structopt(
+        long,
+        name = "REJECTS_CSV",
+        parse(from_os_str),
+        help = "Write every record that could not be parsed to the given CSV file, along with the file and line it was read from, the offending column and value, the reason it was rejected, its memo if it has one, and the whole record as read, so that it can be corrected and resubmitted."
+    )]
+    pub rejects_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        help = "Tolerate sloppily formatted transaction records, by trimming whitespace from around each field and accepting transaction types in any case, e.g. 'Deposit' or 'DEPOSIT'."
+    )]
+    pub lenient_parsing: bool,
+
+    #[structopt(
+        long,
+        help = "Enforce that transaction IDs are unique across all accounts, not just within each account. Each ID belongs to the account that uses it first in the input, even if that use is rejected, and a deposit or withdrawal that reuses another account's transaction ID is rejected. Every transaction ID seen is kept in memory for the whole run."
+    )]
+    pub global_txn_ids: bool,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["journal", "statements-dir", "EXPECTED_CSV", "REDIS_URL", "account-metadata"],
+        help = "Keep a fully isolated account space for each tenant, as named by the tenant column of the input, which every transaction must then have. The same client in two tenants is two different accounts, and transaction IDs need only be unique within a tenant. The accounts are written with their tenant, or to a file per tenant given an output path containing {tenant}, e.g. --output 'csv:accounts-{tenant}.csv', and the metrics are broken down by tenant."
+    )]
+    pub multi_tenant: bool,
+
+    #[structopt(
+        long,
+        name = "COUNT",
+        validator(is_nonzero_count),
+        help = "Hold on to at most this many past deposits and withdrawals per account in case of future disputes, evicting the least recently recorded transactions that aren't in dispute beyond that. Disputes of evicted transactions are errored. The IDs of as many evicted transactions again are kept to detect duplicates of them, and those evicted before are forgotten, unless duplicates are detected with --dedup bloom, in which case none are kept. By default, every transaction is held on to."
+    )]
+    pub max_history_per_account: Option<usize>,
+
+    #[structopt(
+        long,
+        name = "WINDOW",
+        help = "Only detect duplicate deposits and withdrawals among the last this many recorded per account, or among those posted within this span of time if given with a unit, e.g. 90s, 30m, 24h or 7d, as of the timestamp column of the input. Transactions without a timestamp are taken to have been posted with the last one that had one, so a time window forgets nothing of an input without timestamps. Transactions outside of the window are forgotten unless they're in dispute, so they can no longer be disputed, and a later transaction reusing their ID is applied as a new one. This bounds the memory held per account, which is otherwise dominated by the IDs of past deposits, at the cost of letting older duplicates through. By default, duplicates are detected for the whole run."
+    )]
+    pub dedup_window: Option<DedupWindow>,
+
+    #[structopt(
+        long,
+        default_value = "exact",
+        possible_values = &["exact", "bloom"],
+        help = "How duplicate deposits and withdrawals are detected. Exact detection relies on each account's history of transactions, while bloom detection additionally uses a Bloom filter of fixed size, which may occasionally mistake a new transaction for a duplicate."
+    )]
+    pub dedup: Dedup,
+
+    #[structopt(
+        long,
+        name = "TRANSACTIONS",
+        default_value = "100000000",
+        validator(is_nonzero_count),
+        help = "The number of deposits and withdrawals the Bloom filter is sized for. Beyond this, new transactions are increasingly likely to be mistaken for duplicates. Each worker's filter is sized for its share, with room for the more accounts than its share it's likely to end up with given --expected-accounts."
+    )]
+    pub dedup_capacity: u64,
+
+    #[structopt(
+        long,
+        name = "RATE",
+        default_value = "0.0001",
+        validator(is_probability),
+        help = "The rate at which the Bloom filter may mistake a new transaction for a duplicate, up to its capacity. Lower rates take more memory."
+    )]
+    pub dedup_fp_rate: f64,
+
+    #[structopt(
+        long,
+        name = "DELIMITER",
+        default_value = ",",
+        help = "The character that separates the fields of each transaction record, e.g. ';', or '\\t' for a tab."
+    )]
+    pub delimiter: CsvChar,
+
+    #[structopt(
+        long,
+        name = "QUOTE",
+        default_value = "\"",
+        help = "The character that quotes fields containing the delimiter."
+    )]
+    pub quote: CsvChar,
+
+    #[structopt(
+        long,
+        help = "Treat quote characters like any other, for files whose fields are never quoted."
+    )]
+    pub no_quoting: bool,
+
+    #[structopt(
+        long,
+        help = "The transactions files have no header row, and their columns are instead taken to be type, client, tx and amount, in that order."
+    )]
+    pub no_headers: bool,
+
+    #[structopt(
+        long,
+        conflicts_with = "follow",
+        help = "Map CSV transactions files into memory and parse them in parallel, split into chunks of whole rows, rather than streaming them in on a single thread. Records are still processed in the order they appear in. Inputs that can't be mapped, such as pipes and remote files, are streamed as usual."
+    )]
+    pub mmap: bool,
+
+    #[structopt(
+        long,
+        requires = "mmap",
+        help = "Number of threads parsing the chunks of a mapped transactions file. Defaults to the number of physical cores on the system.",
+        validator(is_greater_than_zero)
+    )]
+    pub parse_threads: Option<usize>,
+
+    #[structopt(
+        long,
+        conflicts_with_all = &["follow", "mmap"],
+        help = "Read local transactions files through io_uring, keeping several reads in flight while earlier data is parsed, for filesystems with high latency. Only on Linux, in builds with the io-uring feature."
+    )]
+    pub io_uring: bool,
+
+    #[structopt(
+        long,
+        help = "A hint of how many accounts the transactions are for, so that each worker makes room for its share of them up front rather than as they turn up.",
+        validator(is_greater_than_zero)
+    )]
+    pub expected_accounts: Option<u64>,
+
+    #[structopt(
+        long,
+        requires = "expected-accounts",
+        help = "A hint of how many transactions there are, so that the history of each account is made room for up front, to hold its share of them.",
+        validator(is_greater_than_zero)
+    )]
+    pub expected_transactions: Option<u64>,
+
+    #[structopt(
+        long,
+        default_value = "text",
+        possible_values = &["text", "json"],
+        help = "The format of logs written to stderr, or to --log-file."
+    )]
+    pub log_format: LogFormat,
+
+    #[structopt(
+        short,
+        long,
+        parse(from_occurrences),
+        help = "Log more to stderr: -v logs warnings, such as transactions that were rejected, -vv also logs each transaction read and the progress of the run, and -vvv and -vvvv log debugging and tracing details too. Only errors are logged otherwise. RUST_LOG may still be set to adjust what's logged, on top of the level chosen."
+    )]
+    pub verbose: u8,
+
+    #[structopt(
+        short,
+        long,
+        conflicts_with = "verbose",
+        help = "Log nothing at all, not even errors, unless RUST_LOG says otherwise. Errors that fail the run are still reported."
+    )]
+    pub quiet: bool,
+
+    #[structopt(
+        long,
+        name = "LOG_PATH",
+        parse(from_os_str),
+        help = "Append logs to the given file rather than writing them to stderr."
+    )]
+    pub log_file: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        name = "INTERVAL",
+        help = "Log the number of transactions read, processed and rejected so far, and the number processed per second since the last report, every so often while the input is read, e.g. every '30s', '5m' or '1h', so that long runs show signs of life. These reports are logged even without -v, but not with -q."
+    )]
+    pub report_interval: Option<ReportInterval>,
+
+    #[structopt(
+        long,
+        name = "URL",
+        help = "Push a summary of the run's metrics to the Prometheus pushgateway at the given URL once processing has finished. Runs that follow their input or serve a control socket also push how far behind their workers are as they go."
+    )]
+    pub push_gateway: Option<String>,
+
+    #[structopt(
+        long,
+        name = "REDIS_URL",
+        help = "Mirror the balances of each account into Redis as transactions are applied, as a hash under the key account:{id} with the fields available, held, total, locked, and last_order, so that other services can read near real-time balances, e.g. redis://localhost:6379/0. Only in builds with the redis feature."
+    )]
+    pub redis_url: Option<String>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Append every attempted transaction, whether applied or rejected, along with the resulting account balances to the given audit journal file. A journal with other columns, e.g. one written by an older release, is first renamed with the suffix .1, .2, and so on, and a new one started."
+    )]
+    pub journal: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Export the double-entry postings of every transaction applied in the run to the given general ledger CSV file, with the columns date, account, debit, credit, and reference, for importing into a general ledger system. Accounts are named by client and sub-ledger, e.g. 1/available, and each posting is referenced by the run ID and order of its transaction, as in the audit journal."
+    )]
+    pub general_ledger: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write a snapshot of all accounts into the given directory once every transaction has been processed. Each worker writes its own shard of the accounts in parallel, alongside a manifest."
+    )]
+    pub snapshot_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        name = "RECORDS",
+        requires = "snapshot-dir",
+        validator(is_nonzero_count),
+        help = "Additionally write a checkpoint snapshot into the snapshot directory after every RECORDS records read. Checkpoints are written by the workers, and do not pause reading of the input."
+    )]
+    pub checkpoint_every: Option<u64>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Carry on from the accounts of the given snapshot directory, such as the final snapshot of yesterday's run, applying only the transactions of the input to them rather than reprocessing their whole history. Disputes may refer to transactions applied by earlier runs. Unless --starting-order is given, orders carry on from the snapshot's next_order. Combine with --snapshot-dir to write a snapshot for the next run to carry on from in turn."
+    )]
+    pub base_snapshot: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Have each worker append every transaction to a write-ahead log in the given directory before applying it. Should the run crash, running it again over the same input recovers the accounts from the latest snapshot in the snapshot directory and the transactions logged since, and then carries on from where the run got to. Transactions may be seen twice by the journal and other outputs of a recovered run. The run fails should a transaction not be logged, rather than applying it. The logs are removed once a run completes."
+    )]
+    pub wal_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Write a statement for each account into the given directory, listing its transactions and dispute events in order along with the running balances."
+    )]
+    pub statements_dir: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "csv",
+        help = "The format of the statements written to --statements-dir: csv, or mt940 for SWIFT MT940 customer statements for legacy systems. MT940 statements are of the accounts' total funds, in the currency given as e.g. 'mt940:EUR', which otherwise defaults to XXX (no currency)."
+    )]
+    pub statement_format: StatementFormat,
+
+    #[structopt(
+        long,
+        name = "EXPECTED_CSV",
+        parse(from_os_str),
+        validator(is_file),
+        help = "Compare the resulting accounts against those in the given CSV file, in the same format as the output. Any mismatches are printed to stderr, and the run fails if there are any."
+    )]
+    pub reconcile: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        help = "Load each account's display name, opened-at date, and region from the given CSV file, with the columns client, name, opened_at, and region. The metadata is included in the outputs and the audit journal."
+    )]
+    pub account_metadata: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        parse(from_os_str),
+        conflicts_with = "multi-tenant",
+        help = "Expand the standing orders listed in the given CSV file, with the columns client, type, amount, frequency, start, and end, into deposits or withdrawals made daily, weekly, or monthly from their start until their end, if any, e.g. 7,withdrawal,50.00,weekly,2024-01-01,. Each is made just before the first transaction of the input timestamped after it falls due, by the input's timestamp column of ISO 8601 dates or times in UTC, such as 2024-01-31 or 2024-01-31T09:30:00Z. Only applies to CSV input."
+    )]
+    pub standing_orders: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        default_value = "input",
+        possible_values = &["input", "effective-date"],
+        help = "The order to apply transactions in: the order they appear in the input, or by their effective date, as given by the input's effective_date column of ISO 8601 dates or times in UTC, so that backdated corrections are applied as of when they took effect. Transactions without an effective date take effect when they were posted, by the timestamp column, and those without either take effect along with the transaction before them; those that take effect at the same time are applied in the order they appear. Ordering by effective date holds the whole input in memory until it has been read, so can't be used with --follow, --control-socket, or --checkpoint-every, and only applies to CSV input. Either way, transactions are reported by when they were posted."
+    )]
+    pub apply_order: ApplyOrder,
+
+    #[structopt(
+        long,
+        name = "ORDER",
+        default_value = "0",
+        help = "The order assigned to the first transaction read, so that a run can continue the sequence of a previous one (e.g. the next_order recorded in a snapshot manifest)."
+    )]
+    pub starting_order: u64,
+
+    #[structopt(
+        long,
+        name = "CLASS",
+        possible_values = &["rejected", "ignored", "errored"],
+        use_delimiter = true,
+        help = "Fail the run if any transactions were not applied for the given reasons: rejected by a business rule, ignored as a duplicate or referring to an unknown transaction, or errored due to an internal problem. All accounts are still processed and written out."
+    )]
+    pub fail_on: Vec<ErrorClass>,
+
+    #[structopt(
+        long,
+        help = "Check that the resulting accounts satisfy the ledger's invariants: each account's total equals its deposits less its withdrawals and chargebacks, and neither its held nor available funds are negative. Every transaction is also checked as it is applied, for ordering, deduplication, memory bounds and its effect on the account's balances, which slows processing considerably. Any violations are printed to stderr, and the run fails if there are any."
+    )]
+    pub verify: bool,
+
+    #[structopt(
+        long,
+        name = "PLACES",
+        default_value = "4",
+        help = "The number of decimal places to render balances with in the output, rounding midpoints away from zero. Use 'full' to render balances with their full precision instead."
+    )]
+    pub output_precision: OutputPrecision,
+
+    #[structopt(
+        long,
+        name = "FORMAT[:PATH]",
+        help = "Write the resulting accounts in the given format, either csv, json (one object per line), or arrow (an Arrow IPC or Feather file, only in builds with the arrow feature), to the given path or to stdout if no path or '-' is given. Accounts may also be written into the accounts table of a SQLite database with sqlite://PATH, or sqlite://PATH?journal to write every attempted transaction into its journal table too, in builds with the sqlite feature; the database is only updated once the run has completed. May be given multiple times to write to several outputs at once. Defaults to csv on stdout."
+    )]
+    pub output: Vec<OutputSpec>,
+
+    #[structopt(
+        long,
+        help = "Keep reading the transactions file as new records are appended to it, like `tail -f`, until interrupted with SIGINT or SIGTERM, after which the run completes as usual. Sending SIGHUP writes an interim snapshot of the accounts into the snapshot directory."
+    )]
+    pub follow: bool,
+
+    #[structopt(
+        long,
+        name = "SOCKET",
+        parse(from_os_str),
+        conflicts_with_all = &["follow", "dry-run"],
+        help = "Stay resident once the transactions file has been processed, serving commands on a Unix socket at the given path, one per connection: 'submit PATH' processes another transactions file against the accounts so far, 'flush' waits for every transaction submitted to be processed, 'snapshot DIR' writes a snapshot of the accounts into the given directory, and 'stop' completes the run as usual, as does SIGINT or SIGTERM. Submitted files are read with the same options as the transactions file."
+    )]
+    pub control_socket: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        name = "TXNS_PER_SEC",
+        validator(is_positive_rate),
+        help = "Submit no more than the given number of transactions per second for processing, holding up reading the input for as long as it's faster, to protect the workers from bursty producers when following a file or serving --control-socket. Bursts of up to a second's worth go through at once."
+    )]
+    pub max_rate: Option<f64>,
+
+    #[structopt(
+        long,
+        name = "CLIENT_TXNS_PER_SEC",
+        validator(is_positive_rate),
+        help = "Submit no more than the given number of transactions per second for any one client, rejecting a client's transactions with the 'rate_limited' error code for as long as they arrive faster, so that other clients aren't held up behind it. Bursts of up to a second's worth go through at once."
+    )]
+    pub max_client_rate: Option<f64>,
+
+    #[structopt(
+        long,
+        help = "Include each account's activity in the output: the number of transactions applied, open disputes, lifetime deposits and withdrawals, and the order and time (in milliseconds since the Unix epoch) of the last transaction applied."
+    )]
+    pub extended_output: bool,
+
+    #[structopt(
+        long,
+        help = "Only write out the accounts that are locked, e.g. to review those frozen by a chargeback."
+    )]
+    pub only_locked: bool,
+
+    #[structopt(
+        long,
+        help = "Only write out the accounts that hold any funds, leaving out those whose available and held funds are both zero."
+    )]
+    pub nonzero_only: bool,
+
+    #[structopt(
+        long,
+        name = "CLIENTS",
+        help = "Only write out the accounts of the given clients, as a list of client IDs and ranges of them, e.g. '100-200,5000'. Accounts identified by an alphanumeric ID are left out."
+    )]
+    pub clients: Option<ClientList>,
+
+    #[structopt(
+        long,
+        name = "N",
+        help = "Once the run has completed, print the N accounts with the largest totals, the N with the largest held funds, and the N with the most rejected transactions to stderr, for a quick look over the riskiest accounts."
+    )]
+    pub top: Option<usize>,
+
+    #[structopt(
+        long,
+        name = "STATS_PATH",
+        parse(from_os_str),
+        help = "Write the distributions of the amounts of the run's deposits and withdrawals to the given CSV file: their count, minimum, estimated 50th, 90th and 99th percentiles, and maximum, for the whole run and for each client. Every deposit and withdrawal processed is counted, whether it was applied or not. The distributions of the whole run are also logged and pushed with the run's metrics, as histograms, whether or not this is given."
+    )]
+    pub amount_stats: Option<PathBuf>,
+
+    #[structopt(
+        long,
+        name = "FORMAT:PATH",
+        help = "Export the number of transactions of each type processed by each worker with each outcome (applied, rejected, ignored or errored), and the sum of their amounts, to the given path as either csv or json (one object per line), e.g. 'csv:txn-stats.csv'. Each row is led by the run's ID and the time it started, in seconds since the Unix epoch, so that the exports of many runs can be trended together."
+    )]
+    pub txn_stats: Option<TxnStatsOutput>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorPolicy {
+    Strict,
+    Lenient,
+}
+
+impl FromStr for ErrorPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(format!("'{s}' is not a valid error policy.")),
+        }
+    }
+}
+
+// How duplicate transactions are detected.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Dedup {
+    Exact,
+    Bloom,
+}
+
+impl FromStr for Dedup {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "exact" => Ok(Self::Exact),
+            "bloom" => Ok(Self::Bloom),
+            _ => Err(format!("'{s}' is not a valid dedup mode.")),
+        }
+    }
+}
+
+// What to do with an amount that has more decimal places than the input format allows.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExcessPrecision {
+    Reject,
+    Round,
+}
+
+impl FromStr for ExcessPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(Self::Reject),
+            "round" => Ok(Self::Round),
+            _ => Err(format!("'{s}' is not a valid excess precision policy.")),
+        }
+    }
+}
+
+// The order in which the transactions of the input are applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ApplyOrder {
+    // In the order they're read.
+    Input,
+    // In order of when they took effect, which may be before they were posted.
+    EffectiveDate,
+}
+
+impl FromStr for ApplyOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "input" => Ok(Self::Input),
+            "effective-date" => Ok(Self::EffectiveDate),
+            _ => Err(format!("'{s}' is not a valid apply order.")),
+        }
+    }
+}
 
 #[derive(Debug, StructOpt)]
-pub struct Options {
+pub struct GenerateOptions {
     #[structopt(
-        name = "TRANSACTIONS_FILE",
+        long,
+        default_value = "999999",
+        validator(is_nonzero_count),
+        help = "The number of deposits to generate."
+    )]
+    pub transactions: u64,
+
+    #[structopt(
+        long,
+        default_value = "65535",
+        validator(is_nonzero_client_count),
+        help = "The number of clients to spread the deposits across, in round-robin order."
+    )]
+    pub clients: u16,
+
+    #[structopt(long, default_value = "1.2345", help = "The amount of each deposit.")]
+    pub amount: Decimal,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct MergeOptions {
+    #[structopt(
+        name = "SNAPSHOT_DIR",
         parse(from_os_str),
-        help = "Path to a file containing transactions in CSV format.",
-        validator(is_file)
+        required = true,
+        help = "Directories of the snapshots to merge, each containing a snapshot manifest."
     )]
-    pub input_file: PathBuf,
+    pub snapshot_dirs: Vec<PathBuf>,
 
     #[structopt(
-        short = "w",
+        short = "o",
         long,
-        help = "Number of transaction processing worker threads. Defaults to an optimum number based on the number of physical cores on the system.",
-        validator(is_greater_than_zero)
+        parse(from_os_str),
+        help = "The directory to write the merged snapshot into."
     )]
-    pub num_workers: Option<usize>,
+    pub output_dir: PathBuf,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReconcileOptions {
+    #[structopt(flatten)]
+    pub process: ProcessOptions,
+
+    #[structopt(
+        name = "EXPECTED_ACCOUNTS",
+        parse(from_os_str),
+        validator(is_file),
+        conflicts_with_all = &["EXPECTED_CSV", "multi-tenant"],
+        help = "The accounts the transactions are expected to result in, as a CSV file in the same format as the output."
+    )]
+    pub expected: PathBuf,
+}
+
+impl ReconcileOptions {
+    // The options of the run that does the reconciling.
+    pub fn into_process(self) -> ProcessOptions {
+        ProcessOptions {
+            reconcile: Some(self.expected),
+            ..self.process
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReplayOptions {
+    #[structopt(
+        name = "JOURNAL",
+        parse(from_os_str),
+        help = "The audit journal to replay, as written by --journal."
+    )]
+    pub journal: PathBuf,
+
+    #[structopt(
+        long,
+        help = "Only replay the run with the given ID. By default, every run in the journal is replayed, each from no accounts at all, and the accounts of the last are written out."
+    )]
+    pub run_id: Option<Uuid>,
+
+    #[structopt(
+        long,
+        name = "PLACES",
+        default_value = "4",
+        help = "The number of decimal places to render balances with in the output, rounding midpoints away from zero. Use 'full' to render balances with their full precision instead."
+    )]
+    pub output_precision: OutputPrecision,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct TrialBalanceOptions {
+    #[structopt(
+        name = "GENERAL_LEDGER",
+        parse(from_os_str),
+        help = "The general ledger export to total, as written by --general-ledger."
+    )]
+    pub general_ledger: PathBuf,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutputSpec {
+    pub format: SinkFormat,
+    pub target: SinkTarget,
+}
+
+impl Default for OutputSpec {
+    fn default() -> Self {
+        Self {
+            format: SinkFormat::Csv,
+            target: SinkTarget::Stdout,
+        }
+    }
+}
+
+impl FromStr for OutputSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, target) = s.split_once(':').unwrap_or((s, "-"));
+        let (format, target) = match format {
+            "csv" => (SinkFormat::Csv, target),
+            "json" => (SinkFormat::Json, target),
+            "arrow" => (SinkFormat::Arrow, target),
+            // SQLite databases are given as URLs, e.g. `sqlite://results.db?journal`.
+            "sqlite" => {
+                let path = target.strip_prefix("//").unwrap_or(target);
+                let (path, journal) = match path.strip_suffix("?journal") {
+                    Some(path) => (path, true),
+                    None => (path, false),
+                };
+                if path.is_empty() || path == "-" {
+                    return Err("A SQLite output needs the path of a database.".to_owned());
+                }
+                (SinkFormat::Sqlite { journal }, path)
+            }
+            _ => return Err(format!("'{format}' is not a valid output format.")),
+        };
+
+        Ok(Self {
+            format,
+            target: target.into(),
+        })
+    }
+}
+
+// A single ASCII character of the CSV dialect, such as the delimiter.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CsvChar(pub u8);
+
+impl FromStr for CsvChar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.as_bytes() {
+            b"\\t" => Ok(Self(b'\t')),
+            &[c] if c.is_ascii() => Ok(Self(c)),
+            _ => Err(format!("'{s}' is not a single ASCII character.")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputPrecision {
+    DecimalPlaces(u32),
+    Full,
+}
+
+impl FromStr for OutputPrecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "full" {
+            return Ok(Self::Full);
+        }
+
+        match s.parse::<u32>() {
+            Ok(places) if places <= MAX_DECIMAL_PLACES => Ok(Self::DecimalPlaces(places)),
+            _ => Err(format!(
+                "'{s}' is not a valid output precision; expected 'full' or a number of decimal places up to {MAX_DECIMAL_PLACES}."
+            )),
+        }
+    }
+}
+
+impl OutputPrecision {
+    pub fn decimal_places(self) -> Option<u32> {
+        match self {
+            Self::DecimalPlaces(places) => Some(places),
+            Self::Full => None,
+        }
+    }
 }
 
+// The most decimal places a `Decimal` can represent.
+const MAX_DECIMAL_PLACES: u32 = 28;
+
 fn is_file(path: String) -> Result<(), String> {
-    if Path::new(&path).is_file() {
+    if Path::new(&path).is_file() || input::is_stream(Path::new(&path)) {
         Ok(())
     } else {
         Err(format!(
-            "The specified path '{path}' is not an accessible file."
+            "The specified path '{path}' is not an accessible file or pipe."
         ))
     }
 }
 
+fn is_input_location(path: String) -> Result<(), String> {
+    if Path::new(&path).is_dir() || remote::is_remote(Path::new(&path)) {
+        Ok(())
+    } else {
+        is_file(path)
+    }
+}
+
 fn is_greater_than_zero(num_workers: String) -> Result<(), String> {
     let num_workers = num_workers.parse::<usize>().map_err(|e| e.to_string())?;
 
@@ -40,3 +1069,247 @@ fn is_greater_than_zero(num_workers: String) -> Result<(), String> {
         Err("The specified number of workers cannot be 0.".to_string())
     }
 }
+
+fn is_nonzero_client_count(count: String) -> Result<(), String> {
+    let count = count.parse::<u16>().map_err(|e| e.to_string())?;
+
+    if count > 0 {
+        Ok(())
+    } else {
+        Err("The specified number of clients cannot be 0.".to_string())
+    }
+}
+
+fn is_nonzero_count(count: String) -> Result<(), String> {
+    let count = count.parse::<u64>().map_err(|e| e.to_string())?;
+
+    if count > 0 {
+        Ok(())
+    } else {
+        Err("The specified count cannot be 0.".to_string())
+    }
+}
+
+fn is_probability(rate: String) -> Result<(), String> {
+    let rate = rate.parse::<f64>().map_err(|e| e.to_string())?;
+
+    if rate > 0.0 && rate < 1.0 {
+        Ok(())
+    } else {
+        Err("The specified rate must be between 0 and 1.".to_string())
+    }
+}
+
+fn is_positive_rate(rate: String) -> Result<(), String> {
+    let rate = rate.parse::<f64>().map_err(|e| e.to_string())?;
+
+    if rate > 0.0 && rate.is_finite() {
+        Ok(())
+    } else {
+        Err("The specified rate must be greater than 0.".to_string())
+    }
+}
+
+// How often to report on the throughput of a run, e.g. `30s`, `5m` or `1h`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ReportInterval(pub Duration);
+
+impl FromStr for ReportInterval {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("'{s}' is not a valid report interval.");
+        let unit = match s.chars().last() {
+            Some('s') => 1,
+            Some('m') => 60,
+            Some('h') => 3600,
+            _ => return Err(invalid()),
+        };
+        match s[..s.len() - 1].parse::<u64>() {
+            Ok(span) if span > 0 => span
+                .checked_mul(unit)
+                .map(|secs| Self(Duration::from_secs(secs)))
+                .ok_or_else(invalid),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("'{s}' is not a valid log format.")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::fs;
+
+    #[test]
+    fn report_intervals() -> Result<(), Box<dyn Error>> {
+        assert_eq!("30s".parse::<ReportInterval>()?.0, Duration::from_secs(30));
+        assert_eq!("5m".parse::<ReportInterval>()?.0, Duration::from_secs(300));
+        assert_eq!("2h".parse::<ReportInterval>()?.0, Duration::from_secs(7200));
+        for invalid in [
+            "",
+            "s",
+            "0s",
+            "10",
+            "1.5m",
+            "-1s",
+            "1d",
+            "18446744073709551615h",
+        ] {
+            assert_eq!(
+                invalid.parse::<ReportInterval>().unwrap_err(),
+                format!("'{invalid}' is not a valid report interval.")
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn csv_chars() -> Result<(), Box<dyn Error>> {
+        assert_eq!(";".parse::<CsvChar>()?, CsvChar(b';'));
+        assert_eq!("|".parse::<CsvChar>()?, CsvChar(b'|'));
+        assert_eq!("'".parse::<CsvChar>()?, CsvChar(b'\''));
+        // A tab is hard to give on the command line, so may be written as an escape.
+        assert_eq!("\\t".parse::<CsvChar>()?, CsvChar(b'\t'));
+        assert_eq!("\t".parse::<CsvChar>()?, CsvChar(b'\t'));
+        for invalid in ["", ";;", "\\n", "tab", "§", "é"] {
+            assert_eq!(
+                invalid.parse::<CsvChar>().unwrap_err(),
+                format!("'{invalid}' is not a single ASCII character.")
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn options_given_in_layers() -> Result<(), Box<dyn Error>> {
+        let dir = env::temp_dir().join(format!("options-{}", Uuid::new_v4()));
+        fs::create_dir(&dir)?;
+        let (config, input) = (dir.join("run.toml"), dir.join("txns.csv"));
+        fs::write(&config, "num_workers = 8\nstarting_order = 10\n")?;
+        fs::write(&input, "type,client,tx,amount\n")?;
+        let vars = vec![
+            ("BANKING_CONFIG".into(), config.clone().into()),
+            ("BANKING_NUM_WORKERS".into(), "4".into()),
+            ("BANKING_STARTING_ORDER".into(), "20".into()),
+            ("BANKING_EXPECTED_ACCOUNTS".into(), "5".into()),
+        ];
+
+        // The command line takes precedence over the file, which takes precedence over the
+        // environment.
+        let mut args = vec![
+            "banking-exercise".into(),
+            "process".into(),
+            "-w".into(),
+            "2".into(),
+            input.clone().into(),
+        ];
+        let from_env = with_config(&mut args, vars.clone())?;
+        assert_eq!(from_env, HashSet::from(["expected-accounts".to_owned()]));
+        let Command::Process(opts) = Options::from_iter_safe(&args)?.command else {
+            unreachable!()
+        };
+        assert_eq!(
+            (
+                opts.num_workers,
+                opts.starting_order,
+                opts.expected_accounts
+            ),
+            (Some(2), 10, Some(5))
+        );
+
+        // Without the file, the environment fills in what the command line doesn't give.
+        let mut args = vec!["banking-exercise".into(), "verify".into(), input.into()];
+        with_config(&mut args, vars[1..].to_vec())?;
+        let Command::Verify(opts) = Options::from_iter_safe(&args)?.command else {
+            unreachable!()
+        };
+        assert_eq!((opts.num_workers, opts.starting_order), (Some(4), 20));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn short_options_take_precedence() -> Result<(), Box<dyn Error>> {
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/test1.csv");
+        let parse = |short: &str, var: &str| -> Result<(u8, bool, Option<usize>), Box<dyn Error>> {
+            let mut args = vec![
+                "banking-exercise".into(),
+                "process".into(),
+                short.into(),
+                input.into(),
+            ];
+            let vars = vec![
+                (var.into(), "true".into()),
+                ("BANKING_NUM_WORKERS".into(), "4".into()),
+            ];
+            with_config(&mut args, vars)?;
+            let Command::Process(opts) = Options::from_iter_safe(&args)?.command else {
+                unreachable!()
+            };
+            Ok((opts.verbose, opts.quiet, opts.num_workers))
+        };
+
+        // Short options override the environment just as long ones do, whether the environment
+        // gives the same option or one that conflicts with it.
+        assert_eq!(parse("-v", "BANKING_VERBOSE")?, (1, false, Some(4)));
+        assert_eq!(parse("-v", "BANKING_QUIET")?, (1, false, Some(4)));
+        assert_eq!(parse("-q", "BANKING_VERBOSE")?, (0, true, Some(4)));
+        assert_eq!(parse("-vvw2", "BANKING_QUIET")?, (2, false, Some(2)));
+        Ok(())
+    }
+
+    #[test]
+    fn config_file_yields_to_short_options() -> Result<(), Box<dyn Error>> {
+        let config = env::temp_dir().join(format!("options-{}.toml", Uuid::new_v4()));
+        fs::write(&config, "quiet = true\nnum_workers = 8\n")?;
+        let input = concat!(env!("CARGO_MANIFEST_DIR"), "/samples/test1.csv");
+        let parse = |given: &[&str]| -> Result<(u8, bool, Option<usize>), Box<dyn Error>> {
+            let mut args: Vec<OsString> = ["banking-exercise", "process"]
+                .into_iter()
+                .chain(given.iter().copied())
+                .chain([input])
+                .map(OsString::from)
+                .collect();
+            let vars = vec![
+                ("BANKING_CONFIG".into(), config.clone().into()),
+                ("BANKING_VERBOSE".into(), "true".into()),
+            ];
+            with_config(&mut args, vars)?;
+            let Command::Process(opts) = Options::from_iter_safe(&args)?.command else {
+                unreachable!()
+            };
+            Ok((opts.verbose, opts.quiet, opts.num_workers))
+        };
+
+        // The file's settings override whatever conflicts with them in the environment, and are
+        // overridden by short options on the command line in turn.
+        assert_eq!(parse(&[])?, (0, true, Some(8)));
+        assert_eq!(parse(&["-v"])?, (1, false, Some(8)));
+        assert_eq!(parse(&["-w", "2"])?, (0, true, Some(2)));
+
+        fs::remove_file(config)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::models::account::{AccountId, RawAccountId};
+
+// Decides which of a `TransactionProcessor`'s workers owns each account. Every transaction for an
+// account must be routed to the same worker for the lifetime of the processor, as that worker
+// holds the account's state.
+pub trait Partitioner: Send + Sync {
+    // Returns the index of the worker that owns the given account, which must be less than the
+    // number of workers the partitioner was created for.
+    fn worker_for(&self, account_id: AccountId) -> usize;
+}
+
+// Assigns accounts to workers in turn by their ID. This is cheap, and evenly balanced when account
+// IDs are spread out, but skewed when they cluster on a few residues of the worker count.
+// Alphanumeric IDs are assigned by their hash.
+pub struct Modulo {
+    num_workers: usize,
+}
+
+impl Modulo {
+    pub fn new(num_workers: usize) -> Self {
+        Self { num_workers }
+    }
+}
+
+impl Partitioner for Modulo {
+    fn worker_for(&self, account_id: AccountId) -> usize {
+        // Accounts with alphanumeric IDs are assigned by the hash of their ID instead.
+        let key = match account_id {
+            AccountId::Number(number) => u64::from(number),
+            AccountId::Name(name) => hash(&name),
+        };
+        (key % self.num_workers as u64) as usize
+    }
+}
+
+// Assigns accounts to workers by where the hash of their ID falls on a ring of points belonging to
+// the workers, which spreads out clustered IDs. Each worker has many points on the ring, so that
+// the accounts are shared out evenly.
+pub struct ConsistentHash {
+    ring: Vec<(u64, usize)>,
+}
+
+impl ConsistentHash {
+    const POINTS_PER_WORKER: u32 = 128;
+
+    pub fn new(num_workers: usize) -> Self {
+        let mut ring: Vec<_> = (0..num_workers)
+            .flat_map(|worker_idx| {
+                (0..Self::POINTS_PER_WORKER)
+                    .map(move |point| (hash(&(worker_idx, point)), worker_idx))
+            })
+            .collect();
+        ring.sort_unstable();
+        Self { ring }
+    }
+}
+
+impl Partitioner for ConsistentHash {
+    fn worker_for(&self, account_id: AccountId) -> usize {
+        let hash = hash(&account_id);
+        let idx = self.ring.partition_point(|&(point, _)| point < hash);
+        // Hashes beyond the last point wrap around to the first.
+        self.ring[idx % self.ring.len()].1
+    }
+}
+
+// Assigns ranges of account IDs to specific workers, for when the distribution of accounts is known
+// ahead of time. Accounts outside of every range, including those with alphanumeric IDs, are
+// assigned by `Modulo`.
+pub struct RangeMap {
+    ranges: Vec<(RangeInclusive<RawAccountId>, usize)>,
+    fallback: Modulo,
+}
+
+impl RangeMap {
+    pub fn new(ranges: Vec<(RangeInclusive<RawAccountId>, usize)>, num_workers: usize) -> Self {
+        Self {
+            ranges,
+            fallback: Modulo::new(num_workers),
+        }
+    }
+}
+
+impl Partitioner for RangeMap {
+    fn worker_for(&self, account_id: AccountId) -> usize {
+        self.ranges
+            .iter()
+            .find(|(range, _)| account_id.number().is_some_and(|id| range.contains(&id)))
+            .map_or_else(
+                || self.fallback.worker_for(account_id),
+                |&(_, worker_idx)| worker_idx,
+            )
+    }
+}
+
+// Assigns each account, when its first transaction is routed, to whichever worker has been routed
+// the fewest transactions so far. Accounts stay with the worker they were first assigned to, so
+// this balances the load of accounts as they appear rather than rebalancing existing ones.
+pub struct LeastLoaded {
+    state: Mutex<LoadState>,
+}
+
+struct LoadState {
+    assignments: HashMap<AccountId, usize>,
+    txns_routed: Vec<u64>,
+}
+
+impl LeastLoaded {
+    pub fn new(num_workers: usize) -> Self {
+        Self {
+            state: Mutex::new(LoadState {
+                assignments: HashMap::new(),
+                txns_routed: vec![0; num_workers],
+            }),
+        }
+    }
+}
+
+impl Partitioner for LeastLoaded {
+    fn worker_for(&self, account_id: AccountId) -> usize {
+        let mut state = self.state.lock().expect("partition state lock poisoned");
+        let LoadState {
+            assignments,
+            txns_routed,
+        } = &mut *state;
+        let worker_idx = *assignments.entry(account_id).or_insert_with(|| {
+            (0..txns_routed.len())
+                .min_by_key(|&worker_idx| txns_routed[worker_idx])
+                .unwrap_or(0)
+        });
+        txns_routed[worker_idx] += 1;
+        worker_idx
+    }
+}
+
+fn hash<T: Hash>(item: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    item.hash(&mut hasher);
+    hasher.finish()
+}
+
+// The partitioning strategy chosen on the command line, e.g. `consistent-hash`, or
+// `ranges:1-1000=0,1001-2000=1` to explicitly map account ID ranges to workers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PartitionStrategy {
+    Modulo,
+    ConsistentHash,
+    LeastLoaded,
+    Ranges(Vec<(RangeInclusive<RawAccountId>, usize)>),
+}
+
+impl PartitionStrategy {
+    pub fn partitioner(&self, num_workers: usize) -> Result<Arc<dyn Partitioner>, String> {
+        Ok(match self {
+            Self::Modulo => Arc::new(Modulo::new(num_workers)),
+            Self::ConsistentHash => Arc::new(ConsistentHash::new(num_workers)),
+            Self::LeastLoaded => Arc::new(LeastLoaded::new(num_workers)),
+            Self::Ranges(ranges) => {
+                if let Some((_, worker_idx)) = ranges.iter().find(|(_, idx)| *idx >= num_workers) {
+                    return Err(format!(
+                        "accounts cannot be assigned to worker {worker_idx}, as there are only {num_workers} workers"
+                    ));
+                }
+                Arc::new(RangeMap::new(ranges.clone(), num_workers))
+            }
+        })
+    }
+}
+
+impl FromStr for PartitionStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "modulo" => return Ok(Self::Modulo),
+            "consistent-hash" => return Ok(Self::ConsistentHash),
+            "least-loaded" => return Ok(Self::LeastLoaded),
+            _ => (),
+        }
+
+        let invalid = || format!("'{s}' is not a valid partitioning strategy.");
+        let ranges = s.strip_prefix("ranges:").ok_or_else(invalid)?;
+        ranges
+            .split(',')
+            .map(|range| {
+                let (range, worker_idx) = range.split_once('=')?;
+                let (start, end) = range.split_once('-').unwrap_or((range, range));
+                let range = start.trim().parse().ok()?..=end.trim().parse().ok()?;
+                Some((range, worker_idx.trim().parse().ok()?))
+            })
+            .collect::<Option<_>>()
+            .map(Self::Ranges)
+            .ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitioners() -> Result<(), String> {
+        let strategy: PartitionStrategy = "ranges:1-100=1, 200=0".parse()?;
+        let ranges = strategy.partitioner(2)?;
+        assert_eq!(ranges.worker_for(50.into()), 1);
+        assert_eq!(ranges.worker_for(200.into()), 0);
+        assert_eq!(
+            ranges.worker_for(301.into()),
+            1,
+            "unmapped IDs fall back to modulo"
+        );
+        assert!(strategy.partitioner(1).is_err());
+
+        // Clustered IDs that modulo would put on the same worker should be spread out.
+        let clustered = (0..1000 as RawAccountId).map(|id| AccountId::from(id * 4));
+        let consistent_hash = ConsistentHash::new(4);
+        let mut counts = [0; 4];
+        for account_id in clustered {
+            counts[consistent_hash.worker_for(account_id)] += 1;
+        }
+        assert!(
+            counts.iter().all(|&count| count > 100),
+            "accounts should be spread across every worker, but got {counts:?}"
+        );
+
+        let least_loaded = LeastLoaded::new(2);
+        let workers: Vec<_> = [1, 1, 1, 2, 3, 2]
+            .into_iter()
+            .map(|id: RawAccountId| least_loaded.worker_for(id.into()))
+            .collect();
+        assert_eq!(workers, [0, 0, 0, 1, 1, 1]);
+
+        Ok(())
+    }
+}
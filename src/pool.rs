@@ -0,0 +1,59 @@
+use crossbeam_channel::{bounded, Receiver, Sender};
+
+// A pool of buffers to be reused, such as the records read from a file, so that buffers are handed
+// back and forth between the threads that fill and drain them rather than each being allocated and
+// freed again. The pool holds on to no more than its capacity of buffers, beyond which any given
+// back are freed, and is empty to begin with, so only fills up once buffers are given back.
+pub struct Pool<T> {
+    sender: Sender<T>,
+    receiver: Receiver<T>,
+}
+
+impl<T> Pool<T> {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = bounded(capacity);
+        Self { sender, receiver }
+    }
+
+    // Takes a buffer from the pool, or makes a new one if the pool is empty.
+    pub fn take(&self, make: impl FnOnce() -> T) -> T {
+        self.receiver.try_recv().unwrap_or_else(|_| make())
+    }
+
+    // Gives a buffer back to the pool, for it to be taken again.
+    pub fn give(&self, buffer: T) {
+        let _ = self.sender.try_send(buffer);
+    }
+}
+
+impl<T> Clone for Pool<T> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            receiver: self.receiver.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reuses_buffers() {
+        let pool = Pool::new(2);
+        let buffer = pool.take(|| Vec::<u8>::with_capacity(64));
+        let ptr = buffer.as_ptr();
+        pool.give(buffer);
+        let reused = pool.take(Vec::new);
+        assert_eq!(reused.as_ptr(), ptr, "the buffer should be reused");
+
+        // Beyond its capacity, buffers given back are freed.
+        for _ in 0..3 {
+            pool.give(vec![0u8; 8]);
+        }
+        assert_eq!(pool.take(Vec::new).len(), 8);
+        assert_eq!(pool.take(Vec::new).len(), 8);
+        assert!(pool.take(Vec::new).is_empty(), "the pool should be empty");
+    }
+}
@@ -1,82 +1,2480 @@
 use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard, Weak};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use snafu::{ResultExt, Whatever};
 
-use crate::models::{account::Account, transaction::Transaction};
+use crate::affinity;
+use crate::assertions;
+use crate::autoscale::{AutoScaling, WorkerLoad};
+#[cfg(feature = "chaos")]
+use crate::chaos::{Chaos, FaultInjector};
+use crate::dedup::BloomFilter;
+use crate::hash::FastHashMap;
+use crate::models::{
+    account::{Account, AccountId, AccountKey, DedupWindow, ErrorClass, Tenant, TransactionError},
+    handler::{TransactionHandler, TransactionHandlers},
+    transaction::{OrderedTransaction, Source, Transaction, TransactionId, TransactionType},
+};
+use crate::observer::EventObserver;
+use crate::partition::{Modulo, Partitioner};
+use crate::profile::{self, PipelineProfile, Profiler, Stage};
+use crate::rate_limit::RateLimiter;
+use crate::snapshot::{self, PendingSnapshot, ShardInfo};
+use crate::stats::{ProcessorStats, RunId, WorkerQueueStats, WorkerStats};
+use crate::wal::{Recovery, WalWriter};
 
 pub struct TransactionProcessor {
-    workers: Vec<Worker>,
+    pool: Arc<RwLock<WorkerPool>>,
+    // The thread scaling the workers to their load, if they're autoscaled.
+    scaler: Option<Scaler>,
+    profiling: bool,
+    run_id: RunId,
+    next_order: AtomicU64,
+    // Transactions before this order were applied by a run that has been recovered, and are
+    // skipped as its input is read again.
+    recovered_order: u64,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    // The account that first used each transaction ID, if they're unique across all accounts.
+    txn_ids: Option<Arc<Mutex<TxnIdOwners>>>,
 }
 
 impl TransactionProcessor {
     pub fn new(num_workers: usize) -> Self {
-        let workers = (0..num_workers).map(|_| Worker::start()).collect();
-        Self { workers }
+        Self::builder(num_workers).build()
+    }
+
+    pub fn builder(num_workers: usize) -> TransactionProcessorBuilder {
+        TransactionProcessorBuilder {
+            num_workers,
+            partitioner: None,
+            run_id: RunId::generate(),
+            starting_order: 0,
+            inline: false,
+            context: Default::default(),
+            wal: vec![],
+            recovery: None,
+            restored: vec![],
+            rate_limiter: None,
+            worker_cpus: vec![],
+            autoscaling: None,
+            bloom_dedup: None,
+            expected_accounts: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+
+    pub fn run_id(&self) -> RunId {
+        self.run_id
+    }
+
+    // The order that will be assigned to the next transaction submitted for processing.
+    pub fn next_order(&self) -> u64 {
+        self.next_order.load(Ordering::Relaxed)
     }
 
+    // The total time submitting transactions has been held up by the rate limits, if any.
+    pub fn throttled(&self) -> Duration {
+        self.rate_limiter
+            .as_ref()
+            .map_or(Duration::ZERO, |limiter| {
+                limiter
+                    .lock()
+                    .expect("rate limiter lock poisoned")
+                    .throttled()
+            })
+    }
+
+    // Submits a transaction for processing, assigning it the next order in the sequence.
     pub fn process_txn(&self, txn: Transaction) -> Result<(), Whatever> {
+        let order = self.next_order.fetch_add(1, Ordering::Relaxed);
+        self.dispatch(OrderedTransaction::new(order, txn))
+    }
+
+    // Submits a transaction for processing like `process_txn`, noting where in the input it was
+    // read from so that its outcome can be traced back there, along with its memo if it has one.
+    pub fn process_txn_from(
+        &self,
+        txn: Transaction,
+        source: Source,
+        memo: Option<Arc<str>>,
+    ) -> Result<(), Whatever> {
+        self.process_posted_txn_from(txn, source, memo, None)
+    }
+
+    // Submits a transaction for processing like `process_txn_from`, along with when the input says
+    // it was posted, which the reports of it are dated by rather than by when it was applied.
+    pub fn process_posted_txn_from(
+        &self,
+        txn: Transaction,
+        source: Source,
+        memo: Option<Arc<str>>,
+        posted_at: Option<u64>,
+    ) -> Result<(), Whatever> {
+        let order = self.next_order.fetch_add(1, Ordering::Relaxed);
+        self.dispatch(
+            OrderedTransaction::new(order, txn)
+                .with_source(source)
+                .with_memo(memo)
+                .with_posted_at(posted_at),
+        )
+    }
+
+    // Submits a transaction that has already been assigned its order by the caller. Subsequent
+    // transactions submitted via `process_txn` will continue the sequence from this one.
+    pub fn process_ordered_txn(&self, txn: OrderedTransaction) -> Result<(), Whatever> {
+        self.next_order
+            .fetch_max(txn.order() + 1, Ordering::Relaxed);
+        self.dispatch(txn)
+    }
+
+    // Submits a transaction that has already been assigned its order, like `process_ordered_txn`,
+    // returning a receipt that can be used to wait for the result of the transaction.
+    pub fn process_ordered_txn_with_receipt(
+        &self,
+        txn: OrderedTransaction,
+    ) -> Result<TransactionReceipt, Whatever> {
+        self.next_order
+            .fetch_max(txn.order() + 1, Ordering::Relaxed);
+        let (result_tx, result_rx) = crossbeam_channel::bounded(1);
+        if self.is_recovered(&txn) {
+            result_tx
+                .send(Ok(()))
+                .expect("receipt channel has room for its result");
+            return Ok(TransactionReceipt { result_rx });
+        }
+        self.claim_txn_id(txn.txn());
+        let txn = self.throttle(txn);
+        let dispatched_at = self.profiling.then(Instant::now);
+        self.pool()
+            .worker_for(txn.txn())
+            .process_txn(txn, dispatched_at, Some(result_tx))?;
+        Ok(TransactionReceipt { result_rx })
+    }
+
+    fn dispatch(&self, txn: OrderedTransaction) -> Result<(), Whatever> {
+        if self.is_recovered(&txn) {
+            return Ok(());
+        }
+        self.claim_txn_id(txn.txn());
+        let txn = self.throttle(txn);
+        let dispatched_at = self.profiling.then(Instant::now);
+        self.pool()
+            .worker_for(txn.txn())
+            .process_txn(txn, dispatched_at, None)
+    }
+
+    // Whether the transaction was already got to by the run that a recovery carries on from, in
+    // which case it has been replayed and mustn't be applied again.
+    fn is_recovered(&self, txn: &OrderedTransaction) -> bool {
+        txn.order() < self.recovered_order
+    }
+
+    // Submits a batch of transactions for processing. The batch is split up by worker, and each
+    // worker receives its portion in a single message, which is considerably cheaper than
+    // submitting the transactions one at a time. The returned receipt can be used to wait for the
+    // result of each transaction.
+    pub fn process_batch(&self, txns: &[Transaction]) -> Result<BatchReceipt, Whatever> {
+        let first_order = self
+            .next_order
+            .fetch_add(txns.len() as u64, Ordering::Relaxed);
+        // Transactions recovered from a run that crashed were applied when they were replayed.
+        let mut recovered: BatchResults = vec![];
+        let mut ordered_txns = Vec::with_capacity(txns.len());
+        for (idx, txn) in txns.iter().enumerate() {
+            let ordered_txn = OrderedTransaction::new(first_order + idx as u64, txn.clone());
+            if self.is_recovered(&ordered_txn) {
+                recovered.push((idx, Ok(())));
+            } else {
+                self.claim_txn_id(txn);
+                ordered_txns.push((idx, self.throttle(ordered_txn)));
+            }
+        }
+
+        // The batch is split up while the workers can't be rescaled, so that it's delivered to the
+        // same workers it was split up for.
+        let pool = self.pool();
+        let mut worker_txns: Vec<Vec<(usize, OrderedTransaction)>> =
+            pool.workers.iter().map(|_| Vec::new()).collect();
+        for (idx, txn) in ordered_txns {
+            worker_txns[pool.partitioner.worker_for(txn.txn().account_id())].push((idx, txn));
+        }
+
+        let dispatched_at = self.profiling.then(Instant::now);
+        let mut results_rxs: Vec<_> = pool
+            .workers
+            .iter()
+            .zip(worker_txns)
+            .filter(|(_, txns)| !txns.is_empty())
+            .map(|(worker, txns)| worker.process_batch(txns, dispatched_at))
+            .collect::<Result<_, _>>()?;
+        if !recovered.is_empty() {
+            let (recovered_tx, recovered_rx) = crossbeam_channel::bounded(1);
+            recovered_tx
+                .send(recovered)
+                .expect("receipt channel has room for its results");
+            results_rxs.push(recovered_rx);
+        }
+
+        Ok(BatchReceipt {
+            len: txns.len(),
+            results_rxs,
+        })
+    }
+
+    // Claims the transaction's ID for its account as it's submitted, if IDs are unique across all
+    // accounts, so that the account that uses an ID first in the input owns it however the workers
+    // happen to be scheduled. The worker applying the transaction then rejects it if its account
+    // doesn't own the ID. The ID stays claimed even if its first use is rejected, as whether it is
+    // can't be known until the worker gets to it.
+    fn claim_txn_id(&self, txn: &Transaction) {
+        if let Some(txn_ids) = &self.txn_ids {
+            claim_txn_id(txn_ids, txn);
+        }
+    }
+
+    // Waits until the transaction is within the overall rate limit, if there is one, and marks it
+    // to be turned away if it's over its client's limit.
+    fn throttle(&self, txn: OrderedTransaction) -> OrderedTransaction {
+        let Some(limiter) = &self.rate_limiter else {
+            return txn;
+        };
+        let admitted = limiter
+            .lock()
+            .expect("rate limiter lock poisoned")
+            .acquire(txn.txn().account_key());
+        txn.with_rate_limited(!admitted)
+    }
+
+    fn pool(&self) -> RwLockReadGuard<'_, WorkerPool> {
+        self.pool.read().expect("worker pool lock poisoned")
+    }
+
+    // The number of workers currently processing transactions, which only changes if they're
+    // autoscaled.
+    pub fn num_workers(&self) -> usize {
+        self.pool().workers.len()
+    }
+
+    // Polls how far behind the workers are, which is cheap enough to do while transactions are
+    // being submitted.
+    pub fn stats(&self) -> ProcessorStats {
+        let workers = self
+            .pool()
+            .workers
+            .iter()
+            .enumerate()
+            .map(|(worker_idx, worker)| WorkerQueueStats {
+                worker_idx,
+                queued: worker.txn_tx.len(),
+                peak_queued: worker.peak_queued.load(Ordering::Relaxed),
+                busy: Duration::from_nanos(worker.busy.load(Ordering::Relaxed)),
+            })
+            .collect();
+        ProcessorStats {
+            next_order: self.next_order(),
+            throttled: self.throttled(),
+            workers,
+        }
+    }
+
+    // Begins taking a snapshot of every account into the given directory. Each worker writes out
+    // its own accounts in parallel, once it has processed every transaction that was submitted
+    // before the snapshot. This returns as soon as the workers have been asked to write their
+    // shards, so that processing may continue while they do.
+    pub fn snapshot(&self, dir: &Path) -> Result<PendingSnapshot, Whatever> {
+        snapshot::create_dir(dir)?;
+
+        let shard_rxs = self
+            .pool()
+            .workers
+            .iter()
+            .map(|worker| worker.snapshot(dir))
+            .collect::<Result<_, _>>()?;
+        Ok(PendingSnapshot::new(
+            dir.to_path_buf(),
+            self.run_id,
+            self.next_order(),
+            shard_rxs,
+        ))
+    }
+
+    // Waits for every transaction submitted so far to be processed, leaving the processor running
+    // for more to be submitted.
+    pub fn flush(&self) -> Result<(), Whatever> {
+        let done_rxs = self
+            .pool()
+            .workers
+            .iter()
+            .map(Worker::flush)
+            .collect::<Result<Vec<_>, _>>()?;
+        for done_rx in done_rxs {
+            done_rx
+                .recv()
+                .whatever_context("worker stopped before processing its transactions")?;
+        }
+        Ok(())
+    }
+
+    pub fn shutdown(self) -> Result<Vec<Account>, Whatever> {
+        Ok(self.shutdown_with_stats()?.0)
+    }
+
+    // Shuts down the processor as with `shutdown`, additionally returning how much work each of
+    // the workers did.
+    pub fn shutdown_with_stats(self) -> Result<(Vec<Account>, Vec<WorkerStats>), Whatever> {
+        let mut accounts = self.shutdown_stream()?;
+        let collected = accounts.by_ref().collect::<Result<_, _>>()?;
+        Ok((collected, accounts.worker_stats))
+    }
+
+    // Shuts down the processor, returning the accounts as each worker hands them back rather than
+    // all at once, so that they can be written out without holding on to every one of them.
+    pub fn shutdown_stream(self) -> Result<AccountStream, Whatever> {
+        let pool = self.into_pool();
+        // Ask every worker to stop up front, so that they all drain their queues in parallel. A
+        // worker that can't be asked has already stopped, and why is reported once it's joined.
+        for worker in &pool.workers {
+            let _ = worker.request_stop();
+        }
+
+        Ok(AccountStream {
+            workers: pool.workers.into_iter(),
+            accounts: vec![].into_iter(),
+            worker_stats: pool.retired,
+        })
+    }
+
+    // Shuts down the processor, giving the workers until the timeout elapses to finish processing
+    // their queued transactions. The accounts of every worker that finished in time are returned,
+    // along with the workers that did not, so that the caller can decide how to proceed. Workers
+    // that did not finish in time are left to run in the background.
+    pub fn shutdown_with_timeout(self, timeout: Duration) -> Result<ShutdownReport, Whatever> {
+        let deadline = Instant::now() + timeout;
+        let pool = self.into_pool();
+
+        // Ask every worker to stop up front, so that they all drain their queues in parallel. A
+        // worker that can't be asked has already stopped, and why is reported once it's joined.
+        for worker in &pool.workers {
+            let _ = worker.request_stop();
+        }
+
+        let mut report = ShutdownReport {
+            worker_stats: pool.retired,
+            ..Default::default()
+        };
+        for (worker_idx, worker) in pool.workers.into_iter().enumerate() {
+            match worker.wait_until(deadline) {
+                Ok((accounts, stats)) => {
+                    report.accounts.extend(accounts);
+                    report.worker_stats.push(stats);
+                }
+                Err(reason) => report
+                    .failed_workers
+                    .push(WorkerFailure { worker_idx, reason }),
+            }
+        }
+        Ok(report)
+    }
+
+    // Stops scaling the workers, if they're autoscaled, and takes them back from the scaler.
+    fn into_pool(self) -> WorkerPool {
+        if let Some(scaler) = self.scaler {
+            drop(scaler.stop_tx);
+            scaler.thread.join().expect("worker scaler thread panicked");
+        }
+        Arc::into_inner(self.pool)
+            .expect("worker pool is only shared with the scaler")
+            .into_inner()
+            .expect("worker pool lock poisoned")
+    }
+}
+
+// The workers of a processor, and which of them owns each account.
+struct WorkerPool {
+    workers: Vec<Worker>,
+    partitioner: Arc<dyn Partitioner>,
+    // The stats of the workers retired when the workers were scaled down.
+    retired: Vec<WorkerStats>,
+}
+
+impl WorkerPool {
+    fn worker_for(&self, txn: &Transaction) -> &Worker {
         // Use the target account ID as the partitioning key for distributing transactions across
         // our workers.
-        let account_id: u16 = txn.account_id().into();
-        let worker_idx = account_id as usize % self.workers.len();
-        self.workers[worker_idx].process_txn(txn)
+        &self.workers[self.partitioner.worker_for(txn.account_id())]
     }
 
-    pub fn shutdown(self) -> Result<Vec<Account>, Whatever> {
+    // The load of each worker since the busy time of each was last sampled.
+    fn load(&self, busy: &mut Vec<u64>, elapsed: Duration) -> Vec<WorkerLoad> {
+        busy.resize(self.workers.len(), 0);
         self.workers
+            .iter()
+            .zip(busy)
+            .map(|(worker, last_busy)| {
+                let now_busy = worker.busy.load(Ordering::Relaxed);
+                let utilization =
+                    now_busy.saturating_sub(*last_busy) as f64 / elapsed.as_nanos().max(1) as f64;
+                *last_busy = now_busy;
+                WorkerLoad {
+                    queued: worker.txn_tx.len(),
+                    utilization,
+                }
+            })
+            .collect()
+    }
+
+    // Stops every worker and starts the given number in their place, handing each account over to
+    // whichever worker the new partitioner assigns it to. Transactions already delivered to the
+    // workers are applied before they stop, and those still to be delivered wait until the new
+    // workers have started, so every account's transactions are still applied in order. Should a
+    // worker fail to hand its accounts over, the workers are restarted as they were, with the
+    // accounts that could be, rather than leaving the pool without workers.
+    fn rescale(&mut self, num_workers: usize, scaling: &Scaling) -> Result<(), Whatever> {
+        let partitioner = match (scaling.partitioner_for)(num_workers) {
+            Ok(partitioner) => partitioner,
+            Err(err) => snafu::whatever!("{err}"),
+        };
+        // Every worker is asked to stop and is joined, even once one of them has failed, so that
+        // none is left running. A worker that can't be asked has already stopped, and why is
+        // reported once it's joined.
+        for worker in &self.workers {
+            let _ = worker.request_handover();
+        }
+        let mut result = Ok(());
+        let mut accounts = vec![];
+        let mut worker_stats = vec![];
+        for worker in std::mem::take(&mut self.workers) {
+            match worker.join() {
+                Ok((handed_over, stats)) => {
+                    accounts.extend(handed_over);
+                    worker_stats.push(stats);
+                }
+                Err(err) => {
+                    result = result.and(Err(err));
+                    worker_stats.push(WorkerStats::default());
+                }
+            }
+        }
+        let (num_workers, partitioner) = match result {
+            Ok(()) => (num_workers, partitioner),
+            Err(_) => (worker_stats.len(), self.partitioner.clone()),
+        };
+
+        let mut seeds: Vec<WorkerSeed> = (0..num_workers)
+            .map(|worker_idx| WorkerSeed {
+                cpus: scaling
+                    .worker_cpus
+                    .get(worker_idx)
+                    .cloned()
+                    .unwrap_or_default(),
+                ..Default::default()
+            })
+            .collect();
+        for account in accounts {
+            seeds[partitioner.worker_for(account.id())]
+                .accounts
+                .insert(account.key(), account);
+        }
+        // The new workers carry on counting from the stats of those they replace.
+        for (worker_idx, stats) in worker_stats.into_iter().enumerate() {
+            match seeds.get_mut(worker_idx) {
+                Some(seed) => seed.stats = stats,
+                None => self.retired.push(stats),
+            }
+        }
+
+        self.workers = seeds
+            .into_iter()
+            .enumerate()
+            .map(|(worker_idx, seed)| Worker::start(worker_idx, scaling.context.clone(), seed))
+            .collect();
+        self.partitioner = partitioner;
+        result
+    }
+}
+
+// Decides how many workers there should be for a given load, and how to start them.
+struct Scaling {
+    autoscaling: AutoScaling,
+    partitioner_for: PartitionerFactory,
+    context: WorkerContext,
+    worker_cpus: Vec<Vec<usize>>,
+}
+
+// Creates a partitioner for the given number of workers.
+type PartitionerFactory = Box<dyn Fn(usize) -> Result<Arc<dyn Partitioner>, String> + Send>;
+
+// The thread scaling the workers of a processor, which runs until it's asked to stop by dropping
+// the sender.
+struct Scaler {
+    stop_tx: crossbeam_channel::Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Scaler {
+    fn start(pool: Weak<RwLock<WorkerPool>>, scaling: Scaling) -> Self {
+        let (stop_tx, stop_rx) = crossbeam_channel::bounded::<()>(0);
+        let thread = thread::spawn(move || {
+            let mut busy = vec![];
+            let mut sampled_at = Instant::now();
+            while let Err(crossbeam_channel::RecvTimeoutError::Timeout) =
+                stop_rx.recv_timeout(scaling.autoscaling.interval)
+            {
+                let Some(pool) = pool.upgrade() else {
+                    break;
+                };
+                let load = pool
+                    .read()
+                    .expect("worker pool lock poisoned")
+                    .load(&mut busy, sampled_at.elapsed());
+                sampled_at = Instant::now();
+
+                let num_workers = scaling.autoscaling.workers_for(&load);
+                if num_workers == load.len() {
+                    continue;
+                }
+                tracing::info!(
+                    from = load.len(),
+                    to = num_workers,
+                    ?load,
+                    "Rescaling the workers to their load"
+                );
+                let mut pool = pool.write().expect("worker pool lock poisoned");
+                if let Err(err) = pool.rescale(num_workers, &scaling) {
+                    tracing::error!(
+                        error_code = "rescale_failed",
+                        "Unable to rescale the workers: {err}"
+                    );
+                }
+                // The busy time of the new workers is counted from scratch.
+                busy.clear();
+                sampled_at = Instant::now();
+            }
+        });
+        Self { stop_tx, thread }
+    }
+}
+
+// The accounts of a processor that is shutting down, handed back a worker at a time, in worker
+// order.
+pub struct AccountStream {
+    workers: std::vec::IntoIter<Worker>,
+    accounts: std::vec::IntoIter<Account>,
+    worker_stats: Vec<WorkerStats>,
+}
+
+impl AccountStream {
+    // The stats of every worker whose accounts have been handed back so far.
+    pub fn worker_stats(&self) -> &[WorkerStats] {
+        &self.worker_stats
+    }
+}
+
+impl Iterator for AccountStream {
+    type Item = Result<Account, Whatever>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(account) = self.accounts.next() {
+                return Some(Ok(account));
+            }
+
+            match self.workers.next()?.join() {
+                Ok((accounts, stats)) => {
+                    self.accounts = accounts.into_iter();
+                    self.worker_stats.push(stats);
+                }
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ShutdownReport {
+    pub accounts: Vec<Account>,
+    // The stats of every worker that finished in time.
+    pub worker_stats: Vec<WorkerStats>,
+    pub failed_workers: Vec<WorkerFailure>,
+}
+
+#[derive(Debug)]
+pub struct WorkerFailure {
+    pub worker_idx: usize,
+    pub reason: WorkerFailureReason,
+}
+
+#[derive(Debug)]
+pub enum WorkerFailureReason {
+    // The worker did not finish before the deadline, with this many messages still in its queue.
+    TimedOut { queued: usize },
+    Panicked,
+    // The worker stopped applying transactions, as it was unable to write to its write-ahead log.
+    Failed { error: String },
+}
+
+pub struct TransactionProcessorBuilder {
+    num_workers: usize,
+    partitioner: Option<Arc<dyn Partitioner>>,
+    run_id: RunId,
+    starting_order: u64,
+    inline: bool,
+    context: WorkerContext,
+    wal: Vec<WalWriter>,
+    recovery: Option<Recovery>,
+    restored: Vec<Account>,
+    rate_limiter: Option<RateLimiter>,
+    worker_cpus: Vec<Vec<usize>>,
+    autoscaling: Option<(AutoScaling, PartitionerFactory)>,
+    bloom_dedup: Option<BloomSizing>,
+    expected_accounts: Option<u64>,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Chaos>,
+}
+
+impl TransactionProcessorBuilder {
+    // Identifies the run in the snapshots written by the processor. A new run ID is generated if
+    // one isn't provided.
+    pub fn run_id(mut self, run_id: RunId) -> Self {
+        self.run_id = run_id;
+        self
+    }
+
+    // Decides which worker owns each account, which defaults to assigning accounts to workers in
+    // turn by their ID. The partitioner must have been created for the same number of workers.
+    pub fn partitioner(mut self, partitioner: Arc<dyn Partitioner>) -> Self {
+        self.partitioner = Some(partitioner);
+        self
+    }
+
+    // The order assigned to the first transaction submitted for processing, which defaults to 0.
+    // Runs that continue on from a previous one (e.g. from a snapshot, or over multiple input
+    // files) can use this to carry on the sequence from where it left off.
+    pub fn starting_order(mut self, order: u64) -> Self {
+        self.starting_order = order;
+        self
+    }
+
+    // Limits the rate at which transactions may be submitted for processing, holding up whoever
+    // submits them until they're within the limits. Transactions replayed by a recovery aren't
+    // held up, as they've already been submitted once.
+    pub fn rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(limiter);
+        self
+    }
+
+    // Injects faults into the delivery of transactions to the workers, for testing how the
+    // processor holds up when messages go astray.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, chaos: Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    // Runs the workers inline on the calling thread rather than on threads of their own, so that
+    // each transaction has been applied by the time it has been submitted for processing. This
+    // gives up all parallelism, in exchange for processing that is entirely deterministic.
+    pub fn inline(mut self) -> Self {
+        self.inline = true;
+        self
+    }
+
+    // When a profiler is provided, workers will time how long each transaction waits in their
+    // queue and how long it takes to apply, and merge those timings into the profiler once they
+    // are stopped.
+    pub fn profiler(mut self, profiler: Profiler) -> Self {
+        self.context.profiler = Some(profiler);
+        self
+    }
+
+    // Workers will increment the provided counter each time they finish processing a transaction,
+    // which allows for progress to be observed while the processor is running.
+    pub fn processed_counter(mut self, processed: Arc<AtomicU64>) -> Self {
+        self.context.processed = Some(processed);
+        self
+    }
+
+    // Enables exhaustive assertions on the state of each account as transactions are applied to it,
+    // at some cost to throughput. Each failed assertion is logged, and counted in the provided
+    // counter.
+    pub fn assertions(mut self, failures: Arc<AtomicU64>) -> Self {
+        self.context.assertion_failures = Some(failures);
+        self
+    }
+
+    // Enforces that transaction IDs are unique across all accounts, rather than only within each
+    // account. The account that first uses each ID in the order transactions are submitted is kept
+    // track of for the lifetime of the processor, whether that use is applied or not, and a deposit
+    // or withdrawal on another account with the same ID is rejected.
+    pub fn global_txn_ids(mut self) -> Self {
+        self.context.txn_ids = Some(Default::default());
+        self
+    }
+
+    // Detects duplicate deposits and withdrawals with a Bloom filter, which takes a fixed amount of
+    // memory up to the given capacity of transactions, in addition to the exact check against each
+    // account's history. This keeps detecting duplicates of transactions that are no longer in an
+    // account's history, at the cost of occasionally rejecting a new transaction as a duplicate at
+    // roughly the given false positive rate. Accounts then keep no IDs of the transactions evicted
+    // from their history, as the filter catches duplicates of those.
+    //
+    // Each worker only sees the transactions of its own accounts, and so its filter is only sized
+    // for its share of them, as expected given the number of accounts hinted at by
+    // `capacity_hints`. See `BloomSizing::per_worker`.
+    pub fn bloom_dedup(mut self, capacity: u64, false_positive_rate: f64) -> Self {
+        self.bloom_dedup = Some(BloomSizing {
+            capacity,
+            false_positive_rate,
+        });
+        self
+    }
+
+    // Hints at how many accounts the transactions will be applied to, and how many transactions
+    // there will be, so that the maps of each worker's accounts and of each account's history are
+    // sized up front rather than grown as they fill up. Either may be left unknown. Each worker
+    // makes room for no more than its share of the transactions across all of its accounts, so
+    // that hinting at too few accounts can't exhaust memory.
+    pub fn capacity_hints(mut self, accounts: Option<u64>, txns: Option<u64>) -> Self {
+        let workers = self.num_workers as u64;
+        self.expected_accounts = accounts;
+        self.context.accounts_capacity =
+            accounts.map(|accounts| accounts.div_ceil(workers) as usize);
+        self.context.history_capacity =
+            accounts.zip(txns).map(|(accounts, txns)| HistoryCapacity {
+                per_account: (txns / accounts.max(1)) as usize,
+                per_worker: txns.div_ceil(workers) as usize,
+            });
+        self
+    }
+
+    // Limits the number of past transactions each account holds on to in case of future disputes,
+    // trading memory for the ability to dispute older transactions. See
+    // `Account::with_history_limit`.
+    pub fn history_limit(mut self, limit: usize) -> Self {
+        self.context.history_limit = Some(limit);
+        self
+    }
+
+    // Only detects duplicates within the given window of each account's transactions, trading the
+    // detection of older duplicates for memory. See `Account::with_dedup_window`.
+    pub fn dedup_window(mut self, window: DedupWindow) -> Self {
+        self.context.dedup_window = Some(window);
+        self
+    }
+
+    // Registers an observer that will be notified by the workers as transactions are processed.
+    // Any number of observers may be registered, and they are notified in registration order.
+    pub fn observer(mut self, observer: Arc<dyn EventObserver>) -> Self {
+        self.context.observers.push(observer);
+        self
+    }
+
+    // Registers a handler for transactions with the given custom type name.
+    pub fn handler(
+        mut self,
+        name: impl Into<String>,
+        handler: Arc<dyn TransactionHandler>,
+    ) -> Self {
+        Arc::make_mut(&mut self.context.handlers).register(name, handler);
+        self
+    }
+
+    // Has each worker append every transaction to its own write-ahead log before applying it, as
+    // opened by `wal::open` for the same number of workers, so that a run that crashes can be
+    // recovered from its latest snapshot and the logs.
+    pub fn wal(mut self, writers: Vec<WalWriter>) -> Self {
+        self.wal = writers;
+        self
+    }
+
+    // Carries on from a run that crashed, as recovered by `wal::recover`. Its accounts are restored
+    // and the transactions it logged since its last snapshot are replayed, before any others are
+    // processed. The transactions it had already got to are skipped when its input is read again,
+    // as long as the input is read from the same starting order.
+    pub fn recover(mut self, recovery: Recovery) -> Self {
+        self.recovery = Some(recovery);
+        self
+    }
+
+    // Starts from the given accounts rather than from none, such as those of the snapshot of a
+    // previous run as loaded by `snapshot::load_base`, so that only the transactions since need to
+    // be processed. Accounts recovered from a snapshot take precedence over these.
+    pub fn restore(mut self, accounts: Vec<Account>) -> Self {
+        self.restored = accounts;
+        self
+    }
+
+    // Pins each worker to the CPUs given for it, in worker order, such as those placed by
+    // `WorkerPlacement::cpus_for_workers`. Workers given no CPUs are left to float, as are inline
+    // workers, which run on the threads that deliver to them.
+    pub fn worker_cpus(mut self, cpus: Vec<Vec<usize>>) -> Self {
+        self.worker_cpus = cpus;
+        self
+    }
+
+    // Scales the workers to their load within the given bounds, as a run that stays resident
+    // sees its load come and go. Accounts are handed over to the workers that own them after each
+    // rescale, as assigned by a partitioner for the new number of workers. Workers given CPUs are
+    // pinned to them by their index, however many there are, so CPUs should be given for the most
+    // workers there may be. Workers that write-ahead logs or detect duplicates with a Bloom filter
+    // can't be rescaled, as they'd lose their logs or filters, and inline workers aren't scaled.
+    pub fn autoscale(
+        mut self,
+        autoscaling: AutoScaling,
+        partitioner_for: impl Fn(usize) -> Result<Arc<dyn Partitioner>, String> + Send + 'static,
+    ) -> Self {
+        self.autoscaling = Some((autoscaling, Box::new(partitioner_for)));
+        self
+    }
+
+    pub fn build(mut self) -> TransactionProcessor {
+        self.context.bloom_dedup = self
+            .bloom_dedup
+            .map(|sizing| sizing.per_worker(self.num_workers, self.expected_accounts));
+        let partitioner = self
+            .partitioner
+            .unwrap_or_else(|| Arc::new(Modulo::new(self.num_workers)));
+        let (accounts, replay, recovered_order) = match self.recovery {
+            Some(recovery) => (recovery.accounts, recovery.replay, recovery.next_order),
+            None => (vec![], vec![], 0),
+        };
+        let mut seeds: Vec<WorkerSeed> = (0..self.num_workers)
+            .map(|_| WorkerSeed::default())
+            .collect();
+        for account in self.restored.into_iter().chain(accounts) {
+            seeds[partitioner.worker_for(account.id())]
+                .accounts
+                .insert(account.key(), account);
+        }
+        for (seed, wal) in seeds.iter_mut().zip(self.wal) {
+            seed.wal = Some(wal);
+        }
+        for (seed, cpus) in seeds.iter_mut().zip(&self.worker_cpus) {
+            seed.cpus = cpus.clone();
+        }
+
+        let workers = seeds
             .into_iter()
-            .try_fold(vec![], |mut accounts, worker| {
-                accounts.extend_from_slice(&worker.stop()?);
-                Ok(accounts)
+            .enumerate()
+            .map(|(worker_idx, seed)| {
+                let context = self.context.clone();
+                let worker = if self.inline {
+                    Worker::inline(worker_idx, context, seed)
+                } else {
+                    Worker::start(worker_idx, context, seed)
+                };
+                #[cfg(feature = "chaos")]
+                let worker = Worker {
+                    chaos: self
+                        .chaos
+                        .as_ref()
+                        .map(|chaos| Mutex::new(chaos.injector(worker_idx))),
+                    ..worker
+                };
+                worker
             })
+            .collect();
+        let profiling = self.context.profiler.is_some();
+        let txn_ids = self.context.txn_ids.clone();
+        let pool = Arc::new(RwLock::new(WorkerPool {
+            workers,
+            partitioner,
+            retired: vec![],
+        }));
+        let scaler =
+            self.autoscaling
+                .filter(|_| !self.inline)
+                .map(|(autoscaling, partitioner_for)| {
+                    let scaling = Scaling {
+                        autoscaling,
+                        partitioner_for,
+                        context: self.context,
+                        worker_cpus: self.worker_cpus,
+                    };
+                    Scaler::start(Arc::downgrade(&pool), scaling)
+                });
+        let mut processor = TransactionProcessor {
+            pool,
+            scaler,
+            profiling,
+            run_id: self.run_id,
+            next_order: AtomicU64::new(self.starting_order),
+            recovered_order: 0,
+            rate_limiter: None,
+            txn_ids,
+        };
+
+        // The transactions to replay are delivered before the processor is handed out, so that
+        // they're applied ahead of any that are submitted to it.
+        for txn in replay {
+            processor
+                .dispatch(txn)
+                .expect("workers are running until the processor is shut down");
+        }
+        processor.recovered_order = recovered_order;
+        processor.rate_limiter = self.rate_limiter.map(Mutex::new);
+        processor
+    }
+}
+
+// The size of the Bloom filter each worker detects duplicate transactions with.
+#[derive(Clone, Copy)]
+struct BloomSizing {
+    capacity: u64,
+    false_positive_rate: f64,
+}
+
+impl BloomSizing {
+    // The sizing of each worker's filter, for the transactions it's expected to see of the capacity
+    // across all of them. Accounts aren't shared out between the workers exactly evenly, so each
+    // worker is sized for the most accounts it's likely to end up with, three standard deviations
+    // above its even share, as though each account were assigned to a worker at random. The fewer
+    // the accounts, the more that is beyond its share, up to every account for a handful of them.
+    // Without knowing how many accounts there are, there are taken to be enough to share out evenly.
+    fn per_worker(self, num_workers: usize, accounts: Option<u64>) -> Self {
+        let workers = num_workers.max(1) as f64;
+        let share = accounts.map_or(1.0 / workers, |accounts| {
+            let accounts = accounts.max(1) as f64;
+            let (expected, variance) = (
+                accounts / workers,
+                accounts / workers * (1.0 - 1.0 / workers),
+            );
+            ((expected + 3.0 * variance.sqrt()) / accounts).min(1.0)
+        });
+        Self {
+            capacity: (self.capacity as f64 * share).ceil() as u64,
+            ..self
+        }
+    }
+}
+
+// The room made up front in the history of each account, and in all of a worker's accounts.
+#[derive(Clone, Copy)]
+struct HistoryCapacity {
+    per_account: usize,
+    per_worker: usize,
+}
+
+// State that belongs to a single worker when it is started.
+#[derive(Default)]
+struct WorkerSeed {
+    // The accounts restored from a snapshot.
+    accounts: FastHashMap<AccountKey, Account>,
+    wal: Option<WalWriter>,
+    // The CPUs to pin the worker's thread to, if any.
+    cpus: Vec<usize>,
+    // The stats of the worker it replaces, if the workers have been rescaled.
+    stats: WorkerStats,
+    busy: Arc<AtomicU64>,
+}
+
+// State shared with each of the workers when they are started.
+#[derive(Clone, Default)]
+struct WorkerContext {
+    profiler: Option<Profiler>,
+    processed: Option<Arc<AtomicU64>>,
+    assertion_failures: Option<Arc<AtomicU64>>,
+    txn_ids: Option<Arc<Mutex<TxnIdOwners>>>,
+    bloom_dedup: Option<BloomSizing>,
+    history_limit: Option<usize>,
+    dedup_window: Option<DedupWindow>,
+    accounts_capacity: Option<usize>,
+    history_capacity: Option<HistoryCapacity>,
+    observers: Vec<Arc<dyn EventObserver>>,
+    handlers: Arc<TransactionHandlers>,
+}
+
+#[derive(Clone)]
+enum WorkerMessage {
+    Transaction {
+        txn: OrderedTransaction,
+        dispatched_at: Option<Instant>,
+        result_tx: Option<crossbeam_channel::Sender<Result<(), TransactionError>>>,
+    },
+    Batch {
+        txns: Vec<(usize, OrderedTransaction)>,
+        dispatched_at: Option<Instant>,
+        results_tx: crossbeam_channel::Sender<BatchResults>,
+    },
+    Snapshot {
+        dir: Box<Path>,
+        shard_tx: crossbeam_channel::Sender<Result<ShardInfo, String>>,
+    },
+    Flush {
+        done_tx: crossbeam_channel::Sender<()>,
+    },
+    // Stops the worker. A worker stopped to hand its accounts over to the workers of a rescaled
+    // pool isn't shutting down, so observers aren't told that it has.
+    Stop {
+        handover: bool,
+    },
+}
+
+// What a worker hands back once it has stopped: its accounts and stats, or why it failed.
+type WorkerResult = Result<(Vec<Account>, WorkerStats), String>;
+
+// The results of a worker's portion of a batch, tagged with each transaction's position in the
+// batch.
+type BatchResults = Vec<(usize, Result<(), TransactionError>)>;
+
+// A handle to the results of a batch of transactions submitted via
+// `TransactionProcessor::process_batch`.
+pub struct BatchReceipt {
+    len: usize,
+    results_rxs: Vec<crossbeam_channel::Receiver<BatchResults>>,
+}
+
+impl BatchReceipt {
+    // Waits for every transaction in the batch to be processed, and returns the result of each
+    // one in the order they appeared in the batch.
+    pub fn wait(self) -> Result<Vec<Result<(), TransactionError>>, Whatever> {
+        let mut results: Vec<_> = (0..self.len).map(|_| None).collect();
+        for results_rx in self.results_rxs {
+            let worker_results = results_rx
+                .recv()
+                .whatever_context("worker stopped before processing its batch")?;
+            for (idx, result) in worker_results {
+                results[idx] = Some(result);
+            }
+        }
+
+        Ok(results
+            .into_iter()
+            .map(|result| result.expect("every transaction in the batch has a result"))
+            .collect())
+    }
+}
+
+// A handle to the result of a transaction submitted via
+// `TransactionProcessor::process_ordered_txn_with_receipt`.
+pub struct TransactionReceipt {
+    result_rx: crossbeam_channel::Receiver<Result<(), TransactionError>>,
+}
+
+impl TransactionReceipt {
+    // Waits for the transaction to be processed, and returns its result.
+    pub fn wait(self) -> Result<Result<(), TransactionError>, Whatever> {
+        self.result_rx
+            .recv()
+            .whatever_context("worker stopped before processing the transaction")
+    }
+
+    // Returns the result of the transaction if it has been processed, without waiting for it.
+    pub fn try_result(&self) -> Option<Result<(), TransactionError>> {
+        self.result_rx.try_recv().ok()
     }
 }
 
 struct Worker {
-    thread: JoinHandle<Vec<Account>>,
-    txn_tx: crossbeam_channel::Sender<Option<Transaction>>,
+    // The worker's thread, unless it runs inline on the threads that deliver messages to it.
+    thread: Option<JoinHandle<()>>,
+    inline: Option<Mutex<InlineWorker>>,
+    txn_tx: crossbeam_channel::Sender<WorkerMessage>,
+    accounts_rx: crossbeam_channel::Receiver<WorkerResult>,
+    // The nanoseconds the worker has spent applying transactions, as they're spent.
+    busy: Arc<AtomicU64>,
+    // The most messages that have been queued for the worker at once.
+    peak_queued: AtomicUsize,
+    #[cfg(feature = "chaos")]
+    chaos: Option<Mutex<FaultInjector<WorkerMessage>>>,
 }
 
 impl Worker {
-    fn start() -> Self {
-        let (txn_tx, txn_rx) = crossbeam_channel::unbounded::<Option<Transaction>>();
+    fn start(worker_idx: usize, context: WorkerContext, mut seed: WorkerSeed) -> Self {
+        let (txn_tx, txn_rx) = crossbeam_channel::unbounded::<WorkerMessage>();
+        let (accounts_tx, accounts_rx) = crossbeam_channel::bounded(1);
+        let busy = seed.busy.clone();
+        let peak_queued = AtomicUsize::new(seed.stats.peak_queued);
 
-        // Spin up our worker thread.
+        // Spin up our worker thread. Once it has stopped, it hands back its accounts and stats over
+        // a channel rather than as the result of the thread, so that we are able to stop waiting
+        // on it if need be.
         let thread = thread::spawn(move || {
-            // Each worker thread has local state of accounts for which it will be processing
-            // transactions.
-            let mut accounts = HashMap::new();
+            // A pinned worker is pinned before its state is allocated, so that it's allocated on
+            // the node of its CPUs, and any restored accounts are copied over to it.
+            if !seed.cpus.is_empty() {
+                match affinity::pin_current_thread(&seed.cpus) {
+                    Ok(()) => {
+                        seed.accounts = seed
+                            .accounts
+                            .iter()
+                            .map(|(key, account)| (*key, account.clone()))
+                            .collect();
+                    }
+                    Err(err) => tracing::warn!(
+                        worker = worker_idx,
+                        cpus = ?seed.cpus,
+                        "Unable to pin the worker to its CPUs: {err}"
+                    ),
+                }
+            }
+            let _ = accounts_tx.send(WorkerState::new(worker_idx, context, seed).run(txn_rx));
+        });
 
-            while let Ok(Some(txn)) = txn_rx.recv() {
-                if let Err(txn_err) = accounts
-                    .entry(txn.account_id())
-                    .or_insert_with(|| Account::new(txn.account_id()))
-                    .process_txn(txn)
-                {
-                    tracing::warn!("A problem occurred while processing a transaction: {txn_err}");
+        Self {
+            thread: Some(thread),
+            inline: None,
+            txn_tx,
+            accounts_rx,
+            busy,
+            peak_queued,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+
+    // Creates a worker without a thread of its own, which instead handles each message on the
+    // thread that delivered it, before the delivery returns.
+    fn inline(worker_idx: usize, context: WorkerContext, seed: WorkerSeed) -> Self {
+        let (txn_tx, txn_rx) = crossbeam_channel::unbounded::<WorkerMessage>();
+        let (accounts_tx, accounts_rx) = crossbeam_channel::bounded(1);
+        let busy = seed.busy.clone();
+        let peak_queued = AtomicUsize::new(seed.stats.peak_queued);
+
+        Self {
+            thread: None,
+            inline: Some(Mutex::new(InlineWorker {
+                state: Some(WorkerState::new(worker_idx, context, seed)),
+                txn_rx,
+                accounts_tx,
+            })),
+            txn_tx,
+            accounts_rx,
+            busy,
+            peak_queued,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+        }
+    }
+
+    // Delivers a message to the worker's processing thread, or handles it right away if the worker
+    // runs inline.
+    fn deliver(&self, message: WorkerMessage, context: &'static str) -> Result<(), Whatever> {
+        #[cfg(feature = "chaos")]
+        let messages = match &self.chaos {
+            Some(chaos) => {
+                let faultable = matches!(
+                    message,
+                    WorkerMessage::Transaction { .. } | WorkerMessage::Batch { .. }
+                );
+                chaos
+                    .lock()
+                    .expect("fault injector lock poisoned")
+                    .inject(message, faultable)
+            }
+            None => vec![message],
+        };
+        #[cfg(not(feature = "chaos"))]
+        let messages = [message];
+        for message in messages {
+            self.txn_tx.send(message).whatever_context(context)?;
+        }
+        let queued = self.txn_tx.len();
+        if queued > self.peak_queued.load(Ordering::Relaxed) {
+            self.peak_queued.fetch_max(queued, Ordering::Relaxed);
+        }
+        if let Some(inline) = &self.inline {
+            inline
+                .lock()
+                .expect("inline worker lock poisoned")
+                .run_pending();
+        }
+        Ok(())
+    }
+
+    fn process_txn(
+        &self,
+        txn: OrderedTransaction,
+        dispatched_at: Option<Instant>,
+        result_tx: Option<crossbeam_channel::Sender<Result<(), TransactionError>>>,
+    ) -> Result<(), Whatever> {
+        self.deliver(
+            WorkerMessage::Transaction {
+                txn,
+                dispatched_at,
+                result_tx,
+            },
+            "unable to deliver transaction to worker",
+        )
+    }
+
+    fn process_batch(
+        &self,
+        txns: Vec<(usize, OrderedTransaction)>,
+        dispatched_at: Option<Instant>,
+    ) -> Result<crossbeam_channel::Receiver<BatchResults>, Whatever> {
+        let (results_tx, results_rx) = crossbeam_channel::bounded(1);
+        self.deliver(
+            WorkerMessage::Batch {
+                txns,
+                dispatched_at,
+                results_tx,
+            },
+            "unable to deliver transaction batch to worker",
+        )?;
+        Ok(results_rx)
+    }
+
+    fn snapshot(
+        &self,
+        dir: &Path,
+    ) -> Result<crossbeam_channel::Receiver<Result<ShardInfo, String>>, Whatever> {
+        let (shard_tx, shard_rx) = crossbeam_channel::bounded(1);
+        self.deliver(
+            WorkerMessage::Snapshot {
+                dir: dir.into(),
+                shard_tx,
+            },
+            "unable to request a snapshot from worker",
+        )?;
+        Ok(shard_rx)
+    }
+
+    fn flush(&self) -> Result<crossbeam_channel::Receiver<()>, Whatever> {
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        self.deliver(
+            WorkerMessage::Flush { done_tx },
+            "unable to request a flush from worker",
+        )?;
+        Ok(done_rx)
+    }
+
+    fn request_stop(&self) -> Result<(), Whatever> {
+        self.deliver(
+            WorkerMessage::Stop { handover: false },
+            "unable to cleanly shutdown worker",
+        )
+    }
+
+    // Asks the worker to stop so that its accounts can be handed over to another.
+    fn request_handover(&self) -> Result<(), Whatever> {
+        self.deliver(
+            WorkerMessage::Stop { handover: true },
+            "unable to stop worker to hand its accounts over",
+        )
+    }
+
+    // Waits for the worker to stop, once it has been asked to.
+    fn join(self) -> Result<(Vec<Account>, WorkerStats), Whatever> {
+        let accounts = self.accounts_rx.recv();
+        if let Some(thread) = self.thread {
+            thread.join().expect("worker thread panicked");
+        }
+        let (accounts, stats) =
+            match accounts.whatever_context("worker stopped without returning its accounts")? {
+                Ok(handed_back) => handed_back,
+                Err(err) => snafu::whatever!("{err}"),
+            };
+        Ok((
+            accounts,
+            WorkerStats {
+                peak_queued: self.peak_queued.into_inner(),
+                ..stats
+            },
+        ))
+    }
+
+    fn wait_until(
+        self,
+        deadline: Instant,
+    ) -> Result<(Vec<Account>, WorkerStats), WorkerFailureReason> {
+        match self.accounts_rx.recv_deadline(deadline) {
+            Ok(Ok((accounts, stats))) => {
+                if let Some(thread) = self.thread {
+                    let _ = thread.join();
+                }
+                Ok((
+                    accounts,
+                    WorkerStats {
+                        peak_queued: self.peak_queued.into_inner(),
+                        ..stats
+                    },
+                ))
+            }
+
+            Ok(Err(error)) => {
+                if let Some(thread) = self.thread {
+                    let _ = thread.join();
+                }
+                Err(WorkerFailureReason::Failed { error })
+            }
+
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                Err(WorkerFailureReason::TimedOut {
+                    queued: self.txn_tx.len(),
+                })
+            }
+
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                Err(WorkerFailureReason::Panicked)
+            }
+        }
+    }
+}
+
+// A worker that runs on the threads that deliver messages to it. Its state is handed back once it
+// has been asked to stop.
+struct InlineWorker {
+    state: Option<WorkerState>,
+    txn_rx: crossbeam_channel::Receiver<WorkerMessage>,
+    accounts_tx: crossbeam_channel::Sender<WorkerResult>,
+}
+
+impl InlineWorker {
+    // Handles every message that has been delivered to the worker so far.
+    fn run_pending(&mut self) {
+        while let Some(state) = self.state.as_mut() {
+            let Ok(message) = self.txn_rx.try_recv() else {
+                break;
+            };
+            match state.handle(message) {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(handover)) => {
+                    let state = self
+                        .state
+                        .take()
+                        .expect("inline worker has not yet stopped");
+                    let _ = self.accounts_tx.send(Ok(state.finish(handover)));
+                }
+                // A failed worker discards whatever is still queued for it, as a threaded worker's
+                // queue is once its thread returns, so that nothing waits on it for results.
+                Err(err) => {
+                    self.state = None;
+                    self.txn_rx = crossbeam_channel::never();
+                    let _ = self.accounts_tx.send(Err(err));
+                }
+            }
+        }
+    }
+}
+
+// The state owned by a worker thread.
+struct WorkerState {
+    worker_idx: usize,
+    context: WorkerContext,
+    // Each worker thread has local state of accounts for which it will be processing
+    // transactions.
+    accounts: FastHashMap<AccountKey, Account>,
+    profile: Option<PipelineProfile>,
+    // The deposits and withdrawals applied to the worker's accounts, if duplicates are being
+    // detected with a Bloom filter.
+    applied: Option<BloomFilter>,
+    wal: Option<WalWriter>,
+    stats: WorkerStats,
+    busy: Arc<AtomicU64>,
+    // The room still to be made in the histories of the worker's new accounts.
+    history_budget: usize,
+}
+
+impl WorkerState {
+    fn new(worker_idx: usize, context: WorkerContext, seed: WorkerSeed) -> Self {
+        let profile = context
+            .profiler
+            .as_ref()
+            .map(|_| PipelineProfile::default());
+        let applied = context
+            .bloom_dedup
+            .map(|sizing| BloomFilter::new(sizing.capacity, sizing.false_positive_rate));
+        let mut accounts = seed.accounts;
+        if let Some(capacity) = context.accounts_capacity {
+            accounts.reserve(capacity.saturating_sub(accounts.len()));
+        }
+        let history_budget = context
+            .history_capacity
+            .map_or(0, |capacity| capacity.per_worker);
+
+        Self {
+            worker_idx,
+            context,
+            accounts,
+            profile,
+            applied,
+            wal: seed.wal,
+            stats: WorkerStats {
+                worker_idx,
+                ..seed.stats
+            },
+            busy: seed.busy,
+            history_budget,
+        }
+    }
+
+    fn run(mut self, txn_rx: crossbeam_channel::Receiver<WorkerMessage>) -> WorkerResult {
+        while let Ok(message) = txn_rx.recv() {
+            if let ControlFlow::Break(handover) = self.handle(message)? {
+                return Ok(self.finish(handover));
+            }
+        }
+        Ok(self.finish(false))
+    }
+
+    fn add_busy(&mut self, elapsed: Duration) {
+        self.stats.busy += elapsed;
+        self.busy
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    // Handles a message delivered to the worker, breaking once the worker has been asked to stop
+    // with whether it is handing its accounts over. The worker fails, without applying the
+    // transactions it was handed, if they can't be written to its write-ahead log.
+    fn handle(&mut self, message: WorkerMessage) -> Result<ControlFlow<bool>, String> {
+        match message {
+            WorkerMessage::Transaction {
+                txn,
+                dispatched_at,
+                result_tx,
+            } => {
+                self.record_queue_wait(dispatched_at);
+                self.log(std::slice::from_ref(&txn))?;
+                let started = Instant::now();
+                let result = self.apply(txn);
+                self.add_busy(started.elapsed());
+                // Results are sent without waiting for room, so that a transaction delivered more
+                // than once can't hold up the worker once its first result has been sent.
+                if let Some(result_tx) = result_tx {
+                    let _ = result_tx.try_send(result);
+                }
+            }
+
+            WorkerMessage::Batch {
+                txns,
+                dispatched_at,
+                results_tx,
+            } => {
+                self.record_queue_wait(dispatched_at);
+                self.log(txns.iter().map(|(_, txn)| txn))?;
+                let started = Instant::now();
+                let results = txns
+                    .into_iter()
+                    .map(|(idx, txn)| (idx, self.apply(txn)))
+                    .collect();
+                self.add_busy(started.elapsed());
+                let _ = results_tx.try_send(results);
+            }
+
+            WorkerMessage::Snapshot { dir, shard_tx } => {
+                let shard = self.write_snapshot(&dir);
+                if let Err(err) = &shard {
+                    tracing::error!(
+                        error_code = "snapshot_failed",
+                        "Unable to write snapshot shard: {err}"
+                    );
                 }
+                let _ = shard_tx.send(shard);
             }
 
-            // When we have no more work to do, we will gather all of our account records
-            // and return them.
-            accounts.into_values().collect()
+            WorkerMessage::Flush { done_tx } => {
+                let _ = done_tx.send(());
+            }
+
+            WorkerMessage::Stop { handover } => return Ok(ControlFlow::Break(handover)),
+        }
+        Ok(ControlFlow::Continue(()))
+    }
+
+    // Hands back the worker's accounts and stats once it has stopped. Observers are only told of
+    // the shutdown when the accounts aren't being handed over to another worker.
+    fn finish(mut self, handover: bool) -> (Vec<Account>, WorkerStats) {
+        if let (Some(profiler), Some(profile)) = (&self.context.profiler, &self.profile) {
+            profiler.merge(profile);
+        }
+
+        // When we have no more work to do, we will gather all of our account records
+        // and return them, along with our stats.
+        let accounts: Vec<Account> = self.accounts.into_values().collect();
+        if !handover {
+            for observer in &self.context.observers {
+                observer.on_shutdown(&accounts);
+            }
+        }
+        self.stats.accounts = accounts.len() as u64;
+        (accounts, self.stats)
+    }
+
+    // Writes the worker's shard of a snapshot, along with the complete state of its accounts. With a
+    // write-ahead log, the log starts over from the snapshot.
+    fn write_snapshot(&mut self, dir: &Path) -> Result<ShardInfo, String> {
+        let shard = snapshot::write_shard(dir, self.worker_idx, self.accounts.values())?;
+        snapshot::write_state(dir, self.worker_idx, self.accounts.values())?;
+        if let Some(wal) = self.wal.as_mut() {
+            wal.rotate()
+                .map_err(|err| format!("unable to start a new write-ahead log: {err}"))?;
+        }
+        Ok(shard)
+    }
+
+    // Appends transactions to the write-ahead log, if there is one, and hands them to the OS before
+    // they are applied. Transactions that can't be logged must not be applied, as they would be
+    // lost on recovery, so the worker fails instead.
+    fn log<'a>(
+        &mut self,
+        txns: impl IntoIterator<Item = &'a OrderedTransaction>,
+    ) -> Result<(), String> {
+        let Some(wal) = self.wal.as_mut() else {
+            return Ok(());
+        };
+        let result = txns
+            .into_iter()
+            .try_for_each(|txn| wal.append(txn))
+            .and_then(|()| wal.flush());
+        if let Err(err) = result {
+            tracing::error!(
+                worker = self.worker_idx,
+                error_code = "wal_write_failed",
+                "Unable to write to the write-ahead log: {err}"
+            );
+            return Err(format!(
+                "worker {} was unable to write to the write-ahead log: {err}",
+                self.worker_idx
+            ));
+        }
+        Ok(())
+    }
+
+    fn record_queue_wait(&mut self, dispatched_at: Option<Instant>) {
+        if let (Some(profile), Some(dispatched_at)) = (self.profile.as_mut(), dispatched_at) {
+            profile
+                .stage_mut(Stage::QueueWait)
+                .record(dispatched_at.elapsed());
+        }
+    }
+
+    fn apply(&mut self, ordered_txn: OrderedTransaction) -> Result<(), TransactionError> {
+        let txn = ordered_txn.txn();
+        let context = &self.context;
+        let history_budget = &mut self.history_budget;
+        let account = self.accounts.entry(txn.account_key()).or_insert_with(|| {
+            let mut account = Account::new(txn.account_id()).with_tenant(txn.tenant());
+            if let Some(limit) = context.history_limit {
+                account = account.with_history_limit(limit);
+            }
+            if let Some(capacity) = context.history_capacity {
+                let capacity = capacity
+                    .per_account
+                    .min(context.history_limit.unwrap_or(usize::MAX))
+                    .min(*history_budget);
+                *history_budget -= capacity;
+                account = account.with_history_capacity(capacity);
+            }
+            if let Some(window) = context.dedup_window {
+                account = account.with_dedup_window(window);
+            }
+            if context.bloom_dedup.is_some() {
+                account = account.without_evicted_ids();
+            }
+            account
         });
+        let was_locked = account.locked();
+        let before = context
+            .assertion_failures
+            .is_some()
+            .then(|| assertions::Before::capture(account, &ordered_txn));
+        let applied = &mut self.applied;
+        let introduces_id = introduces_txn_id(txn);
+        let rate_limited = ordered_txn.rate_limited();
+        let result = profile::timed(self.profile.as_mut(), Stage::Apply, || {
+            if rate_limited {
+                return Err(TransactionError::RateLimited {
+                    id: txn.account_id(),
+                    txn_id: txn.id(),
+                });
+            }
+            if let Some(txn_ids) = &context.txn_ids {
+                check_txn_id(txn_ids, txn)?;
+            }
+            if let Some(applied) = applied.as_ref().filter(|_| introduces_id) {
+                if applied.contains(&(txn.account_key(), txn.id())) {
+                    return Err(TransactionError::TransactionAlreadyProcessed {
+                        id: txn.account_id(),
+                        txn_id: txn.id(),
+                    });
+                }
+            }
+            account.process_txn_with_handlers(txn.clone(), &context.handlers)
+        });
+        if let Some(applied) = applied.as_mut().filter(|_| introduces_id && result.is_ok()) {
+            applied.insert(&(txn.account_key(), txn.id()));
+        }
+
+        match &result {
+            Ok(()) => {
+                let applied_at = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |elapsed| elapsed.as_millis() as u64);
+                account.record_applied(ordered_txn.order(), applied_at, ordered_txn.posted_at());
 
-        Self { thread, txn_tx }
+                for observer in &context.observers {
+                    observer.on_applied(&ordered_txn, account);
+                }
+
+                if account.locked() && !was_locked {
+                    for observer in &context.observers {
+                        observer.on_account_locked(account);
+                    }
+                }
+            }
+
+            Err(txn_err) => {
+                let account_id = txn.account_id();
+                let txn_id = txn.id();
+                let file = ordered_txn.source().map(|source| source.file());
+                let line = ordered_txn.source().map(|source| source.line());
+                let error_code = txn_err.code();
+                match txn_err.class() {
+                    ErrorClass::Rejected => tracing::warn!(
+                        %account_id,
+                        %txn_id,
+                        file,
+                        line,
+                        error_code,
+                        "Rejected a transaction: {txn_err}"
+                    ),
+                    ErrorClass::Ignored => tracing::info!(
+                        %account_id,
+                        %txn_id,
+                        file,
+                        line,
+                        error_code,
+                        "Ignored a transaction: {txn_err}"
+                    ),
+                    ErrorClass::Errored => tracing::error!(
+                        %account_id,
+                        %txn_id,
+                        file,
+                        line,
+                        error_code,
+                        "A problem occurred while processing a transaction: {txn_err}"
+                    ),
+                }
+
+                for observer in &context.observers {
+                    observer.on_rejected(&ordered_txn, account, txn_err);
+                }
+            }
+        }
+
+        if let (Some(before), Some(failures)) = (before, &context.assertion_failures) {
+            for failure in assertions::check(
+                &before,
+                &ordered_txn,
+                &result,
+                account,
+                context.bloom_dedup.is_none(),
+            ) {
+                failures.fetch_add(1, Ordering::Relaxed);
+                tracing::error!(
+                    account_id = %txn.account_id(),
+                    txn_id = %txn.id(),
+                    order = ordered_txn.order(),
+                    file = ordered_txn.source().map(|source| source.file()),
+                    line = ordered_txn.source().map(|source| source.line()),
+                    error_code = "assertion_failed",
+                    "Assertion failed while applying a transaction: {failure}"
+                );
+            }
+        }
+
+        if let Some(processed) = &context.processed {
+            processed.fetch_add(1, Ordering::Relaxed);
+        }
+        self.stats.txns_processed += 1;
+        if result.is_err() {
+            self.stats.rejected += 1;
+        }
+        let outcome = match &result {
+            Ok(()) => "applied",
+            Err(txn_err) => txn_err.class().name(),
+        };
+        self.stats
+            .txn_types
+            .entry((txn.txn_type().owned_name(), outcome))
+            .or_default()
+            .add(txn.txn_type().amount());
+
+        result
+    }
+}
+
+// The account that each transaction ID was first used by, within each tenant's account space.
+type TxnIdOwners = HashMap<(Option<Tenant>, TransactionId), AccountId>;
+
+// Claims the transaction's ID for its account, unless it has already been used by another account
+// of the same tenant. Only deposits and withdrawals introduce new transaction IDs; other
+// transactions refer back to them.
+fn claim_txn_id(txn_ids: &Mutex<TxnIdOwners>, txn: &Transaction) {
+    if introduces_txn_id(txn) {
+        txn_ids
+            .lock()
+            .expect("transaction IDs lock poisoned")
+            .entry((txn.tenant(), txn.id()))
+            .or_insert(txn.account_id());
+    }
+}
+
+// Checks that the transaction's ID was claimed by its own account when it was submitted.
+fn check_txn_id(txn_ids: &Mutex<TxnIdOwners>, txn: &Transaction) -> Result<(), TransactionError> {
+    if !introduces_txn_id(txn) {
+        return Ok(());
     }
 
-    fn process_txn(&self, txn: Transaction) -> Result<(), Whatever> {
-        // Deliver the transaction to the worker's processing thread.
-        self.txn_tx
-            .send(Some(txn))
-            .whatever_context("unable to deliver transaction to worker")
+    let owner = txn_ids
+        .lock()
+        .expect("transaction IDs lock poisoned")
+        .get(&(txn.tenant(), txn.id()))
+        .copied()
+        .unwrap_or(txn.account_id());
+    if owner != txn.account_id() {
+        return Err(TransactionError::TransactionIdInUse {
+            id: txn.account_id(),
+            txn_id: txn.id(),
+            owner,
+        });
     }
+    Ok(())
+}
+
+fn introduces_txn_id(txn: &Transaction) -> bool {
+    matches!(
+        txn.txn_type(),
+        TransactionType::Deposit { .. } | TransactionType::Withdrawal { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{account::RawAccountId, transaction::RawTransactionId};
+    use std::error::Error;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct RecordingObserver {
+        events: Mutex<Vec<String>>,
+    }
+
+    impl EventObserver for RecordingObserver {
+        fn on_applied(&self, txn: &OrderedTransaction, _account: &Account) {
+            self.record(format!("applied {} #{}", txn.txn().id(), txn.order()));
+        }
+
+        fn on_rejected(
+            &self,
+            txn: &OrderedTransaction,
+            _account: &Account,
+            err: &TransactionError,
+        ) {
+            self.record(format!(
+                "rejected {} #{} {}",
+                txn.txn().id(),
+                txn.order(),
+                err.code()
+            ));
+        }
+
+        fn on_account_locked(&self, account: &Account) {
+            self.record(format!("locked {}", account.id()));
+        }
+
+        fn on_shutdown(&self, accounts: &[Account]) {
+            self.record(format!("shutdown {}", accounts.len()));
+        }
+    }
+
+    impl RecordingObserver {
+        fn record(&self, event: String) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn observers() -> Result<(), Box<dyn Error>> {
+        let observer = Arc::new(RecordingObserver::default());
+        let processor = TransactionProcessor::builder(1)
+            .starting_order(100)
+            .observer(observer.clone())
+            .build();
+
+        let amount = "100".parse()?;
+        let txns = [
+            (1, TransactionType::Deposit { amount }),
+            (2, TransactionType::Withdrawal { amount }),
+            (1, TransactionType::Dispute),
+            (1, TransactionType::Chargeback),
+            (3, TransactionType::Deposit { amount }),
+        ];
+        for (txn_id, txn_type) in txns {
+            processor.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))?;
+        }
+        processor.shutdown()?;
+
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            [
+                "applied 1 #100",
+                "applied 2 #101",
+                "applied 1 #102",
+                "applied 1 #103",
+                "locked 1",
+                "rejected 3 #104 account_locked",
+                "shutdown 1"
+            ],
+            "observers should be notified of every event in order"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_from_starting_order() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("resume-{}", uuid::Uuid::new_v4()));
+        let observer = Arc::new(RecordingObserver::default());
+        let processor = TransactionProcessor::builder(2)
+            .starting_order(50)
+            .observer(observer.clone())
+            .build();
+        assert_eq!(processor.next_order(), 50);
+
+        let amount = "100".parse()?;
+        let deposit = |txn_id: RawTransactionId, client: RawAccountId| {
+            Transaction::new(
+                txn_id.into(),
+                client.into(),
+                TransactionType::Deposit { amount },
+            )
+        };
+        processor
+            .process_batch(&[deposit(1, 1), deposit(2, 2)])?
+            .wait()?;
+        // A transaction given its own order carries the sequence on from there.
+        processor.process_ordered_txn(OrderedTransaction::new(60, deposit(3, 1)))?;
+        processor.process_txn(deposit(4, 2))?;
+        assert_eq!(processor.next_order(), 62);
+        let manifest = processor.snapshot(&dir)?.wait()?;
+        assert_eq!(manifest.next_order, 62);
+        processor.shutdown()?;
+
+        let mut applied: Vec<_> = observer
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| event.starts_with("applied"))
+            .cloned()
+            .collect();
+        applied.sort();
+        assert_eq!(
+            applied,
+            [
+                "applied 1 #50",
+                "applied 2 #51",
+                "applied 3 #60",
+                "applied 4 #61"
+            ]
+        );
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn inline() -> Result<(), Box<dyn Error>> {
+        let observer = Arc::new(RecordingObserver::default());
+        let processor = TransactionProcessor::builder(2)
+            .inline()
+            .observer(observer.clone())
+            .build();
+
+        let amount = "100".parse()?;
+        processor.process_txn(Transaction::new(
+            1.into(),
+            1.into(),
+            TransactionType::Deposit { amount },
+        ))?;
+        assert_eq!(
+            *observer.events.lock().unwrap(),
+            ["applied 1 #0"],
+            "the transaction should be applied before it is done being submitted"
+        );
+
+        processor.process_txn(Transaction::new(
+            2.into(),
+            2.into(),
+            TransactionType::Deposit { amount },
+        ))?;
+        let (accounts, worker_stats) = processor.shutdown_with_stats()?;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(worker_stats.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn batch() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::new(2);
+
+        let amount = "100".parse()?;
+        let txns = [
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(2.into(), 2.into(), TransactionType::Withdrawal { amount }),
+            Transaction::new(3.into(), 1.into(), TransactionType::Withdrawal { amount }),
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+        ];
+        let results = processor.process_batch(&txns)?.wait()?;
+
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Ok(()),
+                    Err(TransactionError::InsufficientFunds { .. }),
+                    Ok(()),
+                    Err(TransactionError::TransactionAlreadyProcessed { .. }),
+                ]
+            ),
+            "each transaction in the batch should report its own result, in order"
+        );
+
+        let accounts = processor.shutdown()?;
+        assert_eq!(accounts.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn receipts() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::new(2);
+
+        let amount = "100".parse()?;
+        let deposit = processor.process_ordered_txn_with_receipt(OrderedTransaction::new(
+            5,
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+        ))?;
+        let withdrawal = processor.process_ordered_txn_with_receipt(OrderedTransaction::new(
+            6,
+            Transaction::new(2.into(), 2.into(), TransactionType::Withdrawal { amount }),
+        ))?;
+
+        assert!(matches!(deposit.wait()?, Ok(())));
+        assert!(matches!(
+            withdrawal.wait()?,
+            Err(TransactionError::InsufficientFunds { .. })
+        ));
+        assert_eq!(processor.next_order(), 7);
+        processor.shutdown()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn worker_stats() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::new(2);
+
+        let amount = "100".parse()?;
+        let txns = [
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(2.into(), 3.into(), TransactionType::Deposit { amount }),
+            Transaction::new(3.into(), 3.into(), TransactionType::Withdrawal { amount }),
+            Transaction::new(4.into(), 3.into(), TransactionType::Withdrawal { amount }),
+        ];
+        processor.process_batch(&txns)?.wait()?;
+
+        let (accounts, worker_stats) = processor.shutdown_with_stats()?;
+        assert_eq!(accounts.len(), 2);
+        let [idle, busy] = worker_stats.as_slice() else {
+            panic!("expected stats for both workers, but got {worker_stats:?}");
+        };
+        assert_eq!(
+            (idle.worker_idx, idle.txns_processed, idle.accounts),
+            (0, 0, 0)
+        );
+        assert_eq!(
+            (busy.worker_idx, busy.txns_processed, busy.accounts),
+            (1, 4, 2)
+        );
+        assert_eq!(busy.rejected, 1);
+        assert_eq!(crate::stats::worker_skew(&worker_stats), Some(2.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn global_txn_ids() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2).global_txn_ids().build();
+
+        let amount = "100".parse()?;
+        let txns = [
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(2.into(), 2.into(), TransactionType::Deposit { amount }),
+            Transaction::new(1.into(), 2.into(), TransactionType::Withdrawal { amount }),
+            Transaction::new(1.into(), 2.into(), TransactionType::Dispute),
+        ];
+        let results = processor.process_batch(&txns[..1])?.wait()?;
+        assert!(results.iter().all(Result::is_ok));
+        let results = processor.process_batch(&txns[1..])?.wait()?;
+
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Err(TransactionError::TransactionAlreadyProcessed { .. }),
+                    Ok(()),
+                    Err(TransactionError::TransactionIdInUse { .. }),
+                    Err(TransactionError::TransactionNotFound { .. }),
+                ]
+            ),
+            "a transaction ID used by one account should not be reused by another"
+        );
+        processor.shutdown()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn claims_txn_ids_in_input_order() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2).global_txn_ids().build();
+
+        let amount = "100".parse()?;
+        // The first use of the ID is rejected, but still claims it ahead of the account on the
+        // other worker, whichever worker gets to its transaction first.
+        let txns = [
+            Transaction::new(7.into(), 1.into(), TransactionType::Withdrawal { amount }),
+            Transaction::new(7.into(), 2.into(), TransactionType::Deposit { amount }),
+            Transaction::new(7.into(), 1.into(), TransactionType::Deposit { amount }),
+        ];
+        let results = processor.process_batch(&txns)?.wait()?;
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Err(TransactionError::InsufficientFunds { .. }),
+                    Err(TransactionError::TransactionIdInUse { .. }),
+                    Ok(()),
+                ]
+            ),
+            "a transaction ID should belong to the account that uses it first in the input"
+        );
+        processor.shutdown()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn turns_away_txns_over_client_rate() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2)
+            .rate_limiter(RateLimiter::default().with_client_rate(2.0))
+            .build();
+
+        let amount = "100".parse()?;
+        let txns = [
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(2.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(3.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(4.into(), 2.into(), TransactionType::Deposit { amount }),
+        ];
+        let results = processor.process_batch(&txns)?.wait()?;
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Ok(()),
+                    Ok(()),
+                    Err(TransactionError::RateLimited { .. }),
+                    Ok(()),
+                ]
+            ),
+            "a client over its rate limit should be turned away without holding up other clients"
+        );
+        assert_eq!(processor.throttled(), Duration::ZERO);
+        processor.shutdown()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn bloom_dedup() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2)
+            .history_limit(1)
+            .bloom_dedup(1_000, 0.001)
+            .build();
+
+        let amount = "100".parse()?;
+        let txns = [
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(2.into(), 1.into(), TransactionType::Deposit { amount }),
+            Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount }),
+        ];
+        let results = processor.process_batch(&txns)?.wait()?;
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Ok(()),
+                    Ok(()),
+                    Err(TransactionError::TransactionAlreadyProcessed { .. }),
+                ]
+            ),
+            "a duplicate of an evicted transaction should be caught by the filter"
+        );
+        // The account itself needn't keep the IDs of the transactions evicted from its history.
+        let accounts = processor.shutdown()?;
+        assert_eq!(accounts[0].evicted_txns(), 0);
+
+        // Each worker's filter is sized for the share of the accounts it's likely to end up with.
+        let sizing = BloomSizing {
+            capacity: 1_000,
+            false_positive_rate: 0.001,
+        };
+        assert_eq!(sizing.per_worker(4, None).capacity, 250);
+        assert_eq!(sizing.per_worker(4, Some(1)).capacity, 1_000);
+        assert_eq!(sizing.per_worker(4, Some(10_000)).capacity, 263);
+
+        Ok(())
+    }
+
+    #[test]
+    fn isolates_tenants() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2).global_txn_ids().build();
+
+        let (acme, globex) = (Some(Tenant::parse("acme")?), Some(Tenant::parse("globex")?));
+        let amount = "100".parse()?;
+        let deposit = Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount });
+        let txns = [
+            deposit.clone().with_tenant(acme),
+            deposit.clone().with_tenant(globex),
+            deposit.with_tenant(acme),
+            Transaction::new(1.into(), 2.into(), TransactionType::Deposit { amount })
+                .with_tenant(globex),
+            Transaction::new(1.into(), 1.into(), TransactionType::Dispute).with_tenant(globex),
+        ];
+        let results = processor.process_batch(&txns[..2])?.wait()?;
+        assert!(results.iter().all(Result::is_ok));
+        let results = processor.process_batch(&txns[2..])?.wait()?;
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Err(TransactionError::TransactionAlreadyProcessed { .. }),
+                    Err(TransactionError::TransactionIdInUse { .. }),
+                    Ok(()),
+                ]
+            ),
+            "the same client and transaction ID should be independent in each tenant"
+        );
+
+        let mut accounts = processor.shutdown()?;
+        accounts.sort_by_key(|account| account.key());
+        let balances: Vec<_> = accounts
+            .iter()
+            .map(|account| (account.key(), account.available(), account.held()))
+            .collect();
+        assert_eq!(
+            balances,
+            [
+                ((acme, 1.into()), amount, 0.into()),
+                ((globex, 1.into()), 0.into(), amount),
+                ((globex, 2.into()), 0.into(), 0.into()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn carries_on_from_base_snapshot() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("base-{}", uuid::Uuid::new_v4()));
+        let amount = "100".parse()?;
+        let deposit = Transaction::new(1.into(), 1.into(), TransactionType::Deposit { amount });
+
+        let processor = TransactionProcessor::new(2);
+        processor
+            .process_batch(std::slice::from_ref(&deposit))?
+            .wait()?;
+        processor.snapshot(&dir)?.wait()?;
+        processor.shutdown()?;
+
+        let (manifest, accounts) = snapshot::load_base(&dir)?;
+        let processor = TransactionProcessor::builder(3)
+            .starting_order(manifest.next_order)
+            .restore(accounts)
+            .build();
+        let results = processor
+            .process_batch(&[
+                deposit.clone(),
+                Transaction::new(1.into(), 1.into(), TransactionType::Dispute),
+            ])?
+            .wait()?;
+        assert!(
+            matches!(
+                results.as_slice(),
+                [
+                    Err(TransactionError::TransactionAlreadyProcessed { .. }),
+                    Ok(())
+                ]
+            ),
+            "transactions applied before the snapshot should still be known"
+        );
+        let accounts = processor.shutdown()?;
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].held(), amount);
+        assert_eq!(accounts[0].last_order(), Some(manifest.next_order + 1));
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn skips_recovered_txns() -> Result<(), Box<dyn Error>> {
+        let amount = "100".parse()?;
+        let deposit = |id: RawTransactionId| {
+            Transaction::new(id.into(), 1.into(), TransactionType::Deposit { amount })
+        };
+        let processor = TransactionProcessor::builder(2)
+            .recover(Recovery {
+                run_id: RunId::generate(),
+                snapshot: None,
+                accounts: vec![],
+                replay: vec![
+                    OrderedTransaction::new(0, deposit(1)),
+                    OrderedTransaction::new(1, deposit(2)),
+                ],
+                next_order: 2,
+            })
+            .build();
+
+        // The input is read again from the start, whichever way it's submitted.
+        let results = processor.process_batch(&[deposit(1)])?.wait()?;
+        assert!(matches!(results.as_slice(), [Ok(())]));
+        let receipt =
+            processor.process_ordered_txn_with_receipt(OrderedTransaction::new(1, deposit(2)))?;
+        assert!(matches!(receipt.wait()?, Ok(())));
+        let results = processor.process_batch(&[deposit(3)])?.wait()?;
+        assert!(matches!(results.as_slice(), [Ok(())]));
+
+        let accounts = processor.shutdown()?;
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].available(), "300".parse()?);
+        assert_eq!(accounts[0].last_order(), Some(2));
+
+        Ok(())
+    }
+
+    struct StallingObserver;
+
+    impl EventObserver for StallingObserver {
+        fn on_applied(&self, txn: &OrderedTransaction, _account: &Account) {
+            if txn.txn().account_id() == 2.into() {
+                thread::sleep(Duration::from_secs(2));
+            }
+        }
+    }
+
+    #[test]
+    fn capacity_hints() -> Result<(), Box<dyn Error>> {
+        let txns = crate::workload::Workload::new(7)
+            .with_transactions(5_000)
+            .with_clients(50)
+            .generate();
+        let balances = |processor: TransactionProcessor| -> Result<_, Box<dyn Error>> {
+            processor.process_batch(&txns)?.wait()?;
+            let mut accounts: Vec<_> = processor
+                .shutdown()?
+                .into_iter()
+                .map(|account| (account.id(), account.total(), account.recorded_txns()))
+                .collect();
+            accounts.sort();
+            Ok(accounts)
+        };
+
+        // Sizing the maps up front, however far off the hints are, makes no difference to the
+        // accounts.
+        let unhinted = balances(TransactionProcessor::new(4))?;
+        for (accounts, txns) in [
+            (Some(50), Some(5_000)),
+            (Some(1), Some(1_000_000)),
+            (Some(50), None),
+        ] {
+            let processor = TransactionProcessor::builder(4)
+                .capacity_hints(accounts, txns)
+                .build();
+            assert_eq!(balances(processor)?, unhinted);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn autoscale() -> Result<(), Box<dyn Error>> {
+        let txns = crate::workload::Workload::new(11)
+            .with_transactions(5_000)
+            .with_clients(50)
+            .generate();
+        let (half, rest) = txns.split_at(txns.len() / 2);
+        let wait_for_workers = |processor: &TransactionProcessor, num_workers| {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while processor.num_workers() != num_workers {
+                assert!(Instant::now() < deadline, "workers were not rescaled");
+                thread::sleep(Duration::from_millis(5));
+            }
+        };
+        let balances = |accounts: Vec<Account>| {
+            let mut accounts: Vec<_> = accounts
+                .into_iter()
+                .map(|account| (account.id(), account.total(), account.recorded_txns()))
+                .collect();
+            accounts.sort();
+            accounts
+        };
+        let expected = {
+            let processor = TransactionProcessor::new(1);
+            processor.process_batch(&txns)?.wait()?;
+            balances(processor.shutdown()?)
+        };
+
+        // Bounds the workers are outside of are scaled into right away, handing the accounts over
+        // to the workers that now own them as they go.
+        for (from, to) in [(1, 3), (3, 1)] {
+            let observer = Arc::new(RecordingObserver::default());
+            let processor = TransactionProcessor::builder(from)
+                .observer(observer.clone())
+                .autoscale(
+                    AutoScaling::new(to, to).with_interval(Duration::from_millis(5)),
+                    |num_workers| Ok(Arc::new(Modulo::new(num_workers))),
+                )
+                .build();
+            processor.process_batch(half)?.wait()?;
+            wait_for_workers(&processor, to);
+            processor.process_batch(rest)?.wait()?;
+
+            let (accounts, worker_stats) = processor.shutdown_with_stats()?;
+            // Workers handing their accounts over on rescaling aren't shutting down.
+            let shutdowns: Vec<_> = observer
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| event.starts_with("shutdown"))
+                .cloned()
+                .collect();
+            assert_eq!(shutdowns.len(), to);
+            assert_eq!(
+                shutdowns
+                    .iter()
+                    .map(|event| event["shutdown ".len()..].parse::<usize>().unwrap())
+                    .sum::<usize>(),
+                accounts.len()
+            );
+            assert_eq!(balances(accounts), expected);
+            assert_eq!(
+                worker_stats.len(),
+                from.max(to),
+                "retired workers should be reported"
+            );
+            assert_eq!(
+                worker_stats
+                    .iter()
+                    .map(|stats| stats.txns_processed)
+                    .sum::<u64>(),
+                txns.len() as u64
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn shutdown_with_timeout() -> Result<(), Box<dyn Error>> {
+        let processor = TransactionProcessor::builder(2)
+            .observer(Arc::new(StallingObserver))
+            .build();
+
+        let amount = "100".parse()?;
+        for account_id in [1 as RawAccountId, 2] {
+            processor.process_txn(Transaction::new(
+                RawTransactionId::from(account_id).into(),
+                account_id.into(),
+                TransactionType::Deposit { amount },
+            ))?;
+        }
+        let report = processor.shutdown_with_timeout(Duration::from_millis(200))?;
+
+        assert_eq!(
+            report.accounts.len(),
+            1,
+            "the accounts of the worker that drained in time should be returned"
+        );
+        assert!(
+            matches!(
+                report.failed_workers.as_slice(),
+                [WorkerFailure {
+                    worker_idx: 0,
+                    reason: WorkerFailureReason::TimedOut { .. }
+                }]
+            ),
+            "the stalled worker should be reported"
+        );
+
+        Ok(())
+    }
+
+    // Holds up the worker applying each transaction until the gate is opened, by dropping its
+    // sender.
+    struct GatedObserver {
+        gate: crossbeam_channel::Receiver<()>,
+    }
+
+    impl EventObserver for GatedObserver {
+        fn on_applied(&self, _txn: &OrderedTransaction, _account: &Account) {
+            let _ = self.gate.recv();
+        }
+    }
+
+    #[test]
+    fn queue_stats() -> Result<(), Box<dyn Error>> {
+        let (gate_tx, gate) = crossbeam_channel::bounded(0);
+        let processor = TransactionProcessor::builder(2)
+            .observer(Arc::new(GatedObserver { gate }))
+            .build();
+
+        // Every transaction is for an account of the first worker, which is held up by the first.
+        let amount = "100".parse()?;
+        for txn_id in 1 as RawTransactionId..=4 {
+            processor.process_txn(Transaction::new(
+                txn_id.into(),
+                2.into(),
+                TransactionType::Deposit { amount },
+            ))?;
+        }
+        let stats = processor.stats();
+        assert_eq!(stats.next_order, 4);
+        assert!(
+            (3..=4).contains(&stats.workers[0].queued),
+            "the stalled worker's transactions should be queued, but got {stats:?}"
+        );
+        // The worker may take the first transaction off its queue before or after the others are
+        // queued behind it, so the peak is only known not to be below what's still queued.
+        assert!(
+            (stats.workers[0].queued..=4).contains(&stats.workers[0].peak_queued),
+            "the peak should cover the transactions still queued, but got {stats:?}"
+        );
+        assert_eq!(
+            (stats.workers[1].queued, stats.workers[1].peak_queued),
+            (0, 0)
+        );
+
+        drop(gate_tx);
+        processor.flush()?;
+        let stats = processor.stats();
+        assert_eq!(stats.queued(), 0);
+        let (_, worker_stats) = processor.shutdown_with_stats()?;
+        assert_eq!(worker_stats[0].peak_queued, stats.workers[0].peak_queued);
 
-    fn stop(self) -> Result<Vec<Account>, Whatever> {
-        self.txn_tx
-            .send(None)
-            .whatever_context("unable to cleanly shutdown worker")?;
-        Ok(self.thread.join().expect("worker thread panicked"))
+        Ok(())
     }
 }
@@ -0,0 +1,342 @@
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The number of power-of-two nanosecond buckets tracked by a [`Histogram`]. 2^40ns is roughly 18
+/// minutes, which is far beyond anything a single pipeline stage should take.
+const NUM_BUCKETS: usize = 40;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stage {
+    // Opening each input file and working out how to parse its records, which is timed once per
+    // file rather than per transaction.
+    Setup,
+    Read,
+    Deserialize,
+    Dispatch,
+    QueueWait,
+    Apply,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 6] = [
+        Stage::Setup,
+        Stage::Read,
+        Stage::Deserialize,
+        Stage::Dispatch,
+        Stage::QueueWait,
+        Stage::Apply,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Setup => "setup",
+            Stage::Read => "read",
+            Stage::Deserialize => "deserialize",
+            Stage::Dispatch => "dispatch",
+            Stage::QueueWait => "queue wait",
+            Stage::Apply => "apply",
+        }
+    }
+}
+
+/// A latency histogram with power-of-two nanosecond buckets. It is cheap to record into and cheap
+/// to merge, which lets each thread keep its own and combine them at the end of a run.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    buckets: [u64; NUM_BUCKETS],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: [0; NUM_BUCKETS],
+            count: 0,
+            total: Duration::ZERO,
+            max: Duration::ZERO,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&mut self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[bucket.min(NUM_BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.total += elapsed;
+        self.max = self.max.max(elapsed);
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, other_bucket) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *bucket += other_bucket;
+        }
+        self.count += other.count;
+        self.total += other.total;
+        self.max = self.max.max(other.max);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    pub fn mean(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            self.total / u32::try_from(self.count).unwrap_or(u32::MAX)
+        }
+    }
+
+    /// Returns an upper bound of the given percentile (0.0 - 1.0), at the resolution of the
+    /// histogram's buckets.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let target = (self.count as f64 * percentile).ceil() as u64;
+        let mut seen = 0;
+        for (idx, bucket) in self.buckets.iter().enumerate() {
+            seen += bucket;
+            if seen >= target && seen > 0 {
+                return bucket_upper_bound(idx).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(idx, count)| (bucket_upper_bound(idx), *count))
+    }
+}
+
+fn bucket_upper_bound(idx: usize) -> Duration {
+    Duration::from_nanos(1u64 << idx)
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PipelineProfile {
+    setup: Histogram,
+    read: Histogram,
+    deserialize: Histogram,
+    dispatch: Histogram,
+    queue_wait: Histogram,
+    apply: Histogram,
+}
+
+impl PipelineProfile {
+    pub fn stage(&self, stage: Stage) -> &Histogram {
+        match stage {
+            Stage::Setup => &self.setup,
+            Stage::Read => &self.read,
+            Stage::Deserialize => &self.deserialize,
+            Stage::Dispatch => &self.dispatch,
+            Stage::QueueWait => &self.queue_wait,
+            Stage::Apply => &self.apply,
+        }
+    }
+
+    pub fn stage_mut(&mut self, stage: Stage) -> &mut Histogram {
+        match stage {
+            Stage::Setup => &mut self.setup,
+            Stage::Read => &mut self.read,
+            Stage::Deserialize => &mut self.deserialize,
+            Stage::Dispatch => &mut self.dispatch,
+            Stage::QueueWait => &mut self.queue_wait,
+            Stage::Apply => &mut self.apply,
+        }
+    }
+
+    pub fn merge(&mut self, other: &PipelineProfile) {
+        for stage in Stage::ALL {
+            self.stage_mut(stage).merge(other.stage(stage));
+        }
+    }
+
+    /// The share (0.0 - 1.0) of the time spent working on transactions that went on the given
+    /// stage, or `None` for the time spent waiting in the workers' queues, which isn't work. Work
+    /// done on separate threads is added up, so this says where the time goes rather than how long
+    /// the run took.
+    pub fn share(&self, stage: Stage) -> Option<f64> {
+        if stage == Stage::QueueWait {
+            return None;
+        }
+        let working: Duration = Stage::ALL
+            .into_iter()
+            .filter(|&stage| stage != Stage::QueueWait)
+            .map(|stage| self.stage(stage).total())
+            .sum();
+        if working.is_zero() {
+            return Some(0.0);
+        }
+        Some(self.stage(stage).total().as_secs_f64() / working.as_secs_f64())
+    }
+
+    /// The stage that the most time was spent working in, if any time was recorded at all.
+    pub fn busiest(&self) -> Option<Stage> {
+        Stage::ALL
+            .into_iter()
+            .filter(|&stage| self.share(stage).is_some_and(|share| share > 0.0))
+            .max_by(|a, b| {
+                self.share(*a)
+                    .partial_cmp(&self.share(*b))
+                    .expect("shares are finite")
+            })
+    }
+}
+
+impl fmt::Display for PipelineProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Pipeline profile:")?;
+        writeln!(
+            f,
+            "  {:<12} {:>10} {:>14} {:>8} {:>12} {:>12} {:>12} {:>12}",
+            "stage", "count", "total", "share", "mean", "p50", "p99", "max"
+        )?;
+        for stage in Stage::ALL {
+            let hist = self.stage(stage);
+            writeln!(
+                f,
+                "  {:<12} {:>10} {:>14} {:>8} {:>12} {:>12} {:>12} {:>12}",
+                stage.name(),
+                hist.count(),
+                format!("{:.3?}", hist.total()),
+                self.share(stage)
+                    .map_or("-".to_owned(), |share| format!("{:.1}%", share * 100.0)),
+                format!("{:.3?}", hist.mean()),
+                format!("{:.3?}", hist.percentile(0.5)),
+                format!("{:.3?}", hist.percentile(0.99)),
+                format!("{:.3?}", hist.max()),
+            )?;
+        }
+        if let Some(busiest) = self.busiest() {
+            writeln!(
+                f,
+                "  Most of the work went on the {} stage.",
+                busiest.name()
+            )?;
+        }
+
+        writeln!(f)?;
+        writeln!(f, "Queue wait histogram:")?;
+        let peak = self.queue_wait.buckets().map(|(_, count)| count).max();
+        for (upper_bound, count) in self.queue_wait.buckets() {
+            let bar_len = peak.map_or(0, |peak| (count * 50).div_ceil(peak)) as usize;
+            writeln!(
+                f,
+                "  <= {:>12} {:>10} {}",
+                format!("{upper_bound:?}"),
+                count,
+                "#".repeat(bar_len)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A shared handle that threads of the pipeline merge their local profiles into once they have
+/// finished their work.
+#[derive(Clone, Debug, Default)]
+pub struct Profiler(Arc<Mutex<PipelineProfile>>);
+
+impl Profiler {
+    pub fn merge(&self, profile: &PipelineProfile) {
+        self.0
+            .lock()
+            .expect("profiler lock poisoned")
+            .merge(profile);
+    }
+
+    pub fn profile(&self) -> PipelineProfile {
+        self.0.lock().expect("profiler lock poisoned").clone()
+    }
+}
+
+/// Runs the given closure, recording its elapsed time against the stage of the profile if one was
+/// provided. When profiling is disabled, this is a plain function call.
+pub fn timed<T>(profile: Option<&mut PipelineProfile>, stage: Stage, f: impl FnOnce() -> T) -> T {
+    match profile {
+        Some(profile) => {
+            let start = Instant::now();
+            let result = f();
+            profile.stage_mut(stage).record(start.elapsed());
+            result
+        }
+        None => f(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut hist = Histogram::default();
+        assert_eq!(hist.mean(), Duration::ZERO);
+        assert_eq!(hist.percentile(0.99), Duration::ZERO);
+
+        for nanos in [100, 200, 300, 5_000] {
+            hist.record(Duration::from_nanos(nanos));
+        }
+        assert_eq!(hist.count(), 4);
+        assert_eq!(hist.total(), Duration::from_nanos(5_600));
+        assert_eq!(hist.mean(), Duration::from_nanos(1_400));
+        assert_eq!(hist.max(), Duration::from_nanos(5_000));
+        assert_eq!(
+            hist.percentile(0.5),
+            Duration::from_nanos(256),
+            "the median should fall into the 128ns - 256ns bucket"
+        );
+        assert_eq!(
+            hist.percentile(1.0),
+            Duration::from_nanos(5_000),
+            "percentiles should never exceed the largest recorded value"
+        );
+
+        let mut merged = Histogram::default();
+        merged.merge(&hist);
+        merged.merge(&hist);
+        assert_eq!(merged.count(), 8);
+        assert_eq!(merged.buckets().map(|(_, count)| count).sum::<u64>(), 8);
+    }
+
+    #[test]
+    fn breakdown() {
+        let mut profile = PipelineProfile::default();
+        assert_eq!(profile.busiest(), None);
+
+        for (stage, millis) in [
+            (Stage::Read, 10),
+            (Stage::Deserialize, 30),
+            (Stage::QueueWait, 500),
+            (Stage::Apply, 60),
+        ] {
+            profile
+                .stage_mut(stage)
+                .record(Duration::from_millis(millis));
+        }
+        assert_eq!(profile.share(Stage::Deserialize), Some(0.3));
+        assert_eq!(profile.share(Stage::Dispatch), Some(0.0));
+        assert_eq!(
+            profile.share(Stage::QueueWait),
+            None,
+            "waiting in a queue isn't work"
+        );
+        assert_eq!(profile.busiest(), Some(Stage::Apply));
+    }
+}
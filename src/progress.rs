@@ -0,0 +1,73 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+// Redrawing the progress bar for every record would be a measurable cost on large inputs, so we
+// only refresh it after this many records have been read.
+const UPDATE_INTERVAL: u64 = 4096;
+
+// Renders a progress bar to stderr that tracks how far through the input file we've read, along
+// with how many transactions have been read and processed by the workers, and the current
+// throughput.
+pub struct ProgressReporter {
+    bar: ProgressBar,
+    processed: Arc<AtomicU64>,
+    read: u64,
+    started: Instant,
+}
+
+impl ProgressReporter {
+    // Without a total, such as when reading from a pipe, there's no telling how far through the
+    // input we are, so only the bytes read so far are shown.
+    pub fn new(total_bytes: Option<u64>) -> Self {
+        let (bar, template) = match total_bytes {
+            Some(total_bytes) => (
+                ProgressBar::new(total_bytes),
+                "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} (ETA {eta}) {msg}",
+            ),
+            None => (
+                ProgressBar::no_length(),
+                "[{elapsed_precise}] {spinner} {bytes} {msg}",
+            ),
+        };
+        bar.set_style(
+            ProgressStyle::with_template(template).expect("progress bar template is valid"),
+        );
+
+        Self {
+            bar,
+            processed: Default::default(),
+            read: 0,
+            started: Instant::now(),
+        }
+    }
+
+    // The counter that workers increment as they finish processing each transaction.
+    pub fn processed_counter(&self) -> Arc<AtomicU64> {
+        self.processed.clone()
+    }
+
+    pub fn record_read(&mut self, byte_position: u64) {
+        self.read += 1;
+        if self.read.is_multiple_of(UPDATE_INTERVAL) {
+            self.update(byte_position);
+        }
+    }
+
+    pub fn finish(&self, byte_position: u64) {
+        self.update(byte_position);
+        self.bar.finish();
+    }
+
+    fn update(&self, byte_position: u64) {
+        let processed = self.processed.load(Ordering::Relaxed);
+        let rate = processed as f64 / self.started.elapsed().as_secs_f64().max(f64::EPSILON);
+        self.bar.set_position(byte_position);
+        self.bar.set_message(format!(
+            "read: {}, processed: {processed}, {rate:.0} txns/sec",
+            self.read
+        ));
+    }
+}
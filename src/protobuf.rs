@@ -0,0 +1,304 @@
+use std::io::{self, BufReader, Read};
+
+use prost::Message;
+use uuid::Uuid;
+
+use crate::bank_profile::BankProfile;
+use crate::importer::{ImportedTransaction, Importer, StatementRecord, StatementRecords};
+use crate::models::account::{Account, AccountId};
+use crate::rejects::Rejection;
+
+// The types of our protobuf schema, `proto/banking.proto`, as generated by prost.
+#[allow(clippy::all)]
+pub mod proto {
+    include!(concat!(env!("OUT_DIR"), "/banking.v1.rs"));
+}
+
+// Imports streams of length-delimited `Transaction` messages, a message at a time as the stream is
+// read. Each message is checked as a row of our own CSV files would be, and is reported by its
+// number within the stream should it be rejected. Should the stream stop making sense, the
+// remainder of it is rejected, as there's no telling where the next message starts.
+pub struct ProtobufImporter;
+
+impl Importer for ProtobufImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        Messages::new(data)
+            .map(|record| record.expect("reading from memory can't fail"))
+            .collect()
+    }
+
+    fn records<'a>(&'a self, reader: Box<dyn Read + 'a>) -> io::Result<StatementRecords<'a>> {
+        Ok(Box::new(Messages::new(BufReader::new(reader))))
+    }
+
+    fn amount_column(&self) -> &str {
+        "amount"
+    }
+}
+
+// The messages of a stream, decoded one at a time as they're read.
+struct Messages<R> {
+    reader: R,
+    profile: BankProfile,
+    line: u64,
+    done: bool,
+}
+
+impl<R: Read> Messages<R> {
+    fn new(reader: R) -> Self {
+        Self {
+            reader,
+            profile: BankProfile::native(),
+            line: 0,
+            done: false,
+        }
+    }
+
+    // Reads the next message, or `None` at the end of the stream. A message that can't be read in
+    // full is an `InvalidData` error.
+    fn read_message(&mut self) -> io::Result<Option<proto::Transaction>> {
+        let Some(len) = self.read_length()? else {
+            return Ok(None);
+        };
+        // The message is read as it arrives, rather than into a buffer of the length it claims,
+        // which may be nonsense.
+        let mut message = vec![];
+        self.reader.by_ref().take(len).read_to_end(&mut message)?;
+        if (message.len() as u64) < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the stream ends {} bytes into a {len} byte message",
+                    message.len()
+                ),
+            ));
+        }
+        proto::Transaction::decode(message.as_slice())
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    // Reads the varint length that leads a message, or `None` at the end of the stream.
+    fn read_length(&mut self) -> io::Result<Option<u64>> {
+        let mut len = 0;
+        for shift in (0..64).step_by(7) {
+            let mut byte = [0];
+            if read_byte(&mut self.reader, &mut byte)? == 0 {
+                return match shift {
+                    0 => Ok(None),
+                    _ => Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the stream ends part way through a length delimiter",
+                    )),
+                };
+            }
+            len |= u64::from(byte[0] & 0x7f) << shift;
+            if byte[0] & 0x80 == 0 {
+                return Ok(Some(len));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid length delimiter",
+        ))
+    }
+}
+
+fn read_byte(reader: &mut impl Read, byte: &mut [u8; 1]) -> io::Result<usize> {
+    loop {
+        match reader.read(byte) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Messages<R> {
+    type Item = io::Result<StatementRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let line = self.line + 1;
+        let result = match self.read_message() {
+            Ok(Some(message)) => to_transaction(&message, &self.profile, line),
+            Ok(None) => {
+                self.done = true;
+                return None;
+            }
+            Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                self.done = true;
+                Err(Rejection {
+                    line: Some(line),
+                    column: None,
+                    raw_field: None,
+                    reason: format!("The message is not a length-delimited transaction: {err}"),
+                    memo: None,
+                })
+            }
+            Err(err) => {
+                self.done = true;
+                return Some(Err(err));
+            }
+        };
+        self.line = line;
+        Some(Ok(StatementRecord { line, result }))
+    }
+}
+
+fn to_transaction(
+    message: &proto::Transaction,
+    profile: &BankProfile,
+    line: u64,
+) -> Result<ImportedTransaction, Rejection> {
+    use proto::transaction::{Client, Tx};
+
+    let client = match &message.client {
+        Some(Client::ClientNumber(number)) => Some(number.to_string()),
+        Some(Client::ClientCode(code)) => Some(code.clone()),
+        None => None,
+    };
+    let tx = match &message.tx {
+        Some(Tx::TxNumber(number)) => Some(number.to_string()),
+        Some(Tx::TxUuid(bytes)) => Some(
+            Uuid::from_slice(bytes)
+                .map_err(|_| Rejection {
+                    line: Some(line),
+                    column: Some("tx".to_owned()),
+                    raw_field: Some(bytes.iter().map(|b| format!("{b:02x}")).collect()),
+                    reason: format!("A transaction UUID must be 16 bytes, not {}", bytes.len()),
+                    memo: message.memo.clone(),
+                })?
+                .to_string(),
+        ),
+        None => None,
+    };
+    let field = |column: &str| match column {
+        "type" => Some(message.r#type.as_str()),
+        "client" => client.as_deref(),
+        "tx" => tx.as_deref(),
+        "amount" => message.amount.as_deref(),
+        "memo" => message.memo.as_deref(),
+        _ => None,
+    };
+    profile.transaction(field, line)
+}
+
+impl From<&Account> for proto::Account {
+    fn from(account: &Account) -> Self {
+        use proto::account::Client;
+
+        Self {
+            client: Some(match account.id() {
+                AccountId::Number(number) => Client::ClientNumber(number.into()),
+                AccountId::Name(name) => Client::ClientCode(name.to_owned()),
+            }),
+            available: account.available().to_string(),
+            held: account.held().to_string(),
+            total: account.total().to_string(),
+            locked: account.locked(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use rust_decimal::Decimal;
+
+    use crate::models::transaction::TransactionId;
+
+    #[test]
+    fn reads_length_delimited_streams() -> Result<(), Box<dyn Error>> {
+        use proto::transaction::{Client, Tx};
+
+        let uuid = Uuid::parse_str("0190b3c4-5e6f-7a8b-9c0d-1e2f3a4b5c6d")?;
+        let messages = [
+            proto::Transaction {
+                r#type: "deposit".to_owned(),
+                client: Some(Client::ClientNumber(1)),
+                tx: Some(Tx::TxNumber(10)),
+                amount: Some("2.5".to_owned()),
+                memo: Some("Salary".to_owned()),
+            },
+            proto::Transaction {
+                r#type: "withdrawal".to_owned(),
+                client: Some(Client::ClientCode("GB33BUKB20201555555555".to_owned())),
+                tx: Some(Tx::TxUuid(uuid.as_bytes().to_vec())),
+                amount: Some("lots".to_owned()),
+                memo: None,
+            },
+            proto::Transaction {
+                r#type: "dispute".to_owned(),
+                client: Some(Client::ClientNumber(1)),
+                tx: Some(Tx::TxNumber(10)),
+                amount: None,
+                memo: None,
+            },
+        ];
+        let mut data = vec![];
+        for message in &messages {
+            message.encode_length_delimited(&mut data)?;
+        }
+        // A message cut short.
+        data.extend_from_slice(&[0x10, 0x0a]);
+
+        let records = ProtobufImporter.import(&data);
+        assert_eq!(records.len(), 4);
+
+        let deposit = records[0].result.clone()?;
+        assert_eq!(records[0].line, 1);
+        assert_eq!(deposit.txn.id(), TransactionId::Number(10));
+        assert_eq!(deposit.txn.account_id(), AccountId::Number(1));
+        assert_eq!(deposit.txn.txn_type().amount(), Some(Decimal::new(25, 1)));
+        assert_eq!(deposit.memo.as_deref(), Some("Salary"));
+
+        let rejection = records[1].result.clone().unwrap_err();
+        assert_eq!(rejection.column.as_deref(), Some("amount"));
+        assert_eq!(rejection.raw_field.as_deref(), Some("lots"));
+
+        assert_eq!(records[2].result.clone()?.txn.txn_type().name(), "dispute");
+        assert_eq!(records[3].line, 4);
+        assert!(records[3].result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn imports_messages_as_they_arrive() -> Result<(), Box<dyn Error>> {
+        // A stream whose first message has arrived, but which fails before any more do.
+        struct Stalled(io::Cursor<Vec<u8>>);
+
+        impl Read for Stalled {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                match self.0.read(buf)? {
+                    0 => Err(io::Error::new(io::ErrorKind::TimedOut, "stalled")),
+                    read => Ok(read),
+                }
+            }
+        }
+
+        let mut data = vec![];
+        proto::Transaction {
+            r#type: "deposit".to_owned(),
+            client: Some(proto::transaction::Client::ClientNumber(1)),
+            tx: Some(proto::transaction::Tx::TxNumber(10)),
+            amount: Some("2.5".to_owned()),
+            memo: None,
+        }
+        .encode_length_delimited(&mut data)?;
+
+        let mut records = ProtobufImporter.records(Box::new(Stalled(io::Cursor::new(data))))?;
+        let deposit = records.next().ok_or("the first message has arrived")??;
+        assert_eq!((deposit.line, deposit.result?.txn.id()), (1, 10.into()));
+        let err = records
+            .next()
+            .ok_or("the stream hasn't ended")?
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(records.next().is_none());
+        Ok(())
+    }
+}
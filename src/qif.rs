@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use crate::bank_profile::{BankProfile, Columns};
+use crate::importer::StatementRecord;
+
+// The profile of a QIF file that no other has been given for, whose columns are the codes of the
+// fields of each record: the check or reference number, the amount, the memo, and the date.
+pub fn default_profile() -> BankProfile {
+    BankProfile {
+        columns: Columns {
+            tx: Some("N".to_owned()),
+            amount: Some("T".to_owned()),
+            memo: Some("M".to_owned()),
+            date: Some("D".to_owned()),
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+// Reads the transactions of a QIF file, mapping the fields of each record onto a transaction as the
+// given profile describes, with the single-letter codes of the fields as its columns. The account
+// is taken from the file's `!Account` block if it has one, and otherwise from the profile.
+//
+// Records without a reference number are identified by their contents instead, which identify the
+// same transaction every time the file is imported.
+pub fn parse(text: &str, profile: &BankProfile) -> Vec<StatementRecord> {
+    let mut profile = profile.clone();
+    let mut records = vec![];
+    let mut in_account_block = false;
+    // The fields of the record being read, by their codes, and the line it started on.
+    let mut fields: HashMap<char, &str> = HashMap::new();
+    let mut start = None;
+    let mut seen: HashMap<String, u32> = HashMap::new();
+    for (idx, line) in text.lines().enumerate() {
+        let line_number = idx as u64 + 1;
+        let line = line.trim_end();
+        if line.starts_with("!Account") {
+            in_account_block = true;
+            continue;
+        }
+        if line.starts_with('!') || line.is_empty() {
+            continue;
+        }
+
+        if line == "^" {
+            let record_line = start.take().unwrap_or(line_number);
+            let record = std::mem::take(&mut fields);
+            if in_account_block {
+                in_account_block = false;
+                if let Some(name) = record.get(&'N') {
+                    profile.account = Some(name.trim().to_owned());
+                }
+                continue;
+            }
+            records.push(to_record(&profile, &record, record_line, &mut seen));
+            continue;
+        }
+
+        let mut chars = line.chars();
+        let code = chars.next().expect("line is not empty");
+        start.get_or_insert(line_number);
+        fields.entry(code).or_insert(chars.as_str());
+    }
+    records
+}
+
+fn to_record(
+    profile: &BankProfile,
+    fields: &HashMap<char, &str>,
+    line: u64,
+    seen: &mut HashMap<String, u32>,
+) -> StatementRecord {
+    let tx_column = profile.columns.tx.as_deref().unwrap_or("N");
+    let reference = match field(fields, tx_column).map(str::trim) {
+        Some(reference) if !reference.is_empty() => None,
+        _ => {
+            // Identical records are told apart by how many of them came before.
+            let mut codes: Vec<_> = fields.iter().collect();
+            codes.sort();
+            let contents: String = codes
+                .iter()
+                .map(|(code, value)| format!("{code}{value}\n"))
+                .collect();
+            let count = seen.entry(contents.clone()).or_default();
+            *count += 1;
+            Some(format!("{contents}{count}"))
+        }
+    };
+
+    let result = profile.transaction(
+        |column| match (column == tx_column, &reference) {
+            (true, Some(reference)) => Some(reference.as_str()),
+            _ => field(fields, column),
+        },
+        line,
+    );
+    StatementRecord { line, result }
+}
+
+fn field<'a>(fields: &HashMap<char, &'a str>, column: &str) -> Option<&'a str> {
+    let mut chars = column.chars();
+    match (chars.next(), chars.next()) {
+        (Some(code), None) => fields.get(&code).copied(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use rust_decimal::Decimal;
+
+    use crate::models::{account::AccountId, transaction::TransactionId};
+
+    #[test]
+    fn reads_qif_files() -> Result<(), Box<dyn Error>> {
+        let file = "!Account\n\
+                    NChecking-1\n\
+                    TBank\n\
+                    ^\n\
+                    !Type:Bank\n\
+                    D01/02/2024\n\
+                    T1,500.00\n\
+                    N1001\n\
+                    PACME Payroll\n\
+                    MJanuary\n\
+                    ^\n\
+                    D01/03/2024\n\
+                    T-4.50\n\
+                    PCoffee\n\
+                    ^\n\
+                    D01/03/2024\n\
+                    T-4.50\n\
+                    PCoffee\n\
+                    ^\n";
+        let records = parse(file, &default_profile());
+        assert_eq!(records.len(), 3);
+
+        let salary = records[0].result.clone()?;
+        assert_eq!(records[0].line, 6);
+        assert_eq!(salary.txn.id(), TransactionId::Number(1001));
+        assert_eq!(salary.txn.account_id(), AccountId::parse("Checking-1")?);
+        assert_eq!(salary.txn.txn_type().name(), "deposit");
+        assert_eq!(salary.txn.txn_type().amount(), Some(Decimal::new(1500, 0)));
+        assert_eq!(salary.memo.as_deref(), Some("January"));
+
+        let first = records[1].result.clone()?;
+        let second = records[2].result.clone()?;
+        assert_eq!(first.txn.txn_type().name(), "withdrawal");
+        assert_ne!(
+            first.txn.id(),
+            second.txn.id(),
+            "identical records without references should be told apart"
+        );
+        assert_eq!(
+            parse(file, &default_profile())[2].result.clone()?.txn.id(),
+            second.txn.id(),
+            "records without references should be identified the same every time"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn requires_an_account() {
+        let records = parse("!Type:Bank\nT1.00\nN1\n^\n", &default_profile());
+        assert!(records[0].result.is_err());
+
+        let profile = BankProfile {
+            account: Some("7".to_owned()),
+            ..default_profile()
+        };
+        let records = parse("!Type:Bank\nT1.00\nN1\n^\n", &profile);
+        assert_eq!(
+            records[0]
+                .result
+                .as_ref()
+                .map(|imported| imported.txn.account_id())
+                .ok(),
+            Some(AccountId::Number(7))
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::models::account::AccountKey;
+
+// How many transactions are let through between sweeps of the buckets of clients that have gone
+// quiet, which would otherwise be held on to for the rest of the run.
+const SWEEP_EVERY: u64 = 100_000;
+
+// Limits the rate at which transactions are submitted for processing, both overall and for each
+// client, so that a bursty producer can't swamp the workers. Whoever submits a transaction over
+// the overall limit is made to wait until it's within it, which pushes back on the input for as
+// long as it's too fast. A transaction over its client's limit is turned away instead, as holding
+// up the input for one client would hold up every other client's transactions behind it.
+//
+// Each limit is a token bucket holding up to a second's worth of transactions, so short bursts
+// within it go through at once. A transaction that finds the overall bucket empty reserves its
+// token ahead of time, and waits until the token would have been added.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    global: Option<TokenBucket>,
+    per_client: Option<f64>,
+    clients: HashMap<AccountKey, TokenBucket>,
+    acquired: u64,
+    throttled: Duration,
+}
+
+impl RateLimiter {
+    // Limits transactions overall to the given number per second.
+    pub fn with_global_rate(mut self, per_sec: f64) -> Self {
+        self.global = Some(TokenBucket::new(per_sec, Instant::now()));
+        self
+    }
+
+    // Limits each client's transactions to the given number per second.
+    pub fn with_client_rate(mut self, per_sec: f64) -> Self {
+        self.per_client = Some(per_sec);
+        self
+    }
+
+    // The total time submitters have been made to wait.
+    pub fn throttled(&self) -> Duration {
+        self.throttled
+    }
+
+    // Waits until a transaction of the given client is within the overall limit, returning
+    // whether it's within its client's limit too. A transaction over its client's limit isn't
+    // held up, and doesn't count towards the overall limit.
+    pub fn acquire(&mut self, client: AccountKey) -> bool {
+        let now = Instant::now();
+        if !self.admit(client, now) {
+            return false;
+        }
+        let delay = self.delay(now);
+        if !delay.is_zero() {
+            self.throttled += delay;
+            thread::sleep(delay);
+        }
+        true
+    }
+
+    // Takes a token for a transaction of the given client at the given time, if its client has
+    // one to spare.
+    fn admit(&mut self, client: AccountKey, now: Instant) -> bool {
+        let Some(per_sec) = self.per_client else {
+            return true;
+        };
+
+        self.acquired += 1;
+        if self.acquired.is_multiple_of(SWEEP_EVERY) {
+            self.clients.retain(|_, bucket| !bucket.is_full(now));
+        }
+        self.clients
+            .entry(client)
+            .or_insert_with(|| TokenBucket::new(per_sec, now))
+            .try_take(now)
+    }
+
+    // Takes a token for a transaction at the given time within the overall limit, returning how
+    // long the transaction must wait for it.
+    fn delay(&mut self, now: Instant) -> Duration {
+        self.global
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.take(now))
+    }
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    per_sec: f64,
+    // Negative while tokens have been reserved ahead of being added.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_sec: f64, now: Instant) -> Self {
+        Self {
+            per_sec,
+            tokens: per_sec,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_sec).min(self.per_sec);
+        self.updated = now;
+    }
+
+    fn take(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / self.per_sec)
+    }
+
+    // Takes a token if there's one to spare, without reserving one ahead of time.
+    fn try_take(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn is_full(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.tokens >= self.per_sec
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_rates() {
+        let start = Instant::now();
+        let mut limiter = RateLimiter::default()
+            .with_global_rate(4.0)
+            .with_client_rate(2.0);
+        let (client, other) = ((None, 1.into()), (None, 2.into()));
+
+        // A second's worth of each client's transactions goes through, but no more.
+        assert!(limiter.admit(client, start));
+        assert!(limiter.admit(client, start));
+        assert!(!limiter.admit(client, start));
+        // Another client has its own limit, regardless of the client that went over its own.
+        assert!(limiter.admit(other, start));
+
+        // The overall limit holds up transactions rather than turning them away.
+        for _ in 0..4 {
+            assert_eq!(limiter.delay(start), Duration::ZERO);
+        }
+        assert_eq!(limiter.delay(start), Duration::from_millis(250));
+
+        // Tokens are added back over time, up to a second's worth.
+        let later = start + Duration::from_secs(10);
+        for client in [client, client, other, other] {
+            assert!(limiter.admit(client, later));
+        }
+        assert!(!limiter.admit(client, later));
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::models::account::{Account, AccountId};
+
+// An account as it appears in the output of a run, which we expect a run to reproduce.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExpectedAccount {
+    pub client: AccountId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+impl ExpectedAccount {
+    pub fn load(path: &Path) -> csv::Result<Vec<Self>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)?
+            .deserialize()
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Mismatch {
+    Missing {
+        client: AccountId,
+    },
+    Unexpected {
+        client: AccountId,
+    },
+    Differs {
+        client: AccountId,
+        field: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing { client } => write!(f, "client {client}: expected but not found"),
+            Self::Unexpected { client } => write!(f, "client {client}: found but not expected"),
+            Self::Differs {
+                client,
+                field,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "client {client}: {field} expected {expected}, found {actual}"
+            ),
+        }
+    }
+}
+
+// Compares the accounts produced by a run against the expected accounts, returning every
+// difference ordered by client. Amounts are compared by value, so `1.0` and `1` are equal.
+pub fn reconcile(accounts: &[Account], expected: &[ExpectedAccount]) -> Vec<Mismatch> {
+    let mut actual: BTreeMap<_, _> = accounts
+        .iter()
+        .map(|account| (account.id(), account))
+        .collect();
+    let expected: BTreeMap<_, _> = expected
+        .iter()
+        .map(|expected| (expected.client, expected))
+        .collect();
+
+    let mut mismatches = vec![];
+    for (client, expected) in expected {
+        let Some(account) = actual.remove(&client) else {
+            mismatches.push(Mismatch::Missing { client });
+            continue;
+        };
+
+        let amounts = [
+            ("available", expected.available, account.available()),
+            ("held", expected.held, account.held()),
+            ("total", expected.total, account.total()),
+        ];
+        for (field, expected, actual) in amounts {
+            if expected != actual {
+                mismatches.push(Mismatch::Differs {
+                    client,
+                    field,
+                    expected: expected.to_string(),
+                    actual: actual.to_string(),
+                });
+            }
+        }
+
+        if expected.locked != account.locked() {
+            mismatches.push(Mismatch::Differs {
+                client,
+                field: "locked",
+                expected: expected.locked.to_string(),
+                actual: account.locked().to_string(),
+            });
+        }
+    }
+
+    mismatches.extend(
+        actual
+            .into_keys()
+            .map(|client| Mismatch::Unexpected { client }),
+    );
+    mismatches.sort_by_key(|mismatch| match mismatch {
+        Mismatch::Missing { client }
+        | Mismatch::Unexpected { client }
+        | Mismatch::Differs { client, .. } => *client,
+    });
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::models::transaction::{Transaction, TransactionType};
+
+    #[test]
+    fn mismatches() -> Result<(), Box<dyn Error>> {
+        let mut account = Account::new(1.into());
+        account.process_txn(Transaction::new(
+            1.into(),
+            1.into(),
+            TransactionType::Deposit {
+                amount: "1.50".parse()?,
+            },
+        ))?;
+        let accounts = [account, Account::new(3.into())];
+
+        let expected = [
+            ExpectedAccount {
+                client: 1.into(),
+                available: "1.5".parse()?,
+                held: Decimal::ZERO,
+                total: "2".parse()?,
+                locked: false,
+            },
+            ExpectedAccount {
+                client: 2.into(),
+                available: Decimal::ZERO,
+                held: Decimal::ZERO,
+                total: Decimal::ZERO,
+                locked: false,
+            },
+        ];
+
+        assert_eq!(
+            reconcile(&accounts, &expected),
+            [
+                Mismatch::Differs {
+                    client: 1.into(),
+                    field: "total",
+                    expected: "2".to_string(),
+                    actual: "1.50".to_string(),
+                },
+                Mismatch::Missing { client: 2.into() },
+                Mismatch::Unexpected { client: 3.into() },
+            ]
+        );
+
+        Ok(())
+    }
+}
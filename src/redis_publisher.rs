@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crossbeam_channel::{RecvTimeoutError, Sender};
+use rust_decimal::Decimal;
+use snafu::{ResultExt, Whatever};
+
+use crate::models::{
+    account::{Account, AccountId},
+    transaction::OrderedTransaction,
+};
+use crate::observer::EventObserver;
+
+// How often the latest balances are published.
+const PUBLISH_INTERVAL: Duration = Duration::from_millis(100);
+
+// How long Redis is given to respond before it's considered unavailable, so that a server that has
+// stopped responding can't hold up the end of a run.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// The balances of an account as of the last transaction applied to it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Balances {
+    available: Decimal,
+    held: Decimal,
+    total: Decimal,
+    locked: bool,
+    last_order: Option<u64>,
+}
+
+impl From<&Account> for Balances {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.locked(),
+            last_order: account.last_order(),
+        }
+    }
+}
+
+// Mirrors the balances of accounts into Redis as transactions are applied to them, as a hash per
+// account under the key `account:{id}`, so that other services can read near real-time balances.
+//
+// Workers only note down the latest balances of each account they change, so that they're never
+// held up by Redis. A publisher thread of its own writes out whatever has changed every
+// `PUBLISH_INTERVAL` in a single pipeline, so an account changed many times in that interval is
+// only written once. Should Redis be unavailable, the balances are kept until it can be written to
+// again, and the run carries on regardless.
+pub struct RedisPublisher {
+    pending: Arc<Mutex<HashMap<AccountId, Balances>>>,
+    stop: Mutex<Option<Sender<()>>>,
+    publisher: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl RedisPublisher {
+    // Connects to the Redis server at the given URL, e.g. `redis://localhost:6379/0`, and starts
+    // publishing to it.
+    pub fn connect(url: &str) -> Result<Self, Whatever> {
+        let client = redis::Client::open(url)
+            .with_whatever_context(|_| format!("'{url}' is not a valid Redis URL"))?;
+        // Connect up front, so that a server that can't be reached is reported before the run.
+        let connection = connect(&client)
+            .with_whatever_context(|_| format!("unable to connect to Redis at {url}"))?;
+
+        let pending = Arc::new(Mutex::new(HashMap::new()));
+        let (stop, stopped) = crossbeam_channel::bounded(1);
+        let publisher = {
+            let pending = pending.clone();
+            thread::Builder::new()
+                .name("redis-publisher".to_owned())
+                .spawn(move || {
+                    let mut connection = Some(connection);
+                    loop {
+                        let stopping = match stopped.recv_timeout(PUBLISH_INTERVAL) {
+                            Err(RecvTimeoutError::Timeout) => false,
+                            Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+                        };
+                        publish(&client, &mut connection, &pending);
+                        if stopping {
+                            return;
+                        }
+                    }
+                })
+                .whatever_context("unable to start the Redis publisher")?
+        };
+
+        Ok(Self {
+            pending,
+            stop: Mutex::new(Some(stop)),
+            publisher: Mutex::new(Some(publisher)),
+        })
+    }
+
+    // Publishes any balances still waiting to be, and stops publishing.
+    pub fn finish(&self) {
+        if let Some(stop) = self.stop.lock().expect("publisher lock poisoned").take() {
+            let _ = stop.send(());
+        }
+        if let Some(publisher) = self
+            .publisher
+            .lock()
+            .expect("publisher lock poisoned")
+            .take()
+        {
+            let _ = publisher.join();
+        }
+    }
+}
+
+impl EventObserver for RedisPublisher {
+    fn on_applied(&self, _txn: &OrderedTransaction, account: &Account) {
+        self.pending
+            .lock()
+            .expect("publisher lock poisoned")
+            .insert(account.id(), account.into());
+    }
+}
+
+fn connect(client: &redis::Client) -> redis::RedisResult<redis::Connection> {
+    let connection = client.get_connection_with_timeout(TIMEOUT)?;
+    connection.set_read_timeout(Some(TIMEOUT))?;
+    connection.set_write_timeout(Some(TIMEOUT))?;
+    Ok(connection)
+}
+
+// Writes out the balances that have changed since they were last written, reconnecting first if
+// the connection was lost.
+fn publish(
+    client: &redis::Client,
+    connection: &mut Option<redis::Connection>,
+    pending: &Mutex<HashMap<AccountId, Balances>>,
+) {
+    let changed = std::mem::take(&mut *pending.lock().expect("publisher lock poisoned"));
+    if changed.is_empty() {
+        return;
+    }
+
+    let mut pipeline = redis::pipe();
+    for (id, balances) in &changed {
+        let mut fields = vec![
+            ("available", balances.available.to_string()),
+            ("held", balances.held.to_string()),
+            ("total", balances.total.to_string()),
+            ("locked", balances.locked.to_string()),
+        ];
+        if let Some(order) = balances.last_order {
+            fields.push(("last_order", order.to_string()));
+        }
+        pipeline
+            .hset_multiple(format!("account:{id}"), &fields)
+            .ignore();
+    }
+
+    let result = match connection {
+        Some(connection) => pipeline.query::<()>(connection),
+        None => connect(client).and_then(|new| pipeline.query::<()>(connection.insert(new))),
+    };
+    if let Err(err) = result {
+        tracing::warn!(
+            accounts = changed.len(),
+            error_code = "redis_publish_failed",
+            "Unable to publish balances to Redis, will retry: {err}"
+        );
+        *connection = None;
+        // Keep the balances for the next attempt, unless they've since changed again.
+        let mut pending = pending.lock().expect("publisher lock poisoned");
+        for (id, balances) in changed {
+            pending.entry(id).or_insert(balances);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn keeps_balances_until_published() -> Result<(), Box<dyn Error>> {
+        // Nothing listens on this port, so publishing is bound to fail.
+        let client = redis::Client::open("redis://127.0.0.1:1")?;
+        let mut account = Account::new(1.into());
+        account.credit("1.5".parse()?);
+        let balances = Balances::from(&account);
+
+        let pending = Mutex::new(HashMap::from([(account.id(), balances)]));
+        let mut connection = None;
+        publish(&client, &mut connection, &pending);
+        assert_eq!(
+            pending.lock().unwrap().get(&account.id()),
+            Some(&balances),
+            "balances that couldn't be published should be kept for the next attempt"
+        );
+
+        Ok(())
+    }
+}
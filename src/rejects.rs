@@ -0,0 +1,337 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use csv::ByteRecord;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::{
+    account::{Account, TransactionError},
+    transaction::{self, OrderedTransaction, Transaction},
+};
+use crate::observer::EventObserver;
+
+// A record from the input that could not be turned into a transaction, along with as much context
+// as we can gather about why, so that it can be located and corrected in the source file.
+#[derive(Clone, Debug, Serialize)]
+pub struct Rejection {
+    pub line: Option<u64>,
+    pub column: Option<String>,
+    pub raw_field: Option<String>,
+    pub reason: String,
+    pub memo: Option<String>,
+}
+
+impl Rejection {
+    pub fn from_csv_error(err: &csv::Error, headers: &ByteRecord, record: &ByteRecord) -> Self {
+        let line = err
+            .position()
+            .or_else(|| record.position())
+            .map(|pos| pos.line());
+
+        let (field_idx, reason) = match err.kind() {
+            csv::ErrorKind::Deserialize { err, .. } => {
+                // The position of the offending field is not always known by the deserializer
+                // (e.g. when deserializing flattened fields), so if it isn't provided we check
+                // whether the amount is at fault, as that is the only free-form field we accept.
+                let field_idx = err
+                    .field()
+                    .map(|idx| idx as usize)
+                    .or_else(|| invalid_amount_idx(headers, record));
+                (field_idx, err.to_string())
+            }
+            _ => (None, err.to_string()),
+        };
+
+        let column = field_idx
+            .and_then(|idx| headers.get(idx))
+            .map(|name| String::from_utf8_lossy(name).into_owned());
+        let raw_field = field_idx
+            .and_then(|idx| record.get(idx))
+            .map(|bytes| bytes.escape_ascii().to_string());
+
+        Self {
+            line,
+            column,
+            raw_field,
+            reason,
+            memo: memo(headers, record),
+        }
+    }
+
+    // A record whose amount has more decimal places than the input format allows.
+    pub fn excess_precision(headers: &ByteRecord, record: &ByteRecord) -> Self {
+        let idx = headers.iter().position(|name| name == b"amount");
+        Self {
+            line: record.position().map(|pos| pos.line()),
+            column: Some("amount".to_owned()),
+            raw_field: idx
+                .and_then(|idx| record.get(idx))
+                .map(|bytes| bytes.escape_ascii().to_string()),
+            reason: format!(
+                "The amount has more than {} decimal places",
+                transaction::AMOUNT_DECIMAL_PLACES
+            ),
+            memo: memo(headers, record),
+        }
+    }
+
+    // A transaction read from the given line of a bank statement, whose amount has more decimal
+    // places than the input format allows.
+    pub fn excess_precision_in_statement(
+        txn: &Transaction,
+        memo: Option<&str>,
+        line: u64,
+        column: &str,
+    ) -> Self {
+        Self {
+            line: Some(line),
+            column: Some(column.to_owned()),
+            raw_field: txn.txn_type().amount().map(|amount| amount.to_string()),
+            reason: format!(
+                "The amount has more than {} decimal places",
+                transaction::AMOUNT_DECIMAL_PLACES
+            ),
+            memo: memo.map(str::to_owned),
+        }
+    }
+
+    // A transaction read from the given line of the input of a multi-tenant run, which doesn't
+    // name the tenant it is for.
+    pub fn missing_tenant(memo: Option<&str>, line: Option<u64>) -> Self {
+        Self {
+            line,
+            column: Some("tenant".to_owned()),
+            raw_field: None,
+            reason:
+                "The transaction has no tenant, as every transaction of a multi-tenant run must"
+                    .to_owned(),
+            memo: memo.map(str::to_owned),
+        }
+    }
+
+    // A record whose posting timestamp or effective date, as given by the column, isn't a date or
+    // time that the transaction can be placed at.
+    pub fn invalid_date(headers: &ByteRecord, record: &ByteRecord, column: &str) -> Self {
+        let idx = headers.iter().position(|name| name == column.as_bytes());
+        Self {
+            line: record.position().map(|pos| pos.line()),
+            column: Some(column.to_owned()),
+            raw_field: idx
+                .and_then(|idx| record.get(idx))
+                .map(|bytes| bytes.escape_ascii().to_string()),
+            reason: format!("The {column} is not an ISO 8601 date or time in UTC"),
+            memo: memo(headers, record),
+        }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rejected record")?;
+        if let Some(line) = self.line {
+            write!(f, " on line {line}")?;
+        }
+        if let Some(column) = &self.column {
+            write!(f, " in column '{column}'")?;
+        }
+        if let Some(raw_field) = &self.raw_field {
+            write!(f, " with value \"{raw_field}\"")?;
+        }
+        write!(f, ": {}", self.reason)
+    }
+}
+
+impl Error for Rejection {}
+
+// Writes each rejected record to a CSV file, along with the input file it was read from, so that
+// the records skipped over during a run can be corrected and resubmitted. Records read from CSV
+// files are written out whole as well, rewritten as CSV, whereas those of bank statements and
+// transactions rejected by the workers have no record of their own to write.
+pub struct RejectsWriter {
+    writer: csv::Writer<BufWriter<Box<dyn Write>>>,
+}
+
+#[derive(Serialize)]
+struct RejectRecord<'a> {
+    file: &'a str,
+    line: Option<u64>,
+    column: Option<&'a str>,
+    raw_field: Option<&'a str>,
+    reason: &'a str,
+    memo: Option<&'a str>,
+    raw_record: Option<String>,
+}
+
+impl RejectsWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(Box::new(File::create(path)?)))
+    }
+
+    pub fn stdout() -> Self {
+        Self::new(Box::new(io::stdout()))
+    }
+
+    fn new(writer: Box<dyn Write>) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(BufWriter::new(writer)),
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        file: &Path,
+        rejection: &Rejection,
+        raw_record: Option<&ByteRecord>,
+    ) -> csv::Result<()> {
+        self.writer.serialize(RejectRecord {
+            file: &file.to_string_lossy(),
+            line: rejection.line,
+            column: rejection.column.as_deref(),
+            raw_field: rejection.raw_field.as_deref(),
+            reason: &rejection.reason,
+            memo: rejection.memo.as_deref(),
+            raw_record: raw_record.map(self::raw_record).transpose()?,
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// A record rewritten as a line of CSV, quoted wherever it needs to be.
+fn raw_record(record: &ByteRecord) -> csv::Result<String> {
+    let mut line = vec![];
+    let mut writer = csv::WriterBuilder::new()
+        .terminator(csv::Terminator::Any(b'\n'))
+        .from_writer(&mut line);
+    writer.write_byte_record(record)?;
+    writer.flush()?;
+    drop(writer);
+    let line = line.strip_suffix(b"\n").unwrap_or(&line);
+    Ok(String::from_utf8_lossy(line).into_owned())
+}
+
+// The memo of a rejected record, if it has one, which may help whoever corrects the record.
+fn memo(headers: &ByteRecord, record: &ByteRecord) -> Option<String> {
+    let idx = headers.iter().position(|name| name == b"memo")?;
+    record
+        .get(idx)
+        .filter(|memo| !memo.is_empty())
+        .map(|memo| String::from_utf8_lossy(memo).into_owned())
+}
+
+fn invalid_amount_idx(headers: &ByteRecord, record: &ByteRecord) -> Option<usize> {
+    let idx = headers.iter().position(|name| name == b"amount")?;
+    let amount = std::str::from_utf8(record.get(idx)?).ok();
+    match amount {
+        Some("") => None,
+        Some(amount)
+            if amount
+                .parse::<Decimal>()
+                .is_ok_and(|amount| transaction::validate_amount(amount).is_ok()) =>
+        {
+            None
+        }
+        _ => Some(idx),
+    }
+}
+
+// Collects the transactions that the workers don't apply, so that they can be reported alongside
+// the records that couldn't be parsed, as they are by a dry run.
+#[derive(Default)]
+pub struct RejectedTransactions {
+    // Each is kept along with its order, so that they can be put back in the order they were read.
+    rejected: Mutex<Vec<(u64, &'static str, Rejection)>>,
+}
+
+impl RejectedTransactions {
+    // Takes the transactions rejected so far, along with the files they were read from, in the order
+    // they were read.
+    pub fn take(&self) -> Vec<(&'static str, Rejection)> {
+        let mut rejected =
+            std::mem::take(&mut *self.rejected.lock().expect("rejects lock poisoned"));
+        rejected.sort_by_key(|(order, ..)| *order);
+        rejected
+            .into_iter()
+            .map(|(_, file, rejection)| (file, rejection))
+            .collect()
+    }
+}
+
+impl EventObserver for RejectedTransactions {
+    fn on_rejected(&self, txn: &OrderedTransaction, _account: &Account, err: &TransactionError) {
+        let source = txn.source();
+        let rejection = Rejection {
+            line: source.map(|source| source.line()),
+            column: None,
+            raw_field: None,
+            reason: err.to_string(),
+            memo: txn.memo().map(str::to_owned),
+        };
+        self.rejected.lock().expect("rejects lock poisoned").push((
+            txn.order(),
+            source.map_or("", |source| source.file()),
+            rejection,
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::csv_record::RecordParser;
+
+    #[test]
+    fn reports_malformed_amounts() -> Result<(), Box<dyn Error>> {
+        let input = "type,client,tx,amount,memo\n\
+                     deposit,1,1,10,\n\
+                     deposit,1,2,12.x,payroll\n\
+                     withdrawal,1,3,-5,\n";
+        let mut reader = csv::Reader::from_reader(input.as_bytes());
+        let headers = reader.byte_headers()?.clone();
+        let parser = RecordParser::new(headers.clone(), false);
+        let mut rejections = vec![];
+        let mut record = ByteRecord::new();
+        while reader.read_byte_record(&mut record)? {
+            if let Err(err) = parser.parse(&record) {
+                rejections.push(Rejection::from_csv_error(&err, &headers, &record));
+            }
+        }
+
+        let located: Vec<_> = rejections
+            .iter()
+            .map(|rejection| {
+                (
+                    rejection.line,
+                    rejection.column.as_deref(),
+                    rejection.raw_field.as_deref(),
+                    rejection.memo.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            located,
+            [
+                (Some(3), Some("amount"), Some("12.x"), Some("payroll")),
+                (Some(4), Some("amount"), Some("-5"), None)
+            ]
+        );
+        assert_eq!(
+            raw_record(&csv::ByteRecord::from(vec![
+                "deposit", "1", "2", "1,5", "\"x\""
+            ]))?,
+            "deposit,1,2,\"1,5\",\"\"\"x\"\"\""
+        );
+        assert!(rejections[0]
+            .to_string()
+            .starts_with("Rejected record on line 3 in column 'amount' with value \"12.x\": "));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+use std::env;
+use std::future::Future;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use bytes::{Buf, Bytes};
+use futures::StreamExt;
+use object_store::{path::Path as ObjectPath, ObjectStore};
+use url::Url;
+
+// The URL schemes of the object stores that inputs can be streamed from.
+const OBJECT_STORE_SCHEMES: &[&str] = &["s3", "s3a", "gs", "az", "adl", "azure", "abfs", "abfss"];
+
+// Only environment variables with these prefixes are used to configure the object stores, so that
+// unrelated variables such as `TOKEN` aren't mistaken for configuration.
+const CONFIG_PREFIXES: &[&str] = &["aws_", "google_", "azure_"];
+
+// The number of chunks of an object that may be buffered ahead of the reader.
+const CHUNKS_BUFFERED: usize = 16;
+
+// How many times in a row we'll try to resume a download over HTTP before giving up on it, and how
+// long we wait before the first of those attempts. The wait doubles with each attempt.
+const MAX_RESUMES: u32 = 5;
+const RESUME_BACKOFF: Duration = Duration::from_millis(500);
+
+// How long a download over HTTP may stall for before the connection is treated as dropped.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+// Whether the input location refers to a file elsewhere, rather than a local path.
+pub fn is_remote(location: &Path) -> bool {
+    is_object_store_url(location) || is_http_url(location)
+}
+
+// Whether the input location refers to an object in an object store, rather than a local path.
+pub fn is_object_store_url(location: &Path) -> bool {
+    parse_url(location).is_some()
+}
+
+// Whether the input location refers to a file served over HTTP(S), rather than a local path.
+pub fn is_http_url(location: &Path) -> bool {
+    location
+        .to_str()
+        .and_then(|location| Url::parse(location).ok())
+        .is_some_and(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+// Returns the size in bytes of the remote file at the given URL.
+pub fn size(location: &Path) -> io::Result<u64> {
+    if is_http_url(location) {
+        HttpReader::size(location)
+    } else {
+        object_size(location)
+    }
+}
+
+// Opens the remote file at the given URL for streaming.
+pub fn open(location: &Path) -> io::Result<Box<dyn Read>> {
+    Ok(if is_http_url(location) {
+        Box::new(HttpReader::open(location)?)
+    } else {
+        Box::new(ObjectReader::open(location)?)
+    })
+}
+
+fn parse_url(location: &Path) -> Option<Url> {
+    let url = Url::parse(location.to_str()?).ok()?;
+    OBJECT_STORE_SCHEMES.contains(&url.scheme()).then_some(url)
+}
+
+// Opens the object store that holds the object at the given URL, configured from the standard
+// environment variables of each store, e.g. `AWS_ACCESS_KEY_ID` or `GOOGLE_SERVICE_ACCOUNT`.
+fn open_store(location: &Path) -> io::Result<(Arc<dyn ObjectStore>, ObjectPath)> {
+    let url = parse_url(location).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not an object store URL", location.display()),
+        )
+    })?;
+    let options = env::vars()
+        .map(|(key, value)| (key.to_ascii_lowercase(), value))
+        .filter(|(key, _)| CONFIG_PREFIXES.iter().any(|prefix| key.starts_with(prefix)));
+    let (store, path) = object_store::parse_url_opts(&url, options).map_err(io::Error::other)?;
+    Ok((store.into(), path))
+}
+
+// Runs a future to completion on a runtime of its own. Object stores are only accessible
+// asynchronously, whereas the rest of the pipeline is synchronous.
+fn block_on<F: Future>(future: F) -> io::Result<F::Output> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    Ok(runtime.block_on(future))
+}
+
+// Returns the size of the object at the given URL, in bytes.
+pub fn object_size(location: &Path) -> io::Result<u64> {
+    let (store, path) = open_store(location)?;
+    let meta = block_on(async { store.head(&path).await })?.map_err(io::Error::other)?;
+    Ok(meta.size)
+}
+
+// Streams an object out of an object store without first downloading it to disk. The object is
+// fetched by a thread of its own, which buffers a limited number of chunks ahead of the reader.
+pub struct ObjectReader {
+    chunks: crossbeam_channel::Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl ObjectReader {
+    pub fn open(location: &Path) -> io::Result<Self> {
+        let (store, path) = open_store(location)?;
+        Self::from_store(store, path)
+    }
+
+    pub fn from_store(store: Arc<dyn ObjectStore>, path: ObjectPath) -> io::Result<Self> {
+        let (chunks_tx, chunks) = crossbeam_channel::bounded(CHUNKS_BUFFERED);
+        let (opened_tx, opened_rx) = crossbeam_channel::bounded(1);
+        thread::Builder::new()
+            .name(format!("object-reader-{path}"))
+            .spawn(move || {
+                let fetch = async {
+                    let mut stream = match store.get(&path).await {
+                        Ok(result) => {
+                            let _ = opened_tx.send(Ok(()));
+                            result.into_stream()
+                        }
+                        Err(err) => {
+                            let _ = opened_tx.send(Err(io::Error::other(err)));
+                            return;
+                        }
+                    };
+
+                    while let Some(chunk) = stream.next().await {
+                        // The reader has gone away, so there's nobody left to read the rest.
+                        if chunks_tx.send(chunk.map_err(io::Error::other)).is_err() {
+                            return;
+                        }
+                    }
+                };
+                if let Err(err) = block_on(fetch) {
+                    let _ = opened_tx.send(Err(err));
+                }
+            })?;
+
+        // Wait for the object to be found before handing back the reader, so that problems such as
+        // a missing object or bad credentials are reported up front.
+        opened_rx
+            .recv()
+            .map_err(|_| io::Error::other("object reader stopped unexpectedly"))??;
+        Ok(Self {
+            chunks,
+            chunk: Bytes::new(),
+        })
+    }
+}
+
+impl Read for ObjectReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while !self.chunk.has_remaining() {
+            match self.chunks.recv() {
+                Ok(chunk) => self.chunk = chunk?,
+                // Every chunk of the object has been read.
+                Err(_) => return Ok(0),
+            }
+        }
+
+        let n = buf.len().min(self.chunk.remaining());
+        self.chunk.copy_to_slice(&mut buf[..n]);
+        Ok(n)
+    }
+}
+
+// Streams a file served over HTTP(S). Should the connection drop part way through, the download is
+// resumed from where it left off with a range request, so a flaky network doesn't fail the run.
+// The range is only asked for if the file is still the one that was being downloaded, as given by
+// the ETag or Last-Modified date of the first response, and the download fails should the server
+// send the whole file instead, as it may have changed and can't be pieced together.
+pub struct HttpReader {
+    agent: ureq::Agent,
+    url: String,
+    body: Box<dyn Read + Send + Sync>,
+    offset: u64,
+    // The ETag or Last-Modified date of the file, to ask for the rest of it with If-Range.
+    validator: Option<String>,
+}
+
+impl HttpReader {
+    pub fn open(location: &Path) -> io::Result<Self> {
+        let url = location.to_string_lossy().into_owned();
+        let agent = ureq::AgentBuilder::new().timeout_read(READ_TIMEOUT).build();
+        let response = Self::get(&agent, &url, 0, None)?;
+        // Only a strong ETag may be used with If-Range, so a weak one falls back to the date.
+        let validator = response
+            .header("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| response.header("Last-Modified"))
+            .map(str::to_owned);
+        Ok(Self {
+            agent,
+            url,
+            body: Box::new(response.into_reader()),
+            offset: 0,
+            validator,
+        })
+    }
+
+    fn size(location: &Path) -> io::Result<u64> {
+        let url = location.to_string_lossy();
+        let response = ureq::head(&url).call().map_err(io::Error::other)?;
+        response
+            .header("Content-Length")
+            .and_then(|length| length.parse().ok())
+            .ok_or_else(|| io::Error::other(format!("{url} did not report its size")))
+    }
+
+    // Requests the file from the given offset onwards, so long as it still has the given validator.
+    fn get(
+        agent: &ureq::Agent,
+        url: &str,
+        offset: u64,
+        validator: Option<&str>,
+    ) -> io::Result<ureq::Response> {
+        let mut request = agent.get(url);
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={offset}-"));
+        }
+        if let Some(validator) = validator {
+            request = request.set("If-Range", validator);
+        }
+        request.call().map_err(io::Error::other)
+    }
+
+    // Reconnects to the server, and picks up the download from where it was interrupted.
+    fn resume(&mut self, mut err: io::Error) -> io::Result<()> {
+        let mut backoff = RESUME_BACKOFF;
+        for attempt in 1..=MAX_RESUMES {
+            tracing::warn!(
+                url = %self.url,
+                offset = self.offset,
+                attempt,
+                "Resuming an interrupted download: {err}"
+            );
+            thread::sleep(backoff);
+            backoff *= 2;
+
+            match Self::get(&self.agent, &self.url, self.offset, self.validator.as_deref()) {
+                Ok(response) if response.status() == 206 => {
+                    self.body = Box::new(response.into_reader());
+                    return Ok(());
+                }
+                // Retrying won't get us the rest of a file that's changed, or from a server that
+                // doesn't support range requests.
+                Ok(response) => {
+                    return Err(io::Error::other(format!(
+                        "Unable to resume downloading {}, as the server sent the whole file again ({} {}), so it may have changed since the download began",
+                        self.url,
+                        response.status(),
+                        response.status_text()
+                    )))
+                }
+                Err(resume_err) => err = resume_err,
+            }
+        }
+        Err(err)
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.body.read(buf) {
+                Ok(n) => {
+                    self.offset += n as u64;
+                    return Ok(n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => self.resume(err)?,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn streams_objects() -> Result<(), Box<dyn Error>> {
+        let store = Arc::new(InMemory::new());
+        let path = ObjectPath::from("exports/part-1.csv");
+        let contents = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        block_on(store.put(&path, contents.into()))??;
+
+        let mut read = String::new();
+        ObjectReader::from_store(store.clone(), path)?.read_to_string(&mut read)?;
+        assert_eq!(read, contents);
+
+        assert!(
+            ObjectReader::from_store(store, ObjectPath::from("missing.csv")).is_err(),
+            "a missing object should be reported when opened"
+        );
+
+        assert!(is_object_store_url(Path::new(
+            "s3://bucket/exports/part-1.csv"
+        )));
+        assert!(!is_object_store_url(Path::new("exports/part-1.csv")));
+
+        Ok(())
+    }
+
+    // Serves the contents of a file over two connections, cutting the first short as though the
+    // connection dropped, and returns the Range and If-Range headers of the second request. The
+    // second is sent the rest of the file if it asks for it by the file's ETag, or else the whole
+    // file, as though it had changed.
+    fn serve_interrupted(
+        contents: &'static str,
+        etag: &'static str,
+    ) -> io::Result<(String, thread::JoinHandle<io::Result<Vec<String>>>)> {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let url = format!("http://{}/part-1.csv", listener.local_addr()?);
+        let server = thread::spawn(move || {
+            let mut headers = vec![];
+            for (cut, stream) in [20, contents.len()].into_iter().zip(listener.incoming()) {
+                let mut stream = stream?;
+                let (mut range, mut if_range) = (None, None);
+                for line in BufReader::new(&stream).lines() {
+                    let line = line?;
+                    let line = line.trim_end();
+                    if line.is_empty() {
+                        break;
+                    }
+                    if let Some(value) = line.strip_prefix("Range: bytes=") {
+                        range = Some(value.to_owned());
+                    }
+                    if let Some(value) = line.strip_prefix("If-Range: ") {
+                        if_range = Some(value.to_owned());
+                    }
+                }
+
+                let start = match (&range, &if_range) {
+                    (Some(range), Some(if_range)) if if_range == "\"v1\"" => {
+                        range.trim_end_matches('-').parse().unwrap_or(0)
+                    }
+                    _ => 0,
+                };
+                let status = if start > 0 {
+                    "206 Partial Content"
+                } else {
+                    "200 OK"
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\nETag: {etag}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    contents.len() - start,
+                    &contents[start..cut.max(start)]
+                )?;
+                headers.extend(range);
+                headers.extend(if_range);
+            }
+            Ok(headers)
+        });
+        Ok((url, server))
+    }
+
+    #[test]
+    fn resumes_interrupted_downloads() -> Result<(), Box<dyn Error>> {
+        let contents = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,2,2,2.0\n";
+        let (url, server) = serve_interrupted(contents, "\"v1\"")?;
+        let mut read = String::new();
+        HttpReader::open(Path::new(&url))?.read_to_string(&mut read)?;
+        assert_eq!(read, contents);
+        assert_eq!(
+            server.join().unwrap()?,
+            ["20-", "\"v1\""],
+            "the download should be resumed from where it was interrupted, if the file is unchanged"
+        );
+        assert!(is_http_url(Path::new(&url)));
+
+        // A file that has changed since is sent whole, which can't be pieced onto what was read.
+        let (url, server) = serve_interrupted(contents, "\"v2\"")?;
+        let mut read = String::new();
+        assert!(HttpReader::open(Path::new(&url))?
+            .read_to_string(&mut read)
+            .is_err());
+        assert_eq!(server.join().unwrap()?, ["20-", "\"v2\""]);
+
+        Ok(())
+    }
+}
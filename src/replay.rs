@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+
+use rust_decimal::Decimal;
+use snafu::{ResultExt, Whatever};
+use uuid::Uuid;
+
+use crate::journal::JournalRecord;
+use crate::models::{
+    account::{Account, AccountId},
+    transaction::{Transaction, TransactionId, TransactionType},
+};
+
+// The first entry of the journal at which the replay of an account's transactions no longer
+// agrees with it. Nothing more is checked of the account after that, as it's bound to disagree
+// with every entry that follows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub run_id: Uuid,
+    pub client: AccountId,
+    pub tx: TransactionId,
+    pub order: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "run {} client {}: transaction {} (order {}) {}",
+            self.run_id, self.client, self.tx, self.order, self.reason
+        )
+    }
+}
+
+// An account as reconstructed from the journal so far.
+struct ReplayedAccount {
+    account: Account,
+    last_order: Option<u64>,
+    diverged: bool,
+}
+
+impl ReplayedAccount {
+    // Applies the transaction of the entry if it was applied when it was journaled, and checks that
+    // the account was left with the balances the entry recorded.
+    fn replay(&mut self, record: &JournalRecord) -> Result<(), String> {
+        if record.outcome == "applied" {
+            let txn_type = TransactionType::from_name(&record.txn_type, record.amount)
+                .map_err(|err| format!("was applied, but can't be replayed: {err}"))?;
+            self.account
+                .process_txn(Transaction::new(record.tx, record.client, txn_type))
+                .map_err(|err| format!("was applied, but failed to replay: {err}"))?;
+        }
+
+        let account = &self.account;
+        let balances: [(&str, Decimal, Decimal); 3] = [
+            ("available", record.available, account.available()),
+            ("held", record.held, account.held()),
+            ("total", record.total, account.total()),
+        ];
+        if let Some((field, recorded, replayed)) = balances
+            .into_iter()
+            .find(|(_, recorded, replayed)| recorded != replayed)
+        {
+            return Err(format!(
+                "left {field} at {recorded}, but replaying it left {replayed}"
+            ));
+        }
+        if record.locked != account.locked() {
+            return Err(format!(
+                "left the account {}, but replaying it left it {}",
+                if record.locked { "locked" } else { "unlocked" },
+                if account.locked() {
+                    "locked"
+                } else {
+                    "unlocked"
+                }
+            ));
+        }
+        Ok(())
+    }
+}
+
+// Reconstructs the accounts of runs purely from their audit journal, checking each entry against
+// the replay as it goes. Each run is replayed from no accounts at all, so a run that started from a
+// snapshot or checkpoint can't be reconstructed from the journal alone.
+//
+// Entries that recorded a transaction as applied are applied again, whereas the rest are only
+// checked to have left the account as it was, so that a run is replayed the same way whatever it
+// was configured with. Transactions of custom types can't be replayed, as they need the handler that
+// applied them.
+#[derive(Default)]
+pub struct Replay {
+    run_id: Option<Uuid>,
+    // Runs in the order they first appear in the journal.
+    runs: Vec<(Uuid, HashMap<AccountId, ReplayedAccount>)>,
+    entries: u64,
+    duplicates: u64,
+    divergences: Vec<Divergence>,
+}
+
+impl Replay {
+    // Only replays the run with the given ID, ignoring the entries of every other run.
+    pub fn with_run_id(mut self, run_id: Uuid) -> Self {
+        self.run_id = Some(run_id);
+        self
+    }
+
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    // The number of entries skipped as they journaled a transaction again. A run that recovers
+    // from a crash journals the transactions it replays from its write-ahead logs a second time.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    pub fn runs(&self) -> usize {
+        self.runs.len()
+    }
+
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    pub fn apply(&mut self, record: JournalRecord) {
+        if self.run_id.is_some_and(|run_id| run_id != record.run_id) {
+            return;
+        }
+        self.entries += 1;
+
+        // Runs are almost always journaled one after another, so the last is checked first.
+        let run_idx = match self
+            .runs
+            .iter()
+            .rposition(|(run_id, _)| *run_id == record.run_id)
+        {
+            Some(run_idx) => run_idx,
+            None => {
+                self.runs.push((record.run_id, HashMap::new()));
+                self.runs.len() - 1
+            }
+        };
+        let replayed = self.runs[run_idx]
+            .1
+            .entry(record.client)
+            .or_insert_with(|| ReplayedAccount {
+                account: Account::new(record.client),
+                last_order: None,
+                diverged: false,
+            });
+
+        // The transactions of an account are journaled in the order they were applied.
+        if replayed
+            .last_order
+            .is_some_and(|last_order| record.order <= last_order)
+        {
+            self.duplicates += 1;
+            return;
+        }
+        replayed.last_order = Some(record.order);
+        if replayed.diverged {
+            return;
+        }
+
+        if let Err(reason) = replayed.replay(&record) {
+            replayed.diverged = true;
+            self.divergences.push(Divergence {
+                run_id: record.run_id,
+                client: record.client,
+                tx: record.tx,
+                order: record.order,
+                reason,
+            });
+        }
+    }
+
+    // The accounts of the last run replayed, ordered by client.
+    pub fn into_accounts(mut self) -> Vec<Account> {
+        let mut accounts: Vec<_> = self
+            .runs
+            .pop()
+            .map(|(_, accounts)| accounts.into_values().map(|replayed| replayed.account))
+            .into_iter()
+            .flatten()
+            .collect();
+        accounts.sort_by_key(|account| account.id());
+        accounts
+    }
+}
+
+// Replays every entry of the journal read from the given reader.
+pub fn replay(journal: impl Read, mut replay: Replay) -> Result<Replay, Whatever> {
+    let mut reader = csv::Reader::from_reader(journal);
+    for record in reader.deserialize() {
+        replay.apply(record.whatever_context("unable to read the journal")?);
+    }
+    Ok(replay)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::journal::JournalEntry;
+    use crate::metadata::AccountMetadata;
+    use crate::models::transaction::{OrderedTransaction, RawTransactionId};
+    use crate::stats::RunId;
+
+    #[test]
+    fn replays_journal() -> Result<(), Box<dyn Error>> {
+        let run_id = RunId::generate();
+        let amount = "2.5".parse()?;
+        let txns = [
+            (1, TransactionType::Deposit { amount }),
+            (2, TransactionType::Withdrawal { amount }),
+            (2, TransactionType::Withdrawal { amount }),
+            (1, TransactionType::Dispute),
+            (1, TransactionType::Chargeback),
+        ];
+
+        let mut account = Account::new(1.into());
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for (order, (txn_id, txn_type)) in txns.into_iter().enumerate() {
+            let txn_id: RawTransactionId = txn_id;
+            let txn = OrderedTransaction::new(
+                order as u64,
+                Transaction::new(txn_id.into(), account.id(), txn_type),
+            );
+            let result = account.process_txn(txn.txn().clone());
+            let entry = JournalEntry::new(
+                run_id,
+                &txn,
+                &account,
+                &AccountMetadata::EMPTY,
+                result.as_ref().err(),
+            );
+            writer.serialize(entry)?;
+        }
+        let journal = String::from_utf8(writer.into_inner()?)?;
+
+        let replayed = replay(journal.as_bytes(), Replay::default())?;
+        assert_eq!((replayed.entries(), replayed.runs()), (5, 1));
+        assert!(replayed.divergences().is_empty());
+        let accounts = replayed.into_accounts();
+        assert_eq!(accounts.len(), 1);
+        assert!(accounts[0].locked());
+        assert_eq!(accounts[0].total(), -amount);
+
+        // The deposit is recorded as being of more than it left the account with.
+        let tampered = journal.replacen("deposit,2.5,", "deposit,3.5,", 1);
+        assert_ne!(tampered, journal);
+        let replayed = replay(tampered.as_bytes(), Replay::default())?;
+        let divergences = replayed.divergences();
+        assert_eq!(
+            divergences.len(),
+            1,
+            "only the first divergence is reported"
+        );
+        assert_eq!(divergences[0].order, 0);
+        assert!(divergences[0].reason.contains("available"));
+
+        // A recovered run journals the transactions it replays again.
+        let lines: Vec<_> = journal.lines().collect();
+        let recovered = format!("{}\n{}\n", journal.trim_end(), lines[2..].join("\n"));
+        let replayed = replay(recovered.as_bytes(), Replay::default())?;
+        assert_eq!(replayed.duplicates(), 4);
+        assert!(replayed.divergences().is_empty());
+
+        Ok(())
+    }
+}
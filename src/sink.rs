@@ -0,0 +1,554 @@
+use std::collections::btree_map::{BTreeMap, Entry};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use snafu::{ResultExt, Whatever};
+
+#[cfg(feature = "arrow")]
+use crate::arrow_ipc::ArrowSink;
+use crate::metadata::MetadataTable;
+use crate::models::account::{Account, AccountOutput, OutputFormat, RawAccountId, Tenant};
+use crate::observer::EventObserver;
+#[cfg(feature = "sqlite")]
+use crate::sqlite::SqliteSink;
+use crate::stats::RunId;
+
+// A destination for the final state of the accounts once a run has completed.
+pub trait AccountSink {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever>;
+
+    // Called once every account has been written.
+    fn finish(&mut self) -> Result<(), Whatever>;
+
+    // An observer of the transactions of the run, for sinks that record those as well as the
+    // accounts they result in.
+    fn observer(&self) -> Option<Arc<dyn EventObserver>> {
+        None
+    }
+}
+
+// Renders an account in the given format, followed by its metadata if there is a table of it.
+pub(crate) fn output<'a>(
+    account: &'a Account,
+    format: OutputFormat,
+    metadata: Option<&'a MetadataTable>,
+) -> AccountOutput<'a> {
+    let output = account.output(format);
+    match metadata {
+        Some(metadata) => output.with_metadata(metadata.get(account.id())),
+        None => output,
+    }
+}
+
+// Writes accounts as CSV, in the same format as the input to reconciliation.
+pub struct CsvSink<W: Write> {
+    writer: csv::Writer<W>,
+    format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        Self {
+            writer: csv::Writer::from_writer(writer),
+            format,
+            metadata: None,
+        }
+    }
+
+    // Writes out each account's metadata alongside it.
+    pub fn with_metadata(mut self, metadata: Arc<MetadataTable>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+}
+
+impl<W: Write> AccountSink for CsvSink<W> {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        self.writer
+            .serialize(output(account, self.format, self.metadata.as_deref()))
+            .whatever_context("unable to write account as CSV")
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.writer
+            .flush()
+            .whatever_context("unable to write accounts as CSV")
+    }
+}
+
+// Writes accounts as JSON Lines, with one object per account.
+pub struct JsonSink<W: Write> {
+    writer: W,
+    format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+}
+
+impl<W: Write> JsonSink<W> {
+    pub fn new(writer: W, format: OutputFormat) -> Self {
+        Self {
+            writer,
+            format,
+            metadata: None,
+        }
+    }
+
+    // Writes out each account's metadata alongside it.
+    pub fn with_metadata(mut self, metadata: Arc<MetadataTable>) -> Self {
+        self.metadata = Some(metadata);
+        self
+    }
+}
+
+impl<W: Write> AccountSink for JsonSink<W> {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        let output = output(account, self.format, self.metadata.as_deref());
+        serde_json::to_writer(&mut self.writer, &output)
+            .whatever_context("unable to write account as JSON")?;
+        self.writer
+            .write_all(b"\n")
+            .whatever_context("unable to write account as JSON")
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.writer
+            .flush()
+            .whatever_context("unable to write accounts as JSON")
+    }
+}
+
+// Writes every account to each of a number of sinks in turn.
+#[derive(Default)]
+pub struct FanOut {
+    sinks: Vec<Box<dyn AccountSink>>,
+}
+
+impl FanOut {
+    pub fn push(&mut self, sink: Box<dyn AccountSink>) {
+        self.sinks.push(sink);
+    }
+}
+
+impl AccountSink for FanOut {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        self.sinks
+            .iter_mut()
+            .try_for_each(|sink| sink.write(account))
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.sinks.iter_mut().try_for_each(|sink| sink.finish())
+    }
+}
+
+// A list of client IDs and ranges of them, e.g. `100-200,5000`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientList(Vec<RangeInclusive<RawAccountId>>);
+
+impl ClientList {
+    pub fn contains(&self, id: RawAccountId) -> bool {
+        self.0.iter().any(|range| range.contains(&id))
+    }
+}
+
+impl FromStr for ClientList {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split(',')
+            .map(|range| {
+                let (start, end) = range.split_once('-').unwrap_or((range, range));
+                let (start, end) = (start.trim().parse().ok()?, end.trim().parse().ok()?);
+                // A reversed range would match no clients at all, so is surely a mistake.
+                (start <= end).then_some(start..=end)
+            })
+            .collect::<Option<_>>()
+            .map(Self)
+            .ok_or_else(|| format!("'{s}' is not a valid client list."))
+    }
+}
+
+// Which of the accounts of a run are written out. Every account is written out by default.
+#[derive(Clone, Debug, Default)]
+pub struct AccountFilter {
+    pub only_locked: bool,
+    // Leaves out accounts with neither available nor held funds.
+    pub nonzero_only: bool,
+    // Accounts identified by name rather than number are never in a list of clients.
+    pub clients: Option<ClientList>,
+}
+
+impl AccountFilter {
+    pub fn matches(&self, account: &Account) -> bool {
+        (!self.only_locked || account.locked())
+            && (!self.nonzero_only || !account.available().is_zero() || !account.held().is_zero())
+            && self
+                .clients
+                .as_ref()
+                .is_none_or(|clients| account.id().number().is_some_and(|id| clients.contains(id)))
+    }
+}
+
+// Writes only the accounts that match a filter to another sink.
+pub struct Filtered<S> {
+    sink: S,
+    filter: AccountFilter,
+}
+
+impl<S> Filtered<S> {
+    pub fn new(sink: S, filter: AccountFilter) -> Self {
+        Self { sink, filter }
+    }
+}
+
+impl<S: AccountSink> AccountSink for Filtered<S> {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        if self.filter.matches(account) {
+            self.sink.write(account)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.sink.finish()
+    }
+}
+
+// Opens the sink of a tenant.
+type OpenTenantSink = dyn Fn(Tenant) -> Result<Box<dyn AccountSink>, Whatever>;
+
+// Writes the accounts of each tenant of a multi-tenant run to a sink of its own, which is opened
+// once the first of its accounts is written.
+pub struct PerTenant {
+    open: Box<OpenTenantSink>,
+    sinks: BTreeMap<Tenant, Box<dyn AccountSink>>,
+}
+
+impl PerTenant {
+    pub fn new(open: impl Fn(Tenant) -> Result<Box<dyn AccountSink>, Whatever> + 'static) -> Self {
+        Self {
+            open: Box::new(open),
+            sinks: BTreeMap::new(),
+        }
+    }
+}
+
+impl AccountSink for PerTenant {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        let Some(tenant) = account.tenant() else {
+            snafu::whatever!(
+                "client {} belongs to no tenant, so has no output of its own",
+                account.id()
+            )
+        };
+        let sink = match self.sinks.entry(tenant) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert((self.open)(tenant)?),
+        };
+        sink.write(account)
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        self.sinks.values_mut().try_for_each(|sink| sink.finish())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SinkFormat {
+    Csv,
+    Json,
+    // An Arrow IPC file, only in builds with the arrow feature.
+    Arrow,
+    // A SQLite database, along with the journal if asked for, only in builds with the sqlite
+    // feature.
+    Sqlite { journal: bool },
+}
+
+// Where a sink should write to, where a path of `-` means stdout.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SinkTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+// The placeholder in the path of an output that's replaced with the name of each tenant of a
+// multi-tenant run, so that each is written to a file of its own.
+pub const TENANT_PLACEHOLDER: &str = "{tenant}";
+
+impl SinkTarget {
+    // Whether the target is a file for each tenant, rather than one for all of them.
+    pub fn is_per_tenant(&self) -> bool {
+        matches!(self, Self::File(path) if path.to_string_lossy().contains(TENANT_PLACEHOLDER))
+    }
+
+    // The file of the given tenant.
+    pub fn for_tenant(&self, tenant: Tenant) -> Self {
+        match self {
+            Self::File(path) => Self::File(
+                path.to_string_lossy()
+                    .replace(TENANT_PLACEHOLDER, tenant.name())
+                    .into(),
+            ),
+            Self::Stdout => Self::Stdout,
+        }
+    }
+}
+
+impl From<&str> for SinkTarget {
+    fn from(target: &str) -> Self {
+        match target {
+            "-" => Self::Stdout,
+            path => Self::File(path.into()),
+        }
+    }
+}
+
+// Opens a sink of the given format, writing to the given target. Each account's metadata is written
+// out alongside it if a table of metadata is given. Sinks that record the run's transactions as
+// well as its accounts record them as being by the run with the given ID.
+pub fn open(
+    format: SinkFormat,
+    target: &SinkTarget,
+    output_format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+    run_id: RunId,
+) -> Result<Box<dyn AccountSink>, Whatever> {
+    if let SinkFormat::Sqlite { journal } = format {
+        let SinkTarget::File(path) = target else {
+            snafu::whatever!("accounts can only be written to a SQLite database file")
+        };
+        #[cfg(feature = "sqlite")]
+        return Ok(Box::new(SqliteSink::open(
+            path,
+            output_format,
+            metadata,
+            run_id,
+            journal,
+        )?));
+        #[cfg(not(feature = "sqlite"))]
+        {
+            let _ = (path, journal, run_id);
+            snafu::whatever!(
+                "accounts can only be written to SQLite by builds with the sqlite feature"
+            )
+        }
+    }
+
+    let writer: Box<dyn Write> = match target {
+        SinkTarget::Stdout => Box::new(BufWriter::new(io::stdout())),
+        SinkTarget::File(path) => Box::new(BufWriter::new(create_file(path)?)),
+    };
+
+    Ok(match (format, metadata) {
+        (SinkFormat::Csv, None) => Box::new(CsvSink::new(writer, output_format)),
+        (SinkFormat::Csv, Some(metadata)) => {
+            Box::new(CsvSink::new(writer, output_format).with_metadata(metadata))
+        }
+        (SinkFormat::Json, None) => Box::new(JsonSink::new(writer, output_format)),
+        (SinkFormat::Json, Some(metadata)) => {
+            Box::new(JsonSink::new(writer, output_format).with_metadata(metadata))
+        }
+        #[cfg(feature = "arrow")]
+        (SinkFormat::Arrow, metadata) => Box::new(ArrowSink::new(writer, output_format, metadata)?),
+        #[cfg(not(feature = "arrow"))]
+        (SinkFormat::Arrow, _) => {
+            snafu::whatever!(
+                "accounts can only be written as Arrow by builds with the arrow feature"
+            )
+        }
+        (SinkFormat::Sqlite { .. }, _) => unreachable!("SQLite sinks are opened above"),
+    })
+}
+
+// Opens a sink for the accounts of a multi-tenant run, as `open` does. The accounts of every tenant
+// are written together, each led by its tenant, unless the target is a file for each tenant.
+pub fn open_multi_tenant(
+    format: SinkFormat,
+    target: &SinkTarget,
+    output_format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+    run_id: RunId,
+) -> Result<Box<dyn AccountSink>, Whatever> {
+    if !target.is_per_tenant() {
+        if !matches!(format, SinkFormat::Csv | SinkFormat::Json) {
+            snafu::whatever!(
+                "the accounts of many tenants can only be written together as CSV or JSON; give a path containing {TENANT_PLACEHOLDER} to write a file for each tenant instead"
+            )
+        }
+        let output_format = OutputFormat {
+            tenant: true,
+            ..output_format
+        };
+        return open(format, target, output_format, metadata, run_id);
+    }
+
+    // Sinks that record the transactions of the run would need to be opened before the run.
+    if let SinkFormat::Sqlite { journal: true } = format {
+        snafu::whatever!("the journal of a SQLite database can't be written for each tenant")
+    }
+    let target = target.clone();
+    Ok(Box::new(PerTenant::new(move |tenant| {
+        open(
+            format,
+            &target.for_tenant(tenant),
+            output_format,
+            metadata.clone(),
+            run_id,
+        )
+    })))
+}
+
+fn create_file(path: &Path) -> Result<File, Whatever> {
+    File::create(path).with_whatever_context(|_| format!("unable to create {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::sync::{Arc, Mutex};
+
+    // A writer whose output can still be inspected once it has been handed to a sink.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn fan_out() -> Result<(), Box<dyn Error>> {
+        let mut account = Account::new(1.into());
+        account.credit("1.5".parse()?);
+
+        let (csv, json) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut sinks = FanOut::default();
+        sinks.push(Box::new(CsvSink::new(
+            csv.clone(),
+            OutputFormat {
+                decimal_places: Some(2),
+                extended: false,
+                tenant: false,
+            },
+        )));
+        sinks.push(Box::new(JsonSink::new(
+            json.clone(),
+            OutputFormat::default(),
+        )));
+        sinks.write(&account)?;
+        sinks.finish()?;
+
+        assert_eq!(
+            csv.contents(),
+            "client,available,held,total,locked\n1,1.50,0.00,1.50,false\n"
+        );
+        assert_eq!(
+            json.contents(),
+            "{\"client\":1,\"available\":\"1.5\",\"held\":\"0\",\"total\":\"1.5\",\"locked\":false}\n"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn filters_accounts() -> Result<(), Box<dyn Error>> {
+        let clients: ClientList = "100-200, 5000".parse()?;
+        assert!(clients.contains(150) && clients.contains(5000) && !clients.contains(201));
+        assert!("100-".parse::<ClientList>().is_err());
+        assert!("200-100".parse::<ClientList>().is_err());
+
+        let mut locked = Account::new(150.into());
+        locked.lock();
+        let mut funded = Account::new(5000.into());
+        funded.credit("2".parse()?);
+        let accounts = [locked, funded, Account::new(300.into())];
+
+        let written = |filter: AccountFilter| -> Result<String, Box<dyn Error>> {
+            let csv = SharedBuffer::default();
+            let mut sink =
+                Filtered::new(CsvSink::new(csv.clone(), OutputFormat::default()), filter);
+            for account in &accounts {
+                sink.write(account)?;
+            }
+            sink.finish()?;
+            Ok(csv.contents())
+        };
+        let header = "client,available,held,total,locked
+";
+        assert_eq!(
+            written(AccountFilter {
+                only_locked: true,
+                ..Default::default()
+            })?,
+            format!(
+                "{header}150,0,0,0,true
+"
+            )
+        );
+        assert_eq!(
+            written(AccountFilter {
+                nonzero_only: true,
+                ..Default::default()
+            })?,
+            format!(
+                "{header}5000,2,0,2,false
+"
+            )
+        );
+        assert_eq!(
+            written(AccountFilter {
+                clients: Some(clients),
+                ..Default::default()
+            })?,
+            format!(
+                "{header}150,0,0,0,true
+5000,2,0,2,false
+"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn metadata() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("metadata-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "client,name,opened_at,region\n1, Ada Lovelace ,2021-06-30,\n",
+        )?;
+        let metadata = Arc::new(MetadataTable::load(&path)?);
+        std::fs::remove_file(&path)?;
+
+        let csv = SharedBuffer::default();
+        let mut sink = CsvSink::new(csv.clone(), OutputFormat::default()).with_metadata(metadata);
+        sink.write(&Account::new(1.into()))?;
+        sink.write(&Account::new(2.into()))?;
+        sink.finish()?;
+
+        assert_eq!(
+            csv.contents(),
+            "client,available,held,total,locked,name,opened_at,region\n\
+             1,0,0,0,false,Ada Lovelace,2021-06-30,\n\
+             2,0,0,0,false,,,\n"
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use snafu::{FromString, ResultExt, Whatever};
+
+use crate::input::InputOffset;
+use crate::models::account::{Account, AccountId, AccountParts};
+use crate::stats::RunId;
+
+pub const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+// Describes a complete snapshot, which is made up of one shard file per worker.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SnapshotManifest {
+    pub run_id: RunId,
+    pub created_at: u64,
+    // The order of the next transaction that was yet to be processed when the snapshot was taken,
+    // from which a run resuming from this snapshot should carry on.
+    pub next_order: u64,
+    pub shards: Vec<ShardInfo>,
+    // How far through each of the input files the run had read when the snapshot was taken, if
+    // known.
+    #[serde(default)]
+    pub inputs: Vec<InputOffset>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ShardInfo {
+    pub file: String,
+    pub accounts: u64,
+}
+
+// A snapshot whose shards are being written by the workers. Ingestion can carry on while they are
+// written, and the snapshot is only complete once its manifest has been written by `wait`.
+pub struct PendingSnapshot {
+    dir: PathBuf,
+    run_id: RunId,
+    next_order: u64,
+    inputs: Vec<InputOffset>,
+    shard_rxs: Vec<crossbeam_channel::Receiver<Result<ShardInfo, String>>>,
+}
+
+impl PendingSnapshot {
+    pub(crate) fn new(
+        dir: PathBuf,
+        run_id: RunId,
+        next_order: u64,
+        shard_rxs: Vec<crossbeam_channel::Receiver<Result<ShardInfo, String>>>,
+    ) -> Self {
+        Self {
+            dir,
+            run_id,
+            next_order,
+            inputs: vec![],
+            shard_rxs,
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // Records how far through the input files the run had read when the snapshot was taken.
+    pub fn with_inputs(mut self, inputs: Vec<InputOffset>) -> Self {
+        self.inputs = inputs;
+        self
+    }
+
+    // Waits for every worker to finish writing its shard, and then writes out the manifest.
+    pub fn wait(self) -> Result<SnapshotManifest, Whatever> {
+        let shards = self
+            .shard_rxs
+            .into_iter()
+            .map(|shard_rx| {
+                shard_rx
+                    .recv()
+                    .whatever_context("worker stopped before writing its snapshot shard")?
+                    .map_err(Whatever::without_source)
+            })
+            .collect::<Result<Vec<_>, Whatever>>()?;
+
+        let manifest = SnapshotManifest {
+            run_id: self.run_id,
+            created_at: now(),
+            next_order: self.next_order,
+            shards,
+            inputs: self.inputs,
+        };
+        write_manifest(&self.dir, &manifest)?;
+
+        Ok(manifest)
+    }
+}
+
+// An account as it is recorded within a snapshot shard.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AccountRecord {
+    pub client: AccountId,
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+    pub locked: bool,
+}
+
+// Loads a complete snapshot, returning its manifest along with the accounts from all of its shards.
+pub fn load(dir: &Path) -> Result<(SnapshotManifest, Vec<AccountRecord>), Whatever> {
+    let manifest = read_manifest(dir)?;
+    let mut accounts = vec![];
+    for shard in &manifest.shards {
+        let path = dir.join(&shard.file);
+        let records = csv::Reader::from_path(&path)
+            .and_then(|mut reader| reader.deserialize().collect::<Result<Vec<_>, _>>())
+            .with_whatever_context(|_| format!("unable to read shard {}", path.display()))?;
+        accounts.extend(records);
+    }
+
+    Ok((manifest, accounts))
+}
+
+// Loads the complete state of the accounts of a snapshot, such as the final snapshot of a previous
+// run, for a run to carry on from as its base.
+pub fn load_base(dir: &Path) -> Result<(SnapshotManifest, Vec<Account>), Whatever> {
+    let manifest = read_manifest(dir)?;
+    let Some(accounts) = load_state(dir, &manifest)? else {
+        snafu::whatever!(
+            "snapshot {} only holds the balances of its accounts, as it was taken by an older release or merged, and can't be carried on from",
+            dir.display()
+        );
+    };
+    Ok((manifest, accounts))
+}
+
+fn read_manifest(dir: &Path) -> Result<SnapshotManifest, Whatever> {
+    let manifest_path = dir.join(MANIFEST_FILE_NAME);
+    let file = File::open(&manifest_path).with_whatever_context(|_| {
+        format!(
+            "unable to open snapshot manifest {}",
+            manifest_path.display()
+        )
+    })?;
+    serde_json::from_reader(BufReader::new(file)).with_whatever_context(|_| {
+        format!(
+            "unable to read snapshot manifest {}",
+            manifest_path.display()
+        )
+    })
+}
+
+// Finds the complete snapshot within the given directory that the given run took furthest through
+// it, if there is one. Snapshots whose manifest was never written are passed over.
+pub fn latest(
+    snapshot_dir: &Path,
+    run_id: RunId,
+) -> Result<Option<(PathBuf, SnapshotManifest)>, Whatever> {
+    let entries = match fs::read_dir(snapshot_dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_whatever_context(|_| {
+                format!("unable to list snapshots in {}", snapshot_dir.display())
+            })
+        }
+    };
+
+    let mut latest: Option<(PathBuf, SnapshotManifest)> = None;
+    for entry in entries {
+        let dir = entry
+            .with_whatever_context(|_| {
+                format!("unable to list snapshots in {}", snapshot_dir.display())
+            })?
+            .path();
+        if !dir.join(MANIFEST_FILE_NAME).exists() {
+            continue;
+        }
+        let manifest = read_manifest(&dir)?;
+        if manifest.run_id != run_id {
+            continue;
+        }
+        if latest
+            .as_ref()
+            .is_none_or(|(_, latest)| manifest.next_order > latest.next_order)
+        {
+            latest = Some((dir, manifest));
+        }
+    }
+    Ok(latest)
+}
+
+// Combines snapshots of disjoint sets of accounts, such as those taken by separate runs over
+// different account ranges of a dataset, into a single snapshot written to `out_dir`. An account
+// may only appear in more than one of the snapshots if its state is identical in each of them.
+pub fn merge(snapshot_dirs: &[PathBuf], out_dir: &Path) -> Result<SnapshotManifest, Whatever> {
+    let mut merged: BTreeMap<AccountId, (AccountRecord, &Path)> = BTreeMap::new();
+    let mut next_order = 0;
+    let mut inputs = vec![];
+    for dir in snapshot_dirs {
+        let (manifest, accounts) = load(dir)?;
+        next_order = next_order.max(manifest.next_order);
+        inputs.extend(manifest.inputs);
+
+        for account in accounts {
+            if let Some((existing, existing_dir)) = merged.get(&account.client) {
+                if *existing != account {
+                    snafu::whatever!(
+                        "client {} has conflicting state in snapshots {} and {}",
+                        account.client,
+                        existing_dir.display(),
+                        dir.display()
+                    );
+                }
+                continue;
+            }
+            merged.insert(account.client, (account, dir));
+        }
+    }
+
+    create_dir(out_dir)?;
+    let shard = write_shard(out_dir, 0, merged.values().map(|(account, _)| account))
+        .map_err(Whatever::without_source)?;
+    let manifest = SnapshotManifest {
+        run_id: RunId::generate(),
+        created_at: now(),
+        next_order,
+        shards: vec![shard],
+        inputs,
+    };
+    write_manifest(out_dir, &manifest)?;
+
+    Ok(manifest)
+}
+
+fn write_manifest(dir: &Path, manifest: &SnapshotManifest) -> Result<(), Whatever> {
+    let manifest_path = dir.join(MANIFEST_FILE_NAME);
+    let file = File::create(&manifest_path).with_whatever_context(|_| {
+        format!(
+            "unable to create snapshot manifest {}",
+            manifest_path.display()
+        )
+    })?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut writer, manifest)
+        .whatever_context("unable to write snapshot manifest")?;
+    writer
+        .flush()
+        .whatever_context("unable to write snapshot manifest")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+// Writes a worker's accounts out to a shard file within the snapshot directory.
+pub(crate) fn write_shard(
+    dir: &Path,
+    worker_idx: usize,
+    accounts: impl Iterator<Item = impl Serialize>,
+) -> Result<ShardInfo, String> {
+    let file = format!("shard-{worker_idx}.csv");
+    let path = dir.join(&file);
+
+    let mut writer = csv::Writer::from_path(&path)
+        .map_err(|err| format!("unable to create shard {}: {err}", path.display()))?;
+    let mut num_accounts = 0;
+    for account in accounts {
+        writer
+            .serialize(account)
+            .map_err(|err| format!("unable to write shard {}: {err}", path.display()))?;
+        num_accounts += 1;
+    }
+    writer
+        .flush()
+        .map_err(|err| format!("unable to write shard {}: {err}", path.display()))?;
+
+    Ok(ShardInfo {
+        file,
+        accounts: num_accounts,
+    })
+}
+
+// Writes the complete state of a worker's accounts alongside its shard, as one `AccountParts` per
+// line, so that the accounts can be restored from the snapshot exactly as they were, history and
+// all, whether to recover a run that crashed or for a later run to carry on from.
+pub(crate) fn write_state<'a>(
+    dir: &Path,
+    worker_idx: usize,
+    accounts: impl Iterator<Item = &'a Account>,
+) -> Result<(), String> {
+    let path = dir.join(format!("state-{worker_idx}.jsonl"));
+    let write = || -> std::io::Result<()> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for account in accounts {
+            serde_json::to_writer(&mut writer, &account.to_parts())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.into_inner()?.sync_all()
+    };
+    write().map_err(|err| format!("unable to write account state {}: {err}", path.display()))
+}
+
+// Loads the complete state of the accounts of a snapshot, if it was written alongside every one of
+// its shards.
+pub fn load_state(
+    dir: &Path,
+    manifest: &SnapshotManifest,
+) -> Result<Option<Vec<Account>>, Whatever> {
+    let mut accounts = vec![];
+    for worker_idx in 0..manifest.shards.len() {
+        let path = dir.join(format!("state-{worker_idx}.jsonl"));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = File::open(&path).with_whatever_context(|_| {
+            format!("unable to open account state {}", path.display())
+        })?;
+        for parts in serde_json::Deserializer::from_reader(BufReader::new(file)).into_iter() {
+            let parts: AccountParts = parts.with_whatever_context(|_| {
+                format!("unable to read account state {}", path.display())
+            })?;
+            let account = Account::from_parts(parts).with_whatever_context(|_| {
+                format!("unable to restore account from {}", path.display())
+            })?;
+            accounts.push(account);
+        }
+    }
+    Ok(Some(accounts))
+}
+
+pub(crate) fn create_dir(dir: &Path) -> Result<(), Whatever> {
+    fs::create_dir_all(dir)
+        .with_whatever_context(|_| format!("unable to create snapshot directory {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        account::RawAccountId,
+        transaction::{Transaction, TransactionType},
+    };
+    use crate::processor::TransactionProcessor;
+    use std::error::Error;
+
+    use uuid::Uuid;
+
+    fn record(client: RawAccountId, available: &str) -> Result<AccountRecord, Box<dyn Error>> {
+        Ok(AccountRecord {
+            client: client.into(),
+            available: available.parse()?,
+            held: Decimal::ZERO,
+            total: available.parse()?,
+            locked: false,
+        })
+    }
+
+    fn write_snapshot(
+        dir: &Path,
+        next_order: u64,
+        accounts: &[AccountRecord],
+    ) -> Result<(), Box<dyn Error>> {
+        create_dir(dir)?;
+        let shard = write_shard(dir, 0, accounts.iter())?;
+        write_manifest(
+            dir,
+            &SnapshotManifest {
+                run_id: RunId::generate(),
+                created_at: now(),
+                next_order,
+                shards: vec![shard],
+                inputs: vec![],
+            },
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn merge_snapshots() -> Result<(), Box<dyn Error>> {
+        let root = std::env::temp_dir().join(format!("snapshot-merge-{}", Uuid::new_v4()));
+        let (first, second, conflicting, merged) = (
+            root.join("first"),
+            root.join("second"),
+            root.join("conflicting"),
+            root.join("merged"),
+        );
+        write_snapshot(&first, 10, &[record(1, "1")?, record(3, "3")?])?;
+        write_snapshot(&second, 20, &[record(2, "2")?, record(3, "3")?])?;
+        write_snapshot(&conflicting, 5, &[record(1, "1.5")?])?;
+
+        let manifest = merge(&[first.clone(), second], &merged)?;
+        assert_eq!(manifest.next_order, 20);
+        assert_eq!(
+            load(&merged)?.1,
+            [record(1, "1")?, record(2, "2")?, record(3, "3")?],
+            "accounts should be merged in order, with identical accounts only appearing once"
+        );
+
+        assert!(
+            merge(&[first, conflicting], &merged).is_err(),
+            "accounts with conflicting state cannot be merged"
+        );
+
+        fs::remove_dir_all(root)?;
+        Ok(())
+    }
+
+    #[test]
+    fn restores_sharded_snapshot() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("snapshot-{}", Uuid::new_v4()));
+        let txn_processor = TransactionProcessor::new(3);
+        let amount = "10".parse()?;
+        for client in 1..=6 {
+            let client: RawAccountId = client;
+            txn_processor.process_txn(Transaction::new(
+                1.into(),
+                client.into(),
+                TransactionType::Deposit { amount },
+            ))?;
+        }
+        txn_processor.process_txn(Transaction::new(
+            1.into(),
+            2.into(),
+            TransactionType::Dispute,
+        ))?;
+        let manifest = txn_processor.snapshot(&dir)?.wait()?;
+        txn_processor.shutdown()?;
+
+        assert_eq!((manifest.shards.len(), manifest.next_order), (3, 7));
+        assert_eq!(
+            manifest
+                .shards
+                .iter()
+                .map(|shard| shard.accounts)
+                .sum::<u64>(),
+            6
+        );
+        let (_, mut records) = load(&dir)?;
+        records.sort_by_key(|record| record.client);
+        assert_eq!(records.len(), 6);
+        assert_eq!((records[1].held, records[1].total), (amount, amount));
+
+        // The accounts are restored with their history, so the dispute can yet be resolved.
+        let (manifest, accounts) = load_base(&dir)?;
+        assert_eq!((manifest.next_order, accounts.len()), (7, 6));
+        let txn_processor = TransactionProcessor::builder(2)
+            .starting_order(manifest.next_order)
+            .restore(accounts)
+            .build();
+        txn_processor.process_txn(Transaction::new(
+            1.into(),
+            2.into(),
+            TransactionType::Resolve,
+        ))?;
+        let mut accounts = txn_processor.shutdown()?;
+        accounts.sort_by_key(|account| account.id());
+        assert_eq!(accounts.len(), 6);
+        assert_eq!(
+            (accounts[1].available(), accounts[1].held()),
+            (amount, Decimal::ZERO)
+        );
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
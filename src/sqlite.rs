@@ -0,0 +1,346 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use rusqlite::{params_from_iter, types::Value, Connection};
+use serde::Serialize;
+use snafu::{ResultExt, Whatever};
+
+use crate::journal::JournalEntry;
+use crate::metadata::{AccountMetadata, MetadataTable};
+use crate::models::{
+    account::{Account, OutputFormat, TransactionError},
+    transaction::OrderedTransaction,
+};
+use crate::observer::EventObserver;
+use crate::sink::{self, AccountSink};
+use crate::stats::RunId;
+
+// The columns of the journal table, in the order of the fields of a `JournalEntry`.
+const JOURNAL_COLUMNS: [(&str, &str); 19] = [
+    ("client", "TEXT NOT NULL"),
+    ("tx", "TEXT NOT NULL"),
+    ("type", "TEXT NOT NULL"),
+    ("amount", "NUMERIC"),
+    ("outcome", "TEXT NOT NULL"),
+    ("error_code", "TEXT"),
+    ("error", "TEXT"),
+    ("available", "NUMERIC NOT NULL"),
+    ("held", "NUMERIC NOT NULL"),
+    ("total", "NUMERIC NOT NULL"),
+    ("locked", "INTEGER NOT NULL"),
+    ("order", "INTEGER NOT NULL"),
+    ("run_id", "TEXT NOT NULL"),
+    ("file", "TEXT"),
+    ("line", "INTEGER"),
+    ("name", "TEXT"),
+    ("opened_at", "TEXT"),
+    ("region", "TEXT"),
+    ("memo", "TEXT"),
+];
+
+// Writes the accounts into the `accounts` table of a SQLite database, and if asked to, every
+// transaction attempted against them into its `journal` table. Everything is written in a single
+// database transaction that is only committed once every account has been written, so that the
+// database never holds the results of a run that didn't complete.
+//
+// The accounts table holds the accounts of the latest run only, and is replaced by each run,
+// whereas the journal is appended to by each run just as the journal file is. Balances are stored
+// with numeric affinity, so that they can be compared and summed in SQL. Should a transaction
+// not be written to the journal, the first such failure is kept, and the database transaction is
+// rolled back rather than committed once the accounts are written.
+pub struct SqliteSink {
+    connection: Mutex<Connection>,
+    format: OutputFormat,
+    metadata: Option<Arc<MetadataTable>>,
+    run_id: RunId,
+    journal: bool,
+    journal_error: Mutex<Option<String>>,
+}
+
+impl SqliteSink {
+    pub fn open(
+        path: &Path,
+        format: OutputFormat,
+        metadata: Option<Arc<MetadataTable>>,
+        run_id: RunId,
+        journal: bool,
+    ) -> Result<Arc<Self>, Whatever> {
+        let connection = Connection::open(path)
+            .with_whatever_context(|_| format!("unable to open {}", path.display()))?;
+
+        let accounts = account_columns(format, metadata.is_some())
+            .iter()
+            .map(|(name, affinity)| format!("\"{name}\" {affinity}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut schema = format!(
+            "BEGIN IMMEDIATE;
+             DROP TABLE IF EXISTS accounts;
+             CREATE TABLE accounts ({accounts}, PRIMARY KEY (client));
+             CREATE INDEX accounts_locked ON accounts (locked);"
+        );
+        if journal {
+            let columns = JOURNAL_COLUMNS
+                .iter()
+                .map(|(name, affinity)| format!("\"{name}\" {affinity}"))
+                .collect::<Vec<_>>()
+                .join(", ");
+            schema.push_str(&format!(
+                "CREATE TABLE IF NOT EXISTS journal ({columns});
+                 CREATE INDEX IF NOT EXISTS journal_client ON journal (client, \"order\");
+                 CREATE INDEX IF NOT EXISTS journal_tx ON journal (tx);
+                 CREATE INDEX IF NOT EXISTS journal_run ON journal (run_id, outcome);"
+            ));
+        }
+        connection
+            .execute_batch(&schema)
+            .with_whatever_context(|_| {
+                format!("unable to create the tables of {}", path.display())
+            })?;
+
+        Ok(Arc::new(Self {
+            connection: Mutex::new(connection),
+            format,
+            metadata,
+            run_id,
+            journal,
+            journal_error: Mutex::new(None),
+        }))
+    }
+
+    // The observer that records the journal into the database, if it was asked to.
+    pub fn journal(self: &Arc<Self>) -> Option<Arc<dyn EventObserver>> {
+        self.journal.then(|| self.clone() as Arc<dyn EventObserver>)
+    }
+
+    fn metadata(&self, account: &Account) -> &AccountMetadata {
+        self.metadata
+            .as_ref()
+            .map_or(&AccountMetadata::EMPTY, |metadata| {
+                metadata.get(account.id())
+            })
+    }
+
+    // Inserts a row into the given table, whose columns are the fields of the given row.
+    fn insert(&self, table: &str, columns: &[&str], row: impl Serialize) -> rusqlite::Result<()> {
+        let row = serde_json::to_value(row)
+            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(Box::new(err)))?;
+        let values = columns.iter().map(|column| match &row[column] {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Integer(i64::from(*value)),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(number) => Value::Integer(number),
+                None => Value::Text(number.to_string()),
+            },
+            serde_json::Value::String(value) => Value::Text(value.clone()),
+            value => Value::Text(value.to_string()),
+        });
+
+        let names = columns
+            .iter()
+            .map(|column| format!("\"{column}\""))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let placeholders = vec!["?"; columns.len()].join(", ");
+        let connection = self.connection.lock().expect("database lock poisoned");
+        connection
+            .prepare_cached(&format!(
+                "INSERT INTO {table} ({names}) VALUES ({placeholders})"
+            ))?
+            .execute(params_from_iter(values))?;
+        Ok(())
+    }
+
+    fn record(&self, entry: JournalEntry) {
+        let columns = JOURNAL_COLUMNS.map(|(name, _)| name);
+        if let Err(err) = self.insert("journal", &columns, &entry) {
+            tracing::error!(
+                account_id = %entry.client,
+                txn_id = %entry.tx,
+                error_code = "journal_write_failed",
+                "Unable to write transaction to the journal database: {err}"
+            );
+            self.journal_error
+                .lock()
+                .expect("database lock poisoned")
+                .get_or_insert_with(|| {
+                    format!(
+                        "unable to write transaction {} of client {} to the journal: {err}",
+                        entry.tx, entry.client
+                    )
+                });
+        }
+    }
+}
+
+impl EventObserver for SqliteSink {
+    fn on_applied(&self, txn: &OrderedTransaction, account: &Account) {
+        let metadata = self.metadata(account);
+        self.record(JournalEntry::new(self.run_id, txn, account, metadata, None));
+    }
+
+    fn on_rejected(&self, txn: &OrderedTransaction, account: &Account, err: &TransactionError) {
+        let metadata = self.metadata(account);
+        self.record(JournalEntry::new(
+            self.run_id,
+            txn,
+            account,
+            metadata,
+            Some(err),
+        ));
+    }
+}
+
+impl AccountSink for Arc<SqliteSink> {
+    fn write(&mut self, account: &Account) -> Result<(), Whatever> {
+        let columns: Vec<&str> = account_columns(self.format, self.metadata.is_some())
+            .iter()
+            .map(|(name, _)| *name)
+            .collect();
+        let output = sink::output(account, self.format, self.metadata.as_deref());
+        self.insert("accounts", &columns, output)
+            .whatever_context("unable to write account to the database")
+    }
+
+    fn finish(&mut self) -> Result<(), Whatever> {
+        let connection = self.connection.lock().expect("database lock poisoned");
+        if let Some(err) = self
+            .journal_error
+            .lock()
+            .expect("database lock poisoned")
+            .take()
+        {
+            let _ = connection.execute_batch("ROLLBACK");
+            snafu::whatever!("{err}");
+        }
+        connection
+            .execute_batch("COMMIT")
+            .whatever_context("unable to write accounts to the database")
+    }
+
+    fn observer(&self) -> Option<Arc<dyn EventObserver>> {
+        self.journal()
+    }
+}
+
+// The columns of the accounts table, which are those of the CSV output.
+fn account_columns(format: OutputFormat, metadata: bool) -> Vec<(&'static str, &'static str)> {
+    let mut columns = vec![
+        ("client", "TEXT NOT NULL"),
+        ("available", "NUMERIC NOT NULL"),
+        ("held", "NUMERIC NOT NULL"),
+        ("total", "NUMERIC NOT NULL"),
+        ("locked", "INTEGER NOT NULL"),
+    ];
+    if format.extended {
+        columns.extend([
+            ("transactions_applied", "INTEGER NOT NULL"),
+            ("open_disputes", "INTEGER NOT NULL"),
+            ("lifetime_deposits", "NUMERIC NOT NULL"),
+            ("lifetime_withdrawals", "NUMERIC NOT NULL"),
+            ("last_order", "INTEGER"),
+            ("last_applied_at", "INTEGER"),
+        ]);
+    }
+    if metadata {
+        columns.extend([("name", "TEXT"), ("opened_at", "TEXT"), ("region", "TEXT")]);
+    }
+    columns
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use uuid::Uuid;
+
+    use crate::models::transaction::{Transaction, TransactionType};
+
+    #[test]
+    fn writes_accounts_once_finished() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("accounts-{}.db", Uuid::new_v4()));
+        let mut account = Account::new(1.into());
+        account.credit("1.5".parse()?);
+
+        let format = OutputFormat {
+            decimal_places: Some(2),
+            extended: false,
+            tenant: false,
+        };
+        let mut sink = SqliteSink::open(&path, format, None, RunId::generate(), false)?;
+        assert!(sink.observer().is_none(), "the journal wasn't asked for");
+        sink.write(&account)?;
+        sink.write(&Account::new(2.into()))?;
+
+        let count_accounts = || -> rusqlite::Result<i64> {
+            Connection::open(&path)?
+                .query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))
+        };
+        assert!(
+            count_accounts().is_err(),
+            "nothing should be visible until the sink is finished"
+        );
+        sink.finish()?;
+        assert_eq!(count_accounts()?, 2);
+
+        let (client, available): (String, f64) = Connection::open(&path)?.query_row(
+            "SELECT client, available FROM accounts WHERE available > 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((client.as_str(), available), ("1", 1.5));
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn rolls_back_when_journal_fails() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("accounts-{}.db", Uuid::new_v4()));
+        let mut account = Account::new(1.into());
+        let txn = OrderedTransaction::new(
+            0,
+            Transaction::new(
+                1.into(),
+                account.id(),
+                TransactionType::Deposit {
+                    amount: "1.5".parse()?,
+                },
+            ),
+        );
+        account.process_txn(txn.txn().clone())?;
+
+        let format = OutputFormat {
+            decimal_places: Some(2),
+            extended: false,
+            tenant: false,
+        };
+        let mut sink = SqliteSink::open(&path, format, None, RunId::generate(), true)?;
+        // The journal can no longer be written to.
+        sink.connection
+            .lock()
+            .unwrap()
+            .execute_batch("DROP TABLE journal")?;
+        sink.observer()
+            .ok_or("the journal was asked for")?
+            .on_applied(&txn, &account);
+        sink.write(&account)?;
+
+        let err = sink.finish().err().ok_or("the journal failed")?;
+        assert!(
+            err.to_string().starts_with("unable to write transaction"),
+            "{err}"
+        );
+        assert!(
+            Connection::open(&path)?
+                .query_row("SELECT COUNT(*) FROM accounts", [], |row| row
+                    .get::<_, i64>(0))
+                .is_err(),
+            "the accounts of a run whose journal is incomplete shouldn't be committed"
+        );
+
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+}
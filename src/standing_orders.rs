@@ -0,0 +1,257 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use snafu::{ResultExt, Snafu};
+
+use crate::calendar::{civil_from_days, days_from_civil, days_in_month};
+use crate::input::parse_timestamp;
+use crate::models::{
+    account::AccountId,
+    transaction::{Source, Transaction, TransactionId, TransactionType, AMOUNT_DECIMAL_PLACES},
+};
+
+const MILLIS_PER_DAY: u64 = 86_400_000;
+
+// How often a standing order falls due.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    // On the same day of every month as the order started on, or on the last day of the months
+    // too short to have it.
+    Monthly,
+}
+
+impl Frequency {
+    // When the order that started at the given time falls due for the nth time after that, in
+    // milliseconds since the Unix epoch, unless that's too far off to be represented.
+    fn nth(&self, start: u64, n: u32) -> Option<u64> {
+        match self {
+            Self::Daily => start.checked_add(u64::from(n) * MILLIS_PER_DAY),
+            Self::Weekly => start.checked_add(u64::from(n) * 7 * MILLIS_PER_DAY),
+            Self::Monthly => {
+                let (year, month, day) = civil_from_days((start / MILLIS_PER_DAY) as i64);
+                let months = i64::from(month - 1) + i64::from(n);
+                let (year, month) = (year + months / 12, (months % 12) as u32 + 1);
+                let days = days_from_civil(year, month, day.min(days_in_month(year, month)));
+                u64::try_from(days)
+                    .ok()?
+                    .checked_mul(MILLIS_PER_DAY)?
+                    .checked_add(start % MILLIS_PER_DAY)
+            }
+        }
+    }
+}
+
+impl FromStr for Frequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Self::Daily),
+            "weekly" => Ok(Self::Weekly),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(format!("'{s}' is not a valid frequency.")),
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum StandingOrdersError {
+    #[snafu(display("Unable to read the standing orders {}: {source}", path.display()))]
+    ReadOrders { path: PathBuf, source: csv::Error },
+
+    #[snafu(display(
+        "The standing order on line {line} of {} is invalid: {reason}",
+        path.display()
+    ))]
+    InvalidOrder {
+        path: PathBuf,
+        line: u64,
+        reason: String,
+    },
+}
+
+// A row of a standing orders file, which lists one standing order per row.
+#[derive(Deserialize)]
+struct StandingOrderRecord {
+    client: AccountId,
+    #[serde(rename = "type")]
+    txn_type: String,
+    amount: Decimal,
+    frequency: String,
+    start: String,
+    end: Option<String>,
+}
+
+// A deposit or withdrawal that is made again and again, every so often from when it starts until
+// it ends, if it ever does.
+#[derive(Debug)]
+struct StandingOrder {
+    source: Source,
+    account_id: AccountId,
+    txn_type: TransactionType,
+    frequency: Frequency,
+    start: u64,
+    end: Option<u64>,
+    // The number of times the order has fallen due so far.
+    occurrences: u32,
+}
+
+impl StandingOrder {
+    // When the order next falls due, unless it has ended.
+    fn next_due(&self) -> Option<u64> {
+        let due = self.frequency.nth(self.start, self.occurrences)?;
+        self.end.is_none_or(|end| due <= end).then_some(due)
+    }
+
+    // The transaction the order makes when it next falls due. Its ID is derived from the order and
+    // how many times it has fallen due, so it's the same every time the input is processed.
+    fn transaction(&self) -> Transaction {
+        let reference = format!("standing-order/{}/{}", self.source.line(), self.occurrences);
+        Transaction::new(
+            TransactionId::from_reference(&reference),
+            self.account_id,
+            self.txn_type.clone(),
+        )
+    }
+}
+
+// The standing orders of a run, which are expanded into the transactions they make as the input
+// reaches the times they fall due, by the timestamps of its transactions. The input may well have
+// no transaction at the very time an order falls due, so its transaction is made just before the
+// first one in the input that is timestamped after then.
+#[derive(Debug)]
+pub struct StandingOrders {
+    orders: Vec<StandingOrder>,
+}
+
+impl StandingOrders {
+    // Loads a CSV file with the columns `client`, `type`, `amount`, `frequency`, `start`, and
+    // `end`, e.g. `7,withdrawal,50.00,weekly,2024-01-01,`. Only deposits and withdrawals can be
+    // standing orders, and their frequency is one of `daily`, `weekly`, or `monthly`. They start
+    // at and end by the given dates or times, as parsed by `parse_timestamp`, and only end if
+    // given an end.
+    pub fn load(path: &Path) -> Result<Self, StandingOrdersError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .context(ReadOrdersSnafu { path })?;
+        let headers = reader.headers().context(ReadOrdersSnafu { path })?.clone();
+        let file = Source::new(path, 0);
+        let mut orders = vec![];
+        for record in reader.records() {
+            let record = record.context(ReadOrdersSnafu { path })?;
+            let line = record.position().map_or(0, |pos| pos.line());
+            let invalid = |reason: String| InvalidOrderSnafu { path, line, reason }.build();
+            let record: StandingOrderRecord = record
+                .deserialize(Some(&headers))
+                .map_err(|err| invalid(err.to_string()))?;
+
+            let txn_type = match record.txn_type.as_str() {
+                "deposit" | "withdrawal" => {
+                    TransactionType::from_name(&record.txn_type, Some(record.amount))
+                        .map_err(invalid)?
+                }
+                name => return Err(invalid(format!("a {name} cannot be a standing order"))),
+            };
+            if record.amount.normalize().scale() > AMOUNT_DECIMAL_PLACES {
+                return Err(invalid(format!(
+                    "the amount has more than {AMOUNT_DECIMAL_PLACES} decimal places"
+                )));
+            }
+            let timestamp = |field: &str| {
+                parse_timestamp(field)
+                    .ok_or_else(|| invalid(format!("'{field}' is not a valid date or time")))
+            };
+            let start = timestamp(&record.start)?;
+            let end = record.end.as_deref().map(timestamp).transpose()?;
+            if end.is_some_and(|end| end < start) {
+                return Err(invalid("the order ends before it starts".to_owned()));
+            }
+
+            orders.push(StandingOrder {
+                source: file.at_line(line),
+                account_id: record.client,
+                txn_type,
+                frequency: record.frequency.parse().map_err(invalid)?,
+                start,
+                end,
+                occurrences: 0,
+            });
+        }
+        Ok(Self { orders })
+    }
+
+    // Makes the transactions of every order that falls due by the given time, in milliseconds since
+    // the Unix epoch, in the order they fall due. Orders that fall due at the same time are made in
+    // the order they're listed in. Each transaction comes with the line of the order that made it,
+    // and when it fell due, which it's taken to have been posted at.
+    pub fn advance_to(
+        &mut self,
+        now: u64,
+    ) -> impl Iterator<Item = (Transaction, Source, u64)> + '_ {
+        std::iter::from_fn(move || {
+            let (due, order) = self
+                .orders
+                .iter_mut()
+                .filter_map(|order| order.next_due().map(|due| (due, order)))
+                .filter(|(due, _)| *due <= now)
+                .min_by_key(|(due, _)| *due)?;
+            let txn = order.transaction();
+            order.occurrences += 1;
+            Some((txn, order.source, due))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use std::fs;
+
+    #[test]
+    fn expands_orders_as_they_fall_due() -> Result<(), Box<dyn Error>> {
+        let path = std::env::temp_dir().join(format!("orders-{}.csv", uuid::Uuid::new_v4()));
+        fs::write(
+            &path,
+            "client,type,amount,frequency,start,end\n\
+             7,withdrawal,50.00,weekly,2024-01-01,2024-01-15\n\
+             8,deposit,100,monthly,2024-01-31T09:00:00Z,\n",
+        )?;
+        let mut orders = StandingOrders::load(&path)?;
+        let ts = |s| parse_timestamp(s).ok_or("invalid timestamp");
+
+        let due = |orders: &mut StandingOrders, now| -> Vec<(AccountId, u64)> {
+            orders
+                .advance_to(now)
+                .map(|(txn, source, _)| (txn.account_id(), source.line()))
+                .collect()
+        };
+        let (weekly, monthly) = ((AccountId::from(7), 2), (AccountId::from(8), 3));
+        assert_eq!(due(&mut orders, ts("2023-12-31")?), []);
+        assert_eq!(due(&mut orders, ts("2024-01-08")?), [weekly, weekly]);
+        assert_eq!(due(&mut orders, ts("2024-01-08")?), [], "already made");
+        assert_eq!(
+            due(&mut orders, ts("2024-03-01")?),
+            [weekly, monthly, monthly],
+            "the weekly order ends on the 15th, and the monthly one falls due on the 29th of February"
+        );
+        assert_eq!(
+            orders.orders[1].frequency.nth(orders.orders[1].start, 1),
+            Some(ts("2024-02-29T09:00")?)
+        );
+
+        fs::write(
+            &path,
+            "client,type,amount,frequency,start,end\n7,dispute,1,weekly,2024-01-01,\n",
+        )?;
+        assert!(StandingOrders::load(&path).is_err());
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{BuildHasher, RandomState};
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::models::{
+    account::{Account, AccountId},
+    transaction::{OrderedTransaction, TransactionId, TransactionType},
+};
+use crate::mt940;
+use crate::observer::EventObserver;
+
+// The format statements are written in.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum StatementFormat {
+    Csv,
+    // SWIFT MT940 customer statements, for systems that only understand those. The model has no
+    // notion of currency, so the currency of the balances is given along with the format.
+    Mt940 { currency: String },
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':').unwrap_or((s, "")) {
+            ("csv", "") => Ok(Self::Csv),
+            ("mt940", currency) => {
+                // XXX is the ISO 4217 code for transactions in no currency at all.
+                let currency = if currency.is_empty() { "XXX" } else { currency };
+                if currency.len() != 3 || !currency.bytes().all(|b| b.is_ascii_uppercase()) {
+                    return Err(format!(
+                        "'{currency}' is not a valid ISO 4217 currency code."
+                    ));
+                }
+                Ok(Self::Mt940 {
+                    currency: currency.to_owned(),
+                })
+            }
+            _ => Err(format!("'{s}' is not a valid statement format.")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct StatementLine {
+    pub(crate) tx: TransactionId,
+    #[serde(rename = "type")]
+    pub(crate) txn_type: Cow<'static, str>,
+    pub(crate) amount: Option<Decimal>,
+    pub(crate) available: Decimal,
+    pub(crate) held: Decimal,
+    pub(crate) total: Decimal,
+    pub(crate) locked: bool,
+    pub(crate) memo: Option<Arc<str>>,
+    // When the transaction was posted, in milliseconds since the Unix epoch, if the input says, or
+    // else when it was applied.
+    #[serde(skip)]
+    pub(crate) applied_at: Option<u64>,
+}
+
+// How many lines of an account's statement are held before they're written out.
+const LINES_PER_WRITE: usize = 256;
+
+// The number of locks the statements are kept under, so that workers writing the statements of
+// different accounts seldom wait on one another.
+const NUM_SHARDS: usize = 16;
+
+// Produces one statement per account, listing every transaction applied to the account in the
+// order it was applied along with the running balances. Lines are written out to the account's
+// statement a batch at a time as they're applied, and each worker completes the statements of its
+// accounts when it shuts down. MT940 statements are led by their opening and closing balances, so
+// their entries are written to a partial file until then.
+//
+// A statement that can't be written fails the run once it completes, via `finish`.
+pub struct StatementWriter {
+    dir: PathBuf,
+    format: StatementFormat,
+    // The statements in progress, sharded by account.
+    statements: Vec<Mutex<HashMap<AccountId, Statement>>>,
+    hasher: RandomState,
+    error: Mutex<Option<io::Error>>,
+}
+
+#[derive(Default)]
+struct Statement {
+    // The lines yet to be written out.
+    pending: Vec<StatementLine>,
+    // Whether any lines have been written out, so the file is to be appended to.
+    started: bool,
+    entries: mt940::Entries,
+}
+
+impl StatementWriter {
+    pub fn new(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            format: StatementFormat::Csv,
+            statements: (0..NUM_SHARDS).map(|_| Mutex::default()).collect(),
+            hasher: RandomState::new(),
+            error: Mutex::default(),
+        })
+    }
+
+    pub fn with_format(mut self, format: StatementFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    // Fails if any statement couldn't be written.
+    pub fn finish(&self) -> io::Result<()> {
+        match self.error.lock().expect("statement lock poisoned").take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn shard(&self, account_id: AccountId) -> MutexGuard<'_, HashMap<AccountId, Statement>> {
+        let shard = self.hasher.hash_one(account_id) as usize % NUM_SHARDS;
+        self.statements[shard]
+            .lock()
+            .expect("statement lock poisoned")
+    }
+
+    // Writes out the pending lines of an account's statement.
+    fn write_lines(&self, account_id: AccountId, statement: &mut Statement) -> io::Result<()> {
+        let lines = mem::take(&mut statement.pending);
+        let started = mem::replace(&mut statement.started, true);
+        match &self.format {
+            StatementFormat::Csv => {
+                let path = self.dir.join(format!("account-{account_id}.csv"));
+                let file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(started)
+                    .truncate(!started)
+                    .open(path)?;
+                let mut writer = csv::WriterBuilder::new()
+                    .has_headers(!started)
+                    .from_writer(file);
+                for line in &lines {
+                    writer.serialize(line)?;
+                }
+                writer.flush()?;
+            }
+            StatementFormat::Mt940 { .. } => {
+                let mut entries = String::new();
+                statement.entries.render(&lines, &mut entries);
+                let path = self.dir.join(format!("account-{account_id}.sta.part"));
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .append(started)
+                    .truncate(!started)
+                    .open(path)?;
+                file.write_all(entries.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // Writes out the rest of an account's statement, completing it.
+    fn write_statement(&self, account: &Account, mut statement: Statement) -> io::Result<()> {
+        let account_id = account.id();
+        match &self.format {
+            StatementFormat::Csv => self.write_lines(account_id, &mut statement),
+            StatementFormat::Mt940 { currency } => {
+                let mut entries = String::new();
+                statement.entries.render(&statement.pending, &mut entries);
+                let path = self.dir.join(format!("account-{account_id}.sta"));
+                let mut file = BufWriter::new(File::create(path)?);
+                file.write_all(statement.entries.header(account, currency).as_bytes())?;
+                if statement.started {
+                    let path = self.dir.join(format!("account-{account_id}.sta.part"));
+                    io::copy(&mut File::open(&path)?, &mut file)?;
+                    fs::remove_file(path)?;
+                }
+                file.write_all(entries.as_bytes())?;
+                file.write_all(statement.entries.trailer(account, currency).as_bytes())?;
+                file.flush()
+            }
+        }
+    }
+
+    fn fail(&self, account_id: AccountId, err: io::Error) {
+        tracing::error!(
+            %account_id,
+            error_code = "statement_write_failed",
+            "Unable to write account statement: {err}"
+        );
+        self.error
+            .lock()
+            .expect("statement lock poisoned")
+            .get_or_insert_with(|| {
+                io::Error::other(format!(
+                    "unable to write the statement of account {account_id}: {err}"
+                ))
+            });
+    }
+}
+
+impl EventObserver for StatementWriter {
+    fn on_applied(&self, ordered_txn: &OrderedTransaction, account: &Account) {
+        let txn = ordered_txn.txn();
+        // Dispute events don't carry an amount of their own, so we show the amount of the
+        // transaction they refer to.
+        let txn_type = txn.txn_type();
+        let amount = match txn_type {
+            TransactionType::Dispute | TransactionType::Resolve | TransactionType::Chargeback => {
+                account
+                    .past_txn(txn.id())
+                    .and_then(|past_txn| past_txn.txn_type().amount())
+            }
+            _ => txn_type.amount(),
+        };
+
+        let line = StatementLine {
+            tx: txn.id(),
+            txn_type: txn_type.owned_name(),
+            amount,
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.locked(),
+            memo: ordered_txn.shared_memo().cloned(),
+            applied_at: ordered_txn.posted_at().or(account.last_applied_at()),
+        };
+        let account_id = account.id();
+        let mut shard = self.shard(account_id);
+        let statement = shard.entry(account_id).or_default();
+        statement.pending.push(line);
+        if statement.pending.len() >= LINES_PER_WRITE {
+            if let Err(err) = self.write_lines(account_id, statement) {
+                self.fail(account_id, err);
+            }
+        }
+    }
+
+    fn on_shutdown(&self, accounts: &[Account]) {
+        for account in accounts {
+            let statement = self
+                .shard(account.id())
+                .remove(&account.id())
+                .unwrap_or_default();
+
+            if let Err(err) = self.write_statement(account, statement) {
+                self.fail(account.id(), err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::{RawTransactionId, Transaction};
+    use crate::processor::TransactionProcessor;
+    use std::error::Error;
+
+    use uuid::Uuid;
+
+    #[test]
+    fn running_balances() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("statements-{}", Uuid::new_v4()));
+        let txn_processor = TransactionProcessor::builder(1)
+            .observer(Arc::new(StatementWriter::new(&dir)?))
+            .build();
+        for (txn_id, txn_type) in [
+            (
+                1,
+                TransactionType::Deposit {
+                    amount: "100".parse()?,
+                },
+            ),
+            (
+                2,
+                TransactionType::Deposit {
+                    amount: "50".parse()?,
+                },
+            ),
+            (
+                3,
+                TransactionType::Withdrawal {
+                    amount: "30".parse()?,
+                },
+            ),
+            (
+                4,
+                TransactionType::Withdrawal {
+                    amount: "500".parse()?,
+                },
+            ),
+            (1, TransactionType::Dispute),
+            (1, TransactionType::Chargeback),
+            (
+                5,
+                TransactionType::Deposit {
+                    amount: "10".parse()?,
+                },
+            ),
+        ] {
+            let txn_id: RawTransactionId = txn_id;
+            txn_processor.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))?;
+        }
+        txn_processor.shutdown()?;
+
+        // Only the transactions that were applied are listed, so neither the withdrawal without the
+        // funds nor the deposit to the account once it's locked.
+        assert_eq!(
+            fs::read_to_string(dir.join("account-1.csv"))?,
+            "tx,type,amount,available,held,total,locked,memo\n\
+             1,deposit,100,100,0,100,false,\n\
+             2,deposit,50,150,0,150,false,\n\
+             3,withdrawal,30,120,0,120,false,\n\
+             1,dispute,100,20,100,120,false,\n\
+             1,chargeback,100,20,0,20,true,\n"
+        );
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn writes_lines_as_they_go() -> Result<(), Box<dyn Error>> {
+        for format in ["csv", "mt940:EUR"] {
+            let dir = std::env::temp_dir().join(format!("statements-{}", Uuid::new_v4()));
+            let txn_processor = TransactionProcessor::builder(1)
+                .observer(Arc::new(
+                    StatementWriter::new(&dir)?.with_format(format.parse()?),
+                ))
+                .build();
+            let num_txns = LINES_PER_WRITE * 2 + 1;
+            for txn_id in 0..num_txns {
+                let txn_id = txn_id as RawTransactionId;
+                txn_processor.process_txn(Transaction::new(
+                    txn_id.into(),
+                    1.into(),
+                    TransactionType::Deposit { amount: 1.into() },
+                ))?;
+            }
+            txn_processor.shutdown()?;
+
+            // Every line makes it into the statement, with a header only at the start.
+            if format == "csv" {
+                let statement = fs::read_to_string(dir.join("account-1.csv"))?;
+                assert_eq!(statement.lines().count(), num_txns + 1);
+                assert_eq!(statement.matches("tx,type").count(), 1);
+                assert!(statement.ends_with(&format!(
+                    "{},deposit,1,{num_txns},0,{num_txns},false,\n",
+                    num_txns - 1
+                )));
+            } else {
+                let statement = fs::read_to_string(dir.join("account-1.sta"))?;
+                assert_eq!(statement.matches(":61:").count(), num_txns);
+                assert!(statement.contains(":60F:C"));
+                assert!(statement.contains("EUR0,\n:61:"));
+                assert!(statement.contains(&format!("EUR{num_txns},\n-\n")));
+                assert!(!dir.join("account-1.sta.part").exists());
+            }
+
+            fs::remove_dir_all(dir)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fails_once_unable_to_write() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("statements-{}", Uuid::new_v4()));
+        let statements = Arc::new(StatementWriter::new(&dir)?);
+        // A directory in the way of the statement's file keeps it from being written.
+        fs::create_dir(dir.join("account-1.csv"))?;
+        let txn_processor = TransactionProcessor::builder(1)
+            .observer(statements.clone())
+            .build();
+        txn_processor.process_txn(Transaction::new(
+            1.into(),
+            1.into(),
+            TransactionType::Deposit { amount: 1.into() },
+        ))?;
+        txn_processor.shutdown()?;
+
+        let err = statements
+            .finish()
+            .expect_err("the statement can't be written");
+        assert!(err.to_string().contains("statement of account 1"), "{err}");
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,438 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::amounts::AmountDistribution;
+use crate::models::{
+    account::{Account, ErrorClass, Tenant, TransactionError},
+    transaction::OrderedTransaction,
+};
+use crate::observer::EventObserver;
+
+// Uniquely identifies a run, so that the artifacts produced by concurrent or repeated runs can be
+// correlated with one another.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Display, Eq, PartialEq, Serialize)]
+#[display(fmt = "{id}")]
+pub struct RunId {
+    pub id: Uuid,
+    // Seconds since the Unix epoch at which the run started.
+    pub started_at: u64,
+}
+
+impl RunId {
+    pub fn generate() -> Self {
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+
+        Self {
+            id: Uuid::new_v4(),
+            started_at,
+        }
+    }
+}
+
+// Counts the outcomes of transactions as they are processed by the workers. Transactions that are
+// not applied are counted according to their `ErrorClass`. In a multi-tenant run, they're also
+// counted for each tenant.
+#[derive(Debug, Default)]
+pub struct RunStats {
+    applied: AtomicU64,
+    rejected: AtomicU64,
+    ignored: AtomicU64,
+    errored: AtomicU64,
+    locked_accounts: AtomicU64,
+    tenants: Mutex<BTreeMap<Tenant, TenantStats>>,
+}
+
+impl RunStats {
+    pub fn applied(&self) -> u64 {
+        self.applied.load(Ordering::Relaxed)
+    }
+
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    pub fn ignored(&self) -> u64 {
+        self.ignored.load(Ordering::Relaxed)
+    }
+
+    pub fn errored(&self) -> u64 {
+        self.errored.load(Ordering::Relaxed)
+    }
+
+    pub fn count(&self, class: ErrorClass) -> u64 {
+        match class {
+            ErrorClass::Rejected => self.rejected(),
+            ErrorClass::Ignored => self.ignored(),
+            ErrorClass::Errored => self.errored(),
+        }
+    }
+
+    pub fn locked_accounts(&self) -> u64 {
+        self.locked_accounts.load(Ordering::Relaxed)
+    }
+
+    // The stats of each tenant of a multi-tenant run, ordered by tenant.
+    pub fn tenants(&self) -> Vec<TenantStats> {
+        self.tenants
+            .lock()
+            .expect("tenant stats lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    fn update_tenant(&self, account: &Account, update: impl FnOnce(&mut TenantStats)) {
+        let Some(tenant) = account.tenant() else {
+            return;
+        };
+        let mut tenants = self.tenants.lock().expect("tenant stats lock poisoned");
+        update(tenants.entry(tenant).or_insert_with(|| TenantStats {
+            tenant,
+            applied: 0,
+            rejected: 0,
+            ignored: 0,
+            errored: 0,
+            accounts: 0,
+            locked_accounts: 0,
+        }));
+    }
+}
+
+impl EventObserver for RunStats {
+    fn on_applied(&self, _txn: &OrderedTransaction, account: &Account) {
+        self.applied.fetch_add(1, Ordering::Relaxed);
+        self.update_tenant(account, |stats| stats.applied += 1);
+    }
+
+    fn on_rejected(&self, _txn: &OrderedTransaction, account: &Account, err: &TransactionError) {
+        let counter = match err.class() {
+            ErrorClass::Rejected => &self.rejected,
+            ErrorClass::Ignored => &self.ignored,
+            ErrorClass::Errored => &self.errored,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.update_tenant(account, |stats| match err.class() {
+            ErrorClass::Rejected => stats.rejected += 1,
+            ErrorClass::Ignored => stats.ignored += 1,
+            ErrorClass::Errored => stats.errored += 1,
+        });
+    }
+
+    fn on_account_locked(&self, account: &Account) {
+        self.locked_accounts.fetch_add(1, Ordering::Relaxed);
+        self.update_tenant(account, |stats| stats.locked_accounts += 1);
+    }
+
+    fn on_shutdown(&self, accounts: &[Account]) {
+        for account in accounts {
+            self.update_tenant(account, |stats| stats.accounts += 1);
+        }
+    }
+}
+
+// The outcomes of one tenant's transactions over a multi-tenant run, and the accounts it ended with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TenantStats {
+    pub tenant: Tenant,
+    pub applied: u64,
+    pub rejected: u64,
+    pub ignored: u64,
+    pub errored: u64,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+}
+
+// A summary of a completed run.
+#[derive(Clone, Debug, Default)]
+pub struct RunSummary {
+    pub run_id: RunId,
+    pub duration: Duration,
+    pub records_read: u64,
+    pub malformed: u64,
+    pub applied: u64,
+    pub rejected: u64,
+    pub ignored: u64,
+    pub errored: u64,
+    pub accounts: u64,
+    pub locked_accounts: u64,
+    pub peak_memory_bytes: Option<u64>,
+    pub workers: Vec<WorkerStats>,
+    pub tenants: Vec<TenantStats>,
+    // The distributions of the amounts of the run's deposits and withdrawals.
+    pub amounts: AmountDistribution,
+    // How long reading the input was held up by the rate limits.
+    pub throttled: Duration,
+    // Whether the run was interrupted before it had read all of its input, in which case the
+    // accounts are only as they were after the transactions it did read.
+    pub partial: bool,
+}
+
+// How much work one of the processor's workers did over a run, which shows how evenly the accounts
+// were partitioned between the workers.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerStats {
+    pub worker_idx: usize,
+    pub txns_processed: u64,
+    pub accounts: u64,
+    // Transactions that were not applied, whatever their `ErrorClass`.
+    pub rejected: u64,
+    // Time spent applying transactions, as opposed to waiting for them to arrive.
+    pub busy: Duration,
+    // The most messages that were ever queued for the worker at once, where each batch of
+    // transactions is a single message.
+    pub peak_queued: usize,
+    // The transactions processed by type and outcome, where the outcome is `applied` or the name of
+    // the `ErrorClass` of those that were not applied.
+    pub txn_types: BTreeMap<(Cow<'static, str>, &'static str), TxnTypeTotals>,
+}
+
+// The number of transactions of one type with one outcome, and the sum of their amounts, for the
+// types that have amounts.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct TxnTypeTotals {
+    pub count: u64,
+    pub sum: Decimal,
+}
+
+impl TxnTypeTotals {
+    pub fn add(&mut self, amount: Option<Decimal>) {
+        self.count += 1;
+        self.sum += amount.unwrap_or_default();
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TxnStatsFormat {
+    Csv,
+    // One JSON object per line.
+    Json,
+}
+
+// Where to export the transaction counts of a run, and in what format, e.g. `csv:txn-stats.csv`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TxnStatsOutput {
+    pub format: TxnStatsFormat,
+    pub path: PathBuf,
+}
+
+impl FromStr for TxnStatsOutput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let format = match s.split_once(':') {
+            Some(("csv", path)) if !path.is_empty() => (TxnStatsFormat::Csv, path),
+            Some(("json", path)) if !path.is_empty() => (TxnStatsFormat::Json, path),
+            _ => return Err(format!("'{s}' is not a valid transaction stats output.")),
+        };
+        Ok(Self {
+            format: format.0,
+            path: format.1.into(),
+        })
+    }
+}
+
+// A row of the transaction stats export.
+#[derive(Serialize)]
+struct TxnStatsRow<'a> {
+    run_id: Uuid,
+    started_at: u64,
+    worker: usize,
+    #[serde(rename = "type")]
+    txn_type: &'a str,
+    outcome: &'static str,
+    count: u64,
+    sum: Decimal,
+}
+
+// Exports the number of transactions of each type processed by each worker of a run with each
+// outcome, along with the sum of their amounts, with a row for each, led by the run's ID and when
+// it started so that the exports of many runs can be trended together.
+pub fn write_txn_stats(
+    run_id: RunId,
+    workers: &[WorkerStats],
+    format: TxnStatsFormat,
+    mut writer: impl Write,
+) -> Result<(), csv::Error> {
+    let rows = workers.iter().flat_map(|worker| {
+        worker
+            .txn_types
+            .iter()
+            .map(|((txn_type, outcome), totals)| TxnStatsRow {
+                run_id: run_id.id,
+                started_at: run_id.started_at,
+                worker: worker.worker_idx,
+                txn_type,
+                outcome,
+                count: totals.count,
+                sum: totals.sum,
+            })
+    });
+    match format {
+        TxnStatsFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        TxnStatsFormat::Json => {
+            for row in rows {
+                serde_json::to_writer(&mut writer, &row).map_err(io::Error::from)?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+// Where transactions are piling up in a running processor, as polled with
+// `TransactionProcessor::stats`. The workers are dispatched transactions in the order they're
+// submitted, so there is no reordering for them to wait on: a stalled run is either held up
+// submitting them, by the rate limits, or has them queued up for a worker that isn't keeping up.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessorStats {
+    // The order that will be assigned to the next transaction submitted.
+    pub next_order: u64,
+    // How long submitting transactions has been held up by the rate limits.
+    pub throttled: Duration,
+    pub workers: Vec<WorkerQueueStats>,
+}
+
+impl ProcessorStats {
+    // The messages queued across all of the workers.
+    pub fn queued(&self) -> usize {
+        self.workers.iter().map(|worker| worker.queued).sum()
+    }
+}
+
+// How far behind one of the processor's workers is.
+#[derive(Clone, Debug, Default)]
+pub struct WorkerQueueStats {
+    pub worker_idx: usize,
+    // The messages delivered to the worker that it's yet to handle.
+    pub queued: usize,
+    pub peak_queued: usize,
+    pub busy: Duration,
+}
+
+// The ratio of the most transactions processed by any one worker to the mean across all of them,
+// where 1.0 means the work was spread perfectly evenly. Returns `None` if no transactions were
+// processed.
+pub fn worker_skew(workers: &[WorkerStats]) -> Option<f64> {
+    let total: u64 = workers.iter().map(|worker| worker.txns_processed).sum();
+    let max = workers.iter().map(|worker| worker.txns_processed).max()?;
+    (total > 0).then(|| max as f64 / (total as f64 / workers.len() as f64))
+}
+
+// Returns the peak resident set size of the process, if the platform lets us know it.
+pub fn peak_memory_bytes() -> Option<u64> {
+    // On Linux, the high water mark of the resident set size is reported in kB.
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::{RawTransactionId, Transaction, TransactionType};
+    use crate::processor::TransactionProcessor;
+    use std::error::Error;
+    use std::sync::Arc;
+
+    #[test]
+    fn exports_txn_stats() -> Result<(), Box<dyn Error>> {
+        let output: TxnStatsOutput = "json:stats.jsonl".parse()?;
+        assert_eq!(output.format, TxnStatsFormat::Json);
+        assert!("csv:".parse::<TxnStatsOutput>().is_err());
+
+        let mut worker = WorkerStats {
+            worker_idx: 1,
+            ..Default::default()
+        };
+        let deposits = worker
+            .txn_types
+            .entry(("deposit".into(), "applied"))
+            .or_default();
+        deposits.add(Some("1.5".parse()?));
+        deposits.add(Some("2".parse()?));
+        worker
+            .txn_types
+            .entry(("dispute".into(), "ignored"))
+            .or_default()
+            .add(None);
+        let run_id = RunId::generate();
+
+        let mut out = vec![];
+        write_txn_stats(run_id, &[worker], TxnStatsFormat::Csv, &mut out)?;
+        let (id, started_at) = (run_id.id, run_id.started_at);
+        assert_eq!(
+            String::from_utf8(out)?,
+            format!(
+                "run_id,started_at,worker,type,outcome,count,sum\n\
+                 {id},{started_at},1,deposit,applied,2,3.5\n\
+                 {id},{started_at},1,dispute,ignored,1,0\n"
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn classifies_unapplied_txns() -> Result<(), Box<dyn Error>> {
+        let run_stats = Arc::new(RunStats::default());
+        let txn_processor = TransactionProcessor::builder(1)
+            .observer(run_stats.clone())
+            .build();
+        let amount = "10".parse()?;
+        for (txn_id, txn_type) in [
+            (1, TransactionType::Deposit { amount }),
+            // A withdrawal without the funds breaks the account's rules.
+            (
+                2,
+                TransactionType::Withdrawal {
+                    amount: amount * Decimal::TWO,
+                },
+            ),
+            // A duplicate, and a dispute of a transaction that doesn't exist, change nothing.
+            (1, TransactionType::Deposit { amount }),
+            (9, TransactionType::Dispute),
+            // There's no handler for a custom type.
+            (3, TransactionType::custom("bonus", Some(amount))),
+        ] {
+            let txn_id: RawTransactionId = txn_id;
+            txn_processor.process_txn(Transaction::new(txn_id.into(), 1.into(), txn_type))?;
+        }
+        txn_processor.shutdown()?;
+
+        assert_eq!(
+            (
+                run_stats.applied(),
+                run_stats.rejected(),
+                run_stats.ignored(),
+                run_stats.errored()
+            ),
+            (1, 1, 2, 1)
+        );
+        assert_eq!(run_stats.count("ignored".parse()?), 2);
+        assert!("skipped".parse::<ErrorClass>().is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,188 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, Write};
+use std::sync::Mutex;
+
+use rust_decimal::Decimal;
+
+use crate::models::{
+    account::{Account, AccountKey, ErrorClass, TransactionError},
+    transaction::OrderedTransaction,
+};
+use crate::observer::EventObserver;
+
+// The N accounts with the largest values of some measure, where accounts with the same value are
+// ranked by key. Only N accounts are held on to however many are pushed.
+#[derive(Clone, Debug)]
+struct Ranking<V: Ord> {
+    n: usize,
+    // The smallest value ranked is at the top of the heap, so that it's the first to be displaced.
+    heap: BinaryHeap<Reverse<(V, Reverse<AccountKey>)>>,
+}
+
+impl<V: Ord + Copy> Ranking<V> {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            heap: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    fn push(&mut self, value: V, key: AccountKey) {
+        self.heap.push(Reverse((value, Reverse(key))));
+        if self.heap.len() > self.n {
+            self.heap.pop();
+        }
+    }
+
+    // The accounts ranked, from the largest value down.
+    fn sorted(&self) -> Vec<(AccountKey, V)> {
+        self.heap
+            .clone()
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse((value, Reverse(key)))| (key, value))
+            .collect()
+    }
+}
+
+// A report of the accounts with the largest totals, the largest held funds, and the most rejected
+// transactions, for a quick look over the riskiest accounts of a run without loading its output
+// elsewhere. Transactions are counted as they're rejected, while the accounts' balances are ranked
+// as they're handed back at the end of the run, so that they needn't all be held on to.
+#[derive(Debug)]
+pub struct TopAccounts {
+    n: usize,
+    rejected: Mutex<HashMap<AccountKey, u64>>,
+    totals: Mutex<Ranking<Decimal>>,
+    held: Mutex<Ranking<Decimal>>,
+}
+
+impl TopAccounts {
+    pub fn new(n: usize) -> Self {
+        Self {
+            n,
+            rejected: Mutex::default(),
+            totals: Mutex::new(Ranking::new(n)),
+            held: Mutex::new(Ranking::new(n)),
+        }
+    }
+
+    pub fn push(&self, account: &Account) {
+        self.totals
+            .lock()
+            .expect("rankings lock poisoned")
+            .push(account.total(), account.key());
+        self.held
+            .lock()
+            .expect("rankings lock poisoned")
+            .push(account.held(), account.key());
+    }
+
+    // Writes the report as a list of accounts for each measure, e.g. `  7: 1500.25`, leaving out
+    // the accounts that had no transactions rejected.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut most_rejected = Ranking::new(self.n);
+        for (&key, &count) in self
+            .rejected
+            .lock()
+            .expect("rejections lock poisoned")
+            .iter()
+        {
+            most_rejected.push(count, key);
+        }
+        let totals = self.totals.lock().expect("rankings lock poisoned").sorted();
+        let held = self.held.lock().expect("rankings lock poisoned").sorted();
+
+        let n = self.n;
+        writeln!(writer, "Top {n} accounts by total:")?;
+        for (key, total) in totals {
+            writeln!(writer, "  {}: {}", client(key), total.normalize())?;
+        }
+        writeln!(writer, "Top {n} accounts by held funds:")?;
+        for (key, held) in held {
+            writeln!(writer, "  {}: {}", client(key), held.normalize())?;
+        }
+        writeln!(writer, "Top {n} accounts by rejected transactions:")?;
+        for (key, count) in most_rejected.sorted() {
+            writeln!(writer, "  {}: {count}", client(key))?;
+        }
+        Ok(())
+    }
+}
+
+impl EventObserver for TopAccounts {
+    fn on_rejected(&self, _txn: &OrderedTransaction, account: &Account, err: &TransactionError) {
+        if err.class() == ErrorClass::Rejected {
+            *self
+                .rejected
+                .lock()
+                .expect("rejections lock poisoned")
+                .entry(account.key())
+                .or_default() += 1;
+        }
+    }
+}
+
+// The client of an account, led by its tenant in a multi-tenant run.
+fn client((tenant, id): AccountKey) -> String {
+    match tenant {
+        Some(tenant) => format!("{tenant}/{id}"),
+        None => id.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        account::AccountId,
+        transaction::{Transaction, TransactionId, TransactionType},
+    };
+    use std::error::Error;
+
+    #[test]
+    fn ranks_accounts() -> Result<(), Box<dyn Error>> {
+        let top = TopAccounts::new(2);
+        let deposit = |txn_id: TransactionId, id, amount: &str| -> Result<_, Box<dyn Error>> {
+            let amount = amount.parse()?;
+            Ok(Transaction::new(
+                txn_id,
+                id,
+                TransactionType::Deposit { amount },
+            ))
+        };
+        for (id, available, held) in [(1, "5", "0"), (2, "9", "1"), (3, "6", "4"), (4, "0.5", "2")]
+        {
+            let id = AccountId::from(id);
+            let mut account = Account::new(id);
+            account.process_txn(deposit(1.into(), id, available)?)?;
+            if held != "0" {
+                account.process_txn(deposit(2.into(), id, held)?)?;
+                account.process_txn(Transaction::new(2.into(), id, TransactionType::Dispute))?;
+            }
+            top.push(&account);
+        }
+        let account = Account::new(4.into());
+        let withdrawal = Transaction::new(
+            1.into(),
+            4.into(),
+            TransactionType::Withdrawal {
+                amount: "1".parse()?,
+            },
+        );
+        let err = account.clone().process_txn(withdrawal.clone()).unwrap_err();
+        top.on_rejected(&OrderedTransaction::new(0, withdrawal), &account, &err);
+
+        let mut out = vec![];
+        top.write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "Top 2 accounts by total:\n  2: 10\n  3: 10\n\
+             Top 2 accounts by held funds:\n  3: 4\n  4: 2\n\
+             Top 2 accounts by rejected transactions:\n  4: 1\n"
+        );
+
+        Ok(())
+    }
+}
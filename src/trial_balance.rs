@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// A line of the general ledger as it is read back in, as written by `GeneralLedger`.
+#[derive(Debug, Deserialize)]
+struct GeneralLedgerRecord {
+    account: String,
+    debit: Option<Decimal>,
+    credit: Option<Decimal>,
+    reference: String,
+}
+
+// A line of the trial balance, totalling the postings to a sub-ledger across every account.
+#[derive(Debug, Serialize)]
+struct TrialBalanceLine<'a> {
+    sub_ledger: &'a str,
+    debit: Decimal,
+    credit: Decimal,
+    balance: Decimal,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Totals {
+    debits: Decimal,
+    credits: Decimal,
+}
+
+impl Totals {
+    fn add(&mut self, debit: Decimal, credit: Decimal) {
+        self.debits += debit;
+        self.credits += credit;
+    }
+
+    // Credits less debits, so that funds owed to clients have positive balances.
+    fn balance(&self) -> Decimal {
+        self.credits - self.debits
+    }
+}
+
+// The postings of a general ledger export totalled by sub-ledger across every account, for checking
+// at month-end close that the books balance. Every entry is a balanced pair of postings, so the
+// debits of the whole ledger equal its credits, as do those of each transaction's postings; the
+// transactions whose postings don't balance are counted, so that they can be looked for.
+#[derive(Debug, Default)]
+pub struct TrialBalance {
+    sub_ledgers: BTreeMap<String, Totals>,
+    postings: u64,
+    unbalanced_references: Vec<String>,
+}
+
+impl TrialBalance {
+    // Reads the postings of a general ledger export. The postings of each transaction are written
+    // together, so each transaction is checked as soon as the next one's are reached.
+    pub fn read(reader: impl Read) -> Result<Self, csv::Error> {
+        let mut trial_balance = Self::default();
+        let mut current: Option<(String, Totals)> = None;
+        for record in csv::Reader::from_reader(reader).into_deserialize() {
+            let record: GeneralLedgerRecord = record?;
+            let debit = record.debit.unwrap_or_default();
+            let credit = record.credit.unwrap_or_default();
+            let sub_ledger = match record.account.rsplit_once('/') {
+                Some((_, sub_ledger)) => sub_ledger,
+                None => &record.account,
+            };
+            trial_balance
+                .sub_ledgers
+                .entry(sub_ledger.to_owned())
+                .or_default()
+                .add(debit, credit);
+            trial_balance.postings += 1;
+
+            match &mut current {
+                Some((reference, totals)) if *reference == record.reference => {
+                    totals.add(debit, credit)
+                }
+                _ => {
+                    trial_balance.check(current.take());
+                    let mut totals = Totals::default();
+                    totals.add(debit, credit);
+                    current = Some((record.reference, totals));
+                }
+            }
+        }
+        trial_balance.check(current);
+        Ok(trial_balance)
+    }
+
+    fn check(&mut self, reference: Option<(String, Totals)>) {
+        if let Some((reference, totals)) = reference {
+            if !totals.balance().is_zero() {
+                self.unbalanced_references.push(reference);
+            }
+        }
+    }
+
+    fn totals(&self) -> Totals {
+        let mut totals = Totals::default();
+        for sub_ledger in self.sub_ledgers.values() {
+            totals.add(sub_ledger.debits, sub_ledger.credits);
+        }
+        totals
+    }
+
+    pub fn postings(&self) -> u64 {
+        self.postings
+    }
+
+    pub fn debits(&self) -> Decimal {
+        self.totals().debits
+    }
+
+    pub fn credits(&self) -> Decimal {
+        self.totals().credits
+    }
+
+    // The references of the transactions whose postings don't balance, in the order they appear.
+    pub fn unbalanced_references(&self) -> &[String] {
+        &self.unbalanced_references
+    }
+
+    pub fn is_balanced(&self) -> bool {
+        self.debits() == self.credits() && self.unbalanced_references.is_empty()
+    }
+
+    // Writes the trial balance as CSV, with the total debits, credits and balance of each
+    // sub-ledger in order of name, followed by those of the whole ledger. The balances of the
+    // sub-ledgers that hold clients' funds, such as `available` and `held`, are what the bank owes
+    // its clients, and are balanced out by the `suspense` and `chargeback_expense` sub-ledgers.
+    pub fn write(&self, writer: impl Write) -> Result<(), csv::Error> {
+        let mut writer = csv::Writer::from_writer(writer);
+        let total = self.totals();
+        for (sub_ledger, totals) in self
+            .sub_ledgers
+            .iter()
+            .map(|(sub_ledger, totals)| (sub_ledger.as_str(), totals))
+            .chain([("total", &total)])
+        {
+            writer.serialize(TrialBalanceLine {
+                sub_ledger,
+                debit: totals.debits.normalize(),
+                credit: totals.credits.normalize(),
+                balance: totals.balance().normalize(),
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    #[test]
+    fn totals_sub_ledgers() -> Result<(), Box<dyn Error>> {
+        let general_ledger = "\
+date,account,debit,credit,reference
+2024-01-01,1/suspense,10,,run/0
+2024-01-01,1/available,,10,run/0
+2024-01-01,2/suspense,5.5,,run/1
+2024-01-01,2/available,,5.5,run/1
+2024-01-01,1/available,4,,run/2
+2024-01-01,1/held,,4,run/2
+";
+        let trial_balance = TrialBalance::read(general_ledger.as_bytes())?;
+        assert!(trial_balance.is_balanced());
+        assert_eq!(trial_balance.postings(), 6);
+        let mut out = vec![];
+        trial_balance.write(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "sub_ledger,debit,credit,balance\n\
+             available,4,15.5,11.5\n\
+             held,0,4,4\n\
+             suspense,15.5,0,-15.5\n\
+             total,19.5,19.5,0\n"
+        );
+
+        // A transaction whose postings don't balance throws the whole ledger out.
+        let unbalanced = general_ledger.replace("2/available,,5.5", "2/available,,5");
+        let trial_balance = TrialBalance::read(unbalanced.as_bytes())?;
+        assert!(!trial_balance.is_balanced());
+        assert_eq!(trial_balance.unbalanced_references(), ["run/1"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, types, IoUring};
+
+// The size of each read, and how many of them are kept in flight at once.
+const BLOCK_SIZE: usize = 256 * 1024;
+const READS_IN_FLIGHT: usize = 8;
+
+// A block of the file, being read into or read from.
+struct Block {
+    buf: Vec<u8>,
+    offset: u64,
+    // How much of the block has been read from the file so far.
+    filled: usize,
+    in_flight: bool,
+    // Whether the block has been read in full, or up to the end of the file.
+    complete: bool,
+    error: Option<io::Error>,
+}
+
+// Reads a file through io_uring, keeping several reads of the blocks ahead in flight while earlier
+// blocks are read from, so that waiting on the file overlaps with parsing it. The blocks are read
+// in whatever order the reads complete, but are handed out in order. Reads that come up short, as
+// they may from network filesystems, are continued from where they left off.
+pub struct UringReader {
+    file: File,
+    ring: IoUring,
+    blocks: Vec<Block>,
+    // The indexes of the blocks in the order they're to be read from, and how far through the
+    // first of them has been read.
+    order: VecDeque<usize>,
+    pos: usize,
+    next_offset: u64,
+}
+
+impl UringReader {
+    pub fn new(file: File) -> io::Result<Self> {
+        let mut reader = Self {
+            file,
+            ring: IoUring::new(READS_IN_FLIGHT as u32)?,
+            blocks: vec![],
+            order: VecDeque::new(),
+            pos: 0,
+            next_offset: 0,
+        };
+        for idx in 0..READS_IN_FLIGHT {
+            reader.blocks.push(Block {
+                buf: vec![0; BLOCK_SIZE],
+                offset: 0,
+                filled: 0,
+                in_flight: false,
+                complete: false,
+                error: None,
+            });
+            reader.read_next_block(idx)?;
+        }
+        Ok(reader)
+    }
+
+    // Starts reading the next block of the file into the given one.
+    fn read_next_block(&mut self, idx: usize) -> io::Result<()> {
+        let block = &mut self.blocks[idx];
+        block.offset = self.next_offset;
+        block.filled = 0;
+        block.complete = false;
+        self.next_offset += BLOCK_SIZE as u64;
+        self.order.push_back(idx);
+        self.submit(idx)
+    }
+
+    // Submits a read of the rest of the given block.
+    fn submit(&mut self, idx: usize) -> io::Result<()> {
+        let block = &mut self.blocks[idx];
+        let remaining = &mut block.buf[block.filled..];
+        let read = opcode::Read::new(
+            types::Fd(self.file.as_raw_fd()),
+            remaining.as_mut_ptr(),
+            remaining.len() as u32,
+        )
+        .offset(block.offset + block.filled as u64)
+        .build()
+        .user_data(idx as u64);
+        // The block's buffer is neither moved nor freed until the read completes, as blocks are
+        // only ever reused once complete, and dropping the reader waits on every read in flight.
+        unsafe { self.ring.submission().push(&read) }
+            .map_err(|_| io::Error::other("the io_uring submission queue is full"))?;
+        block.in_flight = true;
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    // Waits for at least one read to complete, continuing any that came up short.
+    fn complete_reads(&mut self) -> io::Result<()> {
+        self.ring.submit_and_wait(1)?;
+        let completed: Vec<_> = self
+            .ring
+            .completion()
+            .map(|entry| (entry.user_data() as usize, entry.result()))
+            .collect();
+        for (idx, result) in completed {
+            let block = &mut self.blocks[idx];
+            block.in_flight = false;
+            match result {
+                0 => block.complete = true,
+                n if n > 0 => {
+                    block.filled += n as usize;
+                    if block.filled == block.buf.len() {
+                        block.complete = true;
+                    } else {
+                        self.submit(idx)?;
+                    }
+                }
+                err => match io::Error::from_raw_os_error(-err) {
+                    err if err.kind() == io::ErrorKind::Interrupted => self.submit(idx)?,
+                    err => {
+                        block.error = Some(err);
+                        block.complete = true;
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for UringReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(&idx) = self.order.front() {
+            let block = &mut self.blocks[idx];
+            if !block.complete {
+                self.complete_reads()?;
+                continue;
+            }
+            if let Some(err) = block.error.take() {
+                return Err(err);
+            }
+
+            let n = (block.filled - self.pos).min(buf.len());
+            buf[..n].copy_from_slice(&block.buf[self.pos..self.pos + n]);
+            self.pos += n;
+            if self.pos < block.filled || buf.is_empty() {
+                return Ok(n);
+            }
+
+            // A block that ends short of its size ends the file, so the blocks after it are
+            // abandoned. Otherwise, the block is reused for the next one to be read.
+            self.order.pop_front();
+            self.pos = 0;
+            if block.filled < block.buf.len() {
+                self.order.clear();
+            } else {
+                self.read_next_block(idx)?;
+            }
+            if n > 0 {
+                return Ok(n);
+            }
+        }
+        Ok(0)
+    }
+}
+
+impl Drop for UringReader {
+    fn drop(&mut self) {
+        while self.blocks.iter().any(|block| block.in_flight) {
+            if self.ring.submit_and_wait(1).is_err() {
+                // The kernel may still write into the buffers, so they're leaked rather than freed.
+                std::mem::forget(std::mem::take(&mut self.blocks));
+                return;
+            }
+            let completed: Vec<_> = self
+                .ring
+                .completion()
+                .map(|entry| entry.user_data() as usize)
+                .collect();
+            for idx in completed {
+                self.blocks[idx].in_flight = false;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+
+    #[test]
+    fn reads_blocks_in_order() -> io::Result<()> {
+        let contents: Vec<u8> = (0..BLOCK_SIZE * READS_IN_FLIGHT * 2 + 1234)
+            .map(|idx| (idx % 251) as u8)
+            .collect();
+        let path = env::temp_dir().join(format!("uring-{}.bin", std::process::id()));
+        fs::write(&path, &contents)?;
+
+        let mut read = vec![];
+        UringReader::new(File::open(&path)?)?.read_to_end(&mut read)?;
+        assert!(read == contents, "the file is read back as it is");
+
+        // Stopping part way through leaves no reads behind.
+        let mut reader = UringReader::new(File::open(&path)?)?;
+        reader.read_exact(&mut [0; 100])?;
+        drop(reader);
+
+        fs::remove_file(path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::Mutex;
+
+use rust_decimal::Decimal;
+
+use crate::models::{
+    account::{Account, AccountId, AccountKey, Tenant},
+    transaction::{OrderedTransaction, TransactionType},
+};
+use crate::observer::EventObserver;
+
+// Tracks the net flow of funds into each account as transactions are applied, i.e. the sum of its
+// deposits less the sum of its withdrawals and chargebacks. Custom transaction types are not
+// tracked, as only their handlers know how they affect an account's funds.
+#[derive(Debug, Default)]
+pub struct LedgerTotals {
+    net_flows: Mutex<HashMap<AccountKey, Decimal>>,
+}
+
+impl LedgerTotals {
+    // Starts from the totals of accounts carried on from a previous run, as though their funds had
+    // flowed into them.
+    pub fn opening(accounts: &[Account]) -> Self {
+        Self {
+            net_flows: Mutex::new(
+                accounts
+                    .iter()
+                    .map(|account| (account.key(), account.total()))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn net_flows(&self) -> HashMap<AccountKey, Decimal> {
+        self.net_flows
+            .lock()
+            .expect("ledger totals lock poisoned")
+            .clone()
+    }
+}
+
+impl EventObserver for LedgerTotals {
+    fn on_applied(&self, txn: &OrderedTransaction, account: &Account) {
+        let txn = txn.txn();
+        let flow = match txn.txn_type() {
+            TransactionType::Deposit { amount } => amount,
+            TransactionType::Withdrawal { amount } => -amount,
+            // A chargeback takes back the funds of the transaction it refers to.
+            TransactionType::Chargeback => -account
+                .past_txn(txn.id())
+                .and_then(|past_txn| past_txn.txn_type().amount())
+                .unwrap_or_default(),
+            _ => return,
+        };
+
+        *self
+            .net_flows
+            .lock()
+            .expect("ledger totals lock poisoned")
+            .entry(txn.account_key())
+            .or_default() += flow;
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Violation {
+    // The account's total funds don't match the net flow of funds into it.
+    TotalMismatch {
+        tenant: Option<Tenant>,
+        client: AccountId,
+        net_flow: Decimal,
+        total: Decimal,
+    },
+    NegativeHeld {
+        tenant: Option<Tenant>,
+        client: AccountId,
+        held: Decimal,
+    },
+    // There is no overdraft facility, so available funds should never drop below zero.
+    NegativeAvailable {
+        tenant: Option<Tenant>,
+        client: AccountId,
+        available: Decimal,
+    },
+}
+
+impl Violation {
+    pub fn client(&self) -> AccountId {
+        match self {
+            Self::TotalMismatch { client, .. }
+            | Self::NegativeHeld { client, .. }
+            | Self::NegativeAvailable { client, .. } => *client,
+        }
+    }
+
+    pub fn tenant(&self) -> Option<Tenant> {
+        match self {
+            Self::TotalMismatch { tenant, .. }
+            | Self::NegativeHeld { tenant, .. }
+            | Self::NegativeAvailable { tenant, .. } => *tenant,
+        }
+    }
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(tenant) = self.tenant() {
+            write!(f, "tenant {tenant} ")?;
+        }
+        match self {
+            Self::TotalMismatch {
+                client,
+                net_flow,
+                total,
+                ..
+            } => write!(
+                f,
+                "client {client}: total {total} does not equal deposits less withdrawals and chargebacks of {net_flow}"
+            ),
+            Self::NegativeHeld { client, held, .. } => write!(f, "client {client}: held {held} is negative"),
+            Self::NegativeAvailable {
+                client, available, ..
+            } => {
+                write!(f, "client {client}: available {available} is negative")
+            }
+        }
+    }
+}
+
+// Checks the invariants that should hold for every account once processing has completed,
+// returning every violation ordered by tenant and client. The net flows are those gathered by
+// `LedgerTotals`.
+pub fn verify(accounts: &[Account], net_flows: &HashMap<AccountKey, Decimal>) -> Vec<Violation> {
+    let accounts: BTreeMap<_, _> = accounts
+        .iter()
+        .map(|account| (account.key(), account))
+        .collect();
+
+    let mut violations = vec![];
+    for ((tenant, client), account) in accounts {
+        let net_flow = net_flows
+            .get(&(tenant, client))
+            .copied()
+            .unwrap_or_default();
+        if account.total() != net_flow {
+            violations.push(Violation::TotalMismatch {
+                tenant,
+                client,
+                net_flow,
+                total: account.total(),
+            });
+        }
+        if account.held() < Decimal::ZERO {
+            violations.push(Violation::NegativeHeld {
+                tenant,
+                client,
+                held: account.held(),
+            });
+        }
+        if account.available() < Decimal::ZERO {
+            violations.push(Violation::NegativeAvailable {
+                tenant,
+                client,
+                available: account.available(),
+            });
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use crate::models::transaction::{Transaction, TransactionId};
+
+    #[test]
+    fn violations() -> Result<(), Box<dyn Error>> {
+        let ledger = LedgerTotals::default();
+        let mut accounts = vec![Account::new(1.into()), Account::new(2.into())];
+
+        // Account 1 disputes a deposit after withdrawing most of it, leaving it overdrawn.
+        let txns = [
+            (
+                0,
+                1,
+                TransactionType::Deposit {
+                    amount: "10".parse()?,
+                },
+            ),
+            (
+                0,
+                2,
+                TransactionType::Withdrawal {
+                    amount: "8".parse()?,
+                },
+            ),
+            (0, 1, TransactionType::Dispute),
+            (
+                1,
+                3,
+                TransactionType::Deposit {
+                    amount: "5".parse()?,
+                },
+            ),
+        ];
+        for (order, (idx, txn_id, txn_type)) in txns.into_iter().enumerate() {
+            let account = &mut accounts[idx];
+            let txn = Transaction::new(TransactionId::from(txn_id), account.id(), txn_type);
+            account.process_txn(txn.clone())?;
+            ledger.on_applied(&OrderedTransaction::new(order as u64, txn), account);
+        }
+
+        // Account 2's funds appeared from nowhere.
+        accounts[1].credit("1".parse()?);
+
+        assert_eq!(
+            verify(&accounts, &ledger.net_flows()),
+            [
+                Violation::NegativeAvailable {
+                    tenant: None,
+                    client: 1.into(),
+                    available: "-8".parse()?,
+                },
+                Violation::TotalMismatch {
+                    tenant: None,
+                    client: 2.into(),
+                    net_flow: "5".parse()?,
+                    total: "6".parse()?,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Whatever};
+
+use crate::models::{
+    account::Account,
+    transaction::{OrderedTransaction, Transaction},
+};
+use crate::snapshot;
+use crate::stats::RunId;
+
+// Identifies the run that the write-ahead logs within a directory belong to.
+const RUN_FILE_NAME: &str = "run.json";
+
+// A transaction as it is recorded in a write-ahead log.
+#[derive(Deserialize, Serialize)]
+struct WalEntry {
+    order: u64,
+    txn: Transaction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    memo: Option<Arc<str>>,
+    // Transactions turned away for being over their client's rate limit are logged too, so that
+    // they're turned away again when replayed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    rate_limited: bool,
+}
+
+// A worker's write-ahead log, to which each transaction is appended before the worker applies it,
+// so that the transactions applied since the last snapshot can be replayed should the run crash.
+//
+// Each transaction is handed to the OS before it's applied, which is enough to survive the process
+// crashing, and the log is synced to disk at each snapshot. A snapshot starts a new log, keeping
+// the previous one until the next snapshot, as the snapshot may yet fail to complete.
+pub struct WalWriter {
+    dir: PathBuf,
+    worker_idx: usize,
+    writer: BufWriter<File>,
+}
+
+impl WalWriter {
+    fn open(dir: &Path, worker_idx: usize) -> io::Result<Self> {
+        let path = current_path(dir, worker_idx);
+        trim_partial_entry(&path)?;
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            worker_idx,
+            writer: BufWriter::new(file),
+        })
+    }
+
+    // Appends a transaction to the log, which isn't written out until the log is flushed.
+    pub(crate) fn append(&mut self, txn: &OrderedTransaction) -> io::Result<()> {
+        let entry = WalEntry {
+            order: txn.order(),
+            txn: txn.txn().clone(),
+            memo: txn.shared_memo().cloned(),
+            rate_limited: txn.rate_limited(),
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")
+    }
+
+    // Hands the transactions appended so far to the OS.
+    pub(crate) fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    // Syncs the log to disk and starts a new one, once a snapshot has been taken of every
+    // transaction in it.
+    pub(crate) fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        fs::rename(
+            current_path(&self.dir, self.worker_idx),
+            previous_path(&self.dir, self.worker_idx),
+        )?;
+        *self = Self::open(&self.dir, self.worker_idx)?;
+        Ok(())
+    }
+}
+
+// Removes the last entry of a log if it was only partly written when the run crashed, so that the
+// entries appended after it aren't mistaken for part of it.
+fn trim_partial_entry(path: &Path) -> io::Result<()> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    if data.is_empty() || data.ends_with(b"\n") {
+        return Ok(());
+    }
+    let len = data
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |idx| idx + 1);
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(len as u64)
+}
+
+fn current_path(dir: &Path, worker_idx: usize) -> PathBuf {
+    dir.join(format!("wal-{worker_idx}.jsonl"))
+}
+
+fn previous_path(dir: &Path, worker_idx: usize) -> PathBuf {
+    dir.join(format!("wal-{worker_idx}.prev.jsonl"))
+}
+
+// Opens a write-ahead log for each of the workers of the given run within the given directory,
+// appending to any that are already there.
+pub fn open(dir: &Path, num_workers: usize, run_id: RunId) -> Result<Vec<WalWriter>, Whatever> {
+    fs::create_dir_all(dir).with_whatever_context(|_| {
+        format!(
+            "unable to create write-ahead log directory {}",
+            dir.display()
+        )
+    })?;
+    let run_path = dir.join(RUN_FILE_NAME);
+    serde_json::to_vec(&run_id)
+        .map_err(io::Error::from)
+        .and_then(|run| fs::write(&run_path, run))
+        .with_whatever_context(|_| format!("unable to write {}", run_path.display()))?;
+    (0..num_workers)
+        .map(|worker_idx| {
+            WalWriter::open(dir, worker_idx).with_whatever_context(|_| {
+                format!("unable to open write-ahead log in {}", dir.display())
+            })
+        })
+        .collect()
+}
+
+// Removes the write-ahead logs once the run they belong to has completed, so that the next run
+// doesn't recover from them.
+pub fn clear(dir: &Path) -> Result<(), Whatever> {
+    for path in log_paths(dir)?.into_iter().chain([dir.join(RUN_FILE_NAME)]) {
+        if !path.exists() {
+            continue;
+        }
+        fs::remove_file(&path)
+            .with_whatever_context(|_| format!("unable to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+// What a run that crashed had got through, as recovered from its last snapshot and write-ahead logs.
+pub struct Recovery {
+    // The run that crashed, which the recovered run carries on as.
+    pub run_id: RunId,
+    // The snapshot the accounts were restored from, if there was one.
+    pub snapshot: Option<PathBuf>,
+    pub accounts: Vec<Account>,
+    // The transactions applied after the snapshot was taken, in order.
+    pub replay: Vec<OrderedTransaction>,
+    // The order of the first transaction that the run never logged, up to which the transactions
+    // of its input should be skipped when it's read again.
+    pub next_order: u64,
+}
+
+// Recovers the state of a run that didn't complete from the write-ahead logs in `wal_dir`, by
+// restoring the latest snapshot it took in `snapshot_dir` and replaying the transactions logged
+// since. This returns nothing if there are no logs to recover from, as there aren't after a run
+// completes.
+//
+// The last line of a log is ignored if it was only partly written when the run crashed, as its
+// transaction was never applied.
+pub fn recover(wal_dir: &Path, snapshot_dir: Option<&Path>) -> Result<Option<Recovery>, Whatever> {
+    let paths = log_paths(wal_dir)?;
+    if paths.is_empty() {
+        return Ok(None);
+    }
+
+    let run_path = wal_dir.join(RUN_FILE_NAME);
+    let run_id: RunId = fs::read(&run_path)
+        .map_err(serde_json::Error::io)
+        .and_then(|run| serde_json::from_slice(&run))
+        .with_whatever_context(|_| format!("unable to read {}", run_path.display()))?;
+
+    let latest = snapshot_dir
+        .map(|dir| snapshot::latest(dir, run_id))
+        .transpose()?
+        .flatten();
+    let (snapshot, accounts, snapshot_order) = match latest {
+        Some((dir, manifest)) => {
+            let Some(accounts) = snapshot::load_state(&dir, &manifest)? else {
+                snafu::whatever!(
+                    "snapshot {} has no account state to recover from, as it was taken by an older release",
+                    dir.display()
+                );
+            };
+            (Some(dir), accounts, manifest.next_order)
+        }
+        None => {
+            // A log that has been rotated only holds the transactions since a snapshot.
+            if let Some(path) = paths.iter().find(|path| is_previous(path)) {
+                snafu::whatever!(
+                    "{} was started by a snapshot, and can only be recovered from the snapshot directory it was taken in",
+                    path.display()
+                );
+            }
+            (None, vec![], 0)
+        }
+    };
+
+    // Transactions may appear in more than one log, if they were replayed by a previous recovery.
+    let mut replay = BTreeMap::new();
+    for path in &paths {
+        let file = File::open(path)
+            .with_whatever_context(|_| format!("unable to open {}", path.display()))?;
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line =
+                line.with_whatever_context(|_| format!("unable to read {}", path.display()))?;
+            let entry = match serde_json::from_str::<WalEntry>(&line) {
+                Ok(entry) => entry,
+                Err(err) if err.is_eof() => {
+                    tracing::warn!(
+                        file = %path.display(),
+                        line = idx + 1,
+                        "Ignoring a transaction that was only partly written to the write-ahead log"
+                    );
+                    break;
+                }
+                Err(err) => snafu::whatever!("{} line {}: {err}", path.display(), idx + 1),
+            };
+            if entry.order >= snapshot_order {
+                let txn = OrderedTransaction::new(entry.order, entry.txn)
+                    .with_memo(entry.memo)
+                    .with_rate_limited(entry.rate_limited);
+                replay.insert(entry.order, txn);
+            }
+        }
+    }
+
+    // Each worker logs a transaction as it takes it off its queue, so one that was held up behind
+    // the others may not have logged transactions that were read before ones they did. The run is
+    // resumed from the first transaction that no worker logged, and those logged after it are
+    // applied again as the input is read, rather than replayed out of order.
+    let next_order = replay
+        .keys()
+        .zip(snapshot_order..)
+        .take_while(|&(&order, expected)| order == expected)
+        .last()
+        .map_or(snapshot_order, |(&order, _)| order + 1);
+    replay.split_off(&next_order);
+    Ok(Some(Recovery {
+        run_id,
+        snapshot,
+        accounts,
+        replay: replay.into_values().collect(),
+        next_order,
+    }))
+}
+
+fn is_previous(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(".prev.jsonl")
+}
+
+// The write-ahead logs within the given directory, if it exists.
+fn log_paths(dir: &Path) -> Result<Vec<PathBuf>, Whatever> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(err) => {
+            return Err(err).with_whatever_context(|_| format!("unable to list {}", dir.display()))
+        }
+    };
+
+    let mut paths = vec![];
+    for entry in entries {
+        let path = entry
+            .with_whatever_context(|_| format!("unable to list {}", dir.display()))?
+            .path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if name.starts_with("wal-") && name.ends_with(".jsonl") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use rust_decimal::Decimal;
+    use uuid::Uuid;
+
+    use crate::models::transaction::{RawTransactionId, TransactionId, TransactionType};
+
+    fn deposit(order: u64, id: RawTransactionId) -> OrderedTransaction {
+        let txn = Transaction::new(
+            TransactionId::Number(id),
+            1.into(),
+            TransactionType::Deposit {
+                amount: Decimal::new(15, 1),
+            },
+        );
+        OrderedTransaction::new(order, txn)
+    }
+
+    #[test]
+    fn recovers_logged_transactions() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("wal-{}", Uuid::new_v4()));
+        let run_id = RunId::generate();
+        let mut logs = open(&dir, 2, run_id)?;
+        let with_memo = deposit(2, 3).with_memo(Some("payroll".into()));
+        for (log, txn) in [(0, deposit(0, 1)), (1, deposit(1, 2)), (0, with_memo)] {
+            logs[log].append(&txn)?;
+        }
+        // A transaction replayed by an earlier recovery appears twice.
+        logs[1].append(&deposit(1, 2))?;
+        for log in &mut logs {
+            log.flush()?;
+        }
+        drop(logs);
+        let mut torn = OpenOptions::new()
+            .append(true)
+            .open(current_path(&dir, 0))?;
+        torn.write_all(br#"{"order":3,"txn":{"tx":4,"#)?;
+
+        let recovery = recover(&dir, None)?.ok_or("there are logs to recover from")?;
+        assert_eq!(recovery.run_id, run_id);
+        assert!(recovery.accounts.is_empty());
+        let replayed: Vec<_> = recovery
+            .replay
+            .iter()
+            .map(|txn| (txn.order(), txn.txn().id()))
+            .collect();
+        assert_eq!(
+            replayed,
+            [
+                (0, TransactionId::Number(1)),
+                (1, TransactionId::Number(2)),
+                (2, TransactionId::Number(3))
+            ]
+        );
+        assert_eq!(recovery.replay[2].memo(), Some("payroll"));
+        assert_eq!(recovery.next_order, 3);
+
+        // Reopening the logs drops the partly written transaction, so that it can be logged again.
+        let mut logs = open(&dir, 1, run_id)?;
+        logs[0].append(&deposit(3, 4))?;
+        logs[0].flush()?;
+        assert_eq!(
+            recover(&dir, None)?.map(|recovery| recovery.next_order),
+            Some(4)
+        );
+
+        clear(&dir)?;
+        assert!(recover(&dir, None)?.is_none());
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn resumes_from_stalled_worker() -> Result<(), Box<dyn Error>> {
+        let dir = std::env::temp_dir().join(format!("wal-{}", Uuid::new_v4()));
+        let mut logs = open(&dir, 2, RunId::generate())?;
+        // The run crashes while the second worker is still working through a backlog, before
+        // it gets to the transaction it was handed second.
+        for (log, txn) in [(0, deposit(0, 1)), (0, deposit(2, 3)), (0, deposit(3, 4))] {
+            logs[log].append(&txn)?;
+        }
+        for log in &mut logs {
+            log.flush()?;
+        }
+        drop(logs);
+
+        let recovery = recover(&dir, None)?.ok_or("there are logs to recover from")?;
+        let replayed: Vec<_> = recovery.replay.iter().map(|txn| txn.order()).collect();
+        assert_eq!(replayed, [0]);
+        assert_eq!(recovery.next_order, 1);
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn fails_worker_unable_to_log() -> Result<(), Box<dyn Error>> {
+        use crate::processor::TransactionProcessor;
+
+        // Every write to /dev/full fails as though the disk were full.
+        let log = WalWriter {
+            dir: std::env::temp_dir(),
+            worker_idx: 0,
+            writer: BufWriter::new(OpenOptions::new().write(true).open("/dev/full")?),
+        };
+        let processor = TransactionProcessor::builder(1).wal(vec![log]).build();
+        let receipt = processor.process_batch(&[deposit(0, 1).txn().clone()])?;
+        assert!(
+            receipt.wait().is_err(),
+            "a transaction that can't be logged shouldn't be applied"
+        );
+        let err = processor
+            .shutdown()
+            .err()
+            .ok_or("the run should fail once a worker can't write to its log")?;
+        assert!(
+            err.to_string()
+                .contains("unable to write to the write-ahead log"),
+            "{err}"
+        );
+        Ok(())
+    }
+}
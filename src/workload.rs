@@ -0,0 +1,247 @@
+use std::io::{self, Write};
+
+use rust_decimal::Decimal;
+
+use crate::models::{
+    account::{AccountId, RawAccountId},
+    transaction::{RawTransactionId, Transaction, TransactionId, TransactionType},
+};
+
+// How many of the most recent deposits are held on to as candidates for disputes.
+const RECENT_DEPOSITS: usize = 1024;
+
+// A reproducible stream of transactions, for benchmarking the processor without needing a file of
+// production size at hand. The same seed always generates the same transactions.
+//
+// Clients are picked at random, as are the amounts of deposits and withdrawals. Disputes are of one
+// of the recent deposits, and are later either resolved or, now and again, charged back, which
+// locks the account for the rest of the stream.
+#[derive(Clone, Debug)]
+pub struct Workload {
+    seed: u64,
+    transactions: u64,
+    clients: RawAccountId,
+    withdrawals: f64,
+    disputes: f64,
+}
+
+impl Workload {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            transactions: 100_000,
+            clients: 1_000,
+            withdrawals: 0.2,
+            disputes: 0.02,
+        }
+    }
+
+    // The number of transactions to generate.
+    pub fn with_transactions(mut self, transactions: u64) -> Self {
+        self.transactions = transactions;
+        self
+    }
+
+    // The number of clients to spread the transactions across, with IDs counting up from 1.
+    pub fn with_clients(mut self, clients: RawAccountId) -> Self {
+        self.clients = clients.max(1);
+        self
+    }
+
+    // The share of the transactions that are withdrawals.
+    pub fn with_withdrawals(mut self, share: f64) -> Self {
+        self.withdrawals = share;
+        self
+    }
+
+    // The share of the transactions that are disputes, or the resolutions and chargebacks that
+    // settle them.
+    pub fn with_disputes(mut self, share: f64) -> Self {
+        self.disputes = share;
+        self
+    }
+
+    pub fn iter(&self) -> WorkloadIter {
+        WorkloadIter {
+            workload: self.clone(),
+            rng: SplitMix64(self.seed),
+            generated: 0,
+            next_id: 1,
+            recent: Vec::with_capacity(RECENT_DEPOSITS),
+            disputed: vec![],
+        }
+    }
+
+    pub fn generate(&self) -> Vec<Transaction> {
+        self.iter().collect()
+    }
+
+    // Writes the transactions out as CSV, in the shape they are read from the input.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "type,client,tx,amount")?;
+        for txn in self.iter() {
+            let amount = txn.txn_type().amount();
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                txn.txn_type().name(),
+                txn.account_id(),
+                txn.id(),
+                amount.map(|amount| amount.to_string()).unwrap_or_default()
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+pub struct WorkloadIter {
+    workload: Workload,
+    rng: SplitMix64,
+    generated: u64,
+    next_id: u64,
+    recent: Vec<(AccountId, TransactionId)>,
+    disputed: Vec<(AccountId, TransactionId)>,
+}
+
+impl WorkloadIter {
+    // Disputes one of the recent deposits, or settles one of the deposits in dispute.
+    fn dispute(&mut self) -> Option<Transaction> {
+        if !self.disputed.is_empty() && (self.recent.is_empty() || self.rng.chance(0.5)) {
+            let idx = self.rng.below(self.disputed.len() as u64) as usize;
+            let (client, id) = self.disputed.swap_remove(idx);
+            let txn_type = if self.rng.chance(0.05) {
+                TransactionType::Chargeback
+            } else {
+                TransactionType::Resolve
+            };
+            return Some(Transaction::new(id, client, txn_type));
+        }
+        if self.recent.is_empty() {
+            return None;
+        }
+        let idx = self.rng.below(self.recent.len() as u64) as usize;
+        let (client, id) = self.recent.swap_remove(idx);
+        self.disputed.push((client, id));
+        Some(Transaction::new(id, client, TransactionType::Dispute))
+    }
+
+    // A deposit or withdrawal of a random client, of up to 100 or 50 respectively.
+    fn transfer(&mut self, withdrawal: bool) -> Transaction {
+        let client =
+            AccountId::from((self.rng.below(self.workload.clients.into()) + 1) as RawAccountId);
+        let id = TransactionId::from(self.next_id as RawTransactionId);
+        self.next_id += 1;
+        let scale = if withdrawal { 500_000 } else { 1_000_000 };
+        let amount = Decimal::new(self.rng.below(scale) as i64 + 1, 4);
+
+        if withdrawal {
+            return Transaction::new(id, client, TransactionType::Withdrawal { amount });
+        }
+        if self.recent.len() == RECENT_DEPOSITS {
+            let idx = self.rng.below(RECENT_DEPOSITS as u64) as usize;
+            self.recent[idx] = (client, id);
+        } else {
+            self.recent.push((client, id));
+        }
+        Transaction::new(id, client, TransactionType::Deposit { amount })
+    }
+}
+
+impl Iterator for WorkloadIter {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.generated == self.workload.transactions {
+            return None;
+        }
+        self.generated += 1;
+
+        let roll = self.rng.unit();
+        if roll < self.workload.disputes {
+            if let Some(txn) = self.dispute() {
+                return Some(txn);
+            }
+        }
+        Some(self.transfer(roll < self.workload.disputes + self.workload.withdrawals))
+    }
+}
+
+// A small, fast generator that is good enough for picking transactions, and keeps the workload
+// the same across platforms and releases of any random number crate.
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in [0, 1).
+    pub(crate) fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    pub(crate) fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        self.unit() < probability
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn generates_reproducible_workload() {
+        let workload = Workload::new(7)
+            .with_transactions(10_000)
+            .with_clients(50)
+            .with_disputes(0.1);
+        let txns = workload.generate();
+        assert_eq!(txns.len(), 10_000);
+        let render = |txns: &[Transaction]| -> Vec<String> {
+            txns.iter().map(|txn| txn.to_string()).collect()
+        };
+        assert_eq!(render(&txns), render(&workload.generate()));
+        assert_ne!(
+            render(&txns),
+            render(&Workload::new(8).with_transactions(10_000).generate())
+        );
+
+        // Every dispute, resolution and chargeback refers to an earlier deposit of the same client.
+        let mut deposits = HashMap::new();
+        let mut counts = HashMap::new();
+        for txn in &txns {
+            *counts.entry(txn.type_name()).or_insert(0) += 1;
+            match txn.txn_type() {
+                TransactionType::Deposit { .. } => {
+                    deposits.insert(txn.id(), txn.account_id());
+                }
+                TransactionType::Withdrawal { .. } => {}
+                _ => assert_eq!(deposits.get(&txn.id()), Some(&txn.account_id())),
+            }
+        }
+        for name in ["deposit", "withdrawal", "dispute", "resolve", "chargeback"] {
+            assert!(counts.contains_key(name), "no {name} was generated");
+        }
+
+        let mut csv = vec![];
+        workload
+            .write_csv(&mut csv)
+            .expect("writing to a Vec can't fail");
+        assert_eq!(csv.iter().filter(|&&b| b == b'\n').count(), 10_001);
+    }
+}
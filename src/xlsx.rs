@@ -0,0 +1,161 @@
+use std::io::Cursor;
+
+use calamine::{Data, Range, Reader, Xlsx};
+
+use crate::bank_profile::BankProfile;
+use crate::importer::{Importer, StatementRecord};
+use crate::rejects::Rejection;
+
+// Imports the transactions on a sheet of an Excel workbook, whose first row names its columns.
+// Each row is mapped onto a transaction by the profile, and is reported by its row number should
+// it be rejected.
+pub struct XlsxImporter {
+    // The name of the sheet to read, or otherwise the first.
+    pub sheet: Option<String>,
+    pub profile: BankProfile,
+}
+
+impl Importer for XlsxImporter {
+    fn import(&self, data: &[u8]) -> Vec<StatementRecord> {
+        match read_sheet(data, self.sheet.as_deref()) {
+            Ok(range) => records(&range, &self.profile),
+            Err(reason) => vec![StatementRecord {
+                line: 0,
+                result: Err(Rejection {
+                    line: None,
+                    column: None,
+                    raw_field: None,
+                    reason,
+                    memo: None,
+                }),
+            }],
+        }
+    }
+
+    fn amount_column(&self) -> &str {
+        self.profile.amount_column()
+    }
+}
+
+fn read_sheet(data: &[u8], sheet: Option<&str>) -> Result<Range<Data>, String> {
+    let mut workbook = Xlsx::new(Cursor::new(data))
+        .map_err(|err| format!("The file is not an Excel workbook: {err}"))?;
+    let name = match sheet {
+        Some(name) => name.to_owned(),
+        None => workbook
+            .sheet_names()
+            .first()
+            .cloned()
+            .ok_or("The workbook has no sheets")?,
+    };
+    workbook
+        .worksheet_range(&name)
+        .map_err(|err| format!("Unable to read the sheet '{name}': {err}"))
+}
+
+fn records(range: &Range<Data>, profile: &BankProfile) -> Vec<StatementRecord> {
+    // Rows are numbered from one, as they are in Excel, and the range may not start at the first.
+    let first_row = range.start().map_or(1, |(row, _)| u64::from(row) + 1);
+    let mut rows = range.rows();
+    let Some(headers) = rows.next() else {
+        return vec![];
+    };
+    let headers: Vec<String> = headers.iter().map(cell_text).collect();
+
+    rows.enumerate()
+        .filter(|(_, row)| row.iter().any(|cell| *cell != Data::Empty))
+        .map(|(idx, row)| {
+            let line = first_row + idx as u64 + 1;
+            let cells: Vec<String> = row.iter().map(cell_text).collect();
+            let field = |column: &str| {
+                headers
+                    .iter()
+                    .position(|header| header.trim() == column)
+                    .and_then(|idx| cells.get(idx))
+                    .map(String::as_str)
+            };
+            StatementRecord {
+                line,
+                result: profile.transaction(field, line),
+            }
+        })
+        .collect()
+}
+
+// The text of a cell as it would appear in a CSV file. Dates are written as e.g. `2024-01-02`, so
+// a profile checking the dates of a workbook should expect them in the format `YYYY-MM-DD`.
+fn cell_text(cell: &Data) -> String {
+    match cell {
+        Data::DateTime(datetime) if datetime.is_datetime() => {
+            let (year, month, day, ..) = datetime.to_ymd_hms_milli();
+            format!("{year:04}-{month:02}-{day:02}")
+        }
+        cell => cell.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+
+    use calamine::{ExcelDateTime, ExcelDateTimeType};
+    use rust_decimal::Decimal;
+
+    use crate::models::{account::AccountId, transaction::TransactionId};
+
+    #[test]
+    fn maps_rows() -> Result<(), Box<dyn Error>> {
+        let mut range = Range::new((2, 0), (5, 4));
+        let rows = [
+            ["type", "client", "tx", "amount", "memo"].map(|s| Data::String(s.to_owned())),
+            [
+                Data::String("deposit".to_owned()),
+                Data::Float(1.0),
+                Data::Int(10),
+                Data::Float(2.5),
+                Data::String("Correction".to_owned()),
+            ],
+            [
+                Data::Empty,
+                Data::Empty,
+                Data::Empty,
+                Data::Empty,
+                Data::Empty,
+            ],
+            [
+                Data::String("withdrawal".to_owned()),
+                Data::Float(1.0),
+                Data::Float(11.0),
+                Data::String("lots".to_owned()),
+                Data::Empty,
+            ],
+        ];
+        for (row, cells) in rows.into_iter().enumerate() {
+            for (col, cell) in cells.into_iter().enumerate() {
+                range.set_value((row as u32 + 2, col as u32), cell);
+            }
+        }
+
+        let records = records(&range, &BankProfile::native());
+        assert_eq!(records.len(), 2, "empty rows should be skipped");
+
+        let txn = records[0].result.clone()?;
+        assert_eq!(records[0].line, 4);
+        assert_eq!(txn.txn.id(), TransactionId::Number(10));
+        assert_eq!(txn.txn.account_id(), AccountId::Number(1));
+        assert_eq!(txn.txn.txn_type().amount(), Some(Decimal::new(25, 1)));
+        assert_eq!(txn.memo.as_deref(), Some("Correction"));
+
+        let rejection = records[1].result.clone().unwrap_err();
+        assert_eq!(rejection.line, Some(6));
+        assert_eq!(rejection.column.as_deref(), Some("amount"));
+        Ok(())
+    }
+
+    #[test]
+    fn dates() {
+        let date = ExcelDateTime::new(45293.75, ExcelDateTimeType::DateTime, false);
+        assert_eq!(cell_text(&Data::DateTime(date)), "2024-01-02");
+    }
+}